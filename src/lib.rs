@@ -0,0 +1,16 @@
+//! Pible: a HolyC to BPF compiler.
+//!
+//! Without the default `std` feature the crate is `no_std` and needs only
+//! `alloc`; [`Compiler::compile`] still turns source text into bytecode, but
+//! `#include`, file output and console messages are unavailable.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod pible;
+
+pub use pible::*;
+
+#[cfg(all(test, feature = "std"))]
+mod tests;
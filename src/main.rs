@@ -1,11 +1,20 @@
 use clap::{Arg, Command};
+use std::io::IsTerminal;
 
-mod pible;
+use pible::{
+    bpf_vm, formatter, repl, CompileOptions, CompileTarget, Compiler, OutputFormat, ProgramType,
+};
 
-#[cfg(test)]
-mod tests;
-
-use pible::{CompileOptions, CompileTarget, Compiler};
+/// Values of `--target`; the experimental backends need their feature.
+const TARGETS: &[&str] = &[
+    "linux-bpf",
+    "solana-bpf",
+    "bpf-vm",
+    #[cfg(feature = "riscv")]
+    "riscv",
+    #[cfg(feature = "wasm")]
+    "wasm",
+];
 
 fn main() -> anyhow::Result<()> {
     let matches = Command::new("pible")
@@ -28,7 +37,7 @@ fn main() -> anyhow::Result<()> {
                 .help("Compilation target")
                 .value_name("TARGET")
                 .default_value("linux-bpf")
-                .value_parser(["linux-bpf", "solana-bpf", "bpf-vm"]),
+                .value_parser(clap::builder::PossibleValuesParser::new(TARGETS)),
         )
         .arg(
             Arg::new("generate-idl")
@@ -42,19 +51,171 @@ fn main() -> anyhow::Result<()> {
                 .help("Enable BPF VM testing and emulation")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("format")
+                .long("format")
+                .help("Output format (elf is supported for linux-bpf and solana-bpf, asm for BPF targets)")
+                .value_name("FORMAT")
+                .default_value("raw")
+                .value_parser(["raw", "elf", "asm"]),
+        )
+        .arg(
+            Arg::new("program-type")
+                .long("program-type")
+                .help(
+                    "Linux BPF program type: socket, xdp, kprobe:<function>, \
+                     tracepoint:<category>/<name> or cgroup-skb",
+                )
+                .value_name("TYPE")
+                .default_value("socket")
+                .value_parser(|spec: &str| {
+                    ProgramType::parse(spec).ok_or_else(|| format!("unknown program type {}", spec))
+                }),
+        )
+        .arg(
+            Arg::new("emit-asm")
+                .long("emit-asm")
+                .help("Write a disassembly listing (.s) next to the output")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dump-ast")
+                .long("dump-ast")
+                .help("Print the parsed AST as JSON and exit without generating code")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("emit-deps")
+                .long("emit-deps")
+                .help(
+                    "Print a Makefile rule listing the source and its includes, \
+                     and exit without compiling",
+                )
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("fmt")
+                .long("fmt")
+                .help("Print the source in canonical layout and exit without compiling")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("opt-level")
+                .short('O')
+                .long("opt-level")
+                .help("Optimization level (0 disables constant folding and peephole passes; 2 also allocates registers for arithmetic)")
+                .value_name("LEVEL")
+                .default_value("0")
+                .value_parser(clap::value_parser!(u8).range(0..=2)),
+        )
+        .arg(
+            Arg::new("deny-warnings")
+                .long("deny-warnings")
+                .alias("strict")
+                .help("Treat warnings such as unreachable code or GPL-only helpers as errors")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .help("Check Solana programs against the full verifier ruleset")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("log-helper")
+                .long("log-helper")
+                .help("Helper id PrintF calls, for runtimes with a custom logging helper")
+                .value_name("ID")
+                .value_parser(clap::value_parser!(i32)),
+        )
+        .arg(
+            Arg::new("safe-arrays")
+                .long("safe-arrays")
+                .help("Exit with an error code when an array index is out of bounds")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("inline-threshold")
+                .long("inline-threshold")
+                .help("Inline functions of at most this many syntax tree nodes (0 disables)")
+                .value_name("NODES")
+                .default_value("0")
+                .value_parser(clap::value_parser!(usize)),
+        )
         .arg(
             Arg::new("output-dir")
                 .long("output-dir")
                 .help("Output directory for generated files")
                 .value_name("DIR"),
         )
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("run")
+                .about("Run bytecode compiled for the bpf-vm target in the built-in VM")
+                .arg(
+                    Arg::new("program")
+                        .help("Compiled .bpf file")
+                        .required(true)
+                        .value_name("FILE")
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .help("Bytes, in hex, to place at the start of memory; R1 points at them")
+                        .value_name("HEX")
+                        .value_parser(parse_hex),
+                ),
+        )
+        .subcommand(
+            Command::new("repl")
+                .about("Evaluate HolyC expressions line by line, keeping earlier declarations"),
+        )
         .get_matches();
 
+    if matches.subcommand_matches("repl").is_some() {
+        let stdin = std::io::stdin();
+        let prompt = stdin.is_terminal();
+        repl::run(stdin.lock(), std::io::stdout(), prompt)?;
+        return Ok(());
+    }
+    if let Some(run) = matches.subcommand_matches("run") {
+        let bytecode = std::fs::read(run.get_one::<String>("program").unwrap())?;
+        let input = run.get_one::<Vec<u8>>("input").cloned().unwrap_or_default();
+        println!("{}", bpf_vm::run_bytecode(&bytecode, &input)?);
+        return Ok(());
+    }
+
     let input_file = matches.get_one::<String>("input").unwrap();
+
+    // Machine-readable output only, so skip the banner
+    if matches.get_flag("dump-ast") {
+        let source = std::fs::read_to_string(input_file)?;
+        println!("{}", Compiler::new().dump_ast(&source)?);
+        return Ok(());
+    }
+    if matches.get_flag("fmt") {
+        let source = std::fs::read_to_string(input_file)?;
+        print!("{}", formatter::format_source(&source)?);
+        return Ok(());
+    }
+
     let target = match matches.get_one::<String>("target").unwrap().as_str() {
         "linux-bpf" => CompileTarget::LinuxBpf,
         "solana-bpf" => CompileTarget::SolanaBpf,
         "bpf-vm" => CompileTarget::BpfVm,
+        #[cfg(feature = "riscv")]
+        "riscv" => CompileTarget::RiscV,
+        #[cfg(feature = "wasm")]
+        "wasm" => CompileTarget::Wasm,
+        _ => unreachable!(), // clap ensures valid values
+    };
+
+    let output_format = match matches.get_one::<String>("format").unwrap().as_str() {
+        "raw" => OutputFormat::Raw,
+        "elf" => OutputFormat::Elf,
+        "asm" => OutputFormat::Assembly,
         _ => unreachable!(), // clap ensures valid values
     };
 
@@ -65,8 +226,25 @@ fn main() -> anyhow::Result<()> {
         solana_program_id: None,
         output_directory: matches.get_one::<String>("output-dir").map(|s| s.as_str()),
         output_path: None,
+        output_format,
+        emit_asm: matches.get_flag("emit-asm"),
+        opt_level: *matches.get_one::<u8>("opt-level").unwrap(),
+        program_type: matches
+            .get_one::<ProgramType>("program-type")
+            .unwrap()
+            .clone(),
+        strict: matches.get_flag("deny-warnings"),
+        verify: matches.get_flag("verify"),
+        log_helper_id: matches.get_one::<i32>("log-helper").copied(),
+        safe_arrays: matches.get_flag("safe-arrays"),
+        inline_threshold: *matches.get_one::<usize>("inline-threshold").unwrap(),
     };
 
+    if matches.get_flag("emit-deps") {
+        print!("{}", Compiler::new().dependencies(input_file, &options)?);
+        return Ok(());
+    }
+
     println!("=== Pible - HolyC to BPF Compiler ===");
     println!("Divine compilation initiated...");
     println!("Source: {}", input_file);
@@ -78,3 +256,18 @@ fn main() -> anyhow::Result<()> {
     println!("Divine compilation completed! 🙏");
     Ok(())
 }
+
+/// Bytes spelled as pairs of hex digits, e.g. `2a00ff`.
+fn parse_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) {
+        return Err("expected an even number of hex digits".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("invalid hex byte at offset {}", i))
+        })
+        .collect()
+}
@@ -0,0 +1,105 @@
+use crate::pible::parser::{Node, NodeType, Position};
+use crate::pible::prelude::*;
+use crate::pible::visit::{walk_node, Visitor};
+use core::convert::Infallible;
+use core::fmt;
+
+/// Statements after which nothing in the same block runs.
+const TERMINATORS: &[&str] = &["return", "break", "continue", "goto"];
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// A problem in a program that still compiles
+    Warning,
+    /// A warning promoted by compiling with warnings denied
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        })
+    }
+}
+
+/// A problem found in a program, at a line and column of its source or,
+/// with `line` 0, in the program as a whole.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Diagnostic {
+    /// A warning at `line` and `column`.
+    pub fn warning(line: usize, column: usize, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            message,
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            return f.write_str(&self.message);
+        }
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+/// Run every check over `ast`, returning warnings in source order.
+pub fn analyze(ast: &Node) -> Vec<Diagnostic> {
+    let mut check = UnreachableCode::default();
+    let Ok(()) = check.visit_node(ast);
+    check.warnings
+}
+
+/// Warns once per block about the first statement following a terminator.
+#[derive(Default)]
+struct UnreachableCode {
+    warnings: Vec<Diagnostic>,
+}
+
+impl Visitor for UnreachableCode {
+    type Error = Infallible;
+
+    fn visit_node(&mut self, node: &Node) -> Result<(), Infallible> {
+        if node.node_type == NodeType::Block {
+            let terminator = node.children.iter().position(|statement| {
+                statement.node_type == NodeType::Statement
+                    && TERMINATORS.contains(&statement.value.as_deref().unwrap_or(""))
+            });
+            let dead = terminator.and_then(|index| Some((index, node.children.get(index + 1)?)));
+            if let Some((index, statement)) = dead {
+                if let Some(position) = first_position(statement) {
+                    let terminator = node.children[index].value.as_deref().unwrap_or("");
+                    self.warnings.push(Diagnostic::warning(
+                        position.line,
+                        position.column,
+                        format!("unreachable statement after `{}`", terminator),
+                    ));
+                }
+            }
+        }
+        walk_node(self, node)
+    }
+}
+
+/// Position of `node`, or of the first descendant that has one.
+fn first_position(node: &Node) -> Option<Position> {
+    node.position
+        .or_else(|| node.children.iter().find_map(first_position))
+}
@@ -0,0 +1,371 @@
+//! Textual eBPF assembly in the mnemonic syntax of the ubpf assembler, for
+//! reassembling compiled programs with other toolchains. Unlike
+//! [`disasm`](crate::pible::disasm), which labels jumps and annotates helper
+//! calls for people, every line here encodes back to exactly the instruction
+//! it came from.
+//!
+//! One instruction per line, operands separated by `, `:
+//!
+//! ```text
+//! line      = alu | neg | swap | lddw | load | store | xadd | jump | call | "exit"
+//! alu       = aluop ["32"] " " reg ", " (reg | imm)
+//! aluop     = "add" | "sub" | "mul" | "div" | "sdiv" | "or" | "and" | "lsh"
+//!           | "rsh" | "mod" | "smod" | "xor" | "mov" | "arsh"
+//! neg       = "neg" ["32"] " " reg
+//! swap      = ("le" | "be") ("16" | "32" | "64") " " reg
+//! lddw      = "lddw " reg ", " (hex | "map_fd " imm)
+//! load      = "ldx" size " " reg ", " mem
+//! store     = "st" size " " mem ", " imm | "stx" size " " mem ", " reg
+//! xadd      = "xadd" ("w" | "dw") " " mem ", " reg
+//! jump      = "ja " off | jmpop " " reg ", " (reg | imm) ", " off
+//! jmpop     = "jeq" | "jgt" | "jge" | "jset" | "jne" | "jsgt" | "jsge"
+//!           | "jlt" | "jle" | "jslt" | "jsle"
+//! call      = "call " imm | "call local " off
+//! size      = "b" | "h" | "w" | "dw"
+//! mem       = "[" reg off "]"
+//! reg       = "r0" .. "r10"
+//! imm       = ["-"] decimal
+//! hex       = "0x" hexdigits
+//! off       = ("+" | "-") decimal
+//! ```
+//!
+//! ALU operations without a `32` suffix are 64-bit. `sdiv` and `smod` are
+//! BPF v4's signed division; `map_fd`, `call local` and `xadd` are the
+//! kernel's map loads, BPF-to-BPF calls and atomic adds, which ubpf lacks.
+//! [`assemble`] also skips blank lines and `#` comments.
+
+use crate::pible::codegen::BpfInstruction;
+use crate::pible::prelude::*;
+use core::fmt::Write;
+use thiserror::Error;
+
+const ALU_OPERATIONS: [&str; 13] = [
+    "add", "sub", "mul", "div", "or", "and", "lsh", "rsh", "neg", "mod", "xor", "mov", "arsh",
+];
+const JMP_OPERATIONS: [&str; 14] = [
+    "ja", "jeq", "jgt", "jge", "jset", "jne", "jsgt", "jsge", "call", "exit", "jlt", "jle", "jslt",
+    "jsle",
+];
+const SIZES: [(&str, u8); 4] = [("w", 0x00), ("h", 0x08), ("b", 0x10), ("dw", 0x18)];
+/// `offset` of `sdiv` and `smod`, as in BPF v4
+const SIGNED_OFFSET: i16 = 1;
+/// `src_reg` of an `lddw` loading a map by file descriptor
+const PSEUDO_MAP_FD: u8 = 1;
+/// `src_reg` of a call to a BPF function rather than a helper
+const PSEUDO_CALL: u8 = 1;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AsmError {
+    /// An instruction the grammar can't express exactly
+    #[error("instruction {index} (opcode 0x{opcode:02x}) has no assembly form")]
+    Unsupported { index: usize, opcode: u8 },
+    #[error("line {line}: {message}")]
+    Syntax { line: usize, message: String },
+}
+
+/// Render `instructions` as assembly, one line each; `lddw` covers both of
+/// its slots.
+pub fn emit(instructions: &[BpfInstruction]) -> Result<String, AsmError> {
+    let mut output = String::new();
+    let mut index = 0;
+    while index < instructions.len() {
+        let instruction = &instructions[index];
+        let slots = if instruction.opcode == 0x18 { 2 } else { 1 };
+        let encoded = &instructions[index..(index + slots).min(instructions.len())];
+        let unsupported = AsmError::Unsupported {
+            index,
+            opcode: instruction.opcode,
+        };
+
+        // Anything the text leaves out must be zero, so check each line
+        // assembles back to the same instruction
+        let text = format_instruction(encoded).ok_or(unsupported.clone())?;
+        if parse_line(&text).ok().as_deref() != Some(encoded) {
+            return Err(unsupported);
+        }
+        let _ = writeln!(output, "{}", text);
+        index += slots;
+    }
+    Ok(output)
+}
+
+/// Assemble text in the grammar above back into instructions.
+pub fn assemble(text: &str) -> Result<Vec<BpfInstruction>, AsmError> {
+    let mut instructions = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = parse_line(line).map_err(|message| AsmError::Syntax {
+            line: number + 1,
+            message,
+        })?;
+        instructions.extend(parsed);
+    }
+    Ok(instructions)
+}
+
+/// Text for the instruction starting `slots`, or `None` for an opcode the
+/// grammar has no mnemonic for.
+fn format_instruction(slots: &[BpfInstruction]) -> Option<String> {
+    let instruction = &slots[0];
+    let (opcode, dst, src) = (instruction.opcode, instruction.dst_reg, instruction.src_reg);
+    let (off, imm) = (instruction.offset, instruction.immediate);
+    let uses_reg = opcode & 0x08 != 0;
+    let operand = |uses_reg: bool| {
+        if uses_reg {
+            format!("r{}", src)
+        } else {
+            imm.to_string()
+        }
+    };
+
+    let text = match opcode & 0x07 {
+        // ALU and ALU64
+        0x04 | 0x07 => {
+            let suffix = if opcode & 0x07 == 0x04 { "32" } else { "" };
+            let operation = (opcode >> 4) as usize;
+            if operation == 0xd {
+                if suffix.is_empty() {
+                    return None;
+                }
+                let order = if uses_reg { "be" } else { "le" };
+                return Some(format!("{}{} r{}", order, imm, dst));
+            }
+            let name = match (*ALU_OPERATIONS.get(operation)?, off) {
+                ("div", SIGNED_OFFSET) => "sdiv",
+                ("mod", SIGNED_OFFSET) => "smod",
+                (name, _) => name,
+            };
+            if name == "neg" {
+                format!("neg{} r{}", suffix, dst)
+            } else {
+                format!("{}{} r{}, {}", name, suffix, dst, operand(uses_reg))
+            }
+        }
+        0x05 => match *JMP_OPERATIONS.get((opcode >> 4) as usize)? {
+            "ja" => format!("ja {}", signed(off as i64)),
+            "exit" => "exit".to_string(),
+            "call" if src == PSEUDO_CALL => format!("call local {}", signed(imm as i64)),
+            "call" => format!("call {}", imm),
+            name => format!(
+                "{} r{}, {}, {}",
+                name,
+                dst,
+                operand(uses_reg),
+                signed(off as i64)
+            ),
+        },
+        0x00 if opcode == 0x18 => {
+            let high = slots.get(1)?.immediate as u32 as u64;
+            let value = (high << 32) | imm as u32 as u64;
+            if src == PSEUDO_MAP_FD {
+                format!("lddw r{}, map_fd {}", dst, value as i64)
+            } else {
+                format!("lddw r{}, 0x{:x}", dst, value)
+            }
+        }
+        class @ (0x01..=0x03) => {
+            let size = SIZES.iter().find(|(_, bits)| *bits == opcode & 0x18)?.0;
+            let mem = memory(if class == 0x01 { src } else { dst }, off);
+            match opcode & 0xe0 {
+                0x60 if class == 0x01 => format!("ldx{} r{}, {}", size, dst, mem),
+                0x60 if class == 0x02 => format!("st{} {}, {}", size, mem, imm),
+                0x60 => format!("stx{} {}, r{}", size, mem, src),
+                0xc0 if class == 0x03 && imm == 0 && matches!(size, "w" | "dw") => {
+                    format!("xadd{} {}, r{}", size, mem, src)
+                }
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Parse one line of assembly into the instruction's slots.
+fn parse_line(line: &str) -> Result<Vec<BpfInstruction>, String> {
+    let (mnemonic, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let operands: Vec<&str> = if rest.trim().is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    let arity = |count: usize| {
+        if operands.len() == count {
+            Ok(())
+        } else {
+            Err(format!("{} takes {} operands", mnemonic, count))
+        }
+    };
+    let single = |instruction| Ok(vec![instruction]);
+
+    if mnemonic == "lddw" {
+        arity(2)?;
+        let dst = register(operands[0])?;
+        let (src, value) = match operands[1].strip_prefix("map_fd ") {
+            Some(fd) => (PSEUDO_MAP_FD, immediate(fd)? as i64 as u64),
+            None => {
+                let digits = operands[1]
+                    .strip_prefix("0x")
+                    .ok_or_else(|| format!("expected a hex value, found `{}`", operands[1]))?;
+                let value = u64::from_str_radix(digits, 16)
+                    .map_err(|_| format!("invalid hex value `{}`", operands[1]))?;
+                (0, value)
+            }
+        };
+        return Ok(vec![
+            BpfInstruction::new(0x18, dst, src, 0, value as u32 as i32),
+            BpfInstruction::new(0, 0, 0, 0, (value >> 32) as u32 as i32),
+        ]);
+    }
+
+    // Byte swaps, before `le`/`be` could be mistaken for other mnemonics
+    for (order, opcode) in [("le", 0xd4), ("be", 0xdc)] {
+        if let Some(width) = mnemonic
+            .strip_prefix(order)
+            .filter(|width| matches!(*width, "16" | "32" | "64"))
+        {
+            arity(1)?;
+            let dst = register(operands[0])?;
+            return single(BpfInstruction::new(
+                opcode,
+                dst,
+                0,
+                0,
+                width.parse().unwrap_or(0),
+            ));
+        }
+    }
+
+    let (base, class) = match mnemonic.strip_suffix("32") {
+        Some(base) => (base, 0x04),
+        None => (mnemonic, 0x07),
+    };
+    let (base, off) = match base {
+        "sdiv" => ("div", SIGNED_OFFSET),
+        "smod" => ("mod", SIGNED_OFFSET),
+        base => (base, 0),
+    };
+    if let Some(operation) = ALU_OPERATIONS.iter().position(|&name| name == base) {
+        let opcode = class | (operation as u8) << 4;
+        if base == "neg" {
+            arity(1)?;
+            return single(BpfInstruction::new(opcode, register(operands[0])?, 0, 0, 0));
+        }
+        arity(2)?;
+        let dst = register(operands[0])?;
+        return single(match register(operands[1]) {
+            Ok(src) => BpfInstruction::new(opcode | 0x08, dst, src, off, 0),
+            Err(_) => BpfInstruction::new(opcode, dst, 0, off, immediate(operands[1])?),
+        });
+    }
+
+    if let Some(operation) = JMP_OPERATIONS.iter().position(|&name| name == mnemonic) {
+        let opcode = 0x05 | (operation as u8) << 4;
+        return single(match mnemonic {
+            "ja" => {
+                arity(1)?;
+                BpfInstruction::new(opcode, 0, 0, offset(operands[0])?, 0)
+            }
+            "exit" => {
+                arity(0)?;
+                BpfInstruction::new(opcode, 0, 0, 0, 0)
+            }
+            "call" => {
+                arity(1)?;
+                match operands[0].strip_prefix("local ") {
+                    Some(target) => {
+                        let target = offset(target)?;
+                        BpfInstruction::new(opcode, 0, PSEUDO_CALL, 0, target as i32)
+                    }
+                    None => BpfInstruction::new(opcode, 0, 0, 0, immediate(operands[0])?),
+                }
+            }
+            _ => {
+                arity(3)?;
+                let dst = register(operands[0])?;
+                let off = offset(operands[2])?;
+                match register(operands[1]) {
+                    Ok(src) => BpfInstruction::new(opcode | 0x08, dst, src, off, 0),
+                    Err(_) => BpfInstruction::new(opcode, dst, 0, off, immediate(operands[1])?),
+                }
+            }
+        });
+    }
+
+    let memory_form = [("ldx", 0x61), ("stx", 0x63), ("st", 0x62), ("xadd", 0xc3)]
+        .into_iter()
+        .find_map(|(prefix, opcode)| {
+            let size = mnemonic.strip_prefix(prefix)?;
+            let bits = SIZES.iter().find(|(name, _)| *name == size)?.1;
+            Some((prefix, (opcode & !0x18) | bits))
+        });
+    if let Some((prefix, opcode)) = memory_form {
+        arity(2)?;
+        return single(match prefix {
+            "ldx" => {
+                let (src, off) = memory_operand(operands[1])?;
+                BpfInstruction::new(opcode, register(operands[0])?, src, off, 0)
+            }
+            "st" => {
+                let (dst, off) = memory_operand(operands[0])?;
+                BpfInstruction::new(opcode, dst, 0, off, immediate(operands[1])?)
+            }
+            _ => {
+                let (dst, off) = memory_operand(operands[0])?;
+                BpfInstruction::new(opcode, dst, register(operands[1])?, off, 0)
+            }
+        });
+    }
+
+    Err(format!("unknown mnemonic `{}`", mnemonic))
+}
+
+fn register(text: &str) -> Result<u8, String> {
+    text.strip_prefix('r')
+        .and_then(|number| number.parse::<u8>().ok())
+        .filter(|&number| number <= 10)
+        .ok_or_else(|| format!("expected a register, found `{}`", text))
+}
+
+fn immediate(text: &str) -> Result<i32, String> {
+    text.parse()
+        .map_err(|_| format!("expected a 32-bit integer, found `{}`", text))
+}
+
+/// A signed offset, which always carries its sign.
+fn offset(text: &str) -> Result<i16, String> {
+    let digits = text
+        .strip_prefix('+')
+        .or_else(|| text.starts_with('-').then_some(text))
+        .ok_or_else(|| format!("expected an offset such as +1, found `{}`", text))?;
+    digits
+        .parse()
+        .map_err(|_| format!("offset `{}` is out of range", text))
+}
+
+/// `[rN+off]` as its register and offset.
+fn memory_operand(text: &str) -> Result<(u8, i16), String> {
+    let inner = text
+        .strip_prefix('[')
+        .and_then(|inner| inner.strip_suffix(']'))
+        .ok_or_else(|| format!("expected a memory operand, found `{}`", text))?;
+    let split = inner
+        .find(['+', '-'])
+        .ok_or_else(|| format!("memory operand `{}` needs an offset", text))?;
+    Ok((register(&inner[..split])?, offset(&inner[split..])?))
+}
+
+fn memory(reg: u8, offset: i16) -> String {
+    format!("[r{}{}]", reg, signed(offset as i64))
+}
+
+fn signed(value: i64) -> String {
+    if value < 0 {
+        value.to_string()
+    } else {
+        format!("+{}", value)
+    }
+}
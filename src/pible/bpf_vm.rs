@@ -1,6 +1,13 @@
 use crate::pible::codegen::BpfInstruction;
+use crate::pible::prelude::*;
+use core::fmt;
+use core::ops::Range;
 use thiserror::Error;
 
+/// Host implementation of a BPF helper. Receives R0-R10 and VM memory;
+/// the returned value is written to R0.
+pub type HelperFn = Box<dyn FnMut(&mut [i64; 11], &mut [u8]) -> i64>;
+
 #[derive(Error, Debug)]
 #[allow(dead_code)]
 pub enum VmError {
@@ -12,6 +19,55 @@ pub enum VmError {
     DivisionByZero,
     #[error("Program exit with code: {0}")]
     ProgramExit(i32),
+    #[error("Compute budget of {0} units exceeded")]
+    ComputeBudgetExceeded(u64),
+    #[error("Arithmetic overflow at instruction {0}")]
+    Overflow(usize),
+}
+
+/// How 64-bit add, subtract and multiply treat results that don't fit.
+/// Registers carry no type, so a checked mode says how to read them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wrap around, as eBPF does
+    #[default]
+    Wrap,
+    /// Trap with [`VmError::Overflow`] when the result overflows an `i64`
+    Signed,
+    /// Trap with [`VmError::Overflow`] when the result overflows a `u64`
+    Unsigned,
+}
+
+/// Resource limits for a [`BpfVm`] instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VmConfig {
+    /// Bytes of addressable heap memory
+    pub memory_size: usize,
+    /// Maximum number of instructions executed before aborting
+    pub compute_budget: u64,
+    /// Bytes of stack per call frame; R10 starts at the top of the first frame
+    pub stack_size: usize,
+    /// Maximum nesting of BPF-to-BPF calls
+    pub max_call_depth: usize,
+    /// Record every executed instruction, retrievable with [`BpfVm::trace`]
+    pub trace: bool,
+    /// Wrap or trap on arithmetic overflow
+    pub overflow: Overflow,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        // Mirror Solana's runtime limits: 32KB heap, 64 nested 4KB stack
+        // frames and the 1.4M compute unit transaction cap.
+        Self {
+            memory_size: 32 * 1024,
+            compute_budget: 1_400_000,
+            stack_size: 4096,
+            max_call_depth: 64,
+            trace: false,
+            overflow: Overflow::Wrap,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -20,26 +76,138 @@ pub struct VmResult {
     pub compute_units: u64,
 }
 
+impl fmt::Display for VmResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "exit code: {}\ncompute units: {}",
+            self.exit_code, self.compute_units
+        )
+    }
+}
+
+/// Run raw bytecode, as written for the `bpf-vm` target, with `input`
+/// copied to the start of memory and R1 pointing at it.
+pub fn run_bytecode(bytecode: &[u8], input: &[u8]) -> Result<VmResult, VmError> {
+    let mut vm = BpfVm::from_bytecode(bytecode)?;
+    vm.load_data(0, input)?;
+    vm.set_register(1, 0);
+    vm.execute()
+}
+
+/// Outcome of executing a single instruction with [`BpfVm::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    pub exited: bool,
+    pub opcode: u8,
+}
+
+/// Snapshot of the VM state for debuggers and front-ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmState {
+    pub pc: usize,
+    pub registers: [i64; 11],
+    pub compute_units: u64,
+}
+
+/// One executed instruction, recorded when [`VmConfig::trace`] is set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct TraceEntry {
+    pub pc: usize,
+    pub opcode: u8,
+    /// Source line of the instruction, when given with [`BpfVm::with_source_lines`]
+    pub line: Option<usize>,
+}
+
+/// Caller state saved by a BPF-to-BPF call and restored by its `exit`.
+#[derive(Debug, Clone, Copy)]
+struct CallFrame {
+    return_pc: usize,
+    saved_registers: [i64; 4], // R6-R9
+    frame_pointer: i64,
+}
+
 pub struct BpfVm {
     registers: [i64; 11], // R0-R10
     program: Vec<BpfInstruction>,
     pc: usize,
     compute_units: u64,
+    config: VmConfig,
+    call_stack: Vec<CallFrame>,
+    helpers: HashMap<i32, HelperFn>,
+    trace: Vec<TraceEntry>,
+    /// Source line of each instruction, for the trace
+    source_lines: Vec<Option<usize>>,
     #[allow(dead_code)]
     pub memory: Vec<u8>, // Public memory for testing
 }
 
 impl BpfVm {
     pub fn new(instructions: &[BpfInstruction]) -> Self {
+        Self::with_config(instructions, VmConfig::default())
+    }
+
+    pub fn with_config(instructions: &[BpfInstruction], config: VmConfig) -> Self {
+        let memory = vec![0; config.memory_size + config.stack_size * config.max_call_depth];
+        let mut registers = [0; 11];
+        registers[10] = memory.len() as i64; // Frame pointer at top of stack
+
         Self {
-            registers: [0; 11],
+            registers,
             program: instructions.to_vec(),
             pc: 0,
             compute_units: 0,
-            memory: vec![0; 4096], // 4KB of memory for testing
+            config,
+            call_stack: Vec::new(),
+            helpers: HashMap::new(),
+            trace: Vec::new(),
+            source_lines: Vec::new(),
+            memory,
         }
     }
 
+    /// VM set up like a Solana entrypoint, with the serialized `input` region
+    /// in memory, R1 pointing at it and R2 holding its length. See
+    /// [`BpfVm::set_input`].
+    pub fn with_input(instructions: &[BpfInstruction], input: &[u8]) -> Result<Self, VmError> {
+        let mut vm = Self::new(instructions);
+        vm.set_input(input)?;
+        Ok(vm)
+    }
+
+    /// VM for raw bytecode, which must be whole 8-byte instructions.
+    pub fn from_bytecode(bytecode: &[u8]) -> Result<Self, VmError> {
+        if !bytecode.len().is_multiple_of(8) {
+            return Err(VmError::InvalidInstruction(format!(
+                "Bytecode of {} bytes is not a whole number of instructions",
+                bytecode.len()
+            )));
+        }
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(chunk);
+                BpfInstruction::from_bytes(bytes)
+            })
+            .collect();
+        Ok(Self::new(&instructions))
+    }
+
+    /// Attribute each instruction to a source line in the trace, as from
+    /// [`CodeGen::source_lines`](crate::pible::codegen::CodeGen::source_lines).
+    #[allow(dead_code)]
+    pub fn with_source_lines(mut self, lines: &[Option<usize>]) -> Self {
+        self.source_lines = lines.to_vec();
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
     // Public accessors and mutators for testing
     #[allow(dead_code)]
     pub fn set_register(&mut self, reg: usize, value: i64) {
@@ -67,8 +235,55 @@ impl BpfVm {
         self.pc = pc;
     }
 
+    /// `wrapped`, unless the configured [`Overflow`] mode traps on the
+    /// overflow the flags report.
+    fn overflow_checked(
+        &self,
+        wrapped: i64,
+        signed_overflow: bool,
+        unsigned_overflow: bool,
+    ) -> Result<i64, VmError> {
+        match self.config.overflow {
+            Overflow::Signed if signed_overflow => Err(VmError::Overflow(self.pc)),
+            Overflow::Unsigned if unsigned_overflow => Err(VmError::Overflow(self.pc)),
+            _ => Ok(wrapped),
+        }
+    }
+
+    /// The `size` bytes of memory at `addr`, or an error naming the `access`
+    /// when any of them is outside it.
+    fn memory_range(&self, addr: i64, size: usize, access: &str) -> Result<Range<usize>, VmError> {
+        usize::try_from(addr)
+            .ok()
+            .and_then(|addr| Some(addr..addr.checked_add(size)?))
+            .filter(|range| range.end <= self.memory.len())
+            .ok_or_else(|| {
+                VmError::InvalidInstruction(format!("{} out of bounds at address {}", access, addr))
+            })
+    }
+
+    /// Second operand of an ALU instruction: the sign-extended immediate of
+    /// a `BPF_K` form, or the source register of a `BPF_X` one.
+    fn alu_operand(&self, instruction: &BpfInstruction) -> i64 {
+        if instruction.opcode & 0x08 == 0 {
+            instruction.immediate as i64
+        } else {
+            self.registers[instruction.src_reg as usize]
+        }
+    }
+
+    /// Execute a single instruction. The PC only changes for a taken jump,
+    /// which like every BPF jump offset counts instructions from the one
+    /// after the jump: it moves to `pc + offset + 1`.
     #[allow(dead_code)]
     pub fn execute_instruction(&mut self, instruction: &BpfInstruction) -> Result<(), VmError> {
+        if let Some(taken) = self.jump_taken(instruction) {
+            if taken {
+                self.take_jump(instruction.offset);
+            }
+            return Ok(());
+        }
+
         match instruction.opcode {
             0x95 => {
                 // BPF_EXIT - for testing, we don't actually exit
@@ -87,40 +302,97 @@ impl BpfVm {
             }
             0x79 => {
                 // BPF_LDX | BPF_MEM | BPF_DW (load 64-bit from memory)
-                if instruction.dst_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] =
-                        0x1000 + instruction.offset as i64;
+                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
+                    let addr = self.registers[instruction.src_reg as usize]
+                        .wrapping_add(instruction.offset as i64);
+                    let range = self.memory_range(addr, 8, "Load")?;
+                    let bytes = self.memory[range].try_into().unwrap();
+                    self.registers[instruction.dst_reg as usize] = i64::from_le_bytes(bytes);
                 }
                 Ok(())
             }
-            0x07 => {
-                // BPF_ALU64 | BPF_ADD | BPF_K (add immediate)
-                if instruction.dst_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] += instruction.immediate as i64;
+            0x7b => {
+                // BPF_STX | BPF_MEM | BPF_DW (store 64-bit to memory)
+                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
+                    let addr = self.registers[instruction.dst_reg as usize]
+                        .wrapping_add(instruction.offset as i64);
+                    let range = self.memory_range(addr, 8, "Store")?;
+                    let bytes = self.registers[instruction.src_reg as usize].to_le_bytes();
+                    self.memory[range].copy_from_slice(&bytes);
                 }
                 Ok(())
             }
-            0x0f => {
-                // BPF_ALU64 | BPF_ADD | BPF_X (add register)
+            0x07 | 0x0f => {
+                // BPF_ALU64 | BPF_ADD | BPF_K / BPF_X
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] +=
-                        self.registers[instruction.src_reg as usize];
+                    let (a, b) = (
+                        self.registers[instruction.dst_reg as usize],
+                        self.alu_operand(instruction),
+                    );
+                    // The immediate is signed even when the register is read as unsigned
+                    let unsigned_overflow = match instruction.opcode {
+                        0x07 => (a as u64).checked_add_signed(b).is_none(),
+                        _ => (a as u64).checked_add(b as u64).is_none(),
+                    };
+                    self.registers[instruction.dst_reg as usize] = self.overflow_checked(
+                        a.wrapping_add(b),
+                        a.checked_add(b).is_none(),
+                        unsigned_overflow,
+                    )?;
                 }
                 Ok(())
             }
-            0x1f => {
-                // BPF_ALU64 | BPF_SUB | BPF_X (subtract register)
+            0x17 | 0x1f => {
+                // BPF_ALU64 | BPF_SUB | BPF_K / BPF_X
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] -=
-                        self.registers[instruction.src_reg as usize];
+                    let (a, b) = (
+                        self.registers[instruction.dst_reg as usize],
+                        self.alu_operand(instruction),
+                    );
+                    let unsigned_overflow = match instruction.opcode {
+                        0x17 => (a as u64).checked_add_signed(b.wrapping_neg()).is_none(),
+                        _ => (a as u64).checked_sub(b as u64).is_none(),
+                    };
+                    self.registers[instruction.dst_reg as usize] = self.overflow_checked(
+                        a.wrapping_sub(b),
+                        a.checked_sub(b).is_none(),
+                        unsigned_overflow,
+                    )?;
                 }
                 Ok(())
             }
-            0x2f => {
-                // BPF_ALU64 | BPF_MUL | BPF_X (multiply register)
+            0x27 | 0x2f => {
+                // BPF_ALU64 | BPF_MUL | BPF_K / BPF_X
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] *=
-                        self.registers[instruction.src_reg as usize];
+                    let (a, b) = (
+                        self.registers[instruction.dst_reg as usize],
+                        self.alu_operand(instruction),
+                    );
+                    self.registers[instruction.dst_reg as usize] = self.overflow_checked(
+                        a.wrapping_mul(b),
+                        a.checked_mul(b).is_none(),
+                        (a as u64).checked_mul(b as u64).is_none(),
+                    )?;
+                }
+                Ok(())
+            }
+            0x37 | 0x3f | 0x97 | 0x9f => {
+                // BPF_ALU64 | BPF_DIV / BPF_MOD | BPF_K / BPF_X (divide /
+                // modulo, signed when the offset is 1)
+                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
+                    let divisor = self.alu_operand(instruction);
+                    if divisor == 0 {
+                        return Err(VmError::DivisionByZero);
+                    }
+                    let dividend = self.registers[instruction.dst_reg as usize];
+                    let divide = instruction.opcode & 0xf0 == 0x30;
+                    let result = match (divide, instruction.offset) {
+                        (true, 1) => dividend.wrapping_div(divisor),
+                        (false, 1) => dividend.wrapping_rem(divisor),
+                        (true, _) => ((dividend as u64) / (divisor as u64)) as i64,
+                        (false, _) => ((dividend as u64) % (divisor as u64)) as i64,
+                    };
+                    self.registers[instruction.dst_reg as usize] = result;
                 }
                 Ok(())
             }
@@ -132,35 +404,32 @@ impl BpfVm {
                 }
                 Ok(())
             }
-            0x5f => {
-                // BPF_ALU64 | BPF_AND | BPF_X (bitwise and)
+            0x57 | 0x5f => {
+                // BPF_ALU64 | BPF_AND | BPF_K / BPF_X (bitwise and)
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] &=
-                        self.registers[instruction.src_reg as usize];
+                    self.registers[instruction.dst_reg as usize] &= self.alu_operand(instruction);
                 }
                 Ok(())
             }
-            0x4f => {
-                // BPF_ALU64 | BPF_OR | BPF_X (bitwise or)
+            0x47 | 0x4f => {
+                // BPF_ALU64 | BPF_OR | BPF_K / BPF_X (bitwise or)
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] |=
-                        self.registers[instruction.src_reg as usize];
+                    self.registers[instruction.dst_reg as usize] |= self.alu_operand(instruction);
                 }
                 Ok(())
             }
-            0xaf => {
-                // BPF_ALU64 | BPF_XOR | BPF_X (bitwise xor)
+            0xa7 | 0xaf => {
+                // BPF_ALU64 | BPF_XOR | BPF_K / BPF_X (bitwise xor)
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] ^=
-                        self.registers[instruction.src_reg as usize];
+                    self.registers[instruction.dst_reg as usize] ^= self.alu_operand(instruction);
                 }
                 Ok(())
             }
             0x6f => {
                 // BPF_ALU64 | BPF_LSH | BPF_X (left shift)
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] <<=
-                        self.registers[instruction.src_reg as usize];
+                    let shift = self.registers[instruction.src_reg as usize] as u32 & 63;
+                    self.registers[instruction.dst_reg as usize] <<= shift;
                 }
                 Ok(())
             }
@@ -168,191 +437,159 @@ impl BpfVm {
                 // BPF_ALU64 | BPF_RSH | BPF_X (right shift logical)
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
                     let val = self.registers[instruction.dst_reg as usize] as u64;
-                    let shift = self.registers[instruction.src_reg as usize] as u64;
+                    let shift = self.registers[instruction.src_reg as usize] as u32 & 63;
                     self.registers[instruction.dst_reg as usize] = (val >> shift) as i64;
                 }
                 Ok(())
             }
-            0xc7 => {
-                // BPF_ALU64 | BPF_ARSH | BPF_X (arithmetic right shift)
-                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    self.registers[instruction.dst_reg as usize] >>=
-                        self.registers[instruction.src_reg as usize];
-                }
-                Ok(())
-            }
-            0xcf => {
-                // BPF_ALU64 | BPF_ARSH | BPF_K (arithmetic right shift with immediate)
+            0x67 => {
+                // BPF_ALU64 | BPF_LSH | BPF_K (left shift by immediate)
                 if instruction.dst_reg < 11 {
-                    let value = self.registers[instruction.dst_reg as usize];
-                    let shift_amount = instruction.immediate as u32;
-
-                    // For 32-bit arithmetic right shift in BPF context
-                    let val_32 = value as u32 as i32; // Treat as signed 32-bit
-                    let result_32 = val_32 >> shift_amount;
-                    self.registers[instruction.dst_reg as usize] = result_32 as u32 as i64;
-                    // Zero-extend to 64-bit
-                }
-                Ok(())
-            }
-            0x1d => {
-                // BPF_JMP | BPF_JEQ | BPF_X (jump if equal)
-                if instruction.dst_reg < 11
-                    && instruction.src_reg < 11
-                    && self.registers[instruction.dst_reg as usize]
-                        == self.registers[instruction.src_reg as usize]
-                {
-                    // In single instruction execution, we update pc to simulate the jump
-                    self.pc = instruction.offset as usize;
+                    let shift = instruction.immediate as u32 & 63;
+                    self.registers[instruction.dst_reg as usize] <<= shift;
                 }
                 Ok(())
             }
-            0x2d => {
-                // BPF_JMP | BPF_JGT | BPF_X (jump if greater than)
-                if instruction.dst_reg < 11
-                    && instruction.src_reg < 11
-                    && self.registers[instruction.dst_reg as usize]
-                        > self.registers[instruction.src_reg as usize]
-                {
-                    // In single instruction execution, we update pc to simulate the jump
-                    self.pc = instruction.offset as usize;
-                }
-                Ok(())
-            }
-            0xad => {
-                // BPF_JMP | BPF_JLT | BPF_X (jump if less than)
-                if instruction.dst_reg < 11
-                    && instruction.src_reg < 11
-                    && self.registers[instruction.dst_reg as usize]
-                        < self.registers[instruction.src_reg as usize]
-                {
-                    // In single instruction execution, we update pc to simulate the jump
-                    self.pc = instruction.offset as usize;
-                }
-                Ok(())
-            }
-            0x15 => {
-                // BPF_JMP | BPF_JEQ | BPF_K (jump if equal to immediate)
-                if instruction.dst_reg < 11
-                    && self.registers[instruction.dst_reg as usize] == instruction.immediate as i64
-                {
-                    self.pc = instruction.offset as usize;
+            0x77 => {
+                // BPF_ALU64 | BPF_RSH | BPF_K (logical right shift by immediate)
+                if instruction.dst_reg < 11 {
+                    let val = self.registers[instruction.dst_reg as usize] as u64;
+                    let shift = instruction.immediate as u32 & 63;
+                    self.registers[instruction.dst_reg as usize] = (val >> shift) as i64;
                 }
                 Ok(())
             }
-            0x25 => {
-                // BPF_JMP | BPF_JGT | BPF_K (jump if greater than immediate)
-                if instruction.dst_reg < 11
-                    && self.registers[instruction.dst_reg as usize] > instruction.immediate as i64
-                {
-                    self.pc = instruction.offset as usize;
+            0xc7 => {
+                // BPF_ALU64 | BPF_ARSH | BPF_K (arithmetic right shift by immediate)
+                if instruction.dst_reg < 11 {
+                    let shift = instruction.immediate as u32 & 63;
+                    self.registers[instruction.dst_reg as usize] >>= shift;
                 }
                 Ok(())
             }
-            0xa5 => {
-                // BPF_JMP | BPF_JLT | BPF_K (jump if less than immediate)
-                if instruction.dst_reg < 11
-                    && self.registers[instruction.dst_reg as usize] < instruction.immediate as i64
-                {
-                    self.pc = instruction.offset as usize;
+            0xcf => {
+                // BPF_ALU64 | BPF_ARSH | BPF_X (arithmetic right shift, sign-extending)
+                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
+                    let shift = self.registers[instruction.src_reg as usize] as u32 & 63;
+                    self.registers[instruction.dst_reg as usize] >>= shift;
                 }
                 Ok(())
             }
-            0x61 => {
-                // BPF_LDX | BPF_MEM | BPF_W (load word from memory)
+            0x61 | 0x69 | 0x71 => {
+                // BPF_LDX | BPF_MEM | BPF_W / BPF_H / BPF_B (load word,
+                // halfword or byte from memory, zero-extended)
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    let addr = (self.registers[instruction.src_reg as usize]
-                        + instruction.offset as i64) as usize;
-                    if addr + 4 <= self.memory.len() {
-                        // Load 32-bit little-endian word from memory
-                        let value = u32::from_le_bytes([
-                            self.memory[addr],
-                            self.memory[addr + 1],
-                            self.memory[addr + 2],
-                            self.memory[addr + 3],
-                        ]);
-                        self.registers[instruction.dst_reg as usize] = value as i64;
-                    } else {
-                        self.registers[instruction.dst_reg as usize] = 42; // Fallback for out of bounds
-                    }
+                    let size = match instruction.opcode {
+                        0x71 => 1,
+                        0x69 => 2,
+                        _ => 4,
+                    };
+                    let addr = self.registers[instruction.src_reg as usize]
+                        .wrapping_add(instruction.offset as i64);
+                    let range = self.memory_range(addr, size, "Load")?;
+                    let mut bytes = [0u8; 8];
+                    bytes[..size].copy_from_slice(&self.memory[range]);
+                    self.registers[instruction.dst_reg as usize] = i64::from_le_bytes(bytes);
                 }
                 Ok(())
             }
-            0x69 => {
-                // BPF_LDX | BPF_MEM | BPF_H (load halfword from memory)
+            0x63 | 0x6b | 0x73 => {
+                // BPF_STX | BPF_MEM | BPF_W / BPF_H / BPF_B (store the low
+                // word, halfword or byte of a register to memory)
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    let addr = (self.registers[instruction.src_reg as usize]
-                        + instruction.offset as i64) as usize;
-                    if addr + 2 <= self.memory.len() {
-                        // Load 16-bit little-endian halfword from memory
-                        let value = u16::from_le_bytes([self.memory[addr], self.memory[addr + 1]]);
-                        self.registers[instruction.dst_reg as usize] = value as i64;
-                    } else {
-                        self.registers[instruction.dst_reg as usize] = 0x1234; // Fallback for out of bounds
-                    }
+                    let size = match instruction.opcode {
+                        0x73 => 1,
+                        0x6b => 2,
+                        _ => 4,
+                    };
+                    let addr = self.registers[instruction.dst_reg as usize]
+                        .wrapping_add(instruction.offset as i64);
+                    let range = self.memory_range(addr, size, "Store")?;
+                    let bytes = self.registers[instruction.src_reg as usize].to_le_bytes();
+                    self.memory[range].copy_from_slice(&bytes[..size]);
                 }
                 Ok(())
             }
-            0x71 => {
-                // BPF_LDX | BPF_MEM | BPF_B (load byte from memory)
-                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    let addr = (self.registers[instruction.src_reg as usize]
-                        + instruction.offset as i64) as usize;
-                    if addr < self.memory.len() {
-                        self.registers[instruction.dst_reg as usize] = self.memory[addr] as i64;
-                    } else {
-                        self.registers[instruction.dst_reg as usize] = 0x42; // Fallback for out of bounds
-                    }
+            0x62 | 0x6a | 0x72 | 0x7a => {
+                // BPF_ST | BPF_MEM | BPF_W / BPF_H / BPF_B / BPF_DW (store
+                // immediate); the doubleword store sign-extends the immediate
+                if instruction.dst_reg < 11 {
+                    let size = match instruction.opcode {
+                        0x72 => 1,
+                        0x6a => 2,
+                        0x62 => 4,
+                        _ => 8,
+                    };
+                    let addr = self.registers[instruction.dst_reg as usize]
+                        .wrapping_add(instruction.offset as i64);
+                    let range = self.memory_range(addr, size, "Store")?;
+                    let bytes = (instruction.immediate as i64).to_le_bytes();
+                    self.memory[range].copy_from_slice(&bytes[..size]);
                 }
                 Ok(())
             }
-            0x63 => {
-                // BPF_STX | BPF_MEM | BPF_W (store word to memory)
+            0xc3 | 0xdb => {
+                // BPF_STX | BPF_ATOMIC | BPF_W / BPF_DW (lock xadd). The
+                // immediate selects the operation; only plain add is supported.
+                if instruction.immediate != 0 {
+                    return Err(VmError::InvalidInstruction(format!(
+                        "Unsupported atomic operation: 0x{:02x}",
+                        instruction.immediate
+                    )));
+                }
                 if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    let addr = (self.registers[instruction.dst_reg as usize]
-                        + instruction.offset as i64) as usize;
-                    if addr + 4 <= self.memory.len() {
-                        let value = self.registers[instruction.src_reg as usize] as u32;
-                        let bytes = value.to_le_bytes();
-                        self.memory[addr..addr + 4].copy_from_slice(&bytes);
+                    let size = if instruction.opcode == 0xdb { 8 } else { 4 };
+                    let addr = self.registers[instruction.dst_reg as usize]
+                        .wrapping_add(instruction.offset as i64);
+                    let range = self.memory_range(addr, size, "Atomic add")?;
+                    let src = self.registers[instruction.src_reg as usize];
+                    let bytes = &mut self.memory[range];
+                    if size == 8 {
+                        let value = i64::from_le_bytes(bytes.try_into().unwrap());
+                        bytes.copy_from_slice(&value.wrapping_add(src).to_le_bytes());
+                    } else {
+                        let value = i32::from_le_bytes(bytes.try_into().unwrap());
+                        bytes.copy_from_slice(&value.wrapping_add(src as i32).to_le_bytes());
                     }
                 }
                 Ok(())
             }
-            0x6b => {
-                // BPF_STX | BPF_MEM | BPF_H (store halfword to memory)
-                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    let addr = (self.registers[instruction.dst_reg as usize]
-                        + instruction.offset as i64) as usize;
-                    if addr + 2 <= self.memory.len() {
-                        let value = self.registers[instruction.src_reg as usize] as u16;
-                        let bytes = value.to_le_bytes();
-                        self.memory[addr..addr + 2].copy_from_slice(&bytes);
-                    }
+            0x87 => {
+                // BPF_ALU64 | BPF_NEG (negate register)
+                if instruction.dst_reg < 11 {
+                    let reg = &mut self.registers[instruction.dst_reg as usize];
+                    *reg = reg.wrapping_neg();
                 }
                 Ok(())
             }
-            0x62 => {
-                // BPF_ST | BPF_MEM | BPF_W (store immediate word to memory)
+            0x84 => {
+                // BPF_ALU | BPF_NEG (negate low 32 bits, zero-extend)
                 if instruction.dst_reg < 11 {
-                    let addr = (self.registers[instruction.dst_reg as usize]
-                        + instruction.offset as i64) as usize;
-                    if addr + 4 <= self.memory.len() {
-                        let value = instruction.immediate as u32;
-                        let bytes = value.to_le_bytes();
-                        self.memory[addr..addr + 4].copy_from_slice(&bytes);
-                    }
+                    let reg = &mut self.registers[instruction.dst_reg as usize];
+                    *reg = (*reg as i32).wrapping_neg() as u32 as i64;
                 }
                 Ok(())
             }
-            0x73 => {
-                // BPF_STX | BPF_MEM | BPF_B (store byte to memory)
-                if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                    let addr = (self.registers[instruction.dst_reg as usize]
-                        + instruction.offset as i64) as usize;
-                    if addr < self.memory.len() {
-                        self.memory[addr] = self.registers[instruction.src_reg as usize] as u8;
-                    }
+            0xd4 | 0xdc => {
+                // BPF_ALU | BPF_END | BPF_TO_LE / BPF_TO_BE (byte swap)
+                if instruction.dst_reg < 11 {
+                    let value = self.registers[instruction.dst_reg as usize] as u64;
+                    let to_big_endian = instruction.opcode == 0xdc;
+                    let converted = match (instruction.immediate, to_big_endian) {
+                        (16, false) => value as u16 as u64,
+                        (32, false) => value as u32 as u64,
+                        (64, false) => value,
+                        (16, true) => (value as u16).swap_bytes() as u64,
+                        (32, true) => (value as u32).swap_bytes() as u64,
+                        (64, true) => value.swap_bytes(),
+                        (width, _) => {
+                            return Err(VmError::InvalidInstruction(format!(
+                                "Invalid byte swap width: {}",
+                                width
+                            )))
+                        }
+                    };
+                    self.registers[instruction.dst_reg as usize] = converted as i64;
                 }
                 Ok(())
             }
@@ -366,113 +603,141 @@ impl BpfVm {
         }
     }
 
-    pub fn execute(&mut self) -> Result<VmResult, VmError> {
-        while self.pc < self.program.len() {
-            self.compute_units += 1;
+    /// Execute the instruction at the current PC and advance past it.
+    pub fn step(&mut self) -> Result<StepResult, VmError> {
+        let instruction = *self
+            .program
+            .get(self.pc)
+            .ok_or_else(|| VmError::InvalidInstruction(format!("PC out of bounds: {}", self.pc)))?;
 
-            // Simple execution limit for testing
-            if self.compute_units > 10000 {
-                break;
-            }
+        if self.compute_units >= self.config.compute_budget {
+            return Err(VmError::ComputeBudgetExceeded(self.config.compute_budget));
+        }
+        self.compute_units += 1;
+        if self.config.trace {
+            self.trace.push(TraceEntry {
+                pc: self.pc,
+                opcode: instruction.opcode,
+                line: self.source_lines.get(self.pc).copied().flatten(),
+            });
+        }
 
-            let instruction = &self.program[self.pc];
+        if instruction.opcode == 0x95 {
+            // BPF_EXIT - return to the caller, or stop with the PC on the exit
+            let Some(frame) = self.call_stack.pop() else {
+                return Ok(StepResult {
+                    exited: true,
+                    opcode: instruction.opcode,
+                });
+            };
+            self.registers[6..10].copy_from_slice(&frame.saved_registers);
+            self.registers[10] = frame.frame_pointer;
+            self.pc = frame.return_pc;
+            return Ok(StepResult {
+                exited: false,
+                opcode: instruction.opcode,
+            });
+        }
 
-            match instruction.opcode {
-                0x95 => {
-                    // BPF_EXIT
-                    return Ok(VmResult {
-                        exit_code: self.registers[0] as i32,
-                        compute_units: self.compute_units,
-                    });
-                }
-                0x85 => {
-                    // BPF_CALL
-                    self.handle_call(instruction.immediate)?;
-                }
-                0xb7 => {
-                    // BPF_ALU64 | BPF_MOV | BPF_K (move immediate to register)
-                    if instruction.dst_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] = instruction.immediate as i64;
-                    }
-                }
-                0x79 => {
-                    // BPF_LDX | BPF_MEM | BPF_DW (load 64-bit from memory)
-                    // For simulation, just set register to a test value
-                    if instruction.dst_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] =
-                            0x1000 + instruction.offset as i64;
-                    }
-                }
-                0x07 => {
-                    // BPF_ALU64 | BPF_ADD | BPF_K (add immediate)
-                    if instruction.dst_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] +=
-                            instruction.immediate as i64;
-                    }
-                }
-                0x0f => {
-                    // BPF_ALU64 | BPF_ADD | BPF_X (add register)
-                    if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] +=
-                            self.registers[instruction.src_reg as usize];
-                    }
-                }
-                0x2f => {
-                    // BPF_ALU64 | BPF_MUL | BPF_X (multiply register)
-                    if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] *=
-                            self.registers[instruction.src_reg as usize];
-                    }
-                }
-                0xbf => {
-                    // BPF_ALU64 | BPF_MOV | BPF_X (move register)
-                    if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] =
-                            self.registers[instruction.src_reg as usize];
-                    }
-                }
-                0x63 => {
-                    // BPF_STX | BPF_MEM | BPF_W (store word to memory)
-                    // For simulation, just store in a "memory" register
-                    // This is a simplified implementation
-                }
-                0x61 => {
-                    // BPF_LDX | BPF_MEM | BPF_W (load word from memory)
-                    // For simulation, load the stored value back
-                    if instruction.dst_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] = 42; // Simulated stored value
-                    }
-                }
-                0x2d => {
-                    // BPF_JMP | BPF_JGT | BPF_X (jump if greater than)
-                    if instruction.dst_reg < 11
-                        && instruction.src_reg < 11
-                        && self.registers[instruction.dst_reg as usize]
-                            > self.registers[instruction.src_reg as usize]
-                    {
-                        self.pc = (self.pc as i32 + instruction.offset as i32 + 1) as usize;
-                        continue;
-                    }
-                }
-                0x05 => {
-                    // BPF_JMP | BPF_JA (unconditional jump)
-                    self.pc = (self.pc as i32 + instruction.offset as i32 + 1) as usize;
-                    continue;
-                }
-                0x5f => {
-                    // BPF_ALU64 | BPF_AND | BPF_X (bitwise and)
-                    if instruction.dst_reg < 11 && instruction.src_reg < 11 {
-                        self.registers[instruction.dst_reg as usize] &=
-                            self.registers[instruction.src_reg as usize];
-                    }
-                }
-                _ => {
-                    // Unknown instruction - could be an error or just skip
-                    // For robustness, we'll just continue execution
-                }
+        if instruction.opcode == 0x85 && instruction.src_reg == 1 {
+            // BPF_CALL with BPF_PSEUDO_CALL - relative call to a local function
+            if self.call_stack.len() + 1 >= self.config.max_call_depth {
+                return Err(VmError::StackOverflow);
+            }
+            let mut saved_registers = [0; 4];
+            saved_registers.copy_from_slice(&self.registers[6..10]);
+            self.call_stack.push(CallFrame {
+                return_pc: self.pc + 1,
+                saved_registers,
+                frame_pointer: self.registers[10],
+            });
+            self.registers[10] -= self.config.stack_size as i64;
+            self.pc = (self.pc as i64 + instruction.immediate as i64 + 1) as usize;
+            return Ok(StepResult {
+                exited: false,
+                opcode: instruction.opcode,
+            });
+        }
+
+        if instruction.opcode == 0x18 {
+            // BPF_LD | BPF_IMM | BPF_DW - 64-bit immediate spanning two slots
+            let high = self
+                .program
+                .get(self.pc + 1)
+                .ok_or_else(|| VmError::InvalidInstruction("Truncated lddw".to_string()))?
+                .immediate;
+            if instruction.dst_reg < 11 {
+                self.registers[instruction.dst_reg as usize] =
+                    ((high as u64) << 32 | instruction.immediate as u32 as u64) as i64;
+            }
+            self.pc += 2;
+            return Ok(StepResult {
+                exited: false,
+                opcode: instruction.opcode,
+            });
+        }
+
+        match self.jump_taken(&instruction) {
+            Some(true) => self.take_jump(instruction.offset),
+            Some(false) => {
+                self.pc += 1;
+            }
+            None => {
+                self.execute_instruction(&instruction)?;
+                self.pc += 1;
             }
+        }
+
+        Ok(StepResult {
+            exited: false,
+            opcode: instruction.opcode,
+        })
+    }
+
+    /// Move the PC `offset` instructions past the one after the current jump.
+    fn take_jump(&mut self, offset: i16) {
+        self.pc = (self.pc as i64 + offset as i64 + 1) as usize;
+    }
+
+    /// Evaluate a jump's condition, or `None` if the instruction is not a jump.
+    fn jump_taken(&self, instruction: &BpfInstruction) -> Option<bool> {
+        let dst = self.get_register(instruction.dst_reg as usize);
+        let src = self.get_register(instruction.src_reg as usize);
+        let imm = instruction.immediate as i64;
+        // JGT/JGE/JLT/JLE compare unsigned; the JS* variants compare signed
+        let (udst, usrc, uimm) = (dst as u64, src as u64, imm as u64);
 
-            self.pc += 1;
+        match instruction.opcode {
+            0x05 => Some(true),         // BPF_JA
+            0x15 => Some(dst == imm),   // BPF_JEQ | BPF_K
+            0x1d => Some(dst == src),   // BPF_JEQ | BPF_X
+            0x25 => Some(udst > uimm),  // BPF_JGT | BPF_K
+            0x2d => Some(udst > usrc),  // BPF_JGT | BPF_X
+            0x35 => Some(udst >= uimm), // BPF_JGE | BPF_K
+            0x3d => Some(udst >= usrc), // BPF_JGE | BPF_X
+            0x55 => Some(dst != imm),   // BPF_JNE | BPF_K
+            0x5d => Some(dst != src),   // BPF_JNE | BPF_X
+            0x65 => Some(dst > imm),    // BPF_JSGT | BPF_K
+            0x6d => Some(dst > src),    // BPF_JSGT | BPF_X
+            0x75 => Some(dst >= imm),   // BPF_JSGE | BPF_K
+            0x7d => Some(dst >= src),   // BPF_JSGE | BPF_X
+            0xa5 => Some(udst < uimm),  // BPF_JLT | BPF_K
+            0xad => Some(udst < usrc),  // BPF_JLT | BPF_X
+            0xb5 => Some(udst <= uimm), // BPF_JLE | BPF_K
+            0xbd => Some(udst <= usrc), // BPF_JLE | BPF_X
+            0xc5 => Some(dst < imm),    // BPF_JSLT | BPF_K
+            0xcd => Some(dst < src),    // BPF_JSLT | BPF_X
+            0xd5 => Some(dst <= imm),   // BPF_JSLE | BPF_K
+            0xdd => Some(dst <= src),   // BPF_JSLE | BPF_X
+            _ => None,
+        }
+    }
+
+    pub fn execute(&mut self) -> Result<VmResult, VmError> {
+        while self.pc < self.program.len() {
+            if self.step()?.exited {
+                break;
+            }
         }
 
         Ok(VmResult {
@@ -481,10 +746,81 @@ impl BpfVm {
         })
     }
 
+    /// Read-only view of R0-R10.
+    pub fn registers(&self) -> &[i64; 11] {
+        &self.registers
+    }
+
+    /// Instructions executed so far, in order. Empty unless
+    /// [`VmConfig::trace`] is set.
+    #[allow(dead_code)]
+    pub fn trace(&self) -> &[TraceEntry] {
+        &self.trace
+    }
+
+    /// Snapshot the PC, registers and compute units consumed so far.
+    pub fn dump_state(&self) -> VmState {
+        VmState {
+            pc: self.pc,
+            registers: self.registers,
+            compute_units: self.compute_units,
+        }
+    }
+
+    /// Copy `data` into VM memory at `address`, e.g. to map a program's `.rodata`.
+    pub fn load_data(&mut self, address: usize, data: &[u8]) -> Result<(), VmError> {
+        let end = address
+            .checked_add(data.len())
+            .filter(|&end| end <= self.memory.len())
+            .ok_or_else(|| {
+                VmError::InvalidInstruction(format!(
+                    "Data of {} bytes does not fit at address {}",
+                    data.len(),
+                    address
+                ))
+            })?;
+        self.memory[address..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Copy `input` to the 8-byte aligned end of the heap, clear of `.rodata`
+    /// at address 0, and point R1 at it with its length in R2. The input
+    /// must fit in [`VmConfig::memory_size`].
+    pub fn set_input(&mut self, input: &[u8]) -> Result<(), VmError> {
+        let address = self
+            .config
+            .memory_size
+            .checked_sub(input.len())
+            .map(|address| address & !7)
+            .ok_or_else(|| {
+                VmError::InvalidInstruction(format!(
+                    "Input of {} bytes does not fit in {} bytes of heap",
+                    input.len(),
+                    self.config.memory_size
+                ))
+            })?;
+        self.load_data(address, input)?;
+        self.registers[1] = address as i64;
+        self.registers[2] = input.len() as i64;
+        Ok(())
+    }
+
+    /// Register a helper for `call <id>`, overriding any built-in behavior.
+    #[allow(dead_code)]
+    pub fn register_helper(&mut self, id: i32, helper: HelperFn) {
+        self.helpers.insert(id, helper);
+    }
+
     fn handle_call(&mut self, func_id: i32) -> Result<(), VmError> {
+        if let Some(helper) = self.helpers.get_mut(&func_id) {
+            self.registers[0] = helper(&mut self.registers, &mut self.memory);
+            return Ok(());
+        }
+
         match func_id {
             6 => {
                 // BPF_FUNC_trace_printk - simulate printing
+                #[cfg(feature = "std")]
                 println!("VM: trace_printk called");
             }
             _ => {
@@ -0,0 +1,398 @@
+use crate::pible::prelude::*;
+use thiserror::Error;
+
+use crate::pible::parser::{Node, NodeType};
+use crate::pible::types::{StructLayout, Type};
+
+#[derive(Error, Debug)]
+pub enum BtfError {
+    #[error("Field {field} of struct {name} has unknown size")]
+    UnknownFieldSize { name: String, field: String },
+    #[error("Truncated BTF blob: {0}")]
+    Truncated(String),
+    #[error("Invalid BTF blob: {0}")]
+    InvalidFormat(String),
+}
+
+// Constants from the kernel's include/uapi/linux/btf.h
+#[allow(dead_code)]
+pub mod btf_consts {
+    pub const BTF_MAGIC: u16 = 0xeb9f;
+    pub const BTF_VERSION: u8 = 1;
+    pub const HEADER_SIZE: usize = 24;
+
+    pub const BTF_KIND_INT: u32 = 1;
+    pub const BTF_KIND_PTR: u32 = 2;
+    pub const BTF_KIND_ARRAY: u32 = 3;
+    pub const BTF_KIND_STRUCT: u32 = 4;
+    pub const BTF_KIND_FUNC: u32 = 12;
+    pub const BTF_KIND_FUNC_PROTO: u32 = 13;
+    pub const BTF_KIND_FLOAT: u32 = 16;
+
+    pub const BTF_INT_SIGNED: u32 = 1 << 0;
+    pub const BTF_INT_BOOL: u32 = 1 << 2;
+
+    pub const BTF_FUNC_GLOBAL: u32 = 1;
+}
+
+use btf_consts::*;
+
+/// One type entry; its BTF type id is its index in [`BtfBuilder::types`] plus one.
+#[derive(Debug, Clone)]
+struct BtfType {
+    name: u32,
+    kind: u32,
+    vlen: u32,
+    size_or_type: u32,
+    /// Kind-specific data following the common header
+    extra: Vec<u32>,
+}
+
+#[derive(Debug)]
+struct BtfBuilder {
+    types: Vec<BtfType>,
+    strings: Vec<u8>,
+    /// Ids of types already emitted, so each is described once
+    ids: HashMap<Type, u32>,
+}
+
+/// Describe the structs and functions declared in `ast` as a `.BTF` section:
+/// integer, pointer, array, struct, function prototype and function types.
+pub fn generate_btf(ast: &Node) -> Result<Vec<u8>, BtfError> {
+    let mut builder = BtfBuilder {
+        types: Vec::new(),
+        strings: vec![0],
+        ids: HashMap::new(),
+    };
+
+    let declarations: Vec<&Node> = ast
+        .children
+        .iter()
+        .filter(|node| node.node_type == NodeType::StructDecl)
+        .collect();
+    let names: Vec<String> = declarations
+        .iter()
+        .map(|node| node.value.clone().unwrap_or_default())
+        .collect();
+
+    // Structs may point at each other, so give them all ids before any member
+    for name in &names {
+        let name_offset = builder.string(name);
+        builder
+            .ids
+            .insert(Type::Struct(name.clone()), builder.types.len() as u32 + 1);
+        builder.types.push(BtfType {
+            name: name_offset,
+            kind: BTF_KIND_STRUCT,
+            vlen: 0,
+            size_or_type: 0,
+            extra: Vec::new(),
+        });
+    }
+
+    let mut layouts = HashMap::new();
+    for (index, node) in declarations.iter().enumerate() {
+        let name = &names[index];
+        let fields: Vec<(String, Type)> = node
+            .children
+            .iter()
+            .filter_map(|field| declaration(field.value.as_deref()?, &names))
+            .map(|(ty, field)| (field.to_string(), ty))
+            .collect();
+        let layout = StructLayout::new(name, &fields, &layouts).map_err(|field| {
+            BtfError::UnknownFieldSize {
+                name: name.clone(),
+                field,
+            }
+        })?;
+
+        let mut members = Vec::with_capacity(layout.fields.len() * 3);
+        for field in &layout.fields {
+            members.push(builder.string(&field.name));
+            members.push(builder.type_id(&field.ty));
+            members.push((field.offset * 8) as u32);
+        }
+        let entry = &mut builder.types[index];
+        entry.vlen = layout.fields.len() as u32;
+        entry.size_or_type = layout.size as u32;
+        entry.extra = members;
+        layouts.insert(name.clone(), layout);
+    }
+
+    for function in ast
+        .children
+        .iter()
+        .filter(|node| node.node_type == NodeType::FunctionDecl)
+    {
+        let Some((return_type, name)) = function
+            .value
+            .as_deref()
+            .and_then(|value| declaration(value, &names))
+        else {
+            continue;
+        };
+
+        let mut params = Vec::new();
+        for param in function
+            .children
+            .iter()
+            .filter(|node| node.node_type == NodeType::Identifier)
+        {
+            if let Some((ty, param)) = param.value.as_deref().and_then(|v| declaration(v, &names)) {
+                params.push(builder.string(param));
+                params.push(builder.type_id(&ty));
+            }
+        }
+        let return_id = builder.type_id(&return_type);
+        let proto = builder.push(BtfType {
+            name: 0,
+            kind: BTF_KIND_FUNC_PROTO,
+            vlen: params.len() as u32 / 2,
+            size_or_type: return_id,
+            extra: params,
+        });
+        let name = builder.string(name);
+        builder.push(BtfType {
+            name,
+            kind: BTF_KIND_FUNC,
+            vlen: BTF_FUNC_GLOBAL,
+            size_or_type: proto,
+            extra: Vec::new(),
+        });
+    }
+
+    Ok(builder.build())
+}
+
+impl BtfBuilder {
+    fn push(&mut self, entry: BtfType) -> u32 {
+        self.types.push(entry);
+        self.types.len() as u32
+    }
+
+    fn string(&mut self, value: &str) -> u32 {
+        let offset = self.strings.len() as u32;
+        self.strings.extend_from_slice(value.as_bytes());
+        self.strings.push(0);
+        offset
+    }
+
+    /// Id of `ty`, emitting it and anything it refers to on first use.
+    /// `U0` is id 0, as BTF reserves it for void.
+    fn type_id(&mut self, ty: &Type) -> u32 {
+        if *ty == Type::Void {
+            return 0;
+        }
+        if let Some(&id) = self.ids.get(ty) {
+            return id;
+        }
+
+        let entry = match ty {
+            Type::Int { bits, signed } => {
+                let prefix = if *signed { "I" } else { "U" };
+                let encoding = if *signed { BTF_INT_SIGNED } else { 0 };
+                self.int(&format!("{}{}", prefix, bits), *bits as u32, encoding)
+            }
+            Type::Bool => self.int("Bool", 8, BTF_INT_BOOL),
+            Type::F64 => BtfType {
+                name: self.string("F64"),
+                kind: BTF_KIND_FLOAT,
+                vlen: 0,
+                size_or_type: 8,
+                extra: Vec::new(),
+            },
+            Type::Pointer(pointee) => BtfType {
+                name: 0,
+                kind: BTF_KIND_PTR,
+                vlen: 0,
+                size_or_type: self.type_id(pointee),
+                extra: Vec::new(),
+            },
+            Type::Array(element, count) => {
+                let element = self.type_id(element);
+                let index = self.type_id(&Type::Int {
+                    bits: 32,
+                    signed: false,
+                });
+                BtfType {
+                    name: 0,
+                    kind: BTF_KIND_ARRAY,
+                    vlen: 0,
+                    size_or_type: 0,
+                    extra: vec![element, index, *count as u32],
+                }
+            }
+            Type::Function { ret, params } => {
+                let ret = self.type_id(ret);
+                let params = params
+                    .iter()
+                    .flat_map(|param| [0, self.type_id(param)])
+                    .collect::<Vec<_>>();
+                BtfType {
+                    name: 0,
+                    kind: BTF_KIND_FUNC_PROTO,
+                    vlen: params.len() as u32 / 2,
+                    size_or_type: ret,
+                    extra: params,
+                }
+            }
+            // Undeclared structs are treated as `I64`, as codegen does
+            Type::Struct(_) => return self.type_id(&Type::I64),
+            Type::Void => unreachable!(),
+        };
+        let id = self.push(entry);
+        self.ids.insert(ty.clone(), id);
+        id
+    }
+
+    fn int(&mut self, name: &str, bits: u32, encoding: u32) -> BtfType {
+        BtfType {
+            name: self.string(name),
+            kind: BTF_KIND_INT,
+            vlen: 0,
+            size_or_type: bits / 8,
+            extra: vec![(encoding << 24) | bits],
+        }
+    }
+
+    /// Serialize the header, type section and string section.
+    fn build(&self) -> Vec<u8> {
+        let mut types = Vec::new();
+        for entry in &self.types {
+            let info = (entry.kind << 24) | entry.vlen;
+            for word in [entry.name, info, entry.size_or_type]
+                .iter()
+                .chain(&entry.extra)
+            {
+                types.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+
+        let mut output = Vec::with_capacity(HEADER_SIZE + types.len() + self.strings.len());
+        output.extend_from_slice(&BTF_MAGIC.to_le_bytes());
+        output.push(BTF_VERSION);
+        output.push(0); // flags
+        output.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        output.extend_from_slice(&0u32.to_le_bytes()); // type_off
+        output.extend_from_slice(&(types.len() as u32).to_le_bytes());
+        output.extend_from_slice(&(types.len() as u32).to_le_bytes()); // str_off
+        output.extend_from_slice(&(self.strings.len() as u32).to_le_bytes());
+        output.extend_from_slice(&types);
+        output.extend_from_slice(&self.strings);
+        output
+    }
+}
+
+/// Type and name from a `Type:name` declaration, accepting a declared
+/// struct's bare name as in `Account* account`.
+fn declaration<'v>(value: &'v str, structs: &[String]) -> Option<(Type, &'v str)> {
+    let (ty, name) = value.split_once(':')?;
+    let ty = Type::parse(ty)
+        .or_else(|| {
+            let base = ty.trim_end_matches('*').trim();
+            structs
+                .iter()
+                .any(|name| name == base)
+                .then(|| Type::parse(&format!("struct {}", ty)))
+                .flatten()
+        })
+        .unwrap_or(Type::I64);
+    Some((ty, name))
+}
+
+/// A type entry as read back from a BTF blob.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedType {
+    pub kind: u32,
+    pub name: String,
+    pub size_or_type: u32,
+    /// Struct members or prototype parameters: name and type id
+    pub members: Vec<(String, u32)>,
+}
+
+/// Read back the type entries of a BTF blob, checking its header.
+#[allow(dead_code)]
+pub fn parse_btf(bytes: &[u8]) -> Result<Vec<ParsedType>, BtfError> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(BtfError::Truncated("header".to_string()));
+    }
+    if read_u16(bytes, 0) != BTF_MAGIC {
+        return Err(BtfError::InvalidFormat("bad magic".to_string()));
+    }
+    if bytes[2] != BTF_VERSION {
+        return Err(BtfError::InvalidFormat(format!(
+            "unsupported version {}",
+            bytes[2]
+        )));
+    }
+
+    let header_len = read_u32(bytes, 4) as usize;
+    let type_start = header_len + read_u32(bytes, 8) as usize;
+    let type_end = type_start + read_u32(bytes, 12) as usize;
+    let string_start = header_len + read_u32(bytes, 16) as usize;
+    let string_end = string_start + read_u32(bytes, 20) as usize;
+    if type_end > bytes.len() || string_end > bytes.len() {
+        return Err(BtfError::Truncated("sections".to_string()));
+    }
+    let strings = &bytes[string_start..string_end];
+    let name = |offset: u32| {
+        let tail = strings.get(offset as usize..).unwrap_or(&[]);
+        let end = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+        String::from_utf8_lossy(&tail[..end]).into_owned()
+    };
+
+    let mut parsed = Vec::new();
+    let mut offset = type_start;
+    while offset < type_end {
+        if offset + 12 > type_end {
+            return Err(BtfError::Truncated(format!("type {}", parsed.len() + 1)));
+        }
+        let info = read_u32(bytes, offset + 4);
+        let kind = (info >> 24) & 0x1f;
+        let vlen = (info & 0xffff) as usize;
+        let (extra_words, member_words) = match kind {
+            BTF_KIND_INT => (1, 0),
+            BTF_KIND_ARRAY => (3, 0),
+            BTF_KIND_STRUCT => (vlen * 3, 3),
+            BTF_KIND_FUNC_PROTO => (vlen * 2, 2),
+            _ => (0, 0),
+        };
+        let end = offset + 12 + extra_words * 4;
+        if end > type_end {
+            return Err(BtfError::Truncated(format!("type {}", parsed.len() + 1)));
+        }
+
+        let members = if member_words == 0 {
+            Vec::new()
+        } else {
+            (0..vlen)
+                .map(|member| {
+                    let base = offset + 12 + member * member_words * 4;
+                    (name(read_u32(bytes, base)), read_u32(bytes, base + 4))
+                })
+                .collect()
+        };
+        parsed.push(ParsedType {
+            kind,
+            name: name(read_u32(bytes, offset)),
+            size_or_type: read_u32(bytes, offset + 8),
+            members,
+        });
+        offset = end;
+    }
+
+    Ok(parsed)
+}
+
+#[allow(dead_code)]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[allow(dead_code)]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
@@ -0,0 +1,145 @@
+use crate::pible::codegen::{BpfInstruction, CodeGenError};
+use crate::pible::prelude::*;
+use alloc::collections::BTreeSet;
+
+/// A run of instructions entered only at `start` and left only after the
+/// last one, at `end - 1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+    /// Indices of the blocks control may pass to next
+    pub successors: Vec<usize>,
+    /// Whether control may run past the end of the program, or jump outside
+    /// it, from this block
+    pub falls_off: bool,
+}
+
+/// How an instruction passes control on.
+enum Flow {
+    Next,
+    Exit,
+    Jump(i64),
+    Branch(i64),
+}
+
+fn flow(pc: usize, instruction: &BpfInstruction) -> Flow {
+    if instruction.opcode & 0x07 != 0x05 {
+        return Flow::Next;
+    }
+    let target = pc as i64 + 1 + instruction.offset as i64;
+    match instruction.opcode & 0xf0 {
+        0x00 => Flow::Jump(target),
+        // Calls return to the next instruction
+        0x80 => Flow::Next,
+        0x90 => Flow::Exit,
+        _ => Flow::Branch(target),
+    }
+}
+
+/// Number of slots an instruction takes; `lddw` spans two.
+fn width(instruction: &BpfInstruction) -> usize {
+    if instruction.opcode == 0x18 {
+        2
+    } else {
+        1
+    }
+}
+
+/// Split `instructions` into basic blocks, in program order.
+pub fn basic_blocks(instructions: &[BpfInstruction]) -> Vec<BasicBlock> {
+    let len = instructions.len() as i64;
+    let mut leaders = BTreeSet::new();
+    let mut pc = 0;
+    while pc < instructions.len() {
+        let instruction = &instructions[pc];
+        let next = pc + width(instruction);
+        if instruction.opcode == 0x85 && instruction.src_reg == 1 {
+            let callee = pc as i64 + 1 + instruction.immediate as i64;
+            if (0..len).contains(&callee) {
+                leaders.insert(callee as usize);
+            }
+        }
+        match flow(pc, instruction) {
+            Flow::Next => {}
+            Flow::Exit => {
+                leaders.insert(next);
+            }
+            Flow::Jump(target) | Flow::Branch(target) => {
+                if (0..len).contains(&target) {
+                    leaders.insert(target as usize);
+                }
+                leaders.insert(next);
+            }
+        }
+        pc = next;
+    }
+    leaders.insert(0);
+    leaders.retain(|&leader| leader < instructions.len());
+
+    let starts: Vec<usize> = leaders.into_iter().collect();
+    let block_at = |target: i64| starts.binary_search(&(target as usize)).ok();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, &start)| {
+            let end = starts.get(index + 1).copied().unwrap_or(instructions.len());
+            // The last instruction starts before `end`; skip over `lddw`'s second slot
+            let mut last = start;
+            while last + width(&instructions[last]) < end {
+                last += width(&instructions[last]);
+            }
+
+            let mut successors = Vec::new();
+            let mut falls_off = false;
+            let mut follow = |target: i64| match block_at(target) {
+                Some(block) if (0..len).contains(&target) => successors.push(block),
+                _ => falls_off = true,
+            };
+            match flow(last, &instructions[last]) {
+                Flow::Next => follow(end as i64),
+                Flow::Exit => {}
+                Flow::Jump(target) => follow(target),
+                Flow::Branch(target) => {
+                    follow(end as i64);
+                    follow(target);
+                }
+            }
+
+            BasicBlock {
+                start,
+                end,
+                successors,
+                falls_off,
+            }
+        })
+        .collect()
+}
+
+/// Check that every path reachable from the start of the program, or from
+/// the start of a called function, ends in an `exit`.
+pub fn check_exit_paths(instructions: &[BpfInstruction]) -> Result<(), CodeGenError> {
+    let blocks = basic_blocks(instructions);
+    let mut pending: Vec<usize> = (0..blocks.len().min(1)).collect();
+    for (pc, instruction) in instructions.iter().enumerate() {
+        if instruction.opcode == 0x85 && instruction.src_reg == 1 {
+            let callee = pc as i64 + 1 + instruction.immediate as i64;
+            if let Some(block) = blocks.iter().position(|block| block.start as i64 == callee) {
+                pending.push(block);
+            }
+        }
+    }
+
+    let mut visited = vec![false; blocks.len()];
+    while let Some(index) = pending.pop() {
+        if core::mem::replace(&mut visited[index], true) {
+            continue;
+        }
+        let block = &blocks[index];
+        if block.falls_off {
+            return Err(CodeGenError::MissingReturn(block.end - 1));
+        }
+        pending.extend(&block.successors);
+    }
+    Ok(())
+}
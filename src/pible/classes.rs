@@ -0,0 +1,32 @@
+use crate::pible::parser::{Node, NodeType};
+use crate::pible::prelude::*;
+
+/// Replace each `ClassDecl` in `program` with a `StructDecl` holding its
+/// fields, followed by its methods as ordinary functions. Access modifiers
+/// are kept on the members but not enforced.
+pub fn lower(program: &Node) -> Node {
+    let mut lowered = Node {
+        children: Vec::new(),
+        ..program.clone()
+    };
+
+    for child in &program.children {
+        if child.node_type != NodeType::ClassDecl {
+            lowered.add_child(child.clone());
+            continue;
+        }
+
+        let (methods, fields): (Vec<&Node>, Vec<&Node>) = child
+            .children
+            .iter()
+            .partition(|member| member.node_type == NodeType::FunctionDecl);
+        let mut layout = Node::new(NodeType::StructDecl);
+        layout.value = child.value.clone();
+        layout.position = child.position;
+        layout.children = fields.into_iter().cloned().collect();
+        lowered.add_child(layout);
+        lowered.children.extend(methods.into_iter().cloned());
+    }
+
+    lowered
+}
@@ -1,4 +1,11 @@
+use crate::pible::compiler::{CompileTarget, ProgramType};
+use crate::pible::optimizer;
 use crate::pible::parser::{Node, NodeType};
+use crate::pible::prelude::*;
+use crate::pible::regalloc;
+use crate::pible::solana_bpf;
+use crate::pible::types::{BorshItem, FieldLayout, StructLayout, Type};
+use crate::pible::visit::{self, walk_node, Visitor};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,11 +15,36 @@ pub enum CodeGenError {
     UnsupportedNodeType(NodeType),
     #[error("Function not found: {0}")]
     FunctionNotFound(String),
+    #[error("Undefined variable: {0}")]
+    UndefinedVariable(String),
     #[error("Invalid instruction: {0}")]
     InvalidInstruction(String),
+    #[error("Expression too complex: out of registers")]
+    OutOfRegisters,
+    #[error("Unknown struct: {0}")]
+    UnknownStruct(String),
+    #[error("Struct {0} has no field {1}")]
+    UnknownField(String, String),
+    #[error("Invalid member access: {0}")]
+    InvalidMemberAccess(String),
+    #[error("Unsupported F64 operation: {0}")]
+    UnsupportedFloat(String),
+    #[error("Cannot assign to constant {0}")]
+    AssignToConstant(String),
+    #[error("Constant {0} needs an integer value known at compile time")]
+    NonConstantInitializer(String),
+    #[error("Control can run past instruction {0} without reaching an exit")]
+    MissingReturn(usize),
+    #[error("`{0}` outside of a loop")]
+    OutsideLoop(String),
+    #[error("Integer literal {0}")]
+    LiteralOverflow(String),
+    /// A jump spanning more instructions than its offset field holds
+    #[error("Jump of {0} instructions is too far for the offset field")]
+    JumpTooFar(i64),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
 pub struct BpfInstruction {
     pub opcode: u8,
@@ -33,6 +65,8 @@ impl BpfInstruction {
         }
     }
 
+    /// Encode as the 8-byte little-endian BPF wire format. This is the only
+    /// encoder; [`Self::from_bytes`] is its inverse.
     pub fn as_bytes(self) -> [u8; 8] {
         let mut bytes = [0u8; 8];
 
@@ -52,11 +86,103 @@ impl BpfInstruction {
 
         bytes
     }
+
+    /// Decode one instruction slot written by [`Self::as_bytes`].
+    pub fn from_bytes(bytes: [u8; 8]) -> Self {
+        Self {
+            opcode: bytes[0],
+            dst_reg: bytes[1] & 0x0f,
+            src_reg: (bytes[1] & 0xf0) >> 4,
+            offset: i16::from_le_bytes([bytes[2], bytes[3]]),
+            immediate: i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        }
+    }
+
+    /// Instruction class: the low three bits of the opcode, such as
+    /// `BPF_ALU64` or `BPF_JMP`.
+    pub fn class(&self) -> u8 {
+        self.opcode & 0x07
+    }
+
+    /// Assembler name of the instruction, such as `add64`, `ldxdw` or
+    /// `exit`, or `"unknown"` for an opcode this compiler doesn't know.
+    /// Register and immediate forms share a name.
+    pub fn mnemonic(&self) -> &'static str {
+        let op = (self.opcode >> 4) as usize;
+        let name = match self.class() {
+            bpf_opcodes::BPF_ALU | bpf_opcodes::BPF_ALU64 if op == 0xd => {
+                let big_endian = self.opcode & bpf_opcodes::BPF_X != 0;
+                match (big_endian, self.immediate) {
+                    (false, 16) => "le16",
+                    (false, 32) => "le32",
+                    (false, 64) => "le64",
+                    (true, 16) => "be16",
+                    (true, 32) => "be32",
+                    (true, 64) => "be64",
+                    _ => "",
+                }
+            }
+            bpf_opcodes::BPF_ALU64 if self.offset == bpf_opcodes::BPF_SIGNED_DIV_OFFSET => match op
+            {
+                0x3 => "sdiv64",
+                0x9 => "smod64",
+                _ => ALU64_MNEMONICS[op],
+            },
+            bpf_opcodes::BPF_ALU if self.offset == bpf_opcodes::BPF_SIGNED_DIV_OFFSET => match op {
+                0x3 => "sdiv32",
+                0x9 => "smod32",
+                _ => ALU_MNEMONICS[op],
+            },
+            bpf_opcodes::BPF_ALU64 => ALU64_MNEMONICS[op],
+            bpf_opcodes::BPF_ALU => ALU_MNEMONICS[op],
+            bpf_opcodes::BPF_JMP => JMP_MNEMONICS[op],
+            _ => match self.opcode {
+                0x18 => "lddw",
+                0x61 => "ldxw",
+                0x69 => "ldxh",
+                0x71 => "ldxb",
+                0x79 => "ldxdw",
+                0x62 => "stw",
+                0x6a => "sth",
+                0x72 => "stb",
+                0x7a => "stdw",
+                0x63 => "stxw",
+                0x6b => "stxh",
+                0x73 => "stxb",
+                0x7b => "stxdw",
+                // BPF_ATOMIC with an immediate of BPF_ADD
+                0xc3 if self.immediate == 0 => "xaddw",
+                0xdb if self.immediate == 0 => "xadddw",
+                _ => "",
+            },
+        };
+        if name.is_empty() {
+            "unknown"
+        } else {
+            name
+        }
+    }
 }
 
+/// Names of `BPF_ALU64` and `BPF_ALU` operations, indexed by the high four
+/// bits of the opcode. Byte swaps (0xd) are named by [`BpfInstruction::mnemonic`].
+const ALU64_MNEMONICS: [&str; 16] = [
+    "add64", "sub64", "mul64", "div64", "or64", "and64", "lsh64", "rsh64", "neg64", "mod64",
+    "xor64", "mov64", "arsh64", "", "", "",
+];
+const ALU_MNEMONICS: [&str; 16] = [
+    "add32", "sub32", "mul32", "div32", "or32", "and32", "lsh32", "rsh32", "neg32", "mod32",
+    "xor32", "mov32", "arsh32", "", "", "",
+];
+/// Names of `BPF_JMP` operations, indexed by the high four bits of the opcode.
+const JMP_MNEMONICS: [&str; 16] = [
+    "ja", "jeq", "jgt", "jge", "jset", "jne", "jsgt", "jsge", "call", "exit", "jlt", "jle", "jslt",
+    "jsle", "", "",
+];
+
 // BPF opcodes and instruction classes
 #[allow(dead_code)]
-mod bpf_opcodes {
+pub(crate) mod bpf_opcodes {
     // Instruction classes
     pub const BPF_LD: u8 = 0x00;
     pub const BPF_LDX: u8 = 0x01;
@@ -71,268 +197,3067 @@ mod bpf_opcodes {
     pub const BPF_SUB: u8 = 0x10;
     pub const BPF_MUL: u8 = 0x20;
     pub const BPF_DIV: u8 = 0x30;
+    pub const BPF_OR: u8 = 0x40;
+    pub const BPF_AND: u8 = 0x50;
+    pub const BPF_LSH: u8 = 0x60;
+    pub const BPF_RSH: u8 = 0x70;
+    pub const BPF_NEG: u8 = 0x80;
     pub const BPF_MOD: u8 = 0x90;
+    pub const BPF_XOR: u8 = 0xa0;
     pub const BPF_MOV: u8 = 0xb0;
+    pub const BPF_ARSH: u8 = 0xc0;
+    // Offset marking BPF_DIV/BPF_MOD as signed (BPF ISA v4)
+    pub const BPF_SIGNED_DIV_OFFSET: i16 = 1;
 
     // Jump operations
     pub const BPF_JA: u8 = 0x00;
     pub const BPF_JEQ: u8 = 0x10;
     pub const BPF_JGT: u8 = 0x20;
     pub const BPF_JGE: u8 = 0x30;
+    pub const BPF_JNE: u8 = 0x50;
+    pub const BPF_JSGT: u8 = 0x60;
+    pub const BPF_JSGE: u8 = 0x70;
     pub const BPF_CALL: u8 = 0x80;
     pub const BPF_EXIT: u8 = 0x90;
+    pub const BPF_JLT: u8 = 0xa0;
+    pub const BPF_JLE: u8 = 0xb0;
+    pub const BPF_JSLT: u8 = 0xc0;
+    pub const BPF_JSLE: u8 = 0xd0;
+
+    // Call source: local function rather than helper
+    pub const BPF_PSEUDO_CALL: u8 = 1;
+    // lddw source: the loader replaces the immediate with a map's fd
+    pub const BPF_PSEUDO_MAP_FD: u8 = 1;
 
     // Source operand
     pub const BPF_K: u8 = 0x00; // immediate
     pub const BPF_X: u8 = 0x08; // register
+
+    // Load sizes and modes
+    pub const BPF_IMM: u8 = 0x00;
+    pub const BPF_MEM: u8 = 0x60;
+    pub const BPF_W: u8 = 0x00;
+    pub const BPF_H: u8 = 0x08;
+    pub const BPF_B: u8 = 0x10;
+    pub const BPF_DW: u8 = 0x18;
+}
+
+/// A BPF map declared with `map hash<K, V> name[max_entries];`, laid out as
+/// the kernel's `struct bpf_map_def` in the `maps` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapDefinition {
+    pub name: String,
+    pub map_type: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+}
+
+impl MapDefinition {
+    pub const HASH: u32 = 1; // BPF_MAP_TYPE_HASH
+    pub const ARRAY: u32 = 2; // BPF_MAP_TYPE_ARRAY
+}
+
+/// `F64` values are signed 32.32 fixed point, as eBPF has no floating-point
+/// instructions: the integer part sits in the high 32 bits and the fraction in
+/// the low 32. Addition, subtraction, negation and comparisons work on the raw
+/// bits; multiplication, division, remainder and shifts are rejected.
+const FIXED_POINT_SHIFT: i32 = 32;
+
+/// First and last registers used for expression temporaries. R6-R9 are
+/// callee-saved, so temporaries survive calls to helpers and functions.
+/// Temporaries nested deeper than that reuse the registers in turn, spilling
+/// the value a register held to the stack until it's the innermost again.
+const FIRST_TEMP_REG: u8 = 6;
+const LAST_TEMP_REG: u8 = 9;
+const TEMP_REGS: usize = (LAST_TEMP_REG - FIRST_TEMP_REG + 1) as usize;
+const FRAME_POINTER: u8 = 10;
+
+/// Pending call name of the stub that indirect calls go through; no
+/// function can be called this.
+const DISPATCH: &str = "<dispatch>";
+
+/// Anchor's `InstructionDidNotDeserialize`, returned by an `export`ed
+/// function whose struct argument fails to decode.
+pub const ERROR_DID_NOT_DESERIALIZE: i32 = 102;
+
+/// A local variable's stack slot and declared type.
+#[derive(Debug, Clone)]
+struct Variable {
+    offset: i16,
+    ty: Type,
+}
+
+/// Jumps out of the body of the innermost loop being generated, patched
+/// once the loop's exit and continue targets are known.
+#[derive(Debug, Default)]
+pub struct LoopJumps {
+    pub breaks: Vec<usize>,
+    pub continues: Vec<usize>,
+}
+
+/// A function whose body is being generated in place of a call to it.
+#[derive(Debug)]
+struct InlineCall {
+    name: String,
+    /// Live temporaries at the call
+    temps: usize,
+    /// Jumps from its `return`s to the end of the body, with the value in r0
+    returns: Vec<usize>,
+}
+
+/// A file-scope `const`, substituted as an immediate wherever it's used.
+#[derive(Debug, Clone)]
+struct Constant {
+    ty: Type,
+    value: i64,
+}
+
+/// Exit code of a program that indexed past the end of an array, when
+/// compiled with bounds checks. Spells "OOB" in ASCII.
+pub const INDEX_OUT_OF_BOUNDS: i32 = 0x4f4f42;
+
+/// A memory location: `offset` bytes past the address held in `reg`.
+#[derive(Debug, Clone)]
+struct Place {
+    reg: u8,
+    offset: i16,
+    ty: Type,
 }
 
 pub struct CodeGen {
+    target: CompileTarget,
+    program_type: ProgramType,
+    /// Helper `PrintF` and `sol_log` call instead of the target's logging helper
+    log_helper: Option<i32>,
+    /// Trap with [`INDEX_OUT_OF_BOUNDS`] on indices past the end of an array
+    bounds_checks: bool,
+    /// Functions of at most this many AST nodes are inlined; 0 disables inlining
+    inline_threshold: usize,
+    /// Allocate registers for call-free arithmetic with [`regalloc`]
+    register_allocation: bool,
+    /// Functions small enough to inline, by name
+    inline_functions: HashMap<String, Node>,
+    /// Calls being inlined, innermost last
+    inlining: Vec<InlineCall>,
     instructions: Vec<BpfInstruction>,
-    current_reg: u8,
+    /// Source line each instruction was generated from, parallel to `instructions`
+    source_lines: Vec<Option<usize>>,
+    /// Line of the innermost node being generated
+    current_line: Option<usize>,
+    /// Number of live expression temporaries
+    temps: usize,
+    /// Stack slots holding spilled temporaries, by depth past the registers
+    spill_slots: Vec<i16>,
+    rodata: Vec<u8>,
+    rodata_relocations: Vec<usize>,
+    maps: Vec<MapDefinition>,
+    map_relocations: Vec<(usize, String)>,
+    functions: HashMap<String, usize>,
+    pending_calls: Vec<(usize, String)>,
+    /// Functions used as values; a function pointer holds its index here plus one
+    function_table: Vec<String>,
+    return_types: HashMap<String, Type>,
+    /// Number of named parameters of each variadic function
+    variadic_functions: HashMap<String, usize>,
+    structs: HashMap<String, StructLayout>,
+    constants: HashMap<String, Constant>,
+    /// `export`ed functions that decode Borsh-encoded struct arguments
+    borsh_functions: HashSet<String>,
+    scopes: Vec<HashMap<String, Variable>>,
+    /// Enclosing loops, innermost last
+    loops: Vec<LoopJumps>,
+    /// Return type of the function, or inlined body, being generated
+    return_type: Type,
+    frame_size: i16,
+}
+
+impl Default for CodeGen {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CodeGen {
     pub fn new() -> Self {
         Self {
+            target: CompileTarget::LinuxBpf,
+            program_type: ProgramType::SocketFilter,
+            log_helper: None,
+            bounds_checks: false,
+            inline_threshold: 0,
+            register_allocation: false,
+            inline_functions: HashMap::new(),
+            inlining: Vec::new(),
             instructions: Vec::new(),
-            current_reg: 1, // R0 is return register
+            source_lines: Vec::new(),
+            current_line: None,
+            temps: 0,
+            spill_slots: Vec::new(),
+            rodata: Vec::new(),
+            rodata_relocations: Vec::new(),
+            maps: Vec::new(),
+            map_relocations: Vec::new(),
+            functions: HashMap::new(),
+            pending_calls: Vec::new(),
+            function_table: Vec::new(),
+            return_types: HashMap::new(),
+            variadic_functions: HashMap::new(),
+            structs: HashMap::new(),
+            constants: HashMap::new(),
+            borsh_functions: HashSet::new(),
+            scopes: vec![HashMap::new()],
+            loops: Vec::new(),
+            return_type: Type::I64,
+            frame_size: 0,
+        }
+    }
+
+    /// Code generator for `target`, which decides how builtins such as
+    /// `PrintF` reach the runtime.
+    pub fn for_target(target: CompileTarget) -> Self {
+        Self {
+            target,
+            ..Self::new()
         }
     }
 
+    /// Linux program type, whose context struct is predeclared for the
+    /// entry function.
+    pub fn with_program_type(mut self, program_type: ProgramType) -> Self {
+        self.program_type = program_type;
+        self
+    }
+
+    /// Call helper `id` for `PrintF` and `sol_log`, for runtimes whose
+    /// logging helper isn't the target's usual one.
+    pub fn with_log_helper(mut self, id: Option<i32>) -> Self {
+        self.log_helper = id;
+        self
+    }
+
+    /// Check each index into an array of known length before using it,
+    /// exiting with [`INDEX_OUT_OF_BOUNDS`] when it's out of range. Like
+    /// `Require`, the exit returns from the current function.
+    pub fn with_bounds_checks(mut self, enabled: bool) -> Self {
+        self.bounds_checks = enabled;
+        self
+    }
+
+    /// Generate the bodies of functions of at most `threshold` AST nodes at
+    /// their call sites instead of calling them. 0 disables inlining.
+    pub fn with_inline_threshold(mut self, threshold: usize) -> Self {
+        self.inline_threshold = threshold;
+        self
+    }
+
+    /// Evaluate arithmetic on integer variables and literals with a
+    /// liveness-based allocator over every free register, r1-r9, instead of
+    /// the stack of temporaries in r6-r9.
+    pub fn with_register_allocation(mut self, enabled: bool) -> Self {
+        self.register_allocation = enabled;
+        self
+    }
+
+    /// Forget everything generated so far, keeping the target, program type,
+    /// log helper, bounds checking, inline threshold and register
+    /// allocation, so the same generator can compile another program.
+    pub fn reset(&mut self) {
+        *self = Self {
+            target: self.target,
+            program_type: self.program_type.clone(),
+            log_helper: self.log_helper,
+            bounds_checks: self.bounds_checks,
+            inline_threshold: self.inline_threshold,
+            register_allocation: self.register_allocation,
+            ..Self::new()
+        };
+    }
+
+    /// NUL-terminated string literals collected during generation.
+    pub fn rodata(&self) -> &[u8] {
+        &self.rodata
+    }
+
+    /// Indices of `lddw` instructions whose immediate is an offset into [`Self::rodata`].
+    pub fn rodata_relocations(&self) -> &[usize] {
+        &self.rodata_relocations
+    }
+
+    /// Maps declared in the program, in declaration order.
+    pub fn maps(&self) -> &[MapDefinition] {
+        &self.maps
+    }
+
+    /// Indices of `lddw` instructions loading a map, with the map's name.
+    pub fn map_relocations(&self) -> &[(usize, String)] {
+        &self.map_relocations
+    }
+
+    /// Generate the program for `ast`. The instructions move out to the
+    /// caller; function addresses and relocations stay queryable.
     pub fn generate(&mut self, ast: &Node) -> Result<Vec<BpfInstruction>, CodeGenError> {
         self.visit_node(ast)?;
 
         // Add exit instruction
         self.emit_exit(0);
 
-        Ok(self.instructions.clone())
+        if self.pending_calls.iter().any(|(_, name)| name == DISPATCH) {
+            self.generate_dispatch();
+        }
+        self.resolve_calls()?;
+
+        Ok(core::mem::take(&mut self.instructions))
     }
 
-    fn visit_node(&mut self, node: &Node) -> Result<(), CodeGenError> {
+    /// Source line each generated instruction came from, indexed like the
+    /// instructions [`Self::generate`] returned. Lines count from the start
+    /// of the preprocessed source.
+    pub fn source_lines(&self) -> &[Option<usize>] {
+        &self.source_lines
+    }
+
+    /// Instruction index where each generated function starts.
+    pub fn functions(&self) -> &HashMap<String, usize> {
+        &self.functions
+    }
+
+    /// Declared return type of the function `name`.
+    pub fn return_type(&self, name: &str) -> Option<&Type> {
+        self.return_types.get(name)
+    }
+
+    /// Whether the function `name` decodes Borsh-encoded struct arguments,
+    /// and so may fail with [`ERROR_DID_NOT_DESERIALIZE`].
+    pub fn decodes_arguments(&self, name: &str) -> bool {
+        self.borsh_functions.contains(name)
+    }
+
+    /// Run the peephole pass over generated instructions, keeping `.rodata`
+    /// relocations, function addresses and source lines pointed at the right
+    /// instructions.
+    pub fn optimize(&mut self, instructions: &[BpfInstruction]) -> Vec<BpfInstruction> {
+        let (optimized, index_map) = optimizer::peephole(instructions);
+        for relocation in &mut self.rodata_relocations {
+            *relocation = index_map[*relocation];
+        }
+        for (relocation, _) in &mut self.map_relocations {
+            *relocation = index_map[*relocation];
+        }
+        for address in self.functions.values_mut() {
+            *address = index_map[*address];
+        }
+        // An instruction was kept when the next one's new index moved past it
+        self.source_lines = self
+            .source_lines
+            .iter()
+            .zip(index_map.windows(2))
+            .filter(|(_, indices)| indices[1] > indices[0])
+            .map(|(&line, _)| line)
+            .collect();
+        optimized
+    }
+
+    fn generate_node(&mut self, node: &Node) -> Result<(), CodeGenError> {
         match node.node_type {
             NodeType::Program => {
-                for child in &node.children {
-                    self.visit_node(child)?;
+                // Top-level statements run first, then fall into the entry function
+                let (functions, statements): (Vec<&Node>, Vec<&Node>) = node
+                    .children
+                    .iter()
+                    .partition(|child| child.node_type == NodeType::FunctionDecl);
+
+                // Constants, struct layouts and return types must be known
+                // before any code that uses them is typed
+                let (constants, statements): (Vec<&Node>, Vec<&Node>) =
+                    statements.into_iter().partition(|child| {
+                        matches!(child.node_type, NodeType::ConstDecl | NodeType::EnumDecl)
+                    });
+                for declaration in constants {
+                    self.visit_node(declaration)?;
                 }
-            }
-            NodeType::FunctionDecl => {
-                self.generate_function(node)?;
-            }
-            NodeType::Block => {
-                for child in &node.children {
-                    self.visit_node(child)?;
+                self.declare_context_struct()?;
+                let (structs, statements): (Vec<&Node>, Vec<&Node>) = statements
+                    .into_iter()
+                    .partition(|child| child.node_type == NodeType::StructDecl);
+                for declaration in structs {
+                    self.declare_struct(declaration)?;
                 }
-            }
-            NodeType::Statement => {
-                if let Some(ref value) = node.value {
-                    match value.as_str() {
-                        "return" => {
-                            if !node.children.is_empty() {
-                                self.visit_node(&node.children[0])?;
-                            }
-                            self.emit_exit(0);
-                        }
-                        _ => {
-                            for child in &node.children {
-                                self.visit_node(child)?;
-                            }
-                        }
-                    }
-                } else {
-                    for child in &node.children {
-                        self.visit_node(child)?;
-                    }
+                let (maps, statements): (Vec<&Node>, Vec<&Node>) = statements
+                    .into_iter()
+                    .partition(|child| child.node_type == NodeType::MapDecl);
+                for declaration in maps {
+                    self.declare_map(declaration)?;
                 }
-            }
-            NodeType::Expression => {
-                if let Some(ref value) = node.value {
-                    match value.as_str() {
-                        "call" => {
-                            self.generate_call(node)?;
+                for function in &functions {
+                    if let Some((ret, name)) =
+                        function.value.as_deref().and_then(|v| v.split_once(':'))
+                    {
+                        let ret = self.resolve_type(ret);
+                        self.return_types.insert(name.to_string(), ret);
+                        if function.variadic {
+                            let named = function.children.len().saturating_sub(1);
+                            self.variadic_functions.insert(name.to_string(), named);
                         }
-                        _ => {
-                            for child in &node.children {
-                                self.visit_node(child)?;
-                            }
+                        if self.is_inlinable(function) {
+                            self.inline_functions
+                                .insert(name.to_string(), (*function).clone());
                         }
                     }
-                } else {
-                    for child in &node.children {
-                        self.visit_node(child)?;
+                }
+
+                // The context pointer arrives in r1, which top-level code may
+                // clobber; keep it on the stack until the entry function runs
+                self.begin_frame();
+                let entry = Self::entry_index(&functions);
+                let context_slot = (entry.is_some() && !statements.is_empty()).then(|| {
+                    let slot = self.allocate_slot();
+                    self.emit_store(FRAME_POINTER, slot, 1);
+                    slot
+                });
+                for statement in statements {
+                    self.visit_node(statement)?;
+                }
+                if let Some(slot) = context_slot {
+                    self.emit_load(1, FRAME_POINTER, slot);
+                }
+
+                if let Some(entry) = entry {
+                    self.generate_function(functions[entry])?;
+                }
+                for (i, function) in functions.iter().enumerate() {
+                    if Some(i) != entry {
+                        self.generate_function(function)?;
                     }
                 }
             }
-            NodeType::Identifier => {
-                // Process identifier - could be variable reference or function name
-                if let Some(ref _value) = node.value {
-                    // For identifiers, we might need to load from memory or reference
-                    // This is context-dependent and handled by parent nodes
+            NodeType::FunctionDecl => {
+                self.generate_function(node)?;
+            }
+            NodeType::StructDecl => {
+                self.declare_struct(node)?;
+            }
+            NodeType::MapDecl => {
+                self.declare_map(node)?;
+            }
+            NodeType::ConstDecl => {
+                self.declare_constant(node)?;
+            }
+            NodeType::EnumDecl => {
+                for variant in &node.children {
+                    self.declare_constant(variant)?;
                 }
             }
-            NodeType::Literal => {
-                // Process literal values - numbers, strings, etc.
-                if let Some(ref value) = node.value {
-                    if let Ok(num) = value.parse::<i32>() {
-                        // Numeric literal - load into current register
-                        self.emit_move_immediate(self.current_reg, num);
+            // Classes are lowered to structs and functions before codegen;
+            // initializer lists only appear inside declarations
+            NodeType::ClassDecl | NodeType::InitializerList => {
+                return Err(CodeGenError::UnsupportedNodeType(node.node_type.clone()));
+            }
+            NodeType::Block => {
+                self.scopes.push(HashMap::new());
+                walk_node(self, node)?;
+                self.scopes.pop();
+            }
+            NodeType::Statement => {
+                let kind = node.value.as_deref().unwrap_or("");
+                match kind {
+                    "return" => {
+                        match node.children.first() {
+                            Some(value) => {
+                                let reg = self.generate_expression(value)?;
+                                if self.return_type == Type::Bool {
+                                    self.emit_extend(reg, &Type::Bool);
+                                }
+                                self.emit_move_register(0, reg);
+                                self.release_register();
+                            }
+                            None => self.emit_move_immediate(0, 0),
+                        }
+                        self.emit_return();
                     }
-                    // String literals would need special handling for BPF
+                    "var" => self.generate_var_declaration(node)?,
+                    "if" => self.generate_if(node)?,
+                    "while" => self.generate_while(node)?,
+                    "for" => self.generate_for(node)?,
+                    "break" | "continue" => self.generate_loop_jump(kind)?,
+                    _ => walk_node(self, node)?,
                 }
             }
+            NodeType::Expression
+            | NodeType::Identifier
+            | NodeType::Literal
+            | NodeType::FloatLiteral
+            | NodeType::Ternary
+            | NodeType::Sizeof
+            | NodeType::Cast => {
+                // Expression statement - evaluate for side effects
+                self.generate_expression(node)?;
+                self.release_register();
+            }
         }
         Ok(())
     }
 
+    /// Pick the function execution starts in: `main`, then `entrypoint`, then the first.
+    pub fn entry_index(functions: &[&Node]) -> Option<usize> {
+        ["main", "entrypoint"]
+            .iter()
+            .find_map(|name| {
+                functions
+                    .iter()
+                    .position(|function| function_name(function) == Some(*name))
+            })
+            .or(if functions.is_empty() { None } else { Some(0) })
+    }
+
     fn generate_function(&mut self, node: &Node) -> Result<(), CodeGenError> {
-        // Generate function prologue and process function body
-        if let Some(ref _name) = node.value {
-            // Function entry point - could emit function label here
-            // For BPF, functions are typically inlined or called via helper functions
+        // Function entry point - record its offset for call resolution
+        if let Some(name) = function_name(node) {
+            self.functions
+                .insert(name.to_string(), self.instructions.len());
+            self.return_type = self.return_types.get(name).cloned().unwrap_or(Type::I64);
+        }
+        self.begin_frame();
+
+        // Parameters arrive in R1-R5; spill them to stack slots
+        let params = &node.children[..node.children.len().saturating_sub(1)];
+        if params.len() > 5 {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "function {} has {} parameters, but BPF passes at most 5",
+                function_name(node).unwrap_or(""),
+                params.len()
+            )));
+        }
+        let mut encoded = Vec::new();
+        for (i, param) in params.iter().enumerate() {
+            if let Some((ty, name)) = param
+                .value
+                .as_deref()
+                .and_then(|value| self.declaration(value))
+            {
+                let is_encoded = node.exported && matches!(ty, Type::Struct(_));
+                let slot = self.declare_variable(name, ty.clone())?;
+                if is_encoded {
+                    encoded.push((i, name, slot, ty));
+                } else {
+                    self.emit_store_variable(&Variable { offset: slot, ty }, (i + 1) as u8);
+                }
+            }
+        }
+
+        // A variadic function's extra arguments stay in the caller's frame;
+        // it receives their count and address after its named parameters
+        if node.variadic {
+            let count_reg = params.len() + 1;
+            if count_reg + 1 > 5 {
+                return Err(CodeGenError::InvalidInstruction(format!(
+                    "variadic function {} can have at most 3 named parameters",
+                    function_name(node).unwrap_or("")
+                )));
+            }
+            let argc = self.declare_variable("argc", Type::I64)?;
+            self.emit_store(FRAME_POINTER, argc, count_reg as u8);
+            let argv = self.declare_variable("argv", Type::Pointer(Box::new(Type::I64)))?;
+            self.emit_store(FRAME_POINTER, argv, (count_reg + 1) as u8);
         }
 
-        // Process function parameters if any
-        // Parameters would be in the first children before the body
+        // An exported function's struct parameters arrive Borsh-encoded, as
+        // a pointer followed by the length of the bytes in the next register
+        for (i, name, slot, ty) in encoded {
+            if i + 2 > 5 {
+                return Err(CodeGenError::InvalidInstruction(format!(
+                    "struct parameter {} needs a length argument after it",
+                    name
+                )));
+            }
+            let (items, size) = self.borsh_layout(&ty)?;
+            let value_reg = self.allocate_register()?;
+            self.emit_move_register(value_reg, FRAME_POINTER);
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_K,
+                value_reg,
+                0,
+                0,
+                slot as i32,
+            );
+            let failures =
+                self.emit_borsh(false, value_reg, (i + 1) as u8, (i + 2) as u8, &items, size);
+            self.release_register();
+            let decoded = self.emit_jump_placeholder();
+            for failure in failures {
+                self.patch_jump(failure, self.instructions.len())?;
+            }
+            self.emit_exit(ERROR_DID_NOT_DESERIALIZE);
+            self.patch_jump(decoded, self.instructions.len())?;
+            if let Some(name) = function_name(node) {
+                self.borsh_functions.insert(name.to_string());
+            }
+        }
 
         // Process the function body (last child is typically the block)
         if let Some(body) = node.children.last() {
             self.visit_node(body)?;
         }
+
+        // Implicit `return 0` for bodies that fall off the end
+        self.emit_exit(0);
         Ok(())
     }
 
-    fn generate_call(&mut self, node: &Node) -> Result<(), CodeGenError> {
-        if let Some(callee) = node.children.first() {
-            if let Some(ref value) = callee.value {
-                match value.as_str() {
-                    "PrintF" => {
-                        // Generate BPF helper call for printing
-                        // Set up arguments in registers first
-                        if node.children.len() > 1 {
-                            // Process arguments and load into appropriate registers
-                            for (i, arg) in node.children.iter().skip(1).enumerate() {
-                                if i < 5 {
-                                    // BPF allows up to 5 arguments in R1-R5
-                                    self.current_reg = (i + 1) as u8;
-                                    self.visit_node(arg)?;
-                                }
-                            }
-                        }
-                        self.emit_call(6); // BPF_FUNC_trace_printk
-                    }
-                    func_name => {
-                        // User-defined function call
-                        // In BPF, this might be inlined or use a call instruction
-                        // For now, emit a generic call with function name hash
-                        let func_hash = self.hash_function_name(func_name);
-                        self.emit_call(func_hash);
-                    }
-                }
+    fn generate_var_declaration(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let Some((ty, name)) = node
+            .children
+            .first()
+            .and_then(|child| child.value.as_deref())
+            .and_then(|value| self.declaration(value))
+        else {
+            return Ok(());
+        };
+        let slot = self.declare_variable(name, ty.clone())?;
+
+        match node.children.get(1) {
+            Some(list) if list.node_type == NodeType::InitializerList => {
+                self.generate_array_initializer(list, &ty, slot)?;
             }
+            Some(initializer) => {
+                self.check_literal_fits(initializer, &ty)?;
+                let reg = self.generate_expression(initializer)?;
+                self.emit_convert(reg, &self.expression_type(initializer), &ty);
+                self.emit_store_variable(&Variable { offset: slot, ty }, reg);
+                self.release_register();
+            }
+            None => {}
         }
         Ok(())
     }
 
-    fn hash_function_name(&self, name: &str) -> i32 {
-        // Simple hash function for function names
-        let mut hash = 0i32;
-        for byte in name.bytes() {
-            hash = hash.wrapping_mul(31).wrapping_add(byte as i32);
+    /// Store each element of `{a, b, ...}` into the array at `slot`.
+    /// Elements beyond those listed are left as they were.
+    fn generate_array_initializer(
+        &mut self,
+        list: &Node,
+        ty: &Type,
+        slot: i16,
+    ) -> Result<(), CodeGenError> {
+        let elements = &list.children;
+        let Type::Array(element, length) = ty else {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "initializer list for non-array {:?}",
+                ty
+            )));
+        };
+        if element.is_aggregate() {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "initializer list for array of {:?}",
+                element
+            )));
+        }
+        if elements.len() > *length {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "{} initializers for an array of {}",
+                elements.len(),
+                length
+            )));
+        }
+        let size = self.size_of(element)?;
+        for (i, value) in elements.iter().enumerate() {
+            self.check_literal_fits(value, element)?;
+            let reg = self.generate_expression(value)?;
+            self.emit_convert(reg, &self.expression_type(value), element);
+            // The sized store truncates a narrow integer; a Bool needs more
+            if **element == Type::Bool {
+                self.emit_extend(reg, element);
+            }
+            self.emit_store_sized(size, FRAME_POINTER, slot + (i * size) as i16, reg);
+            self.release_register();
         }
-        hash.abs() % 1000 + 100 // Keep in a reasonable range for BPF
+        Ok(())
     }
 
-    fn emit_instruction(
-        &mut self,
-        opcode: u8,
-        dst_reg: u8,
-        src_reg: u8,
-        offset: i16,
-        immediate: i32,
-    ) {
-        let instruction = BpfInstruction::new(opcode, dst_reg, src_reg, offset, immediate);
-        self.instructions.push(instruction);
+    /// An `if` and its `else if` arms: a false condition falls through to
+    /// the next arm's test and each arm's body jumps to one shared exit.
+    fn generate_if(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let arms = node.children.chunks_exact(2);
+        let otherwise = arms.remainder().first();
+        let count = arms.len();
+        let mut exits = Vec::new();
+        for (i, arm) in arms.enumerate() {
+            let skip = self.generate_condition_jump(&arm[0])?;
+            self.visit_node(&arm[1])?;
+            if i + 1 < count || otherwise.is_some() {
+                exits.push(self.emit_jump_placeholder());
+            }
+            self.patch_jump(skip, self.instructions.len())?;
+        }
+        if let Some(otherwise) = otherwise {
+            self.visit_node(otherwise)?;
+        }
+        self.patch_exits(&exits, self.instructions.len())
     }
 
-    fn emit_move_immediate(&mut self, dst_reg: u8, immediate: i32) {
-        self.emit_instruction(
-            bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_MOV | bpf_opcodes::BPF_K,
-            dst_reg,
-            0,
-            0,
-            immediate,
-        );
+    /// Point each of `exits`, in program order, at `end`. One too far away
+    /// jumps to the furthest later exit it can reach instead, which carries
+    /// on to the end in turn.
+    fn patch_exits(&mut self, exits: &[usize], end: usize) -> Result<(), CodeGenError> {
+        for (i, &exit) in exits.iter().enumerate().rev() {
+            let target = if Self::in_jump_range(exit, end) {
+                end
+            } else {
+                exits[i + 1..]
+                    .iter()
+                    .rev()
+                    .copied()
+                    .find(|&later| Self::in_jump_range(exit, later))
+                    .unwrap_or(end)
+            };
+            self.patch_jump(exit, target)?;
+        }
+        Ok(())
     }
 
-    fn emit_call(&mut self, func_id: i32) {
+    fn generate_while(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let loop_start = self.instructions.len();
+        let exit_jump = self.generate_condition_jump(&node.children[0])?;
+        let jumps = self.generate_loop_body(&node.children[1])?;
+        for jump in jumps.continues {
+            self.patch_jump(jump, loop_start)?;
+        }
+        self.emit_jump_to(loop_start)?;
+        self.patch_jump(exit_jump, self.instructions.len())?;
+        for jump in jumps.breaks {
+            self.patch_jump(jump, self.instructions.len())?;
+        }
+        Ok(())
+    }
+
+    fn generate_for(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        // The init clause may declare a variable scoped to the loop
+        self.scopes.push(HashMap::new());
+        self.visit_node(&node.children[0])?;
+
+        let loop_start = self.instructions.len();
+        let condition = &node.children[1];
+        let exit_jump = if is_empty(condition) {
+            None
+        } else {
+            Some(self.generate_condition_jump(condition)?)
+        };
+
+        let jumps = self.generate_loop_body(&node.children[3])?;
+        // `continue` runs the step before testing the condition again
+        for jump in jumps.continues {
+            self.patch_jump(jump, self.instructions.len())?;
+        }
+        if !is_empty(&node.children[2]) {
+            self.visit_node(&node.children[2])?;
+        }
+        self.emit_jump_to(loop_start)?;
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump, self.instructions.len())?;
+        }
+        for jump in jumps.breaks {
+            self.patch_jump(jump, self.instructions.len())?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// Generate a loop body, returning the `break` and `continue` jumps in it
+    /// that belong to this loop.
+    fn generate_loop_body(&mut self, body: &Node) -> Result<LoopJumps, CodeGenError> {
+        self.loops.push(LoopJumps::default());
+        let generated = self.visit_node(body);
+        let jumps = self.loops.pop().unwrap_or_default();
+        generated.map(|()| jumps)
+    }
+
+    /// `break` or `continue`: a jump patched by the innermost loop.
+    fn generate_loop_jump(&mut self, kind: &str) -> Result<(), CodeGenError> {
+        if self.loops.is_empty() {
+            return Err(CodeGenError::OutsideLoop(kind.to_string()));
+        }
+        let jump = self.emit_jump_placeholder();
+        if let Some(jumps) = self.loops.last_mut() {
+            if kind == "break" {
+                jumps.breaks.push(jump);
+            } else {
+                jumps.continues.push(jump);
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate `condition` and emit a jump, to be patched, taken when it is zero.
+    fn generate_condition_jump(&mut self, condition: &Node) -> Result<usize, CodeGenError> {
+        let reg = self.generate_expression(condition)?;
+        let reg = self.release_for_branch(reg);
+        let jump = self.instructions.len();
         self.emit_instruction(
-            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_CALL,
+            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JEQ | bpf_opcodes::BPF_K,
+            reg,
             0,
             0,
             0,
-            func_id,
         );
+        Ok(jump)
     }
 
-    fn emit_exit(&mut self, exit_code: i32) {
-        // Move exit code to R0
-        self.emit_move_immediate(0, exit_code);
-        // Exit instruction
-        self.emit_instruction(bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_EXIT, 0, 0, 0, 0);
+    /// Release the innermost temporary `reg` ahead of a conditional jump on
+    /// its value, giving the register that still holds the value: `reg`
+    /// itself, or r0 when the release reloads a spilled temporary into `reg`.
+    fn release_for_branch(&mut self, reg: u8) -> u8 {
+        let reg = if self.innermost_displaces() {
+            self.emit_move_register(0, reg);
+            0
+        } else {
+            reg
+        };
+        self.release_register();
+        reg
     }
 
-    pub fn validate_instructions(&self, instructions: &[BpfInstruction]) -> bool {
-        // Basic validation - check for invalid opcodes
-        for instruction in instructions {
-            let class = instruction.opcode & 0x07;
-            match class {
-                bpf_opcodes::BPF_LD
-                | bpf_opcodes::BPF_LDX
-                | bpf_opcodes::BPF_ST
-                | bpf_opcodes::BPF_STX
-                | bpf_opcodes::BPF_ALU
-                | bpf_opcodes::BPF_JMP
-                | bpf_opcodes::BPF_ALU64 => {
-                    // Valid instruction class
+    /// Size in bytes of a `sizeof` operand. A bare name that isn't a variable
+    /// but is a declared struct, as in `sizeof(Account)`, means the struct.
+    fn sizeof_operand(&self, node: &Node) -> Result<usize, CodeGenError> {
+        let ty = match (node.value.as_deref(), node.children.first()) {
+            (Some(type_name), _) => self.resolve_type(type_name),
+            (None, Some(operand)) => match operand.value.as_deref() {
+                Some(name)
+                    if operand.node_type == NodeType::Identifier
+                        && self.lookup_variable(name).is_none()
+                        && self.structs.contains_key(name) =>
+                {
+                    Type::Struct(name.to_string())
                 }
-                _ => return false,
+                _ => self.expression_type(operand),
+            },
+            (None, None) => {
+                return Err(CodeGenError::InvalidInstruction(
+                    "sizeof needs a type or expression".to_string(),
+                ))
             }
-        }
-        true
+        };
+        self.size_of(&ty)
     }
 
-    #[allow(dead_code)]
-    pub fn validate_bpf_program(
-        &self,
-        instructions: &[BpfInstruction],
-    ) -> Result<(), CodeGenError> {
-        // Validate register usage (R0-R10 are valid)
-        for instr in instructions {
-            if instr.dst_reg > 10 || instr.src_reg > 10 {
-                return Err(CodeGenError::InvalidInstruction(
-                    "Invalid register: registers must be R0-R10".to_string(),
-                ));
-            }
-        }
+    /// Evaluate one branch of `cond ? a : b` into a single register.
+    fn generate_ternary(&mut self, node: &Node) -> Result<u8, CodeGenError> {
+        let [condition, then, otherwise] = node.children.as_slice() else {
+            return Err(CodeGenError::InvalidInstruction(
+                "Conditional expression needs a condition and two branches".to_string(),
+            ));
+        };
+        let ty = self.expression_type(node);
 
-        // Only validate jump targets if we have multiple instructions
-        // (single-instruction validation for testing should skip this)
-        if instructions.len() > 1 {
-            // Validate jump targets
-            for (i, instr) in instructions.iter().enumerate() {
-                // Check if it's a jump instruction (opcodes 0x05, 0x15, 0x1d, 0x25, 0x2d, etc.)
-                if (instr.opcode & 0x07) == 0x05 && instr.opcode != 0x95 {
-                    // Jump class, but not exit
-                    let target = i as i32 + 1 + instr.offset as i32;
-                    if target < 0 || target >= instructions.len() as i32 {
-                        return Err(CodeGenError::InvalidInstruction(format!(
-                            "Invalid jump target: {} out of bounds",
-                            target
-                        )));
-                    }
+        let skip_then = self.generate_condition_jump(condition)?;
+        let reg = self.generate_expression(then)?;
+        self.emit_convert(reg, &self.expression_type(then), &ty);
+        let skip_else = self.emit_jump_placeholder();
+        self.patch_jump(skip_then, self.instructions.len())?;
+
+        // Temporaries are a stack, so the else branch lands in the same register.
+        // Any spill made for the then branch didn't run on this path, so the
+        // register is given up without reloading.
+        self.temps -= 1;
+        let other = self.generate_expression(otherwise)?;
+        debug_assert_eq!(other, reg);
+        self.emit_convert(reg, &self.expression_type(otherwise), &ty);
+        self.patch_jump(skip_else, self.instructions.len())?;
+        Ok(reg)
+    }
+
+    /// Evaluate an expression into a freshly allocated register and return it.
+    /// The caller releases the register when done with the value.
+    fn generate_expression(&mut self, node: &Node) -> Result<u8, CodeGenError> {
+        if self.register_allocation
+            && node.node_type == NodeType::Expression
+            && self.is_allocatable(node)
+        {
+            return self.generate_allocated(node);
+        }
+        match node.node_type {
+            NodeType::Literal => {
+                let reg = self.allocate_register()?;
+                let value = node.value.as_deref().unwrap_or("");
+                if let Some(text) = string_literal(value) {
+                    // String literal - load its .rodata address
+                    let offset = self.intern_string(&text);
+                    self.emit_load_rodata_address(reg, offset);
+                } else {
+                    let num = match value {
+                        "true" => 1,
+                        "false" => 0,
+                        _ => integer_value(value).ok_or_else(|| {
+                            CodeGenError::LiteralOverflow(format!("{} doesn't fit in U64", value))
+                        })?,
+                    };
+                    self.emit_load_immediate(reg, num);
                 }
+                Ok(reg)
             }
-
+            NodeType::Ternary => self.generate_ternary(node),
+            NodeType::Cast => {
+                let operand = &node.children[0];
+                let to = self.resolve_type(node.value.as_deref().unwrap_or(""));
+                if to.is_aggregate() {
+                    return Err(CodeGenError::InvalidInstruction(format!(
+                        "cannot cast to {:?}",
+                        to
+                    )));
+                }
+                let reg = self.generate_expression(operand)?;
+                self.emit_cast(reg, &self.expression_type(operand), &to);
+                Ok(reg)
+            }
+            NodeType::Sizeof => {
+                let size = self.sizeof_operand(node)?;
+                let reg = self.allocate_register()?;
+                self.emit_load_immediate(reg, size as i64);
+                Ok(reg)
+            }
+            NodeType::FloatLiteral => {
+                let value = node.value.as_deref().unwrap_or("");
+                let fixed = value
+                    .parse::<f64>()
+                    .ok()
+                    .map(|value| value * (1u64 << FIXED_POINT_SHIFT) as f64)
+                    .filter(|fixed| fixed.abs() < i64::MAX as f64)
+                    .map(round_half_away)
+                    .ok_or_else(|| {
+                        CodeGenError::UnsupportedFloat(format!(
+                            "{} is outside the 32.32 fixed-point range",
+                            value
+                        ))
+                    })?;
+                let reg = self.allocate_register()?;
+                self.emit_load_immediate(reg, fixed);
+                Ok(reg)
+            }
+            NodeType::Identifier => {
+                let reg = self.allocate_register()?;
+                match node
+                    .value
+                    .as_deref()
+                    .and_then(|name| self.lookup_variable(name))
+                {
+                    // Structs and arrays evaluate to their address
+                    Some(variable) if variable.ty.is_aggregate() => {
+                        let offset = variable.offset as i32;
+                        self.emit_move_register(reg, FRAME_POINTER);
+                        self.emit_instruction(
+                            bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_K,
+                            reg,
+                            0,
+                            0,
+                            offset,
+                        );
+                    }
+                    Some(variable) => {
+                        let ty = variable.ty.clone();
+                        self.emit_load_sized(scalar_size(&ty), reg, FRAME_POINTER, variable.offset);
+                        self.emit_sign_extend(reg, &ty);
+                    }
+                    None => match node.value.as_deref() {
+                        Some(name) if self.constants.contains_key(name) => {
+                            self.emit_load_immediate(reg, self.constants[name].value)
+                        }
+                        Some(name) if self.maps.iter().any(|map| map.name == name) => {
+                            self.emit_load_map(reg, name)
+                        }
+                        Some(name) if self.return_types.contains_key(name) => {
+                            let pointer = self.function_pointer(name);
+                            self.emit_move_immediate(reg, pointer)
+                        }
+                        name => {
+                            return Err(CodeGenError::UndefinedVariable(
+                                name.unwrap_or("").to_string(),
+                            ))
+                        }
+                    },
+                }
+                Ok(reg)
+            }
+            NodeType::Expression => {
+                let operator = node.value.as_deref().unwrap_or("");
+                match (operator, node.children.len()) {
+                    ("call", _) => self.generate_call(node),
+                    ("=", 2) => self.generate_assignment(node),
+                    ("." | "->" | "index", 2) if self.is_unresolved_place(node) => {
+                        let reg = self.allocate_register()?;
+                        self.emit_move_immediate(reg, 0);
+                        Ok(reg)
+                    }
+                    ("." | "->" | "index", 2) => {
+                        let place = self.generate_place(node)?;
+                        self.emit_load_place(&place)?;
+                        Ok(place.reg)
+                    }
+                    ("addr", 1) => {
+                        let place = self.generate_place(&node.children[0])?;
+                        self.emit_instruction(
+                            bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_K,
+                            place.reg,
+                            0,
+                            0,
+                            place.offset as i32,
+                        );
+                        Ok(place.reg)
+                    }
+                    ("neg", 1) => {
+                        let reg = self.generate_expression(&node.children[0])?;
+                        self.emit_instruction(
+                            bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_NEG,
+                            reg,
+                            0,
+                            0,
+                            0,
+                        );
+                        Ok(reg)
+                    }
+                    ("!", 1) => {
+                        let reg = self.generate_expression(&node.children[0])?;
+                        self.emit_instruction(
+                            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JEQ | bpf_opcodes::BPF_K,
+                            reg,
+                            0,
+                            2,
+                            0,
+                        );
+                        self.emit_bool_result(reg);
+                        Ok(reg)
+                    }
+                    (_, 2) => self.generate_binary(operator, &node.children[0], &node.children[1]),
+                    _ => {
+                        // Unknown expression - evaluate children for side effects
+                        for child in &node.children {
+                            self.generate_expression(child)?;
+                            self.release_register();
+                        }
+                        let reg = self.allocate_register()?;
+                        self.emit_move_immediate(reg, 0);
+                        Ok(reg)
+                    }
+                }
+            }
+            _ => {
+                self.visit_node(node)?;
+                let reg = self.allocate_register()?;
+                self.emit_move_immediate(reg, 0);
+                Ok(reg)
+            }
+        }
+    }
+
+    fn generate_assignment(&mut self, node: &Node) -> Result<u8, CodeGenError> {
+        let target = &node.children[0];
+        self.check_literal_fits(&node.children[1], &self.expression_type(target))?;
+        let reg = self.generate_expression(&node.children[1])?;
+        self.emit_convert(
+            reg,
+            &self.expression_type(&node.children[1]),
+            &self.expression_type(target),
+        );
+        if let Some(name) = target
+            .value
+            .as_deref()
+            .filter(|_| target.node_type == NodeType::Identifier)
+            .filter(|name| self.lookup_variable(name).is_none())
+            .filter(|name| self.constants.contains_key(*name))
+        {
+            return Err(CodeGenError::AssignToConstant(name.to_string()));
+        }
+        if self.is_unresolved_place(target) {
+            // Nothing to store to; the value is still the expression's result
+        } else if matches!(target.value.as_deref(), Some("." | "->" | "index")) {
+            let place = self.generate_place(target)?;
+            let size = self.size_of(&place.ty)?;
+            self.emit_extend(reg, &place.ty);
+            self.emit_store_sized(size, place.reg, place.offset, reg);
+            self.release_register();
+        } else if target.node_type == NodeType::Identifier {
+            if let Some(variable) = target
+                .value
+                .as_deref()
+                .and_then(|name| self.lookup_variable(name))
+                .cloned()
+            {
+                self.emit_store_variable(&variable, reg);
+            }
+        }
+        Ok(reg)
+    }
+
+    /// Resolve a type name, accepting a declared struct's bare name as in
+    /// `Account* account`. Types codegen doesn't model yet are treated as `I64`.
+    fn resolve_type(&self, name: &str) -> Type {
+        Type::parse(name)
+            .or_else(|| {
+                let base = name.trim_end_matches('*').trim();
+                self.structs
+                    .contains_key(base)
+                    .then(|| Type::parse(&format!("struct {}", name)))
+                    .flatten()
+            })
+            .unwrap_or(Type::I64)
+    }
+
+    /// Type and name from a `Type:name` declaration value.
+    fn declaration<'v>(&self, value: &'v str) -> Option<(Type, &'v str)> {
+        value
+            .split_once(':')
+            .map(|(ty, name)| (self.resolve_type(ty), name))
+    }
+
+    /// Evaluate a `const` declaration, which may use earlier constants. The
+    /// value wraps to the declared integer type.
+    fn declare_constant(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let Some((ty, name)) = node.value.as_deref().and_then(|v| self.declaration(v)) else {
+            return Ok(());
+        };
+        let values: HashMap<String, i64> = self
+            .constants
+            .iter()
+            .map(|(name, constant)| (name.clone(), constant.value))
+            .collect();
+        let value = node
+            .children
+            .first()
+            .and_then(|value| optimizer::constant_value(value, &values))
+            .ok_or_else(|| CodeGenError::NonConstantInitializer(name.to_string()))?;
+        let value = match ty {
+            Type::Bool => (value != 0) as i64,
+            Type::Int { bits: 64, .. } => value,
+            Type::Int { bits, signed } => {
+                let unused = 64 - bits as u32;
+                if signed {
+                    (value << unused) >> unused
+                } else {
+                    ((value as u64) << unused >> unused) as i64
+                }
+            }
+            _ => return Err(CodeGenError::NonConstantInitializer(name.to_string())),
+        };
+        self.constants
+            .insert(name.to_string(), Constant { ty, value });
+        Ok(())
+    }
+
+    /// Record the layout of a `struct` declaration.
+    fn declare_struct(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let name = node.value.clone().unwrap_or_default();
+        let fields: Vec<(String, Type)> = node
+            .children
+            .iter()
+            .filter_map(|field| {
+                field
+                    .value
+                    .as_deref()
+                    .and_then(|value| self.declaration(value))
+            })
+            .map(|(ty, field)| (field.to_string(), ty))
+            .collect();
+
+        let layout = StructLayout::new(&name, &fields, &self.structs).map_err(|field| {
+            CodeGenError::InvalidInstruction(format!(
+                "Field {} of struct {} has unknown size",
+                field, name
+            ))
+        })?;
+        self.structs.insert(name, layout);
+        Ok(())
+    }
+
+    /// Predeclare the struct a Linux program receives in r1, such as `XdpMd`.
+    fn declare_context_struct(&mut self) -> Result<(), CodeGenError> {
+        if self.target != CompileTarget::LinuxBpf {
+            return Ok(());
+        }
+        let Some((name, fields)) = self.program_type.context() else {
+            return Ok(());
+        };
+        let fields: Vec<(String, Type)> = fields
+            .iter()
+            .filter_map(|&(field, ty)| Some((field.to_string(), Type::parse(ty)?)))
+            .collect();
+        let layout = StructLayout::new(name, &fields, &self.structs).map_err(|field| {
+            CodeGenError::InvalidInstruction(format!(
+                "Field {} of struct {} has unknown size",
+                field, name
+            ))
+        })?;
+        self.structs.insert(name.to_string(), layout);
+        Ok(())
+    }
+
+    /// Record a `map` declaration. Maps only exist in Linux BPF objects.
+    fn declare_map(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let name = node.value.clone().unwrap_or_default();
+        if self.target != CompileTarget::LinuxBpf {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "Map {} needs the linux-bpf target",
+                name
+            )));
+        }
+
+        let part = |index: usize| {
+            node.children
+                .get(index)
+                .and_then(|child| child.value.as_deref())
+                .unwrap_or("")
+        };
+        let map_type = match part(0) {
+            "hash" => MapDefinition::HASH,
+            "array" => MapDefinition::ARRAY,
+            kind => {
+                return Err(CodeGenError::InvalidInstruction(format!(
+                    "Map {} has unknown kind {}, expected hash or array",
+                    name, kind
+                )))
+            }
+        };
+        let key_size = self.size_of(&self.resolve_type(part(1)))?;
+        let value_size = self.size_of(&self.resolve_type(part(2)))?;
+        let max_entries = part(3).parse().unwrap_or(0);
+        if map_type == MapDefinition::ARRAY && key_size != 4 {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "Array map {} needs a 4-byte key such as U32",
+                name
+            )));
+        }
+
+        self.maps.push(MapDefinition {
+            name,
+            map_type,
+            key_size: key_size as u32,
+            value_size: value_size as u32,
+            max_entries,
+        });
+        Ok(())
+    }
+
+    /// Compute the address of an lvalue: a local, `obj.field`, `ptr->field`
+    /// or `base[index]`.
+    /// The caller releases `place.reg`.
+    fn generate_place(&mut self, node: &Node) -> Result<Place, CodeGenError> {
+        let value = node.value.as_deref().unwrap_or("");
+        match (&node.node_type, value, node.children.as_slice()) {
+            (NodeType::Identifier, name, _) => {
+                let variable = self
+                    .lookup_variable(name)
+                    .cloned()
+                    .ok_or_else(|| CodeGenError::InvalidMemberAccess(name.to_string()))?;
+                let reg = self.allocate_register()?;
+                self.emit_move_register(reg, FRAME_POINTER);
+                Ok(Place {
+                    reg,
+                    offset: variable.offset,
+                    ty: variable.ty,
+                })
+            }
+            (NodeType::Expression, ".", [object, field]) => {
+                let mut place = self.generate_place(object)?;
+                let field = self.field_of(&place.ty, field)?;
+                self.advance_place(&mut place, field.offset as i64)?;
+                place.ty = field.ty;
+                Ok(place)
+            }
+            (NodeType::Expression, "->", [object, field]) => {
+                let pointee = match self.expression_type(object) {
+                    Type::Pointer(pointee) => *pointee,
+                    other => {
+                        return Err(CodeGenError::InvalidMemberAccess(format!(
+                            "-> on non-pointer {:?}",
+                            other
+                        )))
+                    }
+                };
+                let field = self.field_of(&pointee, field)?;
+                let mut place = Place {
+                    reg: self.generate_expression(object)?,
+                    offset: 0,
+                    ty: field.ty,
+                };
+                self.advance_place(&mut place, field.offset as i64)?;
+                Ok(place)
+            }
+            (NodeType::Expression, "index", [base, index]) => {
+                let base_type = self.expression_type(base);
+                let element = base_type.element().cloned().ok_or_else(|| {
+                    CodeGenError::InvalidMemberAccess(format!("indexing non-array {:?}", base_type))
+                })?;
+                let size = self.size_of(&element)?;
+
+                // Arrays are indexed in place; pointers by the address they hold
+                let mut place = match base_type {
+                    Type::Array(..) => self.generate_place(base)?,
+                    _ => Place {
+                        reg: self.generate_expression(base)?,
+                        offset: 0,
+                        ty: base_type.clone(),
+                    },
+                };
+
+                let length = match base_type {
+                    Type::Array(_, length) if self.bounds_checks => Some(length),
+                    _ => None,
+                };
+                if let Some(index) = integer_literal(index) {
+                    if length.is_some_and(|length| !(0..length as i64).contains(&index)) {
+                        self.emit_move_immediate(0, INDEX_OUT_OF_BOUNDS);
+                        self.emit_return();
+                    }
+                    let bytes = index.checked_mul(size as i64).ok_or_else(|| {
+                        CodeGenError::InvalidMemberAccess(format!(
+                            "index {} is out of range",
+                            index
+                        ))
+                    })?;
+                    self.advance_place(&mut place, bytes)?;
+                } else {
+                    let index = self.generate_expression(index)?;
+                    if let Some(length) = length {
+                        // Unsigned, so a negative index is out of range too
+                        let in_bounds = self.instructions.len();
+                        self.emit_instruction(
+                            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JLT | bpf_opcodes::BPF_K,
+                            index,
+                            0,
+                            0,
+                            length as i32,
+                        );
+                        self.emit_move_immediate(0, INDEX_OUT_OF_BOUNDS);
+                        self.emit_return();
+                        self.patch_jump(in_bounds, self.instructions.len())?;
+                    }
+                    self.emit_scale(index, size)?;
+                    self.emit_instruction(
+                        bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_X,
+                        place.reg,
+                        index,
+                        0,
+                        0,
+                    );
+                    self.release_register();
+                }
+                place.ty = element;
+                Ok(place)
+            }
+            _ => Err(CodeGenError::InvalidMemberAccess(format!(
+                "{:?} is not addressable",
+                node.value
+            ))),
+        }
+    }
+
+    /// Move `place` on by `bytes`, in its offset while that fits the
+    /// instruction's 16 bits and by adding to its register otherwise.
+    fn advance_place(&mut self, place: &mut Place, bytes: i64) -> Result<(), CodeGenError> {
+        if let Some(offset) = i64::from(place.offset)
+            .checked_add(bytes)
+            .and_then(|offset| i16::try_from(offset).ok())
+        {
+            place.offset = offset;
+            return Ok(());
+        }
+        let immediate = i32::try_from(bytes).map_err(|_| {
+            CodeGenError::InvalidMemberAccess(format!("offset of {} bytes is out of range", bytes))
+        })?;
+        self.emit_instruction(
+            bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_K,
+            place.reg,
+            0,
+            0,
+            immediate,
+        );
+        Ok(())
+    }
+
+    /// Multiply `reg` by an element size, as for indexing and pointer arithmetic.
+    fn emit_scale(&mut self, reg: u8, size: usize) -> Result<(), CodeGenError> {
+        if size != 1 {
+            let scale = self.allocate_register()?;
+            self.emit_move_immediate(scale, size as i32);
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_MUL | bpf_opcodes::BPF_X,
+                reg,
+                scale,
+                0,
+                0,
+            );
+            self.release_register();
+        }
+        Ok(())
+    }
+
+    /// Look up `field` (an Identifier node) in the struct type `ty`.
+    fn field_of(&self, ty: &Type, field: &Node) -> Result<FieldLayout, CodeGenError> {
+        let field_name = field.value.as_deref().unwrap_or("");
+        let Type::Struct(struct_name) = ty else {
+            return Err(CodeGenError::InvalidMemberAccess(format!(
+                "field {} of non-struct {:?}",
+                field_name, ty
+            )));
+        };
+        let layout = self
+            .structs
+            .get(struct_name)
+            .ok_or_else(|| CodeGenError::UnknownStruct(struct_name.clone()))?;
+        layout
+            .field(field_name)
+            .cloned()
+            .ok_or_else(|| CodeGenError::UnknownField(struct_name.clone(), field_name.to_string()))
+    }
+
+    /// Replace a place's address in `place.reg` with the value stored there.
+    /// Structs and arrays have no scalar value, so they evaluate to their address.
+    fn emit_load_place(&mut self, place: &Place) -> Result<(), CodeGenError> {
+        if place.ty.is_aggregate() {
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_K,
+                place.reg,
+                0,
+                0,
+                place.offset as i32,
+            );
+        } else {
+            let size = self.size_of(&place.ty)?;
+            self.emit_load_sized(size, place.reg, place.reg, place.offset);
+            self.emit_sign_extend(place.reg, &place.ty);
+        }
+        Ok(())
+    }
+
+    fn size_of(&self, ty: &Type) -> Result<usize, CodeGenError> {
+        ty.size(&self.structs).ok_or_else(|| match ty {
+            Type::Struct(name) => CodeGenError::UnknownStruct(name.clone()),
+            other => CodeGenError::InvalidInstruction(format!("{:?} has no size", other)),
+        })
+    }
+
+    fn generate_binary(
+        &mut self,
+        operator: &str,
+        left: &Node,
+        right: &Node,
+    ) -> Result<u8, CodeGenError> {
+        let left_type = self.expression_type(left);
+        let operand_type = Type::common(&left_type, &self.expression_type(right));
+        let signed = operand_type.is_signed();
+        let float = operand_type == Type::F64;
+        if float && matches!(operator, "*" | "/" | "%" | "<<" | ">>" | "&" | "|" | "^") {
+            return Err(CodeGenError::UnsupportedFloat(format!(
+                "`{}` on fixed-point values",
+                operator
+            )));
+        }
+
+        if matches!(operator, "&&" | "||") {
+            return self.generate_logical(operator, left, right);
+        }
+
+        let dst = self.generate_expression(left)?;
+
+        // Shifting by a constant uses the immediate form
+        if let ("<<" | ">>", Some(amount)) = (operator, integer_literal(right)) {
+            let op = match operator {
+                "<<" => bpf_opcodes::BPF_LSH,
+                _ if left_type.is_signed() => bpf_opcodes::BPF_ARSH,
+                _ => bpf_opcodes::BPF_RSH,
+            };
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_K,
+                dst,
+                0,
+                0,
+                amount as i32,
+            );
+            return Ok(dst);
+        }
+
+        // So does integer arithmetic with a constant that fits in the immediate
+        let immediate = match float {
+            true => None,
+            false => self.immediate_operand(operator, &left_type, right, signed),
+        };
+        if let Some((op, value)) = immediate {
+            let offset = match op {
+                bpf_opcodes::BPF_DIV | bpf_opcodes::BPF_MOD if signed => {
+                    bpf_opcodes::BPF_SIGNED_DIV_OFFSET
+                }
+                _ => 0,
+            };
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_K,
+                dst,
+                0,
+                offset,
+                value,
+            );
+            return Ok(dst);
+        }
+
+        let src = self.generate_expression(right)?;
+        let right_type = self.expression_type(right).decayed();
+        if float {
+            self.emit_convert(dst, &left_type, &Type::F64);
+            self.emit_convert(src, &right_type, &Type::F64);
+        }
+
+        // Pointer arithmetic counts in elements of the pointee
+        let pointee_size = |ty: &Type| match ty {
+            Type::Pointer(pointee) => pointee.size(&self.structs),
+            _ => None,
+        };
+        let (left_size, right_size) = (
+            pointee_size(&left_type.decayed()),
+            pointee_size(&right_type),
+        );
+        let pointer_difference = match (operator, left_size, right_size) {
+            ("+" | "-", Some(size), None) => {
+                self.emit_scale(src, size)?;
+                None
+            }
+            ("+", None, Some(size)) => {
+                self.emit_scale(dst, size)?;
+                None
+            }
+            ("-", Some(size), Some(_)) => Some(size),
+            _ => None,
+        };
+
+        let alu = |op: u8| bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_X;
+        let jump = |op: u8| bpf_opcodes::BPF_JMP | op | bpf_opcodes::BPF_X;
+
+        match operator {
+            "+" => self.emit_instruction(alu(bpf_opcodes::BPF_ADD), dst, src, 0, 0),
+            "-" => self.emit_instruction(alu(bpf_opcodes::BPF_SUB), dst, src, 0, 0),
+            "*" => self.emit_instruction(alu(bpf_opcodes::BPF_MUL), dst, src, 0, 0),
+            // Mixing signed and unsigned operands divides unsigned, as in C
+            "/" | "%" if signed && self.target == CompileTarget::SolanaBpf => {
+                self.emit_signed_division(operator, dst, src)?
+            }
+            "/" | "%" => {
+                let op = if operator == "/" {
+                    bpf_opcodes::BPF_DIV
+                } else {
+                    bpf_opcodes::BPF_MOD
+                };
+                let offset = if signed {
+                    bpf_opcodes::BPF_SIGNED_DIV_OFFSET
+                } else {
+                    0
+                };
+                self.emit_instruction(alu(op), dst, src, offset, 0);
+            }
+            "&" => self.emit_instruction(alu(bpf_opcodes::BPF_AND), dst, src, 0, 0),
+            "|" => self.emit_instruction(alu(bpf_opcodes::BPF_OR), dst, src, 0, 0),
+            "^" => self.emit_instruction(alu(bpf_opcodes::BPF_XOR), dst, src, 0, 0),
+            "<<" => self.emit_instruction(alu(bpf_opcodes::BPF_LSH), dst, src, 0, 0),
+            ">>" => {
+                let op = if left_type.is_signed() {
+                    bpf_opcodes::BPF_ARSH
+                } else {
+                    bpf_opcodes::BPF_RSH
+                };
+                self.emit_instruction(alu(op), dst, src, 0, 0);
+            }
+            "==" | "!=" | "<" | "<=" | ">" | ">=" => {
+                let op = match (operator, signed) {
+                    ("==", _) => bpf_opcodes::BPF_JEQ,
+                    ("!=", _) => bpf_opcodes::BPF_JNE,
+                    ("<", true) => bpf_opcodes::BPF_JSLT,
+                    ("<", false) => bpf_opcodes::BPF_JLT,
+                    ("<=", true) => bpf_opcodes::BPF_JSLE,
+                    ("<=", false) => bpf_opcodes::BPF_JLE,
+                    (">", true) => bpf_opcodes::BPF_JSGT,
+                    (">", false) => bpf_opcodes::BPF_JGT,
+                    (_, true) => bpf_opcodes::BPF_JSGE,
+                    (_, false) => bpf_opcodes::BPF_JGE,
+                };
+                self.emit_instruction(jump(op), dst, src, 2, 0);
+                self.emit_bool_result(dst);
+            }
+            _ => {
+                return Err(CodeGenError::InvalidInstruction(format!(
+                    "Unsupported operator: {}",
+                    operator
+                )))
+            }
+        }
+
+        self.release_register();
+
+        if let Some(size) = pointer_difference.filter(|&size| size > 1) {
+            let scale = self.allocate_register()?;
+            self.emit_move_immediate(scale, size as i32);
+            self.emit_instruction(alu(bpf_opcodes::BPF_DIV), dst, scale, 0, 0);
+            self.release_register();
+        }
+        Ok(dst)
+    }
+
+    /// Whether `node` is arithmetic that [`regalloc`] can evaluate: integer
+    /// `+ - * / % << >>` and negation of 64-bit integer variables, integer
+    /// constants and literals, with no calls to clobber r1-r5.
+    fn is_allocatable(&self, node: &Node) -> bool {
+        let is_int = |ty: &Type| matches!(ty, Type::Int { .. });
+        match node.node_type {
+            NodeType::Literal => integer_literal(node).is_some(),
+            NodeType::Identifier => match node.value.as_deref() {
+                Some(name) => match self.lookup_variable(name) {
+                    Some(variable) => matches!(variable.ty, Type::Int { bits: 64, .. }),
+                    None => self
+                        .constants
+                        .get(name)
+                        .is_some_and(|constant| is_int(&constant.ty)),
+                },
+                None => false,
+            },
+            NodeType::Expression => match (node.value.as_deref(), node.children.as_slice()) {
+                (Some("neg"), [operand]) => self.is_allocatable(operand),
+                (
+                    Some(operator @ ("+" | "-" | "*" | "/" | "%" | "<<" | ">>" | "&" | "|" | "^")),
+                    [left, right],
+                ) => {
+                    // Solana's signed division is a sequence with branches
+                    let signed =
+                        Type::common(&self.expression_type(left), &self.expression_type(right))
+                            .is_signed();
+                    !(matches!(operator, "/" | "%")
+                        && signed
+                        && self.target == CompileTarget::SolanaBpf)
+                        && self.is_allocatable(left)
+                        && self.is_allocatable(right)
+                }
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Evaluate an [`Self::is_allocatable`] expression into a new temporary,
+    /// giving its intermediate values the registers no temporary holds and
+    /// spilling past those.
+    fn generate_allocated(&mut self, node: &Node) -> Result<u8, CodeGenError> {
+        let reg = self.allocate_register()?;
+        let mut block = regalloc::Block::new();
+        let root = self.lower_to_block(node, &mut block);
+
+        // The result's own register first, so the value computed into it can stay
+        let mut registers = vec![reg];
+        registers.extend((self.temps..TEMP_REGS).map(temp_register));
+        registers.extend(1..=5);
+        let allocation = regalloc::allocate(&block, &registers, &[root]);
+
+        // Slots past those of spilled temporaries, which aren't in use meanwhile
+        let base = self.temps.saturating_sub(TEMP_REGS);
+        let slots: Vec<i16> = (0..allocation.spill_slots)
+            .map(|index| self.spill_slot(base + index))
+            .collect();
+        for instruction in regalloc::lower(&block, &allocation, |index| slots[index]) {
+            self.emit_instruction(
+                instruction.opcode,
+                instruction.dst_reg,
+                instruction.src_reg,
+                instruction.offset,
+                instruction.immediate,
+            );
+        }
+        match allocation.result(root) {
+            regalloc::Location::Register(value) => self.emit_move_register(reg, value),
+            regalloc::Location::Spill(index) => self.emit_load(reg, FRAME_POINTER, slots[index]),
+        }
+        Ok(reg)
+    }
+
+    /// Add the operations computing `node` to `block`, as
+    /// [`Self::generate_binary`] would emit them, returning the result's value.
+    fn lower_to_block(&self, node: &Node, block: &mut regalloc::Block) -> regalloc::VReg {
+        use regalloc::Inst;
+
+        let dst;
+        match node.node_type {
+            NodeType::Literal => {
+                dst = block.vreg();
+                let value = integer_literal(node).unwrap_or(0);
+                block.push(Inst::Const { dst, value });
+            }
+            NodeType::Identifier => {
+                dst = block.vreg();
+                let name = node.value.as_deref().unwrap_or("");
+                match self.lookup_variable(name) {
+                    Some(variable) => block.push(Inst::Load {
+                        dst,
+                        offset: variable.offset,
+                    }),
+                    None => block.push(Inst::Const {
+                        dst,
+                        value: self
+                            .constants
+                            .get(name)
+                            .map_or(0, |constant| constant.value),
+                    }),
+                }
+            }
+            _ if node.value.as_deref() == Some("neg") => {
+                dst = self.lower_to_block(&node.children[0], block);
+                block.push(Inst::Neg { dst });
+            }
+            _ => {
+                let operator = node.value.as_deref().unwrap_or("");
+                let (left, right) = (&node.children[0], &node.children[1]);
+                let left_type = self.expression_type(left);
+                let signed = Type::common(&left_type, &self.expression_type(right)).is_signed();
+                let division_offset = if signed {
+                    bpf_opcodes::BPF_SIGNED_DIV_OFFSET
+                } else {
+                    0
+                };
+                let shift_right = if left_type.is_signed() {
+                    bpf_opcodes::BPF_ARSH
+                } else {
+                    bpf_opcodes::BPF_RSH
+                };
+                let op = match operator {
+                    "+" => bpf_opcodes::BPF_ADD,
+                    "-" => bpf_opcodes::BPF_SUB,
+                    "*" => bpf_opcodes::BPF_MUL,
+                    "/" => bpf_opcodes::BPF_DIV,
+                    "%" => bpf_opcodes::BPF_MOD,
+                    "&" => bpf_opcodes::BPF_AND,
+                    "|" => bpf_opcodes::BPF_OR,
+                    "^" => bpf_opcodes::BPF_XOR,
+                    "<<" => bpf_opcodes::BPF_LSH,
+                    _ => shift_right,
+                };
+                let offset = match operator {
+                    "/" | "%" => division_offset,
+                    _ => 0,
+                };
+
+                dst = self.lower_to_block(left, block);
+                let immediate = match (operator, integer_literal(right)) {
+                    ("<<" | ">>", Some(amount)) => Some(amount as i32),
+                    _ => self
+                        .immediate_operand(operator, &left_type, right, signed)
+                        .map(|(_, value)| value),
+                };
+                match immediate {
+                    Some(value) => block.push(Inst::AluImm {
+                        op,
+                        dst,
+                        value,
+                        offset,
+                    }),
+                    None => {
+                        let src = self.lower_to_block(right, block);
+                        block.push(Inst::Alu {
+                            op,
+                            dst,
+                            src,
+                            offset,
+                        });
+                    }
+                }
+            }
+        }
+        dst
+    }
+
+    /// ALU operation and immediate for `left operator right` when `right` is
+    /// an integer literal that fits in 32 bits, scaled to elements for
+    /// pointer arithmetic. Solana's signed division has no instruction of its
+    /// own, so it needs a register.
+    fn immediate_operand(
+        &self,
+        operator: &str,
+        left_type: &Type,
+        right: &Node,
+        signed: bool,
+    ) -> Option<(u8, i32)> {
+        let op = match operator {
+            "+" => bpf_opcodes::BPF_ADD,
+            "-" => bpf_opcodes::BPF_SUB,
+            "*" => bpf_opcodes::BPF_MUL,
+            "/" | "%" if signed && self.target == CompileTarget::SolanaBpf => return None,
+            "/" => bpf_opcodes::BPF_DIV,
+            "%" => bpf_opcodes::BPF_MOD,
+            "&" => bpf_opcodes::BPF_AND,
+            "|" => bpf_opcodes::BPF_OR,
+            "^" => bpf_opcodes::BPF_XOR,
+            _ => return None,
+        };
+        let scale = match left_type.decayed() {
+            Type::Pointer(pointee) if matches!(operator, "+" | "-") => {
+                pointee.size(&self.structs)?
+            }
+            _ => 1,
+        };
+        let value = integer_literal(right)?.checked_mul(scale as i64)?;
+        Some((op, i32::try_from(value).ok()?))
+    }
+
+    /// Signed `/` or `%` for Solana, whose BPF has no signed division:
+    /// divide the magnitudes unsigned, then give a quotient the sign of the
+    /// operands' product and a remainder the sign of the dividend, which
+    /// truncates toward zero like BPF v4's `sdiv` and `smod`. Clobbers `src`.
+    fn emit_signed_division(
+        &mut self,
+        operator: &str,
+        dst: u8,
+        src: u8,
+    ) -> Result<(), CodeGenError> {
+        let alu = |op: u8| bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_X;
+        let dst_sign = self.allocate_register()?;
+        let src_sign = self.allocate_register()?;
+        for (sign, reg) in [(dst_sign, dst), (src_sign, src)] {
+            // All ones when negative, zero otherwise
+            self.emit_move_register(sign, reg);
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ARSH | bpf_opcodes::BPF_K,
+                sign,
+                0,
+                0,
+                63,
+            );
+            self.emit_apply_sign(reg, sign);
+        }
+        if operator == "/" {
+            self.emit_instruction(alu(bpf_opcodes::BPF_DIV), dst, src, 0, 0);
+            self.emit_instruction(alu(bpf_opcodes::BPF_XOR), dst_sign, src_sign, 0, 0);
+        } else {
+            self.emit_instruction(alu(bpf_opcodes::BPF_MOD), dst, src, 0, 0);
+        }
+        self.emit_apply_sign(dst, dst_sign);
+        self.release_register();
+        self.release_register();
+        Ok(())
+    }
+
+    /// Negate `reg` when the sign mask in `sign` is all ones: `(reg ^ sign) - sign`.
+    fn emit_apply_sign(&mut self, reg: u8, sign: u8) {
+        let alu = |op: u8| bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_X;
+        self.emit_instruction(alu(bpf_opcodes::BPF_XOR), reg, sign, 0, 0);
+        self.emit_instruction(alu(bpf_opcodes::BPF_SUB), reg, sign, 0, 0);
+    }
+
+    /// `&&` and `||` with C semantics: the right operand only runs when the
+    /// left one doesn't already decide the result, which ends up 0 or 1.
+    fn generate_logical(
+        &mut self,
+        operator: &str,
+        left: &Node,
+        right: &Node,
+    ) -> Result<u8, CodeGenError> {
+        // `&&` is decided by a zero operand, `||` by a non-zero one
+        let (decided, decided_value, otherwise) = match operator {
+            "&&" => (bpf_opcodes::BPF_JEQ, 0, 1),
+            _ => (bpf_opcodes::BPF_JNE, 1, 0),
+        };
+        let jump = bpf_opcodes::BPF_JMP | decided | bpf_opcodes::BPF_K;
+
+        let dst = self.generate_expression(left)?;
+        let skip_left = self.instructions.len();
+        self.emit_instruction(jump, dst, 0, 0, 0);
+        let src = self.generate_expression(right)?;
+        let src = self.release_for_branch(src);
+        let skip_right = self.instructions.len();
+        self.emit_instruction(jump, src, 0, 0, 0);
+
+        self.emit_move_immediate(dst, otherwise);
+        self.emit_instruction(bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JA, 0, 0, 1, 0);
+        let decided_at = self.instructions.len();
+        self.patch_jump(skip_left, decided_at)?;
+        self.patch_jump(skip_right, decided_at)?;
+        self.emit_move_immediate(dst, decided_value);
+        Ok(dst)
+    }
+
+    /// Static type of an expression, used to pick signed or unsigned instructions.
+    fn expression_type(&self, node: &Node) -> Type {
+        let value = node.value.as_deref().unwrap_or("");
+        match node.node_type {
+            NodeType::Literal if string_literal(value).is_some() => {
+                Type::Pointer(Box::new(Type::Int {
+                    bits: 8,
+                    signed: false,
+                }))
+            }
+            NodeType::Literal if value == "true" || value == "false" => Type::Bool,
+            NodeType::FloatLiteral => Type::F64,
+            NodeType::Sizeof => Type::Int {
+                bits: 64,
+                signed: false,
+            },
+            NodeType::Cast => self.resolve_type(value),
+            NodeType::Ternary => match node.children.as_slice() {
+                [_, then, otherwise] => {
+                    let then = self.expression_type(then);
+                    let otherwise = self.expression_type(otherwise);
+                    if then == otherwise {
+                        then
+                    } else {
+                        Type::common(&then, &otherwise)
+                    }
+                }
+                _ => Type::I64,
+            },
+            NodeType::Identifier => match self.lookup_variable(value) {
+                Some(variable) => variable.ty.clone(),
+                None => self
+                    .constants
+                    .get(value)
+                    .map_or(Type::I64, |constant| constant.ty.clone()),
+            },
+            NodeType::Expression => match value {
+                "==" | "!=" | "<" | "<=" | ">" | ">=" | "&&" | "||" | "!" => Type::Bool,
+                "call" => match node.children.first() {
+                    Some(callee) if !self.is_indirect_call(callee) => callee
+                        .value
+                        .as_deref()
+                        .and_then(|name| self.return_types.get(name))
+                        .cloned()
+                        .unwrap_or(Type::I64),
+                    Some(callee) => match self.expression_type(callee).element() {
+                        Some(Type::Function { ret, .. }) => ret.as_ref().clone(),
+                        _ => Type::I64,
+                    },
+                    None => Type::I64,
+                },
+                "." | "->" => self.member_type(node).unwrap_or(Type::I64),
+                "index" => node
+                    .children
+                    .first()
+                    .and_then(|base| self.expression_type(base).element().cloned())
+                    .unwrap_or(Type::I64),
+                "=" | "neg" | "<<" | ">>" => node
+                    .children
+                    .first()
+                    .map_or(Type::I64, |operand| self.expression_type(operand)),
+                "addr" => node.children.first().map_or(Type::I64, |place| {
+                    Type::Pointer(Box::new(self.expression_type(place)))
+                }),
+                _ => match node.children.as_slice() {
+                    [left, right] => {
+                        let (left, right) =
+                            (self.expression_type(left), self.expression_type(right));
+                        // The distance between two pointers is an element count
+                        if value == "-" && left.element().is_some() && right.element().is_some() {
+                            Type::I64
+                        } else {
+                            Type::common(&left, &right)
+                        }
+                    }
+                    _ => Type::I64,
+                },
+            },
+            _ => Type::I64,
+        }
+    }
+
+    /// Type of the field named by a `.` or `->` expression.
+    fn member_type(&self, node: &Node) -> Option<Type> {
+        let struct_type = self.member_struct(node)?;
+        self.field_of(&struct_type, &node.children[1])
+            .ok()
+            .map(|field| field.ty)
+    }
+
+    /// The declared struct type a `.` or `->` expression accesses, if known.
+    fn member_struct(&self, node: &Node) -> Option<Type> {
+        let [object, _] = node.children.as_slice() else {
+            return None;
+        };
+        let struct_type = match (node.value.as_deref(), self.expression_type(object)) {
+            (Some("."), ty) => ty,
+            (Some("->"), Type::Pointer(pointee)) => *pointee,
+            _ => return None,
+        };
+        match &struct_type {
+            Type::Struct(name) if self.structs.contains_key(name) => Some(struct_type),
+            _ => None,
+        }
+    }
+
+    /// Whether `node` is a member access or index codegen can't lower because
+    /// the struct or element type it names is unknown. Like unknown
+    /// identifiers, these evaluate to 0 for now.
+    fn is_unresolved_place(&self, node: &Node) -> bool {
+        match (node.value.as_deref(), node.children.as_slice()) {
+            (Some("." | "->"), _) => self.member_struct(node).is_none(),
+            (Some("index"), [base, _]) => self
+                .expression_type(base)
+                .element()
+                .and_then(|element| element.size(&self.structs))
+                .is_none(),
+            _ => false,
+        }
+    }
+
+    /// After a conditional jump of +2 taken when true, leave 0 or 1 in `reg`.
+    fn emit_bool_result(&mut self, reg: u8) {
+        self.emit_move_immediate(reg, 0);
+        self.emit_instruction(bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JA, 0, 0, 1, 0);
+        self.emit_move_immediate(reg, 1);
+    }
+
+    fn generate_call(&mut self, node: &Node) -> Result<u8, CodeGenError> {
+        let callee = node
+            .children
+            .first()
+            .and_then(|callee| callee.value.as_deref())
+            .unwrap_or("");
+        let args = node.children.get(1..).unwrap_or(&[]);
+        if matches!(callee, "borsh_deserialize" | "borsh_serialize") {
+            return self.generate_borsh(callee, args);
+        }
+        if callee == "Require" {
+            return self.generate_require(args);
+        }
+        let arity = memory_builtin_arity(callee).or(is_fixed_point_builtin(callee).then_some(3));
+        if let Some(arity) = arity {
+            if args.len() != arity {
+                return Err(CodeGenError::InvalidInstruction(format!(
+                    "{} takes {} arguments",
+                    callee, arity
+                )));
+            }
+        }
+        // MemSet and MemCpy are unrolled when their length is a small constant
+        let length = match (callee, args) {
+            ("MemSet" | "MemCpy", [_, _, length]) => {
+                let values: HashMap<String, i64> = self
+                    .constants
+                    .iter()
+                    .map(|(name, constant)| (name.clone(), constant.value))
+                    .collect();
+                optimizer::constant_value(length, &values)
+            }
+            _ => None,
+        };
+
+        let inline = match node.children.first() {
+            Some(pointer) if self.is_indirect_call(pointer) => None,
+            _ => self
+                .inline_functions
+                .get(callee)
+                .filter(|_| !self.inlining.iter().any(|call| call.name == callee))
+                .cloned(),
+        };
+        if let Some(function) = inline {
+            return self.generate_inline_call(&function, args);
+        }
+
+        let variadic = self.variadic_functions.get(callee).copied();
+        let (named, extra) = args.split_at(variadic.map_or(args.len(), |n| n.min(args.len())));
+
+        // An indirect call stages its function pointer ahead of the arguments
+        let target = match node.children.first() {
+            Some(pointer) if self.is_indirect_call(pointer) => {
+                let reg = self.generate_expression(pointer)?;
+                let slot = self.allocate_slot();
+                self.emit_store(FRAME_POINTER, slot, reg);
+                self.release_register();
+                Some(slot)
+            }
+            _ => None,
+        };
+
+        // Stage arguments in stack slots so evaluating one can't clobber another
+        let mut staged = Vec::new();
+        for arg in named {
+            let reg = self.generate_expression(arg)?;
+            let slot = self.allocate_slot();
+            self.emit_store(FRAME_POINTER, slot, reg);
+            self.release_register();
+            staged.push(Argument::Slot(slot));
+
+            // Log messages are passed as (pointer, length). trace_printk
+            // counts the NUL; sol_log_ takes the bytes of the message only.
+            if matches!(callee, "PrintF" | "sol_log") && staged.len() == 1 {
+                if let Some(text) = arg.value.as_deref().and_then(string_literal) {
+                    let nul = (self.target != CompileTarget::SolanaBpf) as i32;
+                    staged.push(Argument::Immediate(text.len() as i32 + nul));
+                }
+            }
+        }
+
+        // Extra arguments to a variadic function go in consecutive 8-byte
+        // slots, first argument lowest, passed as a count and an address
+        if variadic.is_some() {
+            let base = self.allocate_stack(8 * extra.len());
+            for (i, arg) in extra.iter().enumerate() {
+                let reg = self.generate_expression(arg)?;
+                self.emit_store(FRAME_POINTER, base + 8 * i as i16, reg);
+                self.release_register();
+            }
+            staged.push(Argument::Immediate(extra.len() as i32));
+            staged.push(Argument::Address(base));
+        }
+        // BPF allows up to 5 arguments in R1-R5
+        if staged.len() > 5 {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "call to {} passes {} arguments, but BPF passes at most 5",
+                callee,
+                staged.len()
+            )));
+        }
+
+        // Unsigned CPI passes an empty signer seed list in r4/r5
+        if callee == "sol_invoke" && self.syscall(callee).is_some() {
+            staged.resize(5, Argument::Immediate(0));
+        }
+        // sol_memcmp_ stores its result through a pointer in r4
+        let memcmp_result = (callee == "MemCmp" && self.syscall(callee).is_some()).then(|| {
+            let slot = self.allocate_stack(4);
+            staged.push(Argument::Address(slot));
+            slot
+        });
+
+        for (i, argument) in staged.iter().take(5).enumerate() {
+            let arg_reg = (i + 1) as u8;
+            match *argument {
+                Argument::Slot(slot) => self.emit_load(arg_reg, FRAME_POINTER, slot),
+                Argument::Immediate(value) => self.emit_move_immediate(arg_reg, value),
+                Argument::Address(offset) => {
+                    self.emit_move_register(arg_reg, FRAME_POINTER);
+                    self.emit_instruction(
+                        bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_K,
+                        arg_reg,
+                        0,
+                        0,
+                        offset as i32,
+                    );
+                }
+            }
+        }
+
+        if let Some(slot) = target {
+            self.emit_load(0, FRAME_POINTER, slot);
+            self.emit_local_call(DISPATCH);
+        } else if let Some(id) = self
+            .log_helper
+            .filter(|_| matches!(callee, "PrintF" | "sol_log"))
+        {
+            self.emit_call(id);
+        } else if let Some(id) = self.syscall(callee) {
+            self.emit_call(id);
+            // The syscalls return 0; the builtins give `dst` as in C
+            if let (true, Some(&Argument::Slot(dst))) =
+                (matches!(callee, "MemSet" | "MemCpy"), staged.first())
+            {
+                self.emit_load(0, FRAME_POINTER, dst);
+            }
+            if let Some(slot) = memcmp_result {
+                self.emit_load_sized(4, 0, FRAME_POINTER, slot);
+                self.emit_extend(
+                    0,
+                    &Type::Int {
+                        bits: 32,
+                        signed: true,
+                    },
+                );
+            }
+        } else if memory_builtin_arity(callee).is_some() {
+            self.emit_memory_builtin(callee, length)?;
+        } else if is_fixed_point_builtin(callee) {
+            self.emit_fixed_point(callee);
+        } else if matches!(callee, "PrintF" | "sol_log") {
+            self.emit_call(6); // BPF_FUNC_trace_printk
+        } else if let Some(id) = map_helper(callee) {
+            self.emit_call(id);
+        } else {
+            self.emit_local_call(callee);
+        }
+
+        let reg = self.allocate_register()?;
+        self.emit_move_register(reg, 0);
+        Ok(reg)
+    }
+
+    /// Whether calls to `function` may be replaced by its body: it's small
+    /// enough, isn't exported or variadic, and takes at most five scalars.
+    fn is_inlinable(&self, function: &Node) -> bool {
+        let params = &function.children[..function.children.len().saturating_sub(1)];
+        self.inline_threshold > 0
+            && !function.exported
+            && !function.variadic
+            && params.len() <= 5
+            && params.iter().all(|param| {
+                param
+                    .value
+                    .as_deref()
+                    .and_then(|value| self.declaration(value))
+                    .is_some_and(|(ty, _)| !ty.is_aggregate())
+            })
+            && visit::count_nodes(function) <= self.inline_threshold
+    }
+
+    /// Generate `function`'s body in place of a call to it. The arguments
+    /// are stored to fresh slots in the caller's frame, which the body sees
+    /// as its parameters, and its `return`s jump to the end with the value
+    /// in r0. A function isn't inlined into itself, so recursion still calls.
+    fn generate_inline_call(&mut self, function: &Node, args: &[Node]) -> Result<u8, CodeGenError> {
+        let name = function_name(function).unwrap_or("").to_string();
+        let params = &function.children[..function.children.len().saturating_sub(1)];
+        if args.len() != params.len() {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "{} takes {} arguments",
+                name,
+                params.len()
+            )));
+        }
+
+        let mut scope = HashMap::new();
+        for (param, arg) in params.iter().zip(args) {
+            let Some((ty, param_name)) = param.value.as_deref().and_then(|v| self.declaration(v))
+            else {
+                continue;
+            };
+            let reg = self.generate_expression(arg)?;
+            let offset = self.allocate_slot();
+            self.emit_store(FRAME_POINTER, offset, reg);
+            self.release_register();
+            scope.insert(param_name.to_string(), Variable { offset, ty });
+        }
+
+        // The body can't see the caller's variables or loops
+        let scopes = core::mem::replace(&mut self.scopes, vec![scope]);
+        let loops = core::mem::take(&mut self.loops);
+        let return_type = self.return_types.get(&name).cloned().unwrap_or(Type::I64);
+        let return_type = core::mem::replace(&mut self.return_type, return_type);
+        self.inlining.push(InlineCall {
+            name,
+            temps: self.temps,
+            returns: Vec::new(),
+        });
+        let generated = match function.children.last() {
+            Some(body) => self.visit_node(body),
+            None => Ok(()),
+        };
+        let call = self.inlining.pop();
+        self.scopes = scopes;
+        self.loops = loops;
+        self.return_type = return_type;
+        generated?;
+
+        // Falling off the end returns 0, like the called function would
+        self.emit_move_immediate(0, 0);
+        let end = self.instructions.len();
+        for jump in call.map(|call| call.returns).unwrap_or_default() {
+            self.patch_jump(jump, end)?;
+        }
+
+        let reg = self.allocate_register()?;
+        self.emit_move_register(reg, 0);
+        Ok(reg)
+    }
+
+    /// Lower `MemCmp(a, b, n)`, `StrCmp(a, b)`, `StrCpy(dst, src)`,
+    /// `MemSet(dst, value, n)` or `MemCpy(dst, src, n)` to a byte loop, with
+    /// the arguments in r1-r3 and the result left in r0. The comparisons give
+    /// the difference of the first differing bytes, or 0; the others give
+    /// `dst`. A `length` of at most [`MAX_UNROLLED_BYTES`] is unrolled
+    /// instead. Like a call, the loop is free to clobber r0-r5.
+    fn emit_memory_builtin(
+        &mut self,
+        callee: &str,
+        length: Option<i64>,
+    ) -> Result<(), CodeGenError> {
+        let load_byte = bpf_opcodes::BPF_LDX | bpf_opcodes::BPF_MEM | bpf_opcodes::BPF_B;
+        let add = bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_ADD | bpf_opcodes::BPF_K;
+        let sub = bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_SUB | bpf_opcodes::BPF_X;
+        let jeq = bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JEQ | bpf_opcodes::BPF_K;
+        let jne = bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JNE | bpf_opcodes::BPF_K;
+
+        match callee {
+            "MemSet" | "MemCpy" => {
+                let is_memcpy = callee == "MemCpy";
+                self.emit_move_register(0, 1);
+                // Byte at a time, as the pointers' alignment isn't known
+                if let Some(length) = length.filter(|n| (0..=MAX_UNROLLED_BYTES).contains(n)) {
+                    for offset in 0..length as i16 {
+                        let value = if is_memcpy {
+                            self.emit_instruction(load_byte, 4, 2, offset, 0);
+                            4
+                        } else {
+                            2
+                        };
+                        self.emit_store_sized(1, 1, offset, value);
+                    }
+                    return Ok(());
+                }
+                let top = self.instructions.len();
+                self.emit_instruction(jeq, 3, 0, 0, 0);
+                let value = if is_memcpy {
+                    self.emit_instruction(load_byte, 4, 2, 0, 0);
+                    self.emit_instruction(add, 2, 0, 0, 1);
+                    4
+                } else {
+                    2
+                };
+                self.emit_store_sized(1, 1, 0, value);
+                self.emit_instruction(add, 1, 0, 0, 1);
+                self.emit_instruction(add, 3, 0, 0, -1);
+                self.emit_jump_to(top)?;
+                self.patch_jump(top, self.instructions.len())?;
+            }
+            "StrCpy" => {
+                self.emit_move_register(0, 1);
+                let top = self.instructions.len();
+                self.emit_instruction(load_byte, 4, 2, 0, 0);
+                self.emit_store_sized(1, 1, 0, 4);
+                self.emit_instruction(add, 1, 0, 0, 1);
+                self.emit_instruction(add, 2, 0, 0, 1);
+                let again = self.instructions.len();
+                self.emit_instruction(jne, 4, 0, 0, 0);
+                self.patch_jump(again, top)?;
+            }
+            _ => {
+                let is_memcmp = callee == "MemCmp";
+                self.emit_move_immediate(0, 0);
+                let top = self.instructions.len();
+                let mut exits = Vec::new();
+                if is_memcmp {
+                    exits.push(self.instructions.len());
+                    self.emit_instruction(jeq, 3, 0, 0, 0);
+                }
+                self.emit_instruction(load_byte, 4, 1, 0, 0);
+                self.emit_instruction(load_byte, 5, 2, 0, 0);
+                self.emit_move_register(0, 4);
+                self.emit_instruction(sub, 0, 5, 0, 0);
+                exits.push(self.instructions.len());
+                self.emit_instruction(jne, 0, 0, 0, 0);
+                if !is_memcmp {
+                    // Equal bytes: a NUL ends both strings
+                    exits.push(self.instructions.len());
+                    self.emit_instruction(jeq, 4, 0, 0, 0);
+                }
+                self.emit_instruction(add, 1, 0, 0, 1);
+                self.emit_instruction(add, 2, 0, 0, 1);
+                if is_memcmp {
+                    self.emit_instruction(add, 3, 0, 0, -1);
+                }
+                self.emit_jump_to(top)?;
+                for exit in exits {
+                    self.patch_jump(exit, self.instructions.len())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Lower `FixedMul(a, b, scale)`, `a * b / scale`, or `FixedDiv(a, b,
+    /// scale)`, `a * scale / b`, with the unsigned arguments in r1-r3 and the
+    /// result left in r0. `x * y / d` is computed as `x / d * y + x % d * y /
+    /// d`, which gives the same result without overflowing on `x * y`.
+    fn emit_fixed_point(&mut self, callee: &str) {
+        let (y, divisor) = match callee {
+            "FixedMul" => (2, 3),
+            _ => (3, 2),
+        };
+        let alu = |op| bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_X;
+
+        self.emit_move_register(0, 1);
+        self.emit_instruction(alu(bpf_opcodes::BPF_DIV), 0, divisor, 0, 0);
+        self.emit_instruction(alu(bpf_opcodes::BPF_MUL), 0, y, 0, 0);
+        self.emit_move_register(4, 1);
+        self.emit_instruction(alu(bpf_opcodes::BPF_MOD), 4, divisor, 0, 0);
+        self.emit_instruction(alu(bpf_opcodes::BPF_MUL), 4, y, 0, 0);
+        self.emit_instruction(alu(bpf_opcodes::BPF_DIV), 4, divisor, 0, 0);
+        self.emit_instruction(alu(bpf_opcodes::BPF_ADD), 0, 4, 0, 0);
+    }
+
+    /// `borsh_deserialize(value, data, len)` decodes the Borsh bytes at
+    /// `data` into the struct `value`; `borsh_serialize(value, data, len)`
+    /// encodes it there. Both give the number of bytes used, or -1 when
+    /// `len` is too short or an array's length prefix doesn't match.
+    fn generate_borsh(&mut self, callee: &str, args: &[Node]) -> Result<u8, CodeGenError> {
+        let [value, data, len] = args else {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "{} takes a struct, a buffer and its length",
+                callee
+            )));
+        };
+        let ty = match self.expression_type(value) {
+            Type::Pointer(inner) => *inner,
+            ty => ty,
+        };
+        if !matches!(ty, Type::Struct(_)) {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "{} needs a struct, found {:?}",
+                callee, ty
+            )));
+        }
+        let (items, size) = self.borsh_layout(&ty)?;
+
+        let value_reg = self.generate_expression(value)?;
+        let data_reg = self.generate_expression(data)?;
+        let len_reg = self.generate_expression(len)?;
+        let serialize = callee == "borsh_serialize";
+        let failures = self.emit_borsh(serialize, value_reg, data_reg, len_reg, &items, size);
+
+        self.emit_move_immediate(value_reg, size as i32);
+        let done = self.emit_jump_placeholder();
+        for failure in failures {
+            self.patch_jump(failure, self.instructions.len())?;
+        }
+        self.emit_move_immediate(value_reg, -1);
+        self.patch_jump(done, self.instructions.len())?;
+        // Released once both paths have joined, so each reloads any spills
+        self.release_register();
+        self.release_register();
+        Ok(value_reg)
+    }
+
+    /// Reject an integer literal, or a negated one, stored to an integer
+    /// type too narrow for it. Negated literals stored unsigned wrap as in C.
+    fn check_literal_fits(&self, node: &Node, ty: &Type) -> Result<(), CodeGenError> {
+        let Type::Int { bits, signed } = *ty else {
+            return Ok(());
+        };
+        let (literal, negated) = match (node.value.as_deref(), node.children.as_slice()) {
+            (Some("neg"), [operand]) if node.node_type == NodeType::Expression => (operand, true),
+            _ => (node, false),
+        };
+        let Some(digits) = literal
+            .value
+            .as_deref()
+            .filter(|_| literal.node_type == NodeType::Literal)
+            .filter(|value| value.bytes().all(|byte| byte.is_ascii_digit()))
+        else {
+            return Ok(());
+        };
+        let max = match (signed, negated) {
+            (true, false) => (1u128 << (bits - 1)) - 1,
+            (true, true) => 1u128 << (bits - 1),
+            (false, false) => (1u128 << bits) - 1,
+            (false, true) => return Ok(()),
+        };
+        if digits.parse::<u128>().map_or(true, |value| value > max) {
+            let name = format!("{}{}", if signed { "I" } else { "U" }, bits);
+            let sign = if negated { "-" } else { "" };
+            return Err(CodeGenError::LiteralOverflow(format!(
+                "{}{} doesn't fit in {}",
+                sign, digits, name
+            )));
+        }
+        Ok(())
+    }
+
+    /// `Require(condition, code)` returns `code` from the current function
+    /// when `condition` is false, like `if (!condition) return code;`, and
+    /// otherwise gives 0.
+    fn generate_require(&mut self, args: &[Node]) -> Result<u8, CodeGenError> {
+        let [condition, code] = args else {
+            return Err(CodeGenError::InvalidInstruction(
+                "Require takes a condition and an error code".to_string(),
+            ));
+        };
+        let reg = self.generate_expression(condition)?;
+        let reg = self.release_for_branch(reg);
+        let passed = self.instructions.len();
+        self.emit_instruction(
+            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JNE | bpf_opcodes::BPF_K,
+            reg,
+            0,
+            0,
+            0,
+        );
+
+        let code = self.generate_expression(code)?;
+        self.emit_move_register(0, code);
+        self.release_register();
+        self.emit_return();
+        self.patch_jump(passed, self.instructions.len())?;
+
+        let reg = self.allocate_register()?;
+        self.emit_move_immediate(reg, 0);
+        Ok(reg)
+    }
+
+    /// Borsh layout of `ty`, which must fit the 16-bit offsets of loads and stores.
+    fn borsh_layout(&self, ty: &Type) -> Result<(Vec<BorshItem>, usize), CodeGenError> {
+        let (items, size) = ty.borsh_layout(&self.structs).ok_or_else(|| {
+            CodeGenError::InvalidInstruction(format!("cannot Borsh-encode {:?}", ty))
+        })?;
+        if size.max(self.size_of(ty)?) > i16::MAX as usize {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "{:?} is too large to Borsh-encode",
+                ty
+            )));
+        }
+        Ok((items, size))
+    }
+
+    /// Copy between the struct at `value_reg` and the `size` Borsh bytes at
+    /// `data_reg`, in the direction `serialize` gives. Returns the jumps
+    /// taken when `len_reg` is too short or a length prefix doesn't match.
+    fn emit_borsh(
+        &mut self,
+        serialize: bool,
+        value_reg: u8,
+        data_reg: u8,
+        len_reg: u8,
+        items: &[BorshItem],
+        size: usize,
+    ) -> Vec<usize> {
+        let mut failures = vec![self.instructions.len()];
+        self.emit_instruction(
+            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JLT | bpf_opcodes::BPF_K,
+            len_reg,
+            0,
+            0,
+            size as i32,
+        );
+
+        // R0 carries each value across
+        for item in items {
+            match *item {
+                BorshItem::Scalar {
+                    memory,
+                    encoded,
+                    size,
+                } => {
+                    let (from, from_offset, to, to_offset) = if serialize {
+                        (value_reg, memory, data_reg, encoded)
+                    } else {
+                        (data_reg, encoded, value_reg, memory)
+                    };
+                    self.emit_load_sized(size, 0, from, from_offset as i16);
+                    self.emit_store_sized(size, to, to_offset as i16, 0);
+                }
+                BorshItem::Length { encoded, count } if serialize => {
+                    self.emit_move_immediate(0, count as i32);
+                    self.emit_store_sized(4, data_reg, encoded as i16, 0);
+                }
+                BorshItem::Length { encoded, count } => {
+                    self.emit_load_sized(4, 0, data_reg, encoded as i16);
+                    failures.push(self.instructions.len());
+                    self.emit_instruction(
+                        bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JNE | bpf_opcodes::BPF_K,
+                        0,
+                        0,
+                        0,
+                        count as i32,
+                    );
+                }
+            }
+        }
+        failures
+    }
+
+    /// Call immediate of the Solana syscall `callee` lowers to, when compiling for Solana.
+    fn syscall(&self, callee: &str) -> Option<i32> {
+        if self.target != CompileTarget::SolanaBpf {
+            return None;
+        }
+        solana_bpf::builtin_syscall(callee).map(|symbol| solana_bpf::syscall_id(symbol) as i32)
+    }
+
+    /// Whether calling `callee` goes through a function pointer rather than
+    /// naming a function.
+    fn is_indirect_call(&self, callee: &Node) -> bool {
+        callee.node_type != NodeType::Identifier
+            || callee
+                .value
+                .as_deref()
+                .is_some_and(|name| self.lookup_variable(name).is_some())
+    }
+
+    /// Value of a pointer to the function `name`.
+    fn function_pointer(&mut self, name: &str) -> i32 {
+        let index = match self.function_table.iter().position(|f| f == name) {
+            Some(index) => index,
+            None => {
+                self.function_table.push(name.to_string());
+                self.function_table.len() - 1
+            }
+        };
+        index as i32 + 1
+    }
+
+    /// Append the stub indirect calls go through. It calls the function
+    /// whose pointer is in r0, with the caller's r1-r5, and returns its
+    /// result; any other pointer returns 0.
+    fn generate_dispatch(&mut self) {
+        let start = self.instructions.len();
+        for (i, name) in self.function_table.clone().iter().enumerate() {
+            self.emit_instruction(
+                bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JNE | bpf_opcodes::BPF_K,
+                0,
+                0,
+                2,
+                i as i32 + 1,
+            );
+            self.emit_local_call(name);
+            self.emit_instruction(bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_EXIT, 0, 0, 0, 0);
+        }
+        self.emit_exit(0);
+
+        for (index, name) in &self.pending_calls {
+            if name == DISPATCH {
+                self.instructions[*index].immediate = start as i32 - *index as i32 - 1;
+            }
+        }
+        self.pending_calls.retain(|(_, name)| name != DISPATCH);
+    }
+
+    /// Point local calls at their callee. Solana syscall builtins only get
+    /// here on other targets, which have nothing to lower them to.
+    fn resolve_calls(&mut self) -> Result<(), CodeGenError> {
+        for (index, name) in core::mem::take(&mut self.pending_calls) {
+            let Some(&target) = self.functions.get(&name) else {
+                if solana_bpf::builtin_syscall(&name).is_some() {
+                    return Err(CodeGenError::InvalidInstruction(format!(
+                        "{} is only available on solana-bpf",
+                        name
+                    )));
+                }
+                return Err(CodeGenError::FunctionNotFound(name));
+            };
+            self.instructions[index].immediate = target as i32 - index as i32 - 1;
+        }
+        Ok(())
+    }
+
+    /// Start a fresh stack frame and variable scope for a function body.
+    fn begin_frame(&mut self) {
+        self.scopes = vec![HashMap::new()];
+        self.frame_size = 0;
+        self.temps = 0;
+        self.spill_slots.clear();
+    }
+
+    fn allocate_slot(&mut self) -> i16 {
+        self.allocate_stack(8)
+    }
+
+    /// Reserve `size` bytes of stack, rounded up to whole 8-byte slots.
+    fn allocate_stack(&mut self, size: usize) -> i16 {
+        self.frame_size += size.max(8).next_multiple_of(8) as i16;
+        -self.frame_size
+    }
+
+    fn declare_variable(&mut self, name: &str, ty: Type) -> Result<i16, CodeGenError> {
+        let offset = if ty.is_aggregate() {
+            let size = self.size_of(&ty)?;
+            self.allocate_stack(size)
+        } else {
+            self.allocate_slot()
+        };
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Variable { offset, ty });
+        }
+        Ok(offset)
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Temporaries form a stack: allocating takes the next register in turn,
+    /// first spilling the older temporary still held there.
+    fn allocate_register(&mut self) -> Result<u8, CodeGenError> {
+        let reg = temp_register(self.temps);
+        if self.temps >= TEMP_REGS {
+            let slot = self.spill_slot(self.temps - TEMP_REGS);
+            self.emit_store(FRAME_POINTER, slot, reg);
+        }
+        self.temps += 1;
+        Ok(reg)
+    }
+
+    /// Release the innermost temporary, reloading the one it displaced.
+    fn release_register(&mut self) {
+        if self.temps == 0 {
+            return;
+        }
+        self.temps -= 1;
+        if self.temps >= TEMP_REGS {
+            let slot = self.spill_slots[self.temps - TEMP_REGS];
+            self.emit_load(temp_register(self.temps), FRAME_POINTER, slot);
+        }
+    }
+
+    /// Whether releasing the innermost temporary reloads a spilled one into
+    /// its register. A jump taken before the release would skip the reload,
+    /// so branches test a copy of such a register instead.
+    fn innermost_displaces(&self) -> bool {
+        self.temps > TEMP_REGS
+    }
+
+    /// Stack slot for the temporary `depth` places past the registers,
+    /// reserved the first time a function spills that deep.
+    fn spill_slot(&mut self, depth: usize) -> i16 {
+        if depth == self.spill_slots.len() {
+            let slot = self.allocate_slot();
+            self.spill_slots.push(slot);
+        }
+        self.spill_slots[depth]
+    }
+
+    fn emit_instruction(
+        &mut self,
+        opcode: u8,
+        dst_reg: u8,
+        src_reg: u8,
+        offset: i16,
+        immediate: i32,
+    ) {
+        let instruction = BpfInstruction::new(opcode, dst_reg, src_reg, offset, immediate);
+        self.instructions.push(instruction);
+        self.source_lines.push(self.current_line);
+    }
+
+    fn emit_move_immediate(&mut self, dst_reg: u8, immediate: i32) {
+        self.emit_instruction(
+            bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_MOV | bpf_opcodes::BPF_K,
+            dst_reg,
+            0,
+            0,
+            immediate,
+        );
+    }
+
+    /// Load a 64-bit constant, using `lddw` only when it doesn't fit in 32 bits.
+    /// Convert the value in `reg` from `from` to `to` where they differ
+    /// between integer and fixed-point `F64`; integers truncate towards
+    /// negative infinity.
+    fn emit_convert(&mut self, reg: u8, from: &Type, to: &Type) {
+        let integer = |ty: &Type| matches!(ty, Type::Int { .. } | Type::Bool);
+        let op = match (from, to) {
+            (from, Type::F64) if integer(from) => bpf_opcodes::BPF_LSH,
+            (Type::F64, to) if integer(to) => bpf_opcodes::BPF_ARSH,
+            _ => return,
+        };
+        self.emit_instruction(
+            bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_K,
+            reg,
+            0,
+            0,
+            FIXED_POINT_SHIFT,
+        );
+    }
+
+    /// Convert the value in `reg` for an explicit cast. Integers are
+    /// truncated or extended to the target width; pointers keep their bits.
+    fn emit_cast(&mut self, reg: u8, from: &Type, to: &Type) {
+        match (from, to) {
+            (_, Type::Bool) if *from != Type::Bool => self.emit_extend(reg, to),
+            (
+                Type::Int {
+                    bits: from_bits, ..
+                },
+                Type::Int { bits, signed },
+            ) => {
+                // A widened value must first be in range for its own type
+                if from_bits < bits {
+                    self.emit_extend(reg, from);
+                }
+                if *bits < 64 {
+                    self.emit_extend(
+                        reg,
+                        &Type::Int {
+                            bits: *bits,
+                            signed: *signed,
+                        },
+                    );
+                }
+            }
+            (Type::F64, Type::Int { .. }) => {
+                self.emit_convert(reg, from, to);
+                self.emit_extend(reg, to);
+            }
+            _ => self.emit_convert(reg, from, to),
+        }
+    }
+
+    /// Sign-extend `reg` after a narrow load of a signed integer of `ty`; the
+    /// load already zero-extends unsigned ones.
+    fn emit_sign_extend(&mut self, reg: u8, ty: &Type) {
+        if ty.is_signed() && scalar_size(ty) < 8 {
+            self.emit_extend(reg, ty);
+        }
+    }
+
+    /// Store `reg` to the local `variable`, truncating a narrow integer to
+    /// its width first so `reg` also holds the value stored.
+    fn emit_store_variable(&mut self, variable: &Variable, reg: u8) {
+        self.emit_extend(reg, &variable.ty);
+        self.emit_store_sized(
+            scalar_size(&variable.ty),
+            FRAME_POINTER,
+            variable.offset,
+            reg,
+        );
+    }
+
+    /// Sign- or zero-extend the low bits of `reg` that hold an integer of
+    /// `ty`, or turn any nonzero value into 1 for a `Bool`.
+    fn emit_extend(&mut self, reg: u8, ty: &Type) {
+        if *ty == Type::Bool {
+            self.emit_instruction(
+                bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JNE | bpf_opcodes::BPF_K,
+                reg,
+                0,
+                2,
+                0,
+            );
+            self.emit_bool_result(reg);
+            return;
+        }
+        let Type::Int { bits, signed } = *ty else {
+            return;
+        };
+        if bits >= 64 {
+            return;
+        }
+        let shift = 64 - bits as i32;
+        let right = if signed {
+            bpf_opcodes::BPF_ARSH
+        } else {
+            bpf_opcodes::BPF_RSH
+        };
+        for op in [bpf_opcodes::BPF_LSH, right] {
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | op | bpf_opcodes::BPF_K,
+                reg,
+                0,
+                0,
+                shift,
+            );
+        }
+    }
+
+    fn emit_load_immediate(&mut self, dst_reg: u8, value: i64) {
+        match i32::try_from(value) {
+            Ok(immediate) => self.emit_move_immediate(dst_reg, immediate),
+            Err(_) => {
+                self.emit_instruction(
+                    bpf_opcodes::BPF_LD | bpf_opcodes::BPF_IMM | bpf_opcodes::BPF_DW,
+                    dst_reg,
+                    0,
+                    0,
+                    value as i32,
+                );
+                self.emit_instruction(0, 0, 0, 0, (value >> 32) as i32);
+            }
+        }
+    }
+
+    fn emit_move_register(&mut self, dst_reg: u8, src_reg: u8) {
+        if dst_reg != src_reg {
+            self.emit_instruction(
+                bpf_opcodes::BPF_ALU64 | bpf_opcodes::BPF_MOV | bpf_opcodes::BPF_X,
+                dst_reg,
+                src_reg,
+                0,
+                0,
+            );
+        }
+    }
+
+    fn emit_load(&mut self, dst_reg: u8, src_reg: u8, offset: i16) {
+        self.emit_load_sized(8, dst_reg, src_reg, offset);
+    }
+
+    fn emit_store(&mut self, dst_reg: u8, offset: i16, src_reg: u8) {
+        self.emit_store_sized(8, dst_reg, offset, src_reg);
+    }
+
+    /// `ldx` of a 1, 2, 4 or 8 byte value.
+    fn emit_load_sized(&mut self, size: usize, dst_reg: u8, src_reg: u8, offset: i16) {
+        self.emit_instruction(
+            bpf_opcodes::BPF_LDX | bpf_opcodes::BPF_MEM | size_mode(size),
+            dst_reg,
+            src_reg,
+            offset,
+            0,
+        );
+    }
+
+    /// `stx` of a 1, 2, 4 or 8 byte value.
+    fn emit_store_sized(&mut self, size: usize, dst_reg: u8, offset: i16, src_reg: u8) {
+        self.emit_instruction(
+            bpf_opcodes::BPF_STX | bpf_opcodes::BPF_MEM | size_mode(size),
+            dst_reg,
+            src_reg,
+            offset,
+            0,
+        );
+    }
+
+    /// Emit an unconditional jump whose target is filled in by [`Self::patch_jump`].
+    fn emit_jump_placeholder(&mut self) -> usize {
+        let index = self.instructions.len();
+        self.emit_instruction(bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_JA, 0, 0, 0, 0);
+        index
+    }
+
+    fn emit_jump_to(&mut self, target: usize) -> Result<(), CodeGenError> {
+        let index = self.emit_jump_placeholder();
+        self.patch_jump(index, target)
+    }
+
+    /// Point the jump at `index` to `target`. BPF offsets are signed 16-bit
+    /// instruction counts; a jump past that range is an error rather than
+    /// an offset wrapped to somewhere else.
+    fn patch_jump(&mut self, index: usize, target: usize) -> Result<(), CodeGenError> {
+        let distance = target as i64 - index as i64 - 1;
+        self.instructions[index].offset =
+            i16::try_from(distance).map_err(|_| CodeGenError::JumpTooFar(distance))?;
+        Ok(())
+    }
+
+    /// Whether a jump at `index` can reach `target` directly.
+    fn in_jump_range(index: usize, target: usize) -> bool {
+        i16::try_from(target as i64 - index as i64 - 1).is_ok()
+    }
+
+    /// Add a NUL-terminated string to `.rodata`, reusing an identical earlier entry.
+    fn intern_string(&mut self, text: &str) -> usize {
+        let mut bytes = text.as_bytes().to_vec();
+        bytes.push(0);
+        if let Some(offset) = self
+            .rodata
+            .windows(bytes.len())
+            .position(|window| window == bytes.as_slice())
+        {
+            return offset;
+        }
+        let offset = self.rodata.len();
+        self.rodata.extend_from_slice(&bytes);
+        offset
+    }
+
+    fn emit_load_rodata_address(&mut self, dst_reg: u8, offset: usize) {
+        // lddw occupies two slots; the second carries the high 32 bits
+        self.rodata_relocations.push(self.instructions.len());
+        self.emit_instruction(
+            bpf_opcodes::BPF_LD | bpf_opcodes::BPF_IMM | bpf_opcodes::BPF_DW,
+            dst_reg,
+            0,
+            0,
+            offset as i32,
+        );
+        self.emit_instruction(0, 0, 0, 0, 0);
+    }
+
+    fn emit_load_map(&mut self, dst_reg: u8, name: &str) {
+        self.map_relocations
+            .push((self.instructions.len(), name.to_string()));
+        self.emit_instruction(
+            bpf_opcodes::BPF_LD | bpf_opcodes::BPF_IMM | bpf_opcodes::BPF_DW,
+            dst_reg,
+            bpf_opcodes::BPF_PSEUDO_MAP_FD,
+            0,
+            0,
+        );
+        self.emit_instruction(0, 0, 0, 0, 0);
+    }
+
+    fn emit_call(&mut self, func_id: i32) {
+        self.emit_instruction(
+            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_CALL,
+            0,
+            0,
+            0,
+            func_id,
+        );
+    }
+
+    /// Call the function `name`, resolved once all functions are laid out.
+    fn emit_local_call(&mut self, name: &str) {
+        self.pending_calls
+            .push((self.instructions.len(), name.to_string()));
+        self.emit_instruction(
+            bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_CALL,
+            0,
+            bpf_opcodes::BPF_PSEUDO_CALL,
+            0,
+            0,
+        );
+    }
+
+    /// Return from the current function with the value in r0. In a body
+    /// being inlined, that's a jump past the end of the body, first
+    /// reloading any of the caller's temporaries still displaced.
+    fn emit_return(&mut self) {
+        let Some(temps) = self.inlining.last().map(|call| call.temps) else {
+            self.emit_instruction(bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_EXIT, 0, 0, 0, 0);
+            return;
+        };
+        for depth in (temps.max(TEMP_REGS)..self.temps).rev() {
+            let slot = self.spill_slots[depth - TEMP_REGS];
+            self.emit_load(temp_register(depth), FRAME_POINTER, slot);
+        }
+        let jump = self.emit_jump_placeholder();
+        if let Some(call) = self.inlining.last_mut() {
+            call.returns.push(jump);
+        }
+    }
+
+    fn emit_exit(&mut self, exit_code: i32) {
+        // Move exit code to R0
+        self.emit_move_immediate(0, exit_code);
+        // Exit instruction
+        self.emit_instruction(bpf_opcodes::BPF_JMP | bpf_opcodes::BPF_EXIT, 0, 0, 0, 0);
+    }
+
+    pub fn validate_instructions(&self, instructions: &[BpfInstruction]) -> bool {
+        // Basic validation - check for invalid opcodes
+        for instruction in instructions {
+            let class = instruction.opcode & 0x07;
+            match class {
+                bpf_opcodes::BPF_LD
+                | bpf_opcodes::BPF_LDX
+                | bpf_opcodes::BPF_ST
+                | bpf_opcodes::BPF_STX
+                | bpf_opcodes::BPF_ALU
+                | bpf_opcodes::BPF_JMP
+                | bpf_opcodes::BPF_ALU64 => {
+                    // Valid instruction class
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    #[allow(dead_code)]
+    pub fn validate_bpf_program(
+        &self,
+        instructions: &[BpfInstruction],
+    ) -> Result<(), CodeGenError> {
+        // Validate register usage (R0-R10 are valid)
+        for instr in instructions {
+            if instr.dst_reg > 10 || instr.src_reg > 10 {
+                return Err(CodeGenError::InvalidInstruction(
+                    "Invalid register: registers must be R0-R10".to_string(),
+                ));
+            }
+        }
+
+        // Only validate jump targets if we have multiple instructions
+        // (single-instruction validation for testing should skip this)
+        if instructions.len() > 1 {
+            // Validate jump targets
+            for (i, instr) in instructions.iter().enumerate() {
+                // Check if it's a jump instruction (opcodes 0x05, 0x15, 0x1d, 0x25, 0x2d, etc.)
+                if (instr.opcode & 0x07) == 0x05 && instr.opcode != 0x95 {
+                    // Jump class, but not exit
+                    let target = i as i32 + 1 + instr.offset as i32;
+                    if target < 0 || target >= instructions.len() as i32 {
+                        return Err(CodeGenError::InvalidInstruction(format!(
+                            "Invalid jump target: {} out of bounds",
+                            target
+                        )));
+                    }
+                }
+            }
+
             // Check for exit instruction only for multi-instruction programs
             if !instructions.iter().any(|instr| instr.opcode == 0x95) {
                 return Err(CodeGenError::InvalidInstruction(
@@ -344,3 +3269,159 @@ impl CodeGen {
         Ok(())
     }
 }
+
+impl Visitor for CodeGen {
+    type Error = CodeGenError;
+
+    /// Generate `node`, attributing its instructions to its source line
+    /// unless a nested node has a line of its own.
+    fn visit_node(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let outer = self.current_line;
+        if let Some(position) = node.position {
+            self.current_line = Some(position.line);
+        }
+        let result = self.generate_node(node);
+        self.current_line = outer;
+        result
+    }
+}
+
+/// Where a staged call argument comes from.
+#[derive(Debug, Clone, Copy)]
+enum Argument {
+    Slot(i16),
+    Immediate(i32),
+    /// Address of a stack offset in the current frame
+    Address(i16),
+}
+
+/// Helper id of the map builtins, which take the map itself as first argument.
+fn map_helper(callee: &str) -> Option<i32> {
+    match callee {
+        "map_lookup_elem" => Some(1),
+        "map_update_elem" => Some(2),
+        "map_delete_elem" => Some(3),
+        _ => None,
+    }
+}
+
+/// Round `value` to the nearest integer, halves away from zero, like
+/// `f64::round` but without needing std. `value` must fit in an `i64`.
+fn round_half_away(value: f64) -> i64 {
+    let truncated = value as i64;
+    let fraction = value - truncated as f64;
+    if fraction >= 0.5 {
+        truncated + 1
+    } else if fraction <= -0.5 {
+        truncated - 1
+    } else {
+        truncated
+    }
+}
+
+/// Longest constant `MemSet` or `MemCpy` emitted without a loop.
+const MAX_UNROLLED_BYTES: i64 = 16;
+
+/// Number of arguments of the byte-string builtins, which lower to loops
+/// or, on Solana, to a syscall where one exists.
+fn memory_builtin_arity(callee: &str) -> Option<usize> {
+    match callee {
+        "MemCmp" | "MemSet" | "MemCpy" => Some(3),
+        "StrCmp" | "StrCpy" => Some(2),
+        _ => None,
+    }
+}
+
+/// Whether `callee` is `FixedMul` or `FixedDiv`, the fixed-point
+/// arithmetic builtins taking two values and their scale.
+fn is_fixed_point_builtin(callee: &str) -> bool {
+    matches!(callee, "FixedMul" | "FixedDiv")
+}
+
+/// Whether `name` is a builtin the code generator lowers itself rather
+/// than a function the program must declare.
+pub fn is_builtin(name: &str) -> bool {
+    matches!(name, "borsh_deserialize" | "borsh_serialize" | "Require")
+        || map_helper(name).is_some()
+        || memory_builtin_arity(name).is_some()
+        || is_fixed_point_builtin(name)
+        || solana_bpf::builtin_syscall(name).is_some()
+}
+
+/// Name from a `RetType:name` function declaration value.
+pub fn function_name(node: &Node) -> Option<&str> {
+    node.value.as_deref().and_then(declared_name)
+}
+
+/// Name from a `Type:name` declaration value.
+fn declared_name(value: &str) -> Option<&str> {
+    value.split_once(':').map(|(_, name)| name)
+}
+
+/// Bytes of a local's stack slot a value of `ty` occupies: an integer's
+/// width, one for a `Bool`, or all 8 for anything else.
+fn scalar_size(ty: &Type) -> usize {
+    match *ty {
+        Type::Int { bits, .. } => bits as usize / 8,
+        Type::Bool => 1,
+        _ => 8,
+    }
+}
+
+/// Memory-access size bits for a value of `size` bytes.
+fn size_mode(size: usize) -> u8 {
+    match size {
+        1 => bpf_opcodes::BPF_B,
+        2 => bpf_opcodes::BPF_H,
+        4 => bpf_opcodes::BPF_W,
+        _ => bpf_opcodes::BPF_DW,
+    }
+}
+
+/// Value of a plain integer literal node.
+/// Register holding the temporary at `depth`.
+fn temp_register(depth: usize) -> u8 {
+    FIRST_TEMP_REG + (depth % TEMP_REGS) as u8
+}
+
+fn integer_literal(node: &Node) -> Option<i64> {
+    if node.node_type != NodeType::Literal {
+        return None;
+    }
+    integer_value(node.value.as_deref()?)
+}
+
+/// Value of an integer literal's text. Literals past `I64` up to the `U64`
+/// maximum keep their bits, as `U64` values.
+pub fn integer_value(text: &str) -> Option<i64> {
+    text.parse::<i64>()
+        .ok()
+        .or_else(|| text.parse::<u64>().ok().map(|value| value as i64))
+}
+
+/// Placeholder produced by the parser for omitted `for` clauses.
+pub fn is_empty(node: &Node) -> bool {
+    node.node_type == NodeType::Block && node.children.is_empty()
+}
+
+/// Unescape a quoted string literal lexeme, or `None` if it isn't one.
+pub fn string_literal(lexeme: &str) -> Option<String> {
+    let inner = lexeme.strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => text.push('\n'),
+            Some('t') => text.push('\t'),
+            Some('r') => text.push('\r'),
+            Some('0') => text.push('\0'),
+            Some(other) => text.push(other),
+            None => text.push('\\'),
+        }
+    }
+    Some(text)
+}
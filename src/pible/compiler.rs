@@ -1,16 +1,175 @@
-use std::fs;
-use std::path::Path;
+use crate::pible::prelude::*;
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    io::{Read, Write},
+    path::Path,
+};
 use thiserror::Error;
 
 use crate::pible::{
-    bpf_vm::BpfVm, codegen::CodeGen, lexer::Lexer, parser::Parser, solana_bpf::SolanaBpf,
+    analysis::{self, Diagnostic, Severity},
+    asm::{self, AsmError},
+    bpf_vm::{BpfVm, VmResult},
+    btf::{self, BtfError},
+    cfg, classes,
+    codegen::{self, BpfInstruction, CodeGen, CodeGenError},
+    elf,
+    lexer::{self, LexError, Lexer, Token},
+    optimizer,
+    parser::{self, Node, NodeType, Parser},
+    preprocessor::{self, Pragmas, PreprocessError, Preprocessed},
+    resolve::{self, ResolveError},
+    solana_bpf::{self, SolanaBpf, SolanaError, VerifyError},
+    typecheck::{self, TypeError},
 };
 
+#[cfg(feature = "std")]
+use crate::pible::disasm;
+#[cfg(feature = "riscv")]
+use crate::pible::riscv;
+#[cfg(feature = "wasm")]
+use crate::pible::wasm;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CompileTarget {
     LinuxBpf,
     SolanaBpf,
     BpfVm,
+    /// Experimental RV64I machine code
+    #[cfg(feature = "riscv")]
+    RiscV,
+    /// Experimental WebAssembly module exporting `main`
+    #[cfg(feature = "wasm")]
+    Wasm,
+}
+
+/// Kind of Linux BPF program, which decides the ELF section the loader
+/// reads it from and the context struct it receives in r1.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ProgramType {
+    #[default]
+    SocketFilter,
+    Xdp,
+    /// Attached to the named kernel function
+    Kprobe(String),
+    /// Attached to a tracepoint given as `category/name`
+    Tracepoint(String),
+    /// Attached to a cgroup's ingress path
+    CgroupSkb,
+}
+
+impl ProgramType {
+    /// Parse the command-line spelling: `socket`, `xdp`, `kprobe:<function>`,
+    /// `tracepoint:<category>/<name>` or `cgroup-skb`.
+    pub fn parse(spec: &str) -> Option<Self> {
+        match spec.split_once(':') {
+            Some(("kprobe", function)) if !function.is_empty() => {
+                Some(ProgramType::Kprobe(function.to_string()))
+            }
+            Some(("tracepoint", point)) if point.contains('/') => {
+                Some(ProgramType::Tracepoint(point.to_string()))
+            }
+            None => match spec {
+                "socket" => Some(ProgramType::SocketFilter),
+                "xdp" => Some(ProgramType::Xdp),
+                "cgroup-skb" => Some(ProgramType::CgroupSkb),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// ELF section name libbpf maps to this program type.
+    pub fn section_name(&self) -> String {
+        match self {
+            ProgramType::SocketFilter => "socket".to_string(),
+            ProgramType::Xdp => "xdp".to_string(),
+            ProgramType::Kprobe(function) => format!("kprobe/{}", function),
+            ProgramType::Tracepoint(point) => format!("tracepoint/{}", point),
+            ProgramType::CgroupSkb => "cgroup_skb/ingress".to_string(),
+        }
+    }
+
+    /// Name and fields of the struct r1 points at on entry, predeclared so
+    /// the entry function can take e.g. `XdpMd* ctx`. Tracepoint records
+    /// differ per tracepoint, so they have none.
+    pub fn context(&self) -> Option<(&'static str, &'static [(&'static str, &'static str)])> {
+        match self {
+            ProgramType::SocketFilter | ProgramType::CgroupSkb => Some(("SkBuff", SK_BUFF)),
+            ProgramType::Xdp => Some(("XdpMd", XDP_MD)),
+            ProgramType::Kprobe(_) => Some(("PtRegs", PT_REGS)),
+            ProgramType::Tracepoint(_) => None,
+        }
+    }
+}
+
+/// Leading fields of the kernel's `struct __sk_buff`, up to `data_end`.
+const SK_BUFF: &[(&str, &str)] = &[
+    ("len", "U32"),
+    ("pkt_type", "U32"),
+    ("mark", "U32"),
+    ("queue_mapping", "U32"),
+    ("protocol", "U32"),
+    ("vlan_present", "U32"),
+    ("vlan_tci", "U32"),
+    ("vlan_proto", "U32"),
+    ("priority", "U32"),
+    ("ingress_ifindex", "U32"),
+    ("ifindex", "U32"),
+    ("tc_index", "U32"),
+    ("cb", "U32[5]"),
+    ("hash", "U32"),
+    ("tc_classid", "U32"),
+    ("data", "U32"),
+    ("data_end", "U32"),
+];
+
+/// The kernel's `struct xdp_md`.
+const XDP_MD: &[(&str, &str)] = &[
+    ("data", "U32"),
+    ("data_end", "U32"),
+    ("data_meta", "U32"),
+    ("ingress_ifindex", "U32"),
+    ("rx_queue_index", "U32"),
+    ("egress_ifindex", "U32"),
+];
+
+/// x86-64 `struct pt_regs`.
+const PT_REGS: &[(&str, &str)] = &[
+    ("r15", "U64"),
+    ("r14", "U64"),
+    ("r13", "U64"),
+    ("r12", "U64"),
+    ("bp", "U64"),
+    ("bx", "U64"),
+    ("r11", "U64"),
+    ("r10", "U64"),
+    ("r9", "U64"),
+    ("r8", "U64"),
+    ("ax", "U64"),
+    ("cx", "U64"),
+    ("dx", "U64"),
+    ("si", "U64"),
+    ("di", "U64"),
+    ("orig_ax", "U64"),
+    ("ip", "U64"),
+    ("cs", "U64"),
+    ("flags", "U64"),
+    ("sp", "U64"),
+    ("ss", "U64"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    /// Bare concatenation of 8-byte instructions
+    #[default]
+    Raw,
+    /// ELF64 relocatable object loadable by libbpf/bpftool
+    Elf,
+    /// eBPF assembly text that reassembles to the raw output; see
+    /// [`asm`](crate::pible::asm) for the syntax
+    Assembly,
 }
 
 #[derive(Debug, Clone)]
@@ -22,6 +181,24 @@ pub struct CompileOptions<'a> {
     pub solana_program_id: Option<[u8; 32]>,
     pub output_directory: Option<&'a str>,
     pub output_path: Option<String>,
+    pub output_format: OutputFormat,
+    pub emit_asm: bool,
+    /// 0 disables optimization; 1 or higher folds constants and runs the peephole pass
+    pub opt_level: u8,
+    /// Linux BPF program type; ignored for other targets
+    pub program_type: ProgramType,
+    /// Treat warnings as errors, as `--deny-warnings` does
+    pub strict: bool,
+    /// Run [`SolanaBpf::verify`] over the program; ignored for other targets
+    pub verify: bool,
+    /// Helper id `PrintF` and `sol_log` call, overriding the target's
+    /// `trace_printk` or `sol_log_`
+    pub log_helper_id: Option<i32>,
+    /// Trap when an index into an array of known length is out of range
+    pub safe_arrays: bool,
+    /// Inline functions of at most this many AST nodes at their call
+    /// sites; 0 disables inlining
+    pub inline_threshold: usize,
 }
 
 impl<'a> Default for CompileOptions<'a> {
@@ -33,19 +210,53 @@ impl<'a> Default for CompileOptions<'a> {
             solana_program_id: None,
             output_directory: None,
             output_path: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         }
     }
 }
 
+/// Output of a successful compile together with any warnings it raised.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Compilation {
+    pub bytecode: Vec<u8>,
+    /// Warnings from the analysis passes and code generation, in the order
+    /// they were raised
+    pub warnings: Vec<Diagnostic>,
+    /// Functions in the output, in address order; empty for WebAssembly,
+    /// whose functions aren't laid out in one code section
+    pub symbols: Vec<Symbol>,
+}
+
 #[derive(Error, Debug)]
 #[allow(dead_code)]
 pub enum CompileError {
-    #[error("Lexical analysis failed: {0}")]
-    LexError(String),
-    #[error("Syntax analysis failed: {0}")]
-    ParseError(String),
+    #[error("Lexical analysis failed at line {line}, column {column}\n{diagnostic}")]
+    LexError {
+        line: usize,
+        column: usize,
+        diagnostic: String,
+        source: lexer::LexError,
+    },
+    #[error("Syntax analysis failed at line {line}, column {column}\n{diagnostic}")]
+    ParseError {
+        line: usize,
+        column: usize,
+        diagnostic: String,
+        source: parser::ParseError,
+    },
     #[error("Code generation failed: {0}")]
-    CodeGenError(String),
+    CodeGenError(#[source] CodeGenError),
+    #[error("Solana program generation failed: {0}")]
+    SolanaError(#[from] SolanaError),
     #[error("Invalid syntax: {0}")]
     InvalidSyntax(String),
     #[error("Undefined variable: {0}")]
@@ -60,8 +271,154 @@ pub enum CompileError {
     IdlGenerationError(String),
     #[error("VM execution failed: {0}")]
     VmExecutionError(String),
+    #[cfg(feature = "std")]
     #[error("File I/O error: {0}")]
     IoError(#[from] std::io::Error),
+    /// A failed `#include`, or another preprocessor directive or macro error
+    #[error("Preprocessing failed: {0}")]
+    IncludeError(#[from] PreprocessError),
+    /// An `F64` operation the fixed-point lowering can't express
+    #[error("Unsupported floating-point operation: {0}")]
+    UnsupportedFloat(String),
+    /// An integer literal too large for the type it's stored to
+    #[error("Integer literal {0}")]
+    LiteralOverflow(String),
+    #[error("BTF generation failed: {0}")]
+    BtfError(#[from] BtfError),
+    #[error("Assembly output failed: {0}")]
+    AsmError(#[from] AsmError),
+    /// Warnings raised while compiling with `strict` set, each promoted to
+    /// [`Severity::Error`]
+    #[error("Warnings treated as errors:\n{}", format_warnings(.0))]
+    StrictWarnings(Vec<Diagnostic>),
+    /// A panic inside the compiler, caught by [`Compiler::try_compile`]
+    #[error("Internal compiler error: {0}")]
+    InternalError(String),
+    /// Verifier rules broken by a program compiled with `verify` set
+    #[error("Verification failed:\n{}", format_verify_errors(.0))]
+    VerifyFailed(Vec<VerifyError>),
+}
+
+/// Where a function starts in the compiled output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct Symbol {
+    pub name: String,
+    /// Index of the function's first instruction
+    pub instruction: usize,
+    /// Byte offset of that instruction in the bytecode, or in the `.text`
+    /// section of an ELF object
+    pub offset: usize,
+}
+
+/// Symbols for functions starting at the given instruction indices, with
+/// `base` instructions of `width` bytes placed before them.
+fn symbols(functions: &HashMap<String, usize>, base: usize, width: usize) -> Vec<Symbol> {
+    let mut symbols: Vec<Symbol> = functions
+        .iter()
+        .map(|(name, &index)| Symbol {
+            name: name.clone(),
+            instruction: base + index,
+            offset: (base + index) * width,
+        })
+        .collect();
+    symbols.sort_by(|a, b| (a.instruction, &a.name).cmp(&(b.instruction, &b.name)));
+    symbols
+}
+
+fn format_warnings(warnings: &[Diagnostic]) -> String {
+    warnings
+        .iter()
+        .map(|warning| format!("{}: {}", warning.severity, warning))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_verify_errors(errors: &[VerifyError]) -> String {
+    errors
+        .iter()
+        .map(|error| format!("error: {}", error))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl From<CodeGenError> for CompileError {
+    fn from(error: CodeGenError) -> Self {
+        match error {
+            CodeGenError::UnsupportedFloat(operation) => CompileError::UnsupportedFloat(operation),
+            CodeGenError::LiteralOverflow(literal) => CompileError::LiteralOverflow(literal),
+            other => CompileError::CodeGenError(other),
+        }
+    }
+}
+
+impl From<TypeError> for CompileError {
+    fn from(error: TypeError) -> Self {
+        CompileError::TypeMismatch(error.to_string())
+    }
+}
+
+impl From<ResolveError> for CompileError {
+    fn from(error: ResolveError) -> Self {
+        match error {
+            ResolveError::UndefinedVariable(name) => CompileError::UndefinedVariable(name),
+            ResolveError::UndefinedFunction(name) => CompileError::UndefinedFunction(name),
+        }
+    }
+}
+
+/// Files written by [`Compiler::compile_file_cached`] for one compile, and
+/// the warnings it raised.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+struct Artifacts {
+    warnings: Vec<Diagnostic>,
+    /// Paths and contents, the compiled output first
+    files: Vec<(String, Vec<u8>)>,
+}
+
+/// Outputs of earlier [`Compiler::compile_file_cached`] calls, such as a
+/// watch loop's, so a file that hasn't changed isn't compiled again. Each
+/// is keyed by the SHA-256 of the input path, the preprocessed source with
+/// its includes spliced in, its pragmas and the options; editing the file or
+/// anything it includes gives a new key.
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct CompilerCache {
+    entries: HashMap<[u8; 32], Artifacts>,
+    hits: usize,
+    misses: usize,
+}
+
+#[cfg(feature = "std")]
+impl CompilerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn key(input_path: &str, unit: &Preprocessed, options: &CompileOptions) -> [u8; 32] {
+        // Output depends on nothing else, which reproducible builds rely on
+        let fingerprint = format!(
+            "{}\0{}\0{:?}\0{:?}",
+            input_path, unit.source, unit.pragmas, options
+        );
+        solana_bpf::sha256(fingerprint.as_bytes())
+    }
+
+    /// Number of compiles that reused a cached output.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of compiles that had to run the compiler.
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    /// Forget every cached output.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
 }
 
 #[allow(dead_code)]
@@ -69,6 +426,12 @@ pub struct Compiler {
     error_messages: Vec<String>,
 }
 
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Compiler {
     pub fn new() -> Self {
         Self {
@@ -76,131 +439,628 @@ impl Compiler {
         }
     }
 
+    #[cfg(feature = "std")]
     pub fn compile_file(
         &self,
         input_path: &str,
         options: &CompileOptions,
+    ) -> Result<(), CompileError> {
+        self.compile_file_cached(input_path, options, &mut CompilerCache::new())
+    }
+
+    /// Compile `input_path` as [`Self::compile_file`] does, unless `cache`
+    /// holds the files from compiling the same preprocessed source, includes
+    /// and all, with the same options; then only write those files again.
+    #[cfg(feature = "std")]
+    pub fn compile_file_cached(
+        &self,
+        input_path: &str,
+        options: &CompileOptions,
+        cache: &mut CompilerCache,
     ) -> Result<(), CompileError> {
         let source = fs::read_to_string(input_path).map_err(CompileError::IoError)?;
+        let unit = preprocessor::preprocess(&source, Some(input_path))?;
+        let key = CompilerCache::key(input_path, &unit, options);
+        if let Some(artifacts) = cache.entries.get(&key) {
+            cache.hits += 1;
+            for warning in &artifacts.warnings {
+                eprintln!("{}: {}: {}", warning.severity, input_path, warning);
+            }
+            for (path, contents) in &artifacts.files {
+                fs::write(path, contents).map_err(CompileError::IoError)?;
+            }
+            println!(
+                "Unchanged, reused cached output: {} -> {}",
+                input_path, artifacts.files[0].0
+            );
+            return Ok(());
+        }
+        cache.misses += 1;
 
-        let output = self.compile(&source, options)?;
+        let ast = classes::lower(&self.parse_unit(&unit)?);
+        let mut warnings = self.analyze(&unit, &ast);
+        let (output, _) = self.compile_ast(&ast, &unit.pragmas, options, &mut warnings)?;
+        let warnings = self.deny_warnings(warnings, options)?;
+        for warning in &warnings {
+            eprintln!("{}: {}: {}", warning.severity, input_path, warning);
+        }
+        let mut artifacts = Artifacts {
+            warnings,
+            files: Vec::new(),
+        };
 
         // Determine output path
         let output_path = self.determine_output_path(input_path, options);
 
         // Write compiled output
-        fs::write(&output_path, output).map_err(CompileError::IoError)?;
+        fs::write(&output_path, &output).map_err(CompileError::IoError)?;
+        artifacts.files.push((output_path.clone(), output.clone()));
 
         println!("Compiled successfully: {} -> {}", input_path, output_path);
+        let hash = solana_bpf::sha256(&output);
+        let hash: String = hash.iter().map(|byte| format!("{:02x}", byte)).collect();
+        println!("SHA-256: {}", hash);
 
         // Generate IDL if requested
         if options.generate_idl && options.target == CompileTarget::SolanaBpf {
             let idl_path = output_path.replace(".bpf", ".json");
-            let idl_json = self.generate_idl_json(&source, options)?;
-            fs::write(&idl_path, idl_json).map_err(CompileError::IoError)?;
+            let idl_json = self.generate_idl_json(&source, &ast, options)?;
+            fs::write(&idl_path, &idl_json).map_err(CompileError::IoError)?;
             println!("IDL generated: {}", idl_path);
+            artifacts.files.push((idl_path, idl_json.into_bytes()));
+        }
+
+        // Write disassembly alongside the output if requested, unless the
+        // output is assembly already
+        if options.emit_asm && options.output_format != OutputFormat::Assembly {
+            let bytecode = match options.output_format {
+                OutputFormat::Raw => output,
+                OutputFormat::Elf | OutputFormat::Assembly => {
+                    self.compile_ast(
+                        &ast,
+                        &unit.pragmas,
+                        &CompileOptions {
+                            output_format: OutputFormat::Raw,
+                            ..options.clone()
+                        },
+                        &mut Vec::new(),
+                    )?
+                    .0
+                }
+            };
+            let asm_path = Path::new(&output_path).with_extension("s");
+            let disassembly = disasm::disassemble_bytes(&bytecode);
+            fs::write(&asm_path, &disassembly).map_err(CompileError::IoError)?;
+            println!("Assembly written: {}", asm_path.display());
+            artifacts
+                .files
+                .push((asm_path.display().to_string(), disassembly.into_bytes()));
         }
 
+        cache.entries.insert(key, artifacts);
         Ok(())
     }
 
+    /// A Makefile rule making the output for `input_path` depend on it and
+    /// every file it includes, transitively, without generating code.
+    #[cfg(feature = "std")]
+    pub fn dependencies(
+        &self,
+        input_path: &str,
+        options: &CompileOptions,
+    ) -> Result<String, CompileError> {
+        let source = fs::read_to_string(input_path).map_err(CompileError::IoError)?;
+        let unit = preprocessor::preprocess(&source, Some(input_path))?;
+        let target = self.determine_output_path(input_path, options);
+        let prerequisites: Vec<String> = core::iter::once(input_path)
+            .chain(unit.includes.iter().map(String::as_str))
+            .map(|path| path.replace(' ', "\\ "))
+            .collect();
+        Ok(format!(
+            "{}: {}\n",
+            target.replace(' ', "\\ "),
+            prerequisites.join(" ")
+        ))
+    }
+
+    /// Compile the source read from `reader`, such as a pipe or an in-memory
+    /// buffer; `#include`s resolve relative to the working directory.
+    #[cfg(feature = "std")]
+    pub fn compile_reader<R: Read>(
+        &self,
+        mut reader: R,
+        options: &CompileOptions,
+    ) -> Result<Vec<u8>, CompileError> {
+        let mut source = String::new();
+        reader
+            .read_to_string(&mut source)
+            .map_err(CompileError::IoError)?;
+        self.compile(&source, options)
+    }
+
+    /// Compile the source read from `reader` and write the output to `writer`.
+    #[cfg(feature = "std")]
+    pub fn compile_to_writer<R: Read, W: Write>(
+        &self,
+        reader: R,
+        mut writer: W,
+        options: &CompileOptions,
+    ) -> Result<(), CompileError> {
+        let output = self.compile_reader(reader, options)?;
+        writer.write_all(&output).map_err(CompileError::IoError)?;
+        writer.flush().map_err(CompileError::IoError)
+    }
+
+    /// Compile `source`; `#include`s resolve relative to the working directory.
     pub fn compile(&self, source: &str, options: &CompileOptions) -> Result<Vec<u8>, CompileError> {
-        // Lexical analysis
-        let mut lexer = Lexer::new(source);
-        let tokens = lexer
-            .scan_tokens()
-            .map_err(|e| CompileError::LexError(format!("{:?}", e)))?;
+        Ok(self.compile_with_warnings(source, options)?.bytecode)
+    }
 
-        // Syntax analysis
-        let mut parser = Parser::new(tokens);
-        let ast = parser
-            .parse()
-            .map_err(|e| CompileError::ParseError(format!("{:?}", e)))?;
+    /// Compile `source` like [`Self::compile`], but for untrusted input such
+    /// as a fuzzer's or a web playground's: a panic anywhere in the compiler
+    /// becomes [`CompileError::InternalError`]. Input nested too deeply for
+    /// the recursive passes already fails to parse, so it can't overflow the
+    /// stack. Panics are only caught when built with `panic = "unwind"`, as
+    /// the release profile is.
+    #[cfg(feature = "std")]
+    pub fn try_compile(
+        &self,
+        source: &str,
+        options: &CompileOptions,
+    ) -> Result<Vec<u8>, CompileError> {
+        std::panic::catch_unwind(|| self.compile(source, options)).unwrap_or_else(|panic| {
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Err(CompileError::InternalError(message))
+        })
+    }
+
+    /// Compile `source` and hash the bytecode with SHA-256, so a build can be
+    /// checked against a published one. Output depends only on the source and
+    /// options; symbol tables are sorted rather than in hash map order.
+    pub fn compile_with_hash(
+        &self,
+        source: &str,
+        options: &CompileOptions,
+    ) -> Result<(Vec<u8>, [u8; 32]), CompileError> {
+        let bytecode = self.compile(source, options)?;
+        let hash = solana_bpf::sha256(&bytecode);
+        Ok((bytecode, hash))
+    }
+
+    /// Compile `source`, also returning warnings from the analysis passes and
+    /// code generation. With `options.strict` set, any warning fails the
+    /// compile instead.
+    pub fn compile_with_warnings(
+        &self,
+        source: &str,
+        options: &CompileOptions,
+    ) -> Result<Compilation, CompileError> {
+        let unit = preprocessor::preprocess(source, None)?;
+        let ast = classes::lower(&self.parse_unit(&unit)?);
+        let mut warnings = self.analyze(&unit, &ast);
+        let (bytecode, symbols) = self.compile_ast(&ast, &unit.pragmas, options, &mut warnings)?;
+        Ok(Compilation {
+            bytecode,
+            warnings: self.deny_warnings(warnings, options)?,
+            symbols,
+        })
+    }
+
+    /// Analyze `ast`, mapping warning lines back to the file they came from.
+    fn analyze(&self, unit: &Preprocessed, ast: &Node) -> Vec<Diagnostic> {
+        analysis::analyze(ast)
+            .into_iter()
+            .map(|warning| Diagnostic {
+                line: unit.origin(warning.line).line,
+                ..warning
+            })
+            .collect()
+    }
+
+    /// Fail with `warnings` as errors when `options.strict` is set.
+    fn deny_warnings(
+        &self,
+        warnings: Vec<Diagnostic>,
+        options: &CompileOptions,
+    ) -> Result<Vec<Diagnostic>, CompileError> {
+        if options.strict && !warnings.is_empty() {
+            let errors = warnings
+                .into_iter()
+                .map(|warning| Diagnostic {
+                    severity: Severity::Error,
+                    ..warning
+                })
+                .collect();
+            return Err(CompileError::StrictWarnings(errors));
+        }
+        Ok(warnings)
+    }
+
+    /// Compile several independent sources with the same options, stopping
+    /// at the first one that fails.
+    pub fn compile_many(
+        &self,
+        sources: &[&str],
+        options: &CompileOptions,
+    ) -> Result<Vec<Vec<u8>>, CompileError> {
+        sources
+            .iter()
+            .map(|source| self.compile(source, options))
+            .collect()
+    }
+
+    /// Generate the output for `ast` and the symbols of its functions,
+    /// adding any warnings about the output to `warnings`.
+    fn compile_ast(
+        &self,
+        ast: &Node,
+        pragmas: &Pragmas,
+        options: &CompileOptions,
+        warnings: &mut Vec<Diagnostic>,
+    ) -> Result<(Vec<u8>, Vec<Symbol>), CompileError> {
+        if ast.children.is_empty() {
+            return Err(CompileError::InvalidSyntax(
+                "program has no declarations".to_string(),
+            ));
+        }
+        self.check(ast)?;
+        let bpf_output = match options.output_format {
+            OutputFormat::Raw => true,
+            OutputFormat::Elf => matches!(
+                options.target,
+                CompileTarget::LinuxBpf | CompileTarget::SolanaBpf
+            ),
+            OutputFormat::Assembly => matches!(
+                options.target,
+                CompileTarget::LinuxBpf | CompileTarget::SolanaBpf | CompileTarget::BpfVm
+            ),
+        };
+        if !bpf_output {
+            return Err(CompileError::UnsupportedTarget(options.target));
+        }
 
         // Code generation based on target
         match options.target {
-            CompileTarget::LinuxBpf => self.compile_linux_bpf(&ast, options),
-            CompileTarget::SolanaBpf => self.compile_solana_bpf(&ast, options),
-            CompileTarget::BpfVm => self.compile_for_vm(&ast, options),
+            CompileTarget::LinuxBpf => self.compile_linux_bpf(ast, pragmas, options, warnings),
+            CompileTarget::SolanaBpf => self.compile_solana_bpf(ast, options, warnings),
+            CompileTarget::BpfVm => self.compile_for_vm(ast, options),
+            #[cfg(feature = "riscv")]
+            CompileTarget::RiscV => self.compile_riscv(ast, options),
+            #[cfg(feature = "wasm")]
+            CompileTarget::Wasm => self.compile_wasm(ast, options),
         }
     }
 
+    /// Resolve names in `ast`, then type-check it.
+    fn check(&self, ast: &Node) -> Result<(), CompileError> {
+        resolve::resolve(ast)?;
+        typecheck::check(ast)?;
+        Ok(())
+    }
+
+    /// Parse `source` and render its AST as JSON without generating code.
+    pub fn dump_ast(&self, source: &str) -> Result<String, CompileError> {
+        Ok(self.parse(source)?.to_json())
+    }
+
+    /// Preprocess, lex and parse `source`, reporting failures with a source snippet.
+    pub fn parse(&self, source: &str) -> Result<Node, CompileError> {
+        let unit = preprocessor::preprocess(source, None)?;
+        self.parse_unit(&unit)
+    }
+
+    /// Tokens of `source` as written, for tools such as highlighters.
+    /// Comments and preprocessor directives are kept, macros aren't expanded
+    /// and positions refer to `source` itself.
+    pub fn lex_only<'a>(&self, source: &'a str) -> Result<Vec<Token<'a>>, LexError> {
+        Lexer::new(source).keeping_comments().scan_tokens()
+    }
+
+    /// AST of `source` without name resolution, type-checking or code
+    /// generation, for tools such as outlines and code folding. Classes are
+    /// left as written rather than lowered to structs and functions, and as
+    /// when compiling, a function that doesn't parse is left out.
+    pub fn parse_only(&self, source: &str) -> Result<Node, CompileError> {
+        self.parse(source)
+    }
+
+    /// Lex and parse preprocessed source. Error positions refer to the file
+    /// and line each token originally came from.
+    fn parse_unit(&self, unit: &Preprocessed) -> Result<Node, CompileError> {
+        let source = unit.source.as_str();
+
+        // Lexical analysis
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.scan_tokens().map_err(|e| {
+            let (line, column) = e.position();
+            let (line, diagnostic) = render_located(unit, line, column, &e.to_string());
+            CompileError::LexError {
+                line,
+                column,
+                diagnostic,
+                source: e,
+            }
+        })?;
+
+        // Syntax analysis
+        let mut parser = Parser::new(tokens);
+        parser.parse().map_err(|e| {
+            let (line, column) = e.position().unwrap_or_else(|| end_of_source(source));
+            let (line, diagnostic) = render_located(unit, line, column, &e.to_string());
+            CompileError::ParseError {
+                line,
+                column,
+                diagnostic,
+                source: e,
+            }
+        })
+    }
+
     fn compile_linux_bpf(
         &self,
-        ast: &crate::pible::parser::Node,
-        _options: &CompileOptions,
-    ) -> Result<Vec<u8>, CompileError> {
-        let mut codegen = CodeGen::new();
-        let instructions = codegen
-            .generate(ast)
-            .map_err(|e| CompileError::CodeGenError(format!("{:?}", e)))?;
+        ast: &Node,
+        pragmas: &Pragmas,
+        options: &CompileOptions,
+        warnings: &mut Vec<Diagnostic>,
+    ) -> Result<(Vec<u8>, Vec<Symbol>), CompileError> {
+        let (codegen, instructions) = self.generate_code(ast, options)?;
 
         // Validate generated bytecode
         if !codegen.validate_instructions(&instructions) {
             return Err(CompileError::CodeGenError(
-                "Generated invalid BPF instructions".to_string(),
+                CodeGenError::InvalidInstruction("Generated invalid BPF instructions".to_string()),
             ));
         }
 
-        Ok(self.instructions_to_bytes(&instructions))
+        let output = match options.output_format {
+            OutputFormat::Raw => self.instructions_to_bytes(&instructions),
+            OutputFormat::Assembly => asm::emit(&instructions)?.into_bytes(),
+            OutputFormat::Elf => {
+                let license = pragmas.license.as_deref().unwrap_or(elf::DEFAULT_LICENSE);
+                if !elf::is_gpl_compatible(license) {
+                    for helper in elf::gpl_only_helpers(&instructions) {
+                        warnings.push(Diagnostic::warning(
+                            0,
+                            0,
+                            format!(
+                                "helper bpf_{} is GPL-only, but the program's license is \"{}\"; add #pragma license(\"GPL\")",
+                                helper, license
+                            ),
+                        ));
+                    }
+                }
+                elf::write_bpf_object(&elf::BpfObject {
+                    instructions: &instructions,
+                    rodata: codegen.rodata(),
+                    rodata_relocations: codegen.rodata_relocations(),
+                    section_name: &pragmas
+                        .section
+                        .clone()
+                        .unwrap_or_else(|| options.program_type.section_name()),
+                    entry_symbol: &self.entry_symbol(ast),
+                    license: Some(license),
+                    btf: &btf::generate_btf(ast)?,
+                    maps: codegen.maps(),
+                    map_relocations: codegen.map_relocations(),
+                    // The kernel binds helpers by their numeric id
+                    call_relocations: &[],
+                })
+            }
+        };
+        Ok((output, symbols(codegen.functions(), 0, 8)))
     }
 
-    fn compile_solana_bpf(
+    /// Generate instructions for `ast`, optimizing them when `opt_level` asks
+    /// for it, and check that every path through them ends in an `exit`.
+    fn generate_code(
         &self,
-        ast: &crate::pible::parser::Node,
-        _options: &CompileOptions,
-    ) -> Result<Vec<u8>, CompileError> {
-        let mut codegen = CodeGen::new();
+        ast: &Node,
+        options: &CompileOptions,
+    ) -> Result<(CodeGen, Vec<BpfInstruction>), CompileError> {
+        let mut codegen = CodeGen::for_target(options.target)
+            .with_program_type(options.program_type.clone())
+            .with_log_helper(options.log_helper_id)
+            .with_bounds_checks(options.safe_arrays)
+            .with_inline_threshold(options.inline_threshold)
+            .with_register_allocation(options.opt_level >= 2);
 
+        let instructions = if options.opt_level == 0 {
+            codegen.generate(ast)?
+        } else {
+            let folded = optimizer::fold_constants(ast);
+            let instructions = codegen.generate(&folded)?;
+            codegen.optimize(&instructions)
+        };
+        cfg::check_exit_paths(&instructions)?;
+        Ok((codegen, instructions))
+    }
+
+    /// Name of the first function in the program, used as the ELF entry symbol.
+    fn entry_symbol(&self, ast: &Node) -> String {
+        ast.children
+            .iter()
+            .filter(|child| child.node_type == NodeType::FunctionDecl)
+            .filter_map(|child| child.value.as_deref())
+            .filter_map(|value| value.split(':').nth(1))
+            .next()
+            .unwrap_or("main")
+            .to_string()
+    }
+
+    fn compile_solana_bpf(
+        &self,
+        ast: &Node,
+        options: &CompileOptions,
+        warnings: &mut Vec<Diagnostic>,
+    ) -> Result<(Vec<u8>, Vec<Symbol>), CompileError> {
         // Generate regular code first
-        let instructions = codegen
-            .generate(ast)
-            .map_err(|e| CompileError::CodeGenError(format!("{:?}", e)))?;
+        let (mut codegen, mut instructions) = self.generate_code(ast, options)?;
 
         // Create solana codegen and validate
-        let solana_codegen = SolanaBpf::new(&mut codegen);
+        let mut solana_codegen = SolanaBpf::new(&mut codegen);
 
-        // Generate Solana-specific entrypoint
-        // solana_codegen.generate_entrypoint("entrypoint")
-        //     .map_err(|e| CompileError::CodeGenError(format!("{:?}", e)))?;
+        // An `entrypoint` function receives the deserialized program input;
+        // otherwise Anchor clients select an `export`ed function to call
+        let handlers = anchor_instructions(ast);
+        if codegen.functions().contains_key("entrypoint") {
+            solana_codegen.generate_entrypoint("entrypoint")?;
+        } else if !handlers.is_empty() {
+            solana_codegen.generate_dispatcher(&handlers)?;
+        }
+        // The entrypoint goes in front of the generated functions
+        let base = solana_codegen.get_instructions().len();
+        if base > 0 {
+            let mut program = solana_codegen.get_instructions().to_vec();
+            program.append(&mut instructions);
+            instructions = program;
+        }
 
         // Validate Solana BPF constraints
         if !solana_codegen.validate_solana_program(&instructions) {
-            return Err(CompileError::CodeGenError(
+            return Err(SolanaError::InvalidProgram(
                 "Generated program violates Solana BPF constraints".to_string(),
+            )
+            .into());
+        }
+        cfg::check_exit_paths(&instructions)?;
+        if options.verify {
+            SolanaBpf::verify(&instructions).map_err(CompileError::VerifyFailed)?;
+        }
+
+        let compute_units = solana_bpf::estimate_compute_units(&instructions);
+        if compute_units > solana_bpf::DEFAULT_COMPUTE_UNIT_LIMIT {
+            warnings.push(Diagnostic::warning(
+                0,
+                0,
+                format!(
+                    "program may use up to {} compute units, over the default limit of {}",
+                    compute_units,
+                    solana_bpf::DEFAULT_COMPUTE_UNIT_LIMIT
+                ),
             ));
         }
 
-        Ok(self.instructions_to_bytes(&instructions))
+        let output = match options.output_format {
+            OutputFormat::Raw => self.instructions_to_bytes(&instructions),
+            OutputFormat::Assembly => asm::emit(&instructions)?.into_bytes(),
+            OutputFormat::Elf => {
+                // Syscalls are bound by symbol when the loader links the object
+                let rodata_relocations: Vec<usize> = codegen
+                    .rodata_relocations()
+                    .iter()
+                    .map(|index| index + base)
+                    .collect();
+                elf::write_bpf_object(&elf::BpfObject {
+                    instructions: &instructions,
+                    rodata: codegen.rodata(),
+                    rodata_relocations: &rodata_relocations,
+                    section_name: ".text",
+                    entry_symbol: "entrypoint",
+                    license: None,
+                    btf: &[],
+                    maps: &[],
+                    map_relocations: &[],
+                    call_relocations: &solana_bpf::syscall_call_sites(&instructions),
+                })
+            }
+        };
+        Ok((output, symbols(codegen.functions(), base, 8)))
     }
 
     fn compile_for_vm(
         &self,
-        ast: &crate::pible::parser::Node,
+        ast: &Node,
         options: &CompileOptions,
-    ) -> Result<Vec<u8>, CompileError> {
-        let mut codegen = CodeGen::new();
-        let instructions = codegen
-            .generate(ast)
-            .map_err(|e| CompileError::CodeGenError(format!("{:?}", e)))?;
+    ) -> Result<(Vec<u8>, Vec<Symbol>), CompileError> {
+        let (codegen, instructions) = self.generate_code(ast, options)?;
 
         // Test execution in VM if enabled
         if options.enable_vm_testing {
-            self.test_in_vm(&instructions)?;
+            self.test_in_vm(&instructions, codegen.rodata())?;
         }
 
-        Ok(self.instructions_to_bytes(&instructions))
+        let symbols = symbols(codegen.functions(), 0, 8);
+        let output = match options.output_format {
+            OutputFormat::Assembly => asm::emit(&instructions)?.into_bytes(),
+            _ => self.instructions_to_bytes(&instructions),
+        };
+        Ok((output, symbols))
     }
 
-    fn test_in_vm(
+    #[cfg(feature = "riscv")]
+    fn compile_riscv(
         &self,
-        instructions: &[crate::pible::codegen::BpfInstruction],
-    ) -> Result<(), CompileError> {
+        ast: &Node,
+        options: &CompileOptions,
+    ) -> Result<(Vec<u8>, Vec<Symbol>), CompileError> {
+        let mut codegen = riscv::RiscVCodeGen::new();
+        let instructions = if options.opt_level == 0 {
+            codegen.generate(ast)?
+        } else {
+            codegen.generate(&optimizer::fold_constants(ast))?
+        };
+        let symbols = symbols(codegen.functions(), 0, 4);
+        Ok((riscv::to_bytes(&instructions), symbols))
+    }
+
+    #[cfg(feature = "wasm")]
+    fn compile_wasm(
+        &self,
+        ast: &Node,
+        options: &CompileOptions,
+    ) -> Result<(Vec<u8>, Vec<Symbol>), CompileError> {
+        let mut codegen = wasm::WasmCodeGen::new();
+        let functions = if options.opt_level == 0 {
+            codegen.generate(ast)?
+        } else {
+            codegen.generate(&optimizer::fold_constants(ast))?
+        };
+        Ok((wasm::to_bytes(&functions), Vec::new()))
+    }
+
+    /// Compile `source` for the VM and execute it, returning the exit code
+    /// and compute units consumed. `options.target` is ignored.
+    #[allow(dead_code)]
+    pub fn compile_and_run(
+        &self,
+        source: &str,
+        options: &CompileOptions,
+    ) -> Result<VmResult, CompileError> {
+        let ast = classes::lower(&self.parse(source)?);
+        self.check(&ast)?;
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            ..options.clone()
+        };
+        let (codegen, instructions) = self.generate_code(&ast, &options)?;
+        self.run_in_vm(&instructions, codegen.rodata())
+    }
+
+    fn run_in_vm(
+        &self,
+        instructions: &[BpfInstruction],
+        rodata: &[u8],
+    ) -> Result<VmResult, CompileError> {
         let mut vm = BpfVm::new(instructions);
-        let result = vm
-            .execute()
+        vm.load_data(0, rodata)
             .map_err(|e| CompileError::VmExecutionError(format!("{:?}", e)))?;
+        vm.execute()
+            .map_err(|e| CompileError::VmExecutionError(format!("{:?}", e)))
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn test_in_vm(
+        &self,
+        instructions: &[BpfInstruction],
+        rodata: &[u8],
+    ) -> Result<(), CompileError> {
+        let result = self.run_in_vm(instructions, rodata)?;
 
+        #[cfg(feature = "std")]
         println!(
             "VM test completed: exit_code={}, compute_units={}",
             result.exit_code, result.compute_units
@@ -208,17 +1068,15 @@ impl Compiler {
         Ok(())
     }
 
-    fn generate_idl_json(
+    pub fn generate_idl_json(
         &self,
         source: &str,
+        ast: &Node,
         _options: &CompileOptions,
     ) -> Result<String, CompileError> {
-        // For now, generate a basic IDL structure
-        // In a full implementation, this would extract from the AST
-        let idl = serde_json::json!({
-            "version": "0.1.0",
-            "name": "holyc_program",
-            "instructions": [
+        let handlers = anchor_instructions(ast);
+        let instructions = if handlers.is_empty() {
+            serde_json::json!([
                 {
                     "name": "entrypoint",
                     "args": [
@@ -229,11 +1087,29 @@ impl Compiler {
                     ],
                     "accounts": []
                 }
-            ],
+            ])
+        } else {
+            handlers
+                .iter()
+                .map(|name| {
+                    serde_json::json!({
+                        "name": name,
+                        "discriminator": solana_bpf::anchor_discriminator(name),
+                        "args": [],
+                        "accounts": []
+                    })
+                })
+                .collect()
+        };
+
+        let idl = serde_json::json!({
+            "version": "0.1.0",
+            "name": "holyc_program",
+            "instructions": instructions,
             "accounts": [],
             "types": [],
             "events": [],
-            "errors": [],
+            "errors": idl_errors(ast),
             "metadata": {
                 "description": "Divine HolyC program compiled to Solana BPF",
                 "source": source.len()
@@ -244,17 +1120,15 @@ impl Compiler {
             .map_err(|e| CompileError::IdlGenerationError(e.to_string()))
     }
 
-    fn instructions_to_bytes(
-        &self,
-        instructions: &[crate::pible::codegen::BpfInstruction],
-    ) -> Vec<u8> {
-        let mut output = Vec::new();
+    fn instructions_to_bytes(&self, instructions: &[BpfInstruction]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(instructions.len() * 8);
         for instruction in instructions {
             output.extend_from_slice(&instruction.as_bytes());
         }
         output
     }
 
+    #[cfg(feature = "std")]
     fn determine_output_path(&self, input_path: &str, options: &CompileOptions) -> String {
         // Use explicit output_path if provided
         if let Some(ref output_path) = options.output_path {
@@ -263,17 +1137,26 @@ impl Compiler {
 
         // Otherwise, determine based on input path and output directory
         let input_path = Path::new(input_path);
-        let file_stem = input_path.file_stem().unwrap().to_str().unwrap();
+        let file_stem = input_path
+            .file_stem()
+            .map_or_else(|| "out".into(), |stem| stem.to_string_lossy());
         let dir = if let Some(output_dir) = options.output_directory {
             Path::new(output_dir)
         } else {
             input_path.parent().unwrap_or(Path::new("."))
         };
 
-        dir.join(format!("{}.bpf", file_stem))
-            .to_str()
-            .unwrap()
-            .to_string()
+        let extension = match (options.target, &options.output_format) {
+            #[cfg(feature = "wasm")]
+            (CompileTarget::Wasm, _) => "wasm",
+            (_, OutputFormat::Raw) => "bpf",
+            (_, OutputFormat::Elf) => "o",
+            (_, OutputFormat::Assembly) => "s",
+        };
+
+        dir.join(format!("{}.{}", file_stem, extension))
+            .to_string_lossy()
+            .into_owned()
     }
 
     #[allow(dead_code)]
@@ -281,3 +1164,104 @@ impl Compiler {
         &self.error_messages
     }
 }
+
+/// Names of the `export`ed functions Anchor clients dispatch to, in source
+/// order; none when the program handles its input in an `entrypoint`.
+fn anchor_instructions(ast: &Node) -> Vec<&str> {
+    let functions: Vec<&Node> = ast
+        .children
+        .iter()
+        .filter(|node| node.node_type == NodeType::FunctionDecl)
+        .collect();
+    if functions
+        .iter()
+        .any(|function| codegen::function_name(function) == Some("entrypoint"))
+    {
+        return Vec::new();
+    }
+    functions
+        .into_iter()
+        .filter(|function| function.exported)
+        .filter_map(codegen::function_name)
+        .collect()
+}
+
+/// The IDL's `errors`: the code and name of each variant of every enum
+/// whose name ends in `Error` or `ErrorCode`, in declaration order.
+fn idl_errors(ast: &Node) -> Vec<serde_json::Value> {
+    ast.children
+        .iter()
+        .filter(|node| node.node_type == NodeType::EnumDecl)
+        .filter(|node| {
+            let name = node.value.as_deref().unwrap_or("");
+            name.ends_with("Error") || name.ends_with("ErrorCode")
+        })
+        .flat_map(|node| &node.children)
+        .filter_map(|variant| {
+            let name = variant.value.as_deref()?.split_once(':')?.1;
+            let code: i64 = variant.children.first()?.value.as_deref()?.parse().ok()?;
+            Some(serde_json::json!({ "code": code, "name": name }))
+        })
+        .collect()
+}
+
+/// Render a rustc-style diagnostic: the message, the offending source line,
+/// and a caret under the 1-based `column`.
+#[allow(dead_code)]
+pub fn render_diagnostic(source: &str, line: usize, column: usize, message: &str) -> String {
+    let text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    render_snippet(None, text, line, column, message)
+}
+
+fn render_snippet(
+    file: Option<&str>,
+    text: &str,
+    line: usize,
+    column: usize,
+    message: &str,
+) -> String {
+    let gutter = " ".repeat(line.to_string().len());
+    let location = match file {
+        Some(file) => format!("{file}, line {line}, column {column}"),
+        None => format!("line {line}, column {column}"),
+    };
+
+    // Keep tabs so the caret lines up with the source as displayed
+    let padding: String = text
+        .chars()
+        .take(column.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    format!(
+        "error: {message}\n{gutter}--> {location}\n{gutter} |\n{line} | {text}\n{gutter} | {padding}^"
+    )
+}
+
+/// Render a diagnostic for a position in preprocessed source, naming the
+/// file it came from. Returns the line within that file and the rendering.
+fn render_located(
+    unit: &Preprocessed,
+    line: usize,
+    column: usize,
+    message: &str,
+) -> (usize, String) {
+    let origin = unit.origin(line);
+    let text = unit
+        .source
+        .lines()
+        .nth(line.saturating_sub(1))
+        .unwrap_or("");
+    let file = origin.file.clone();
+    (
+        origin.line,
+        render_snippet(file.as_deref(), text, origin.line, column, message),
+    )
+}
+
+/// Line and column just past the last character of `source`.
+fn end_of_source(source: &str) -> (usize, usize) {
+    let line = source.lines().count().max(1);
+    let column = source.lines().last().map_or(0, |text| text.chars().count()) + 1;
+    (line, column)
+}
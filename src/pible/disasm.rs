@@ -0,0 +1,170 @@
+use crate::pible::codegen::BpfInstruction;
+use crate::pible::prelude::*;
+use alloc::collections::BTreeSet;
+use core::fmt::Write;
+
+/// Render instructions as eBPF assembly, labelling jump targets and helper calls.
+pub fn disassemble(instructions: &[BpfInstruction]) -> String {
+    disassemble_with_lines(instructions, &[])
+}
+
+/// Like [`disassemble`], with a `; line N` comment wherever the source line
+/// changes. `lines` holds each instruction's line, as from
+/// [`CodeGen::source_lines`](crate::pible::codegen::CodeGen::source_lines).
+pub fn disassemble_with_lines(instructions: &[BpfInstruction], lines: &[Option<usize>]) -> String {
+    let targets = jump_targets(instructions);
+    let mut output = String::new();
+    let mut pc = 0;
+    let mut current_line = None;
+
+    while pc < instructions.len() {
+        if targets.contains(&pc) {
+            let _ = writeln!(output, "{}:", label(pc));
+        }
+        if let Some(line) = lines.get(pc).copied().flatten() {
+            if current_line != Some(line) {
+                let _ = writeln!(output, "    ; line {}", line);
+                current_line = Some(line);
+            }
+        }
+
+        let instruction = &instructions[pc];
+        let (text, slots) = if is_lddw(instruction) {
+            let high = instructions.get(pc + 1).map_or(0, |next| next.immediate);
+            let value = ((high as u32 as u64) << 32) | instruction.immediate as u32 as u64;
+            (format!("lddw r{}, 0x{:x}", instruction.dst_reg, value), 2)
+        } else {
+            (format_instruction(instruction), 1)
+        };
+
+        let annotation = annotate(pc, instruction);
+        if annotation.is_empty() {
+            let _ = writeln!(output, "    {}", text);
+        } else {
+            let _ = writeln!(output, "    {} ; {}", text, annotation);
+        }
+        pc += slots;
+    }
+
+    output
+}
+
+/// Disassemble raw bytecode; trailing bytes that don't form a full instruction are ignored.
+pub fn disassemble_bytes(bytecode: &[u8]) -> String {
+    let instructions: Vec<BpfInstruction> = bytecode
+        .chunks_exact(8)
+        .map(|chunk| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(chunk);
+            BpfInstruction::from_bytes(bytes)
+        })
+        .collect();
+    disassemble(&instructions)
+}
+
+/// Well-known helper ids, used to annotate `call` instructions.
+pub fn helper_name(id: i32) -> Option<&'static str> {
+    match id {
+        1 => Some("map_lookup_elem"),
+        2 => Some("map_update_elem"),
+        3 => Some("map_delete_elem"),
+        5 => Some("ktime_get_ns"),
+        6 => Some("trace_printk"),
+        _ => None,
+    }
+}
+
+/// Format a single instruction without labels or annotations.
+pub fn format_instruction(instruction: &BpfInstruction) -> String {
+    let name = instruction.mnemonic();
+    let dst = instruction.dst_reg;
+    let src = instruction.src_reg;
+    let imm = instruction.immediate;
+    let off = instruction.offset;
+    let uses_reg = instruction.opcode & 0x08 != 0;
+
+    if name == "unknown" {
+        return unknown(instruction);
+    }
+    match instruction.class() {
+        0x04 | 0x07 => match instruction.opcode & 0xf0 {
+            0x80 | 0xd0 => format!("{} r{}", name, dst),
+            _ if uses_reg => format!("{} r{}, r{}", name, dst, src),
+            _ => format!("{} r{}, {}", name, dst, imm),
+        },
+        0x05 => match instruction.opcode & 0xf0 {
+            0x00 => format!("{} {}", name, signed(off as i32)),
+            0x80 if src == 1 => format!("{} {}", name, signed(imm)),
+            0x80 => format!("{} {}", name, imm),
+            0x90 => name.to_string(),
+            _ if uses_reg => format!("{} r{}, r{}, {}", name, dst, src, signed(off as i32)),
+            _ => format!("{} r{}, {}, {}", name, dst, imm, signed(off as i32)),
+        },
+        0x01 => format!("{} r{}, {}", name, dst, memory_operand(src, off)),
+        0x02 => format!("{} {}, {}", name, memory_operand(dst, off), imm),
+        0x03 => format!("{} {}, r{}", name, memory_operand(dst, off), src),
+        // A lone `lddw` slot, without the second half holding the high bits
+        _ => format!("{} r{}, 0x{:x}", name, dst, imm as u32),
+    }
+}
+
+fn jump_targets(instructions: &[BpfInstruction]) -> BTreeSet<usize> {
+    let mut targets = BTreeSet::new();
+    for (pc, instruction) in instructions.iter().enumerate() {
+        if let Some(target) = branch_target(pc, instruction) {
+            targets.insert(target);
+        }
+    }
+    targets
+}
+
+/// Absolute target of a jump or local call, if the instruction has one.
+fn branch_target(pc: usize, instruction: &BpfInstruction) -> Option<usize> {
+    if instruction.opcode & 0x07 != 0x05 {
+        return None;
+    }
+    let relative = match instruction.opcode & 0xf0 {
+        0x90 => return None,
+        0x80 if instruction.src_reg == 1 => instruction.immediate as i64,
+        0x80 => return None,
+        _ => instruction.offset as i64,
+    };
+    let target = pc as i64 + 1 + relative;
+    (target >= 0).then_some(target as usize)
+}
+
+fn annotate(pc: usize, instruction: &BpfInstruction) -> String {
+    if let Some(target) = branch_target(pc, instruction) {
+        return label(target);
+    }
+    if instruction.opcode == 0x85 {
+        if let Some(name) = helper_name(instruction.immediate) {
+            return name.to_string();
+        }
+    }
+    String::new()
+}
+
+fn is_lddw(instruction: &BpfInstruction) -> bool {
+    instruction.opcode == 0x18
+}
+
+fn label(pc: usize) -> String {
+    format!("lbl_{}", pc)
+}
+
+fn memory_operand(reg: u8, offset: i16) -> String {
+    format!("[r{}{}]", reg, signed(offset as i32))
+}
+
+fn signed(value: i32) -> String {
+    if value < 0 {
+        format!("{}", value)
+    } else {
+        format!("+{}", value)
+    }
+}
+
+fn unknown(instruction: &BpfInstruction) -> String {
+    format!(".byte 0x{:02x} ; unknown opcode", instruction.opcode)
+}
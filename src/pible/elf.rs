@@ -0,0 +1,712 @@
+use crate::pible::codegen::{BpfInstruction, MapDefinition};
+use crate::pible::prelude::*;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+#[allow(dead_code)]
+pub enum ElfError {
+    #[error("Truncated ELF object: {0}")]
+    Truncated(String),
+    #[error("Invalid ELF object: {0}")]
+    InvalidFormat(String),
+}
+
+// ELF64 constants used by the BPF object format
+#[allow(dead_code)]
+pub mod elf_consts {
+    pub const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+    pub const ELFCLASS64: u8 = 2;
+    pub const ELFDATA2LSB: u8 = 1;
+    pub const EV_CURRENT: u8 = 1;
+    pub const ET_REL: u16 = 1;
+    pub const EM_BPF: u16 = 247;
+
+    pub const SHT_NULL: u32 = 0;
+    pub const SHT_PROGBITS: u32 = 1;
+    pub const SHT_SYMTAB: u32 = 2;
+    pub const SHT_STRTAB: u32 = 3;
+    pub const SHT_REL: u32 = 9;
+
+    pub const SHF_WRITE: u64 = 0x1;
+    pub const SHF_ALLOC: u64 = 0x2;
+    pub const SHF_EXECINSTR: u64 = 0x4;
+
+    pub const STB_LOCAL: u8 = 0;
+    pub const STB_GLOBAL: u8 = 1;
+    pub const STT_NOTYPE: u8 = 0;
+    pub const STT_OBJECT: u8 = 1;
+    pub const STT_FUNC: u8 = 2;
+    pub const STT_SECTION: u8 = 3;
+
+    pub const R_BPF_64_64: u32 = 1;
+    /// A `call` whose immediate the linker binds to a named function or syscall
+    pub const R_BPF_64_32: u32 = 10;
+
+    pub const EHDR_SIZE: usize = 64;
+    pub const SHDR_SIZE: usize = 64;
+    pub const SYM_SIZE: usize = 24;
+    pub const REL_SIZE: usize = 16;
+    /// `struct bpf_map_def`: type, key size, value size, max entries, flags
+    pub const MAP_DEF_SIZE: usize = 20;
+}
+
+use elf_consts::*;
+
+/// A section to be placed in the object, in insertion order after the null section.
+#[derive(Debug, Clone)]
+pub struct ElfSection {
+    pub name: String,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub data: Vec<u8>,
+    pub align: u64,
+}
+
+/// A symbol defined in one of the builder's sections, or left for the linker.
+#[derive(Debug, Clone)]
+pub struct ElfSymbol {
+    pub name: String,
+    /// Index into the builder's section list (not counting the null section),
+    /// or `None` for an undefined symbol
+    pub section: Option<usize>,
+    pub value: u64,
+    pub size: u64,
+    pub info: u8,
+}
+
+/// A relocation applied to an instruction in one of the builder's sections.
+#[derive(Debug, Clone)]
+pub struct ElfRelocation {
+    /// Index of the section being patched
+    pub section: usize,
+    /// Byte offset of the instruction within that section
+    pub offset: u64,
+    /// Index of the symbol as returned by [`ElfBuilder::add_symbol`]
+    pub symbol: usize,
+    pub rel_type: u32,
+}
+
+/// Builds a minimal ELF64 relocatable object for the BPF machine.
+#[derive(Debug, Default)]
+pub struct ElfBuilder {
+    sections: Vec<ElfSection>,
+    symbols: Vec<ElfSymbol>,
+    relocations: Vec<ElfRelocation>,
+}
+
+impl ElfBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a section and return its index for use in symbols.
+    pub fn add_section(&mut self, section: ElfSection) -> usize {
+        self.sections.push(section);
+        self.sections.len() - 1
+    }
+
+    /// Add an executable program section holding `instructions`.
+    pub fn add_program(&mut self, name: &str, instructions: &[BpfInstruction]) -> usize {
+        let mut data = Vec::with_capacity(instructions.len() * 8);
+        for instruction in instructions {
+            data.extend_from_slice(&instruction.as_bytes());
+        }
+        self.add_section(ElfSection {
+            name: name.to_string(),
+            sh_type: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_EXECINSTR,
+            data,
+            align: 8,
+        })
+    }
+
+    /// Add the NUL-terminated `license` section consulted by the kernel.
+    pub fn add_license(&mut self, license: &str) -> usize {
+        let mut data = license.as_bytes().to_vec();
+        data.push(0);
+        self.add_section(ElfSection {
+            name: "license".to_string(),
+            sh_type: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_WRITE,
+            data,
+            align: 1,
+        })
+    }
+
+    /// Add a symbol and return its index for use in relocations.
+    pub fn add_symbol(&mut self, symbol: ElfSymbol) -> usize {
+        self.symbols.push(symbol);
+        self.symbols.len() - 1
+    }
+
+    /// Add a section symbol so relocations can reference the section's base.
+    pub fn add_section_symbol(&mut self, section: usize) -> usize {
+        self.add_symbol(ElfSymbol {
+            name: String::new(),
+            section: Some(section),
+            value: 0,
+            size: 0,
+            info: (STB_LOCAL << 4) | STT_SECTION,
+        })
+    }
+
+    /// Add an undefined global symbol, such as a syscall bound at load time,
+    /// reusing an earlier one with the same name.
+    pub fn add_undefined_symbol(&mut self, name: &str) -> usize {
+        if let Some(index) = self
+            .symbols
+            .iter()
+            .position(|symbol| symbol.section.is_none() && symbol.name == name)
+        {
+            return index;
+        }
+        self.add_symbol(ElfSymbol {
+            name: name.to_string(),
+            section: None,
+            value: 0,
+            size: 0,
+            info: (STB_GLOBAL << 4) | STT_NOTYPE,
+        })
+    }
+
+    pub fn add_relocation(&mut self, relocation: ElfRelocation) {
+        self.relocations.push(relocation);
+    }
+
+    /// Serialize the object: header, section contents, then section headers.
+    pub fn build(&self) -> Vec<u8> {
+        // Sections needing a `.rel<name>` companion, in section order
+        let mut relocated: Vec<usize> = self.relocations.iter().map(|r| r.section).collect();
+        relocated.sort_unstable();
+        relocated.dedup();
+
+        // Section header indices: 0 = null, 1..=n user sections, relocations, then the tables
+        let symtab_index = self.sections.len() + relocated.len() + 1;
+        let strtab_index = symtab_index + 1;
+        let shstrtab_index = strtab_index + 1;
+        let section_count = shstrtab_index + 1;
+
+        // ELF requires local symbols to precede globals in the symbol table
+        let mut order: Vec<usize> = (0..self.symbols.len()).collect();
+        order.sort_by_key(|&index| self.symbols[index].info >> 4 != STB_LOCAL);
+        let mut symbol_index = vec![0usize; self.symbols.len()];
+        for (position, &index) in order.iter().enumerate() {
+            symbol_index[index] = position + 1;
+        }
+        let first_global = order
+            .iter()
+            .position(|&index| self.symbols[index].info >> 4 != STB_LOCAL)
+            .unwrap_or(order.len())
+            + 1;
+
+        let mut strtab = vec![0u8];
+        let mut symtab = vec![0u8; SYM_SIZE]; // Null symbol
+        for &index in &order {
+            let symbol = &self.symbols[index];
+            let name_offset = if symbol.name.is_empty() {
+                0
+            } else {
+                append_string(&mut strtab, &symbol.name)
+            };
+            symtab.extend_from_slice(&(name_offset as u32).to_le_bytes());
+            symtab.push(symbol.info);
+            symtab.push(0); // st_other
+            let shndx = symbol.section.map_or(0, |section| section + 1) as u16;
+            symtab.extend_from_slice(&shndx.to_le_bytes());
+            symtab.extend_from_slice(&symbol.value.to_le_bytes());
+            symtab.extend_from_slice(&symbol.size.to_le_bytes());
+        }
+
+        let mut shstrtab = vec![0u8];
+        let mut name_offsets = Vec::with_capacity(self.sections.len());
+        for section in &self.sections {
+            name_offsets.push(append_string(&mut shstrtab, &section.name));
+        }
+        let rel_name_offsets: Vec<usize> = relocated
+            .iter()
+            .map(|&section| {
+                append_string(
+                    &mut shstrtab,
+                    &format!(".rel{}", self.sections[section].name),
+                )
+            })
+            .collect();
+        let symtab_name = append_string(&mut shstrtab, ".symtab");
+        let strtab_name = append_string(&mut shstrtab, ".strtab");
+        let shstrtab_name = append_string(&mut shstrtab, ".shstrtab");
+
+        let mut output = vec![0u8; EHDR_SIZE];
+        let mut headers = vec![0u8; SHDR_SIZE]; // Null section header
+
+        for (section, name_offset) in self.sections.iter().zip(&name_offsets) {
+            let offset = append_aligned(&mut output, &section.data, section.align);
+            write_section_header(
+                &mut headers,
+                SectionHeader {
+                    name: *name_offset,
+                    sh_type: section.sh_type,
+                    flags: section.flags,
+                    offset,
+                    size: section.data.len() as u64,
+                    link: 0,
+                    info: 0,
+                    align: section.align,
+                    entsize: 0,
+                },
+            );
+        }
+
+        for (&section, name_offset) in relocated.iter().zip(&rel_name_offsets) {
+            let mut entries = Vec::new();
+            for relocation in self.relocations.iter().filter(|r| r.section == section) {
+                let info =
+                    ((symbol_index[relocation.symbol] as u64) << 32) | relocation.rel_type as u64;
+                entries.extend_from_slice(&relocation.offset.to_le_bytes());
+                entries.extend_from_slice(&info.to_le_bytes());
+            }
+            let offset = append_aligned(&mut output, &entries, 8);
+            write_section_header(
+                &mut headers,
+                SectionHeader {
+                    name: *name_offset,
+                    sh_type: SHT_REL,
+                    flags: 0,
+                    offset,
+                    size: entries.len() as u64,
+                    link: symtab_index as u32,
+                    info: (section + 1) as u32,
+                    align: 8,
+                    entsize: REL_SIZE as u64,
+                },
+            );
+        }
+
+        let offset = append_aligned(&mut output, &symtab, 8);
+        write_section_header(
+            &mut headers,
+            SectionHeader {
+                name: symtab_name,
+                sh_type: SHT_SYMTAB,
+                flags: 0,
+                offset,
+                size: symtab.len() as u64,
+                link: strtab_index as u32,
+                info: first_global as u32,
+                align: 8,
+                entsize: SYM_SIZE as u64,
+            },
+        );
+
+        for (name, table) in [(strtab_name, &strtab), (shstrtab_name, &shstrtab)] {
+            let offset = append_aligned(&mut output, table, 1);
+            write_section_header(
+                &mut headers,
+                SectionHeader {
+                    name,
+                    sh_type: SHT_STRTAB,
+                    flags: 0,
+                    offset,
+                    size: table.len() as u64,
+                    link: 0,
+                    info: 0,
+                    align: 1,
+                    entsize: 0,
+                },
+            );
+        }
+
+        let shoff = append_aligned(&mut output, &headers, 8);
+        let header = elf_header(shoff, section_count as u16, shstrtab_index as u16);
+        output[..EHDR_SIZE].copy_from_slice(&header);
+        output
+    }
+}
+
+struct SectionHeader {
+    name: usize,
+    sh_type: u32,
+    flags: u64,
+    offset: u64,
+    size: u64,
+    link: u32,
+    info: u32,
+    align: u64,
+    entsize: u64,
+}
+
+fn append_string(table: &mut Vec<u8>, name: &str) -> usize {
+    let offset = table.len();
+    table.extend_from_slice(name.as_bytes());
+    table.push(0);
+    offset
+}
+
+fn append_aligned(output: &mut Vec<u8>, data: &[u8], align: u64) -> u64 {
+    let align = align.max(1) as usize;
+    while !output.len().is_multiple_of(align) {
+        output.push(0);
+    }
+    let offset = output.len() as u64;
+    output.extend_from_slice(data);
+    offset
+}
+
+fn write_section_header(headers: &mut Vec<u8>, header: SectionHeader) {
+    headers.extend_from_slice(&(header.name as u32).to_le_bytes());
+    headers.extend_from_slice(&header.sh_type.to_le_bytes());
+    headers.extend_from_slice(&header.flags.to_le_bytes());
+    headers.extend_from_slice(&0u64.to_le_bytes()); // sh_addr
+    headers.extend_from_slice(&header.offset.to_le_bytes());
+    headers.extend_from_slice(&header.size.to_le_bytes());
+    headers.extend_from_slice(&header.link.to_le_bytes());
+    headers.extend_from_slice(&header.info.to_le_bytes());
+    headers.extend_from_slice(&header.align.to_le_bytes());
+    headers.extend_from_slice(&header.entsize.to_le_bytes());
+}
+
+fn elf_header(shoff: u64, shnum: u16, shstrndx: u16) -> [u8; EHDR_SIZE] {
+    let mut header = [0u8; EHDR_SIZE];
+    header[0..4].copy_from_slice(&ELF_MAGIC);
+    header[4] = ELFCLASS64;
+    header[5] = ELFDATA2LSB;
+    header[6] = EV_CURRENT;
+    header[16..18].copy_from_slice(&ET_REL.to_le_bytes());
+    header[18..20].copy_from_slice(&EM_BPF.to_le_bytes());
+    header[20..24].copy_from_slice(&(EV_CURRENT as u32).to_le_bytes());
+    // e_entry and e_phoff stay zero for relocatable objects
+    header[40..48].copy_from_slice(&shoff.to_le_bytes());
+    header[52..54].copy_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+    header[58..60].copy_from_slice(&(SHDR_SIZE as u16).to_le_bytes());
+    header[60..62].copy_from_slice(&shnum.to_le_bytes());
+    header[62..64].copy_from_slice(&shstrndx.to_le_bytes());
+    header
+}
+
+/// License recorded when the source has no `#pragma license`. It isn't
+/// GPL-compatible, so the kernel refuses GPL-only helpers.
+pub const DEFAULT_LICENSE: &str = "Proprietary";
+
+/// Whether the kernel treats `license` as GPL-compatible and grants the
+/// program GPL-only helpers.
+pub fn is_gpl_compatible(license: &str) -> bool {
+    matches!(
+        license,
+        "GPL"
+            | "GPL v2"
+            | "GPL and additional rights"
+            | "Dual BSD/GPL"
+            | "Dual MIT/GPL"
+            | "Dual MPL/GPL"
+    )
+}
+
+/// Names of the GPL-only helpers `instructions` call, once each.
+pub fn gpl_only_helpers(instructions: &[BpfInstruction]) -> Vec<&'static str> {
+    let mut helpers = Vec::new();
+    for instruction in instructions {
+        if instruction.opcode != 0x85 || instruction.src_reg != 0 {
+            continue;
+        }
+        let name = match instruction.immediate {
+            4 => "probe_read",
+            6 => "trace_printk",
+            25 => "perf_event_output",
+            35 => "get_current_task",
+            36 => "probe_write_user",
+            112 => "probe_read_user",
+            113 => "probe_read_kernel",
+            114 => "probe_read_user_str",
+            115 => "probe_read_kernel_str",
+            _ => continue,
+        };
+        if !helpers.contains(&name) {
+            helpers.push(name);
+        }
+    }
+    helpers
+}
+
+/// Everything needed to emit a Linux BPF object file.
+#[derive(Debug, Clone)]
+pub struct BpfObject<'a> {
+    pub instructions: &'a [BpfInstruction],
+    /// Read-only data referenced by `lddw` instructions
+    pub rodata: &'a [u8],
+    /// Instruction indices of `lddw` loads whose immediate is a `.rodata` offset
+    pub rodata_relocations: &'a [usize],
+    pub section_name: &'a str,
+    pub entry_symbol: &'a str,
+    /// Contents of the `license` section; omitted when `None`
+    pub license: Option<&'a str>,
+    /// Type information for the `.BTF` section; omitted when empty
+    pub btf: &'a [u8],
+    /// Maps for the `maps` section; omitted when empty
+    pub maps: &'a [MapDefinition],
+    /// Instruction indices of `lddw` loads of a map, with the map's name
+    pub map_relocations: &'a [(usize, String)],
+    /// Instruction indices of `call`s bound by name, with the callee's symbol
+    pub call_relocations: &'a [(usize, String)],
+}
+
+/// Wrap a BPF program in a relocatable object with its license and entry symbol.
+pub fn write_bpf_object(object: &BpfObject) -> Vec<u8> {
+    let mut builder = ElfBuilder::new();
+    let program = builder.add_program(object.section_name, object.instructions);
+    if let Some(license) = object.license {
+        builder.add_license(license);
+    }
+    builder.add_symbol(ElfSymbol {
+        name: object.entry_symbol.to_string(),
+        section: Some(program),
+        value: 0,
+        size: (object.instructions.len() * 8) as u64,
+        info: (STB_GLOBAL << 4) | STT_FUNC,
+    });
+
+    if !object.rodata.is_empty() {
+        let rodata = builder.add_section(ElfSection {
+            name: ".rodata".to_string(),
+            sh_type: SHT_PROGBITS,
+            flags: SHF_ALLOC,
+            data: object.rodata.to_vec(),
+            align: 1,
+        });
+        let rodata_symbol = builder.add_section_symbol(rodata);
+        for &index in object.rodata_relocations {
+            builder.add_relocation(ElfRelocation {
+                section: program,
+                offset: (index * 8) as u64,
+                symbol: rodata_symbol,
+                rel_type: R_BPF_64_64,
+            });
+        }
+    }
+
+    if !object.maps.is_empty() {
+        let mut data = Vec::with_capacity(object.maps.len() * MAP_DEF_SIZE);
+        for map in object.maps {
+            for field in [
+                map.map_type,
+                map.key_size,
+                map.value_size,
+                map.max_entries,
+                0,
+            ] {
+                data.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        let section = builder.add_section(ElfSection {
+            name: "maps".to_string(),
+            sh_type: SHT_PROGBITS,
+            flags: SHF_ALLOC | SHF_WRITE,
+            data,
+            align: 4,
+        });
+
+        // Loaders find each map's definition through its symbol
+        let symbols: Vec<usize> = object
+            .maps
+            .iter()
+            .enumerate()
+            .map(|(index, map)| {
+                builder.add_symbol(ElfSymbol {
+                    name: map.name.clone(),
+                    section: Some(section),
+                    value: (index * MAP_DEF_SIZE) as u64,
+                    size: MAP_DEF_SIZE as u64,
+                    info: (STB_GLOBAL << 4) | STT_OBJECT,
+                })
+            })
+            .collect();
+        for (index, name) in object.map_relocations {
+            if let Some(map) = object.maps.iter().position(|map| map.name == *name) {
+                builder.add_relocation(ElfRelocation {
+                    section: program,
+                    offset: (index * 8) as u64,
+                    symbol: symbols[map],
+                    rel_type: R_BPF_64_64,
+                });
+            }
+        }
+    }
+
+    for (index, name) in object.call_relocations {
+        let symbol = builder.add_undefined_symbol(name);
+        builder.add_relocation(ElfRelocation {
+            section: program,
+            offset: (index * 8) as u64,
+            symbol,
+            rel_type: R_BPF_64_32,
+        });
+    }
+
+    if !object.btf.is_empty() {
+        builder.add_section(ElfSection {
+            name: ".BTF".to_string(),
+            sh_type: SHT_PROGBITS,
+            flags: 0,
+            data: object.btf.to_vec(),
+            align: 4,
+        });
+    }
+
+    builder.build()
+}
+
+/// The fields of an ELF64 header needed to locate sections.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfHeader {
+    pub elf_type: u16,
+    pub machine: u16,
+    pub section_header_offset: u64,
+    pub section_count: u16,
+    pub section_names_index: u16,
+}
+
+#[allow(dead_code)]
+impl ElfHeader {
+    pub fn parse(bytes: &[u8]) -> Result<Self, ElfError> {
+        if bytes.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated("header".to_string()));
+        }
+        if bytes[0..4] != ELF_MAGIC {
+            return Err(ElfError::InvalidFormat("bad magic".to_string()));
+        }
+        if bytes[4] != ELFCLASS64 || bytes[5] != ELFDATA2LSB {
+            return Err(ElfError::InvalidFormat(
+                "expected 64-bit little-endian object".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            elf_type: read_u16(bytes, 16),
+            machine: read_u16(bytes, 18),
+            section_header_offset: read_u64(bytes, 40),
+            section_count: read_u16(bytes, 60),
+            section_names_index: read_u16(bytes, 62),
+        })
+    }
+}
+
+/// Section name, type and contents as read back from an object.
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct ParsedSection<'a> {
+    pub name: String,
+    pub sh_type: u32,
+    pub data: &'a [u8],
+}
+
+/// Read back every section of an ELF64 object, including the null section.
+#[allow(dead_code)]
+pub fn parse_sections(bytes: &[u8]) -> Result<Vec<ParsedSection<'_>>, ElfError> {
+    let header = ElfHeader::parse(bytes)?;
+    let table_start = header.section_header_offset as usize;
+    let table_end = table_start + header.section_count as usize * SHDR_SIZE;
+    if table_end > bytes.len() {
+        return Err(ElfError::Truncated("section headers".to_string()));
+    }
+
+    let mut raw = Vec::with_capacity(header.section_count as usize);
+    for index in 0..header.section_count as usize {
+        let base = table_start + index * SHDR_SIZE;
+        let offset = read_u64(bytes, base + 24) as usize;
+        let size = read_u64(bytes, base + 32) as usize;
+        if offset + size > bytes.len() {
+            return Err(ElfError::Truncated(format!("section {}", index)));
+        }
+        raw.push((
+            read_u32(bytes, base),
+            read_u32(bytes, base + 4),
+            &bytes[offset..offset + size],
+        ));
+    }
+
+    let names = raw
+        .get(header.section_names_index as usize)
+        .map(|(_, _, data)| *data)
+        .ok_or_else(|| ElfError::InvalidFormat("missing section name table".to_string()))?;
+
+    Ok(raw
+        .into_iter()
+        .map(|(name_offset, sh_type, data)| ParsedSection {
+            name: read_c_string(names, name_offset as usize),
+            sh_type,
+            data,
+        })
+        .collect())
+}
+
+/// A relocation as read back from an object, with its symbol's name.
+#[allow(dead_code)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedRelocation {
+    pub offset: u64,
+    pub symbol: String,
+    pub rel_type: u32,
+}
+
+/// Read back the relocations applied to the section called `name`.
+#[allow(dead_code)]
+pub fn parse_relocations(bytes: &[u8], name: &str) -> Result<Vec<ParsedRelocation>, ElfError> {
+    let entries = find_section(bytes, &format!(".rel{}", name))?.unwrap_or(&[]);
+    let symtab = find_section(bytes, ".symtab")?
+        .ok_or_else(|| ElfError::InvalidFormat("missing symbol table".to_string()))?;
+    let strtab = find_section(bytes, ".strtab")?
+        .ok_or_else(|| ElfError::InvalidFormat("missing string table".to_string()))?;
+
+    entries
+        .chunks_exact(REL_SIZE)
+        .map(|entry| {
+            let info = read_u64(entry, 8);
+            let symbol = (info >> 32) as usize * SYM_SIZE;
+            if symbol + SYM_SIZE > symtab.len() {
+                return Err(ElfError::InvalidFormat(format!(
+                    "relocation symbol {} out of range",
+                    info >> 32
+                )));
+            }
+            Ok(ParsedRelocation {
+                offset: read_u64(entry, 0),
+                symbol: read_c_string(strtab, read_u32(symtab, symbol) as usize),
+                rel_type: info as u32,
+            })
+        })
+        .collect()
+}
+
+/// Find a section's contents by name.
+#[allow(dead_code)]
+pub fn find_section<'a>(bytes: &'a [u8], name: &str) -> Result<Option<&'a [u8]>, ElfError> {
+    Ok(parse_sections(bytes)?
+        .into_iter()
+        .find(|section| section.name == name)
+        .map(|section| section.data))
+}
+
+#[allow(dead_code)]
+fn read_c_string(table: &[u8], offset: usize) -> String {
+    let tail = table.get(offset..).unwrap_or(&[]);
+    let end = tail.iter().position(|&b| b == 0).unwrap_or(tail.len());
+    String::from_utf8_lossy(&tail[..end]).into_owned()
+}
+
+#[allow(dead_code)]
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[allow(dead_code)]
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut buf = [0u8; 4];
+    buf.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(buf)
+}
+
+#[allow(dead_code)]
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    let mut buf = [0u8; 8];
+    buf.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(buf)
+}
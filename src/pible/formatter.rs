@@ -0,0 +1,294 @@
+use crate::pible::lexer::{LexError, Lexer, Token, TokenType};
+use crate::pible::prelude::*;
+
+const INDENT: &str = "    ";
+
+/// Re-emit HolyC source in canonical layout: four-space indentation, opening
+/// braces on the line of their statement, one statement per line and single
+/// spaces around binary operators.
+///
+/// Comments and preprocessor directives are kept, as is a single blank line
+/// wherever the source had one or more. Formatting formatted source is a no-op.
+pub fn format_source(source: &str) -> Result<String, LexError> {
+    let tokens = Lexer::new(source).keeping_comments().scan_tokens()?;
+    let mut formatter = Formatter::default();
+    for i in 0..tokens.len() {
+        formatter.token(&tokens, i);
+    }
+    if !formatter.output.is_empty() {
+        formatter.output.push('\n');
+    }
+    Ok(formatter.output)
+}
+
+#[derive(Default)]
+struct Formatter {
+    output: String,
+    depth: usize,
+    /// Open parentheses; a `;` inside `for (...)` doesn't end the line
+    parens: usize,
+    /// The next token starts a new line
+    line_done: bool,
+    /// The previous token was a prefix operator
+    attach_next: bool,
+    /// Inside an `enum`'s braces, where each variant takes a line
+    enum_body: bool,
+}
+
+impl Formatter {
+    fn token(&mut self, tokens: &[Token], i: usize) {
+        let token = &tokens[i];
+        let kind = token.token_type;
+        if kind == TokenType::Eof {
+            return;
+        }
+        let prev = i.checked_sub(1).map(|p| &tokens[p]);
+        let next = tokens.get(i + 1).map(|next| next.token_type);
+
+        if kind == TokenType::RightBrace {
+            self.depth = self.depth.saturating_sub(1);
+            self.enum_body = false;
+        }
+
+        let starts_line = match (kind, prev) {
+            (_, None) => true,
+            // Trailing comments stay on the line they annotate
+            (TokenType::Comment, Some(prev)) => prev.line < token.line,
+            (TokenType::RightBrace | TokenType::Directive, _) => true,
+            _ => self.line_done,
+        };
+
+        if starts_line {
+            if let Some(prev) = prev {
+                self.output.push('\n');
+                let prev_end = prev.line + prev.lexeme.matches('\n').count();
+                let blank = token.line > prev_end + 1
+                    && prev.token_type != TokenType::LeftBrace
+                    && kind != TokenType::RightBrace;
+                if blank {
+                    self.output.push('\n');
+                }
+            }
+            if kind != TokenType::Directive {
+                self.output.push_str(&INDENT.repeat(self.depth));
+            }
+        } else if prev.is_some_and(|prev| self.needs_space(prev, token, tokens, i)) {
+            self.output.push(' ');
+        }
+
+        match kind {
+            TokenType::Comment | TokenType::Directive => {
+                self.output.push_str(token.lexeme.trim_end())
+            }
+            _ => self.output.push_str(token.lexeme),
+        }
+
+        self.attach_next = match kind {
+            TokenType::Bang => true,
+            // After a cast or another prefix operator, `-x` is a negation
+            TokenType::Minus | TokenType::Plus => is_prefix(prev) || self.attach_next,
+            TokenType::PlusPlus | TokenType::MinusMinus => is_prefix(prev),
+            TokenType::RightParen => is_cast(tokens, i),
+            TokenType::Star => is_prefix(prev) && !is_pointer_star(tokens, i),
+            TokenType::Ampersand => is_prefix(prev),
+            _ => false,
+        };
+
+        self.line_done = match kind {
+            TokenType::LeftBrace => {
+                self.depth += 1;
+                self.enum_body = i >= 2 && tokens[i - 2].token_type == TokenType::Enum;
+                true
+            }
+            TokenType::Comma => self.enum_body,
+            TokenType::RightBrace => !matches!(
+                next,
+                Some(TokenType::Else | TokenType::Semicolon | TokenType::Comma)
+            ),
+            TokenType::Semicolon => self.parens == 0,
+            TokenType::Directive => true,
+            // A block comment is followed by whatever followed it in the source
+            TokenType::Comment => {
+                let end = token.line + token.lexeme.matches('\n').count();
+                token.lexeme.starts_with("//")
+                    || tokens.get(i + 1).is_some_and(|next| next.line > end)
+            }
+            TokenType::LeftParen => {
+                self.parens += 1;
+                false
+            }
+            TokenType::RightParen => {
+                self.parens = self.parens.saturating_sub(1);
+                false
+            }
+            _ => false,
+        };
+    }
+
+    /// Whether a space separates `prev` from `token` on the same line.
+    fn needs_space(&self, prev: &Token, token: &Token, tokens: &[Token], i: usize) -> bool {
+        let prev_kind = prev.token_type;
+        match token.token_type {
+            TokenType::RightParen
+            | TokenType::RightBracket
+            | TokenType::LeftBracket
+            | TokenType::Semicolon
+            | TokenType::Comma
+            | TokenType::Dot
+            | TokenType::Arrow => return false,
+            TokenType::Star if is_pointer_star(tokens, i) => {
+                // `U8*` and `U8**` keep the stars on the type
+                return false;
+            }
+            TokenType::Less | TokenType::Greater if is_map_bracket(tokens, i) => return false,
+            // Postfix `x++` and `x--`
+            TokenType::PlusPlus | TokenType::MinusMinus if !is_prefix(Some(prev)) => return false,
+            TokenType::LeftParen
+                if matches!(
+                    prev_kind,
+                    TokenType::Identifier
+                        | TokenType::PrintF
+                        | TokenType::Sizeof
+                        | TokenType::RightParen
+                        | TokenType::RightBracket
+                ) =>
+            {
+                return false;
+            }
+            _ => {}
+        }
+
+        if prev_kind == TokenType::Less && is_map_bracket(tokens, i - 1) {
+            return false;
+        }
+        !self.attach_next
+            && !matches!(
+                prev_kind,
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::Dot | TokenType::Arrow
+            )
+    }
+}
+
+/// Whether an operator following `prev` is a prefix operator rather than a
+/// binary one.
+fn is_prefix(prev: Option<&Token>) -> bool {
+    !prev.is_some_and(|prev| {
+        matches!(
+            prev.token_type,
+            TokenType::Identifier
+                | TokenType::NumberLiteral
+                | TokenType::FloatLiteral
+                | TokenType::StringLiteral
+                | TokenType::True
+                | TokenType::False
+                | TokenType::RightParen
+                | TokenType::RightBracket
+        )
+    })
+}
+
+/// Whether the `*` at `i` belongs to a pointer type, as in `U8* p` or
+/// `struct Account* acc`, rather than being a multiplication.
+fn is_pointer_star(tokens: &[Token], i: usize) -> bool {
+    if tokens[i].token_type != TokenType::Star {
+        return false;
+    }
+    let Some(prev) = i.checked_sub(1).map(|p| &tokens[p]) else {
+        return false;
+    };
+    if is_type_keyword(prev.token_type) || is_pointer_star(tokens, i - 1) {
+        return true;
+    }
+    if prev.token_type != TokenType::Identifier {
+        return false;
+    }
+
+    // A named type starts a declaration: `Account* acc` at the start of a
+    // statement or parameter
+    let before = i.checked_sub(2).map(|p| tokens[p].token_type);
+    let declaration_start = matches!(
+        before,
+        None | Some(
+            TokenType::Struct
+                | TokenType::LeftBrace
+                | TokenType::RightBrace
+                | TokenType::Semicolon
+                | TokenType::LeftParen
+                | TokenType::Comma
+                | TokenType::Comment
+                | TokenType::Directive
+        )
+    );
+    // `(Account*)` can only be a type, as in a cast
+    let names_declared = matches!(
+        tokens.get(i + 1).map(|next| next.token_type),
+        Some(TokenType::Identifier | TokenType::Star | TokenType::RightParen)
+    );
+    declaration_start && names_declared
+}
+
+/// Whether the `)` at `i` closes the type of a cast such as `(U64*)x`. A
+/// bare name without stars could be a typedef or a variable, so it isn't
+/// treated as a cast.
+fn is_cast(tokens: &[Token], i: usize) -> bool {
+    let mut j = i;
+    while j > 0 && tokens[j - 1].token_type == TokenType::Star {
+        j -= 1;
+    }
+    let stars = i - j;
+    let Some(name) = j.checked_sub(1) else {
+        return false;
+    };
+    let open = match tokens[name].token_type {
+        kind if is_type_keyword(kind) => name.checked_sub(1),
+        TokenType::Identifier if name >= 1 && tokens[name - 1].token_type == TokenType::Struct => {
+            name.checked_sub(2)
+        }
+        TokenType::Identifier if stars > 0 => name.checked_sub(1),
+        _ => None,
+    };
+    open.is_some_and(|open| {
+        tokens[open].token_type == TokenType::LeftParen
+            && !open
+                .checked_sub(1)
+                .is_some_and(|before| tokens[before].token_type == TokenType::Sizeof)
+    })
+}
+
+/// Whether the `<` or `>` at `i` delimits the types of a `map` declaration,
+/// as in `map hash<U32, U64> counts[64];`.
+fn is_map_bracket(tokens: &[Token], i: usize) -> bool {
+    match tokens[i].token_type {
+        TokenType::Less => i >= 2 && tokens[i - 2].token_type == TokenType::Map,
+        TokenType::Greater => tokens[..i]
+            .iter()
+            .rposition(|token| {
+                matches!(
+                    token.token_type,
+                    TokenType::Less
+                        | TokenType::Semicolon
+                        | TokenType::LeftBrace
+                        | TokenType::RightBrace
+                )
+            })
+            .is_some_and(|open| is_map_bracket(tokens, open)),
+        _ => false,
+    }
+}
+
+fn is_type_keyword(kind: TokenType) -> bool {
+    matches!(
+        kind,
+        TokenType::U0
+            | TokenType::U8
+            | TokenType::U16
+            | TokenType::U32
+            | TokenType::U64
+            | TokenType::I8
+            | TokenType::I16
+            | TokenType::I32
+            | TokenType::I64
+            | TokenType::F64
+            | TokenType::Bool
+    )
+}
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use crate::pible::prelude::*;
 use thiserror::Error;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -21,12 +21,18 @@ pub enum TokenType {
     While,
     For,
     Return,
+    Struct,
     Break,
     Continue,
     Class,
     Public,
     Private,
     Export,
+    Map,
+    Sizeof,
+    Const,
+    Enum,
+    Typedef,
 
     // Built-in functions
     PrintF,
@@ -41,6 +47,9 @@ pub enum TokenType {
     Semicolon,
     Comma,
     Dot,
+    /// `...`, closing a variadic parameter list
+    Ellipsis,
+    Arrow,
     Plus,
     Minus,
     Star,
@@ -54,16 +63,42 @@ pub enum TokenType {
     LessEqual,
     Greater,
     GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    /// `&`, which is also address-of, `|` and `^`
+    Ampersand,
+    Pipe,
+    Caret,
+    PlusPlus,
+    MinusMinus,
+    /// `+=`, `-=`, `*=`, `/=`, `%=`, `<<=`, `>>=`, `&=`, `|=` and `^=`
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    PercentEqual,
+    ShiftLeftEqual,
+    ShiftRightEqual,
+    AmpersandEqual,
+    PipeEqual,
+    CaretEqual,
     And,
     Or,
+    Question,
+    Colon,
 
     // Literals
     Identifier,
     StringLiteral,
     NumberLiteral,
+    FloatLiteral,
     True,
     False,
 
+    // Only produced when the lexer keeps comments, for the formatter
+    Comment,
+    Directive,
+
     // Special
     Eof,
     Invalid,
@@ -76,24 +111,45 @@ pub struct Token<'a> {
     pub lexeme: &'a str,
     pub line: usize,
     pub column: usize,
+    /// Byte offset of the first character of the lexeme in the source
+    pub start: usize,
+    /// Byte offset just past the lexeme, so `&source[start..end] == lexeme`
+    pub end: usize,
 }
 
 #[derive(Error, Debug)]
 pub enum LexError {
-    #[error("Unterminated string at line {0}")]
-    UnterminatedString(usize),
+    #[error("Unterminated string at line {0}, column {1}")]
+    UnterminatedString(usize, usize),
     #[error("Invalid character '{0}' at line {1}, column {2}")]
     InvalidCharacter(char, usize, usize),
 }
 
+impl LexError {
+    /// Line and column the error points at.
+    pub fn position(&self) -> (usize, usize) {
+        match *self {
+            LexError::UnterminatedString(line, column) => (line, column),
+            LexError::InvalidCharacter(_, line, column) => (line, column),
+        }
+    }
+}
+
 pub struct Lexer<'a> {
     source: &'a str,
-    chars: std::str::Chars<'a>,
+    chars: core::str::Chars<'a>,
     current: usize,
     line: usize,
     column: usize,
     start: usize,
+    start_line: usize,
+    start_column: usize,
     keywords: HashMap<&'static str, TokenType>,
+    keep_comments: bool,
+    /// Token scanned but not yet handed out by the iterator
+    pending: Vec<Token<'a>>,
+    /// `Eof` or an error has been returned
+    finished: bool,
 }
 
 impl<'a> Lexer<'a> {
@@ -115,12 +171,18 @@ impl<'a> Lexer<'a> {
         keywords.insert("while", TokenType::While);
         keywords.insert("for", TokenType::For);
         keywords.insert("return", TokenType::Return);
+        keywords.insert("struct", TokenType::Struct);
         keywords.insert("break", TokenType::Break);
         keywords.insert("continue", TokenType::Continue);
         keywords.insert("class", TokenType::Class);
         keywords.insert("public", TokenType::Public);
         keywords.insert("private", TokenType::Private);
         keywords.insert("export", TokenType::Export);
+        keywords.insert("map", TokenType::Map);
+        keywords.insert("sizeof", TokenType::Sizeof);
+        keywords.insert("const", TokenType::Const);
+        keywords.insert("enum", TokenType::Enum);
+        keywords.insert("typedef", TokenType::Typedef);
         keywords.insert("true", TokenType::True);
         keywords.insert("false", TokenType::False);
         keywords.insert("PrintF", TokenType::PrintF);
@@ -132,26 +194,26 @@ impl<'a> Lexer<'a> {
             line: 1,
             column: 1,
             start: 0,
+            start_line: 1,
+            start_column: 1,
             keywords,
+            keep_comments: false,
+            pending: Vec::with_capacity(1),
+            finished: false,
         }
     }
 
-    pub fn scan_tokens(&mut self) -> Result<Vec<Token<'a>>, LexError> {
-        let mut tokens = Vec::new();
-
-        while !self.is_at_end() {
-            self.start = self.current;
-            self.scan_token(&mut tokens)?;
-        }
-
-        tokens.push(Token {
-            token_type: TokenType::Eof,
-            lexeme: "",
-            line: self.line,
-            column: self.column,
-        });
+    /// Emit `//` comments and preprocessor directive lines as tokens instead
+    /// of skipping them.
+    pub fn keeping_comments(mut self) -> Self {
+        self.keep_comments = true;
+        self
+    }
 
-        Ok(tokens)
+    /// Scan the whole source, ending with an `Eof` token. Iterating the
+    /// lexer yields the same tokens one at a time.
+    pub fn scan_tokens(&mut self) -> Result<Vec<Token<'a>>, LexError> {
+        self.collect()
     }
 
     fn scan_token(&mut self, tokens: &mut Vec<Token<'a>>) -> Result<(), LexError> {
@@ -166,17 +228,67 @@ impl<'a> Lexer<'a> {
             ']' => self.add_token(tokens, TokenType::RightBracket),
             ';' => self.add_token(tokens, TokenType::Semicolon),
             ',' => self.add_token(tokens, TokenType::Comma),
+            '.' if self.peek() == '.' && self.peek_next() == '.' => {
+                self.advance();
+                self.advance();
+                self.add_token(tokens, TokenType::Ellipsis);
+            }
+            // A decimal point is consumed by `number`, so any `.` left is
+            // member access
             '.' => self.add_token(tokens, TokenType::Dot),
-            '+' => self.add_token(tokens, TokenType::Plus),
-            '-' => self.add_token(tokens, TokenType::Minus),
-            '*' => self.add_token(tokens, TokenType::Star),
-            '%' => self.add_token(tokens, TokenType::Percent),
+            '+' => {
+                let token_type = if self.match_char('+') {
+                    TokenType::PlusPlus
+                } else if self.match_char('=') {
+                    TokenType::PlusEqual
+                } else {
+                    TokenType::Plus
+                };
+                self.add_token(tokens, token_type);
+            }
+            '-' => {
+                let token_type = if self.match_char('>') {
+                    TokenType::Arrow
+                } else if self.match_char('-') {
+                    TokenType::MinusMinus
+                } else if self.match_char('=') {
+                    TokenType::MinusEqual
+                } else {
+                    TokenType::Minus
+                };
+                self.add_token(tokens, token_type);
+            }
+            '*' => {
+                let token_type = if self.match_char('=') {
+                    TokenType::StarEqual
+                } else {
+                    TokenType::Star
+                };
+                self.add_token(tokens, token_type);
+            }
+            '%' => {
+                let token_type = if self.match_char('=') {
+                    TokenType::PercentEqual
+                } else {
+                    TokenType::Percent
+                };
+                self.add_token(tokens, token_type);
+            }
+            '?' => self.add_token(tokens, TokenType::Question),
+            ':' => self.add_token(tokens, TokenType::Colon),
             '/' => {
                 if self.match_char('/') {
                     // Comment until end of line
                     while !self.is_at_end() && self.peek() != '\n' {
                         self.advance();
                     }
+                    if self.keep_comments {
+                        self.add_token(tokens, TokenType::Comment);
+                    }
+                } else if self.keep_comments && self.match_char('*') {
+                    self.block_comment(tokens);
+                } else if self.match_char('=') {
+                    self.add_token(tokens, TokenType::SlashEqual);
                 } else {
                     self.add_token(tokens, TokenType::Slash);
                 }
@@ -200,6 +312,12 @@ impl<'a> Lexer<'a> {
             '<' => {
                 let token_type = if self.match_char('=') {
                     TokenType::LessEqual
+                } else if self.match_char('<') {
+                    if self.match_char('=') {
+                        TokenType::ShiftLeftEqual
+                    } else {
+                        TokenType::ShiftLeft
+                    }
                 } else {
                     TokenType::Less
                 };
@@ -208,20 +326,44 @@ impl<'a> Lexer<'a> {
             '>' => {
                 let token_type = if self.match_char('=') {
                     TokenType::GreaterEqual
+                } else if self.match_char('>') {
+                    if self.match_char('=') {
+                        TokenType::ShiftRightEqual
+                    } else {
+                        TokenType::ShiftRight
+                    }
                 } else {
                     TokenType::Greater
                 };
                 self.add_token(tokens, token_type);
             }
             '&' => {
-                if self.match_char('&') {
-                    self.add_token(tokens, TokenType::And);
-                }
+                let token_type = if self.match_char('&') {
+                    TokenType::And
+                } else if self.match_char('=') {
+                    TokenType::AmpersandEqual
+                } else {
+                    TokenType::Ampersand
+                };
+                self.add_token(tokens, token_type);
             }
             '|' => {
-                if self.match_char('|') {
-                    self.add_token(tokens, TokenType::Or);
-                }
+                let token_type = if self.match_char('|') {
+                    TokenType::Or
+                } else if self.match_char('=') {
+                    TokenType::PipeEqual
+                } else {
+                    TokenType::Pipe
+                };
+                self.add_token(tokens, token_type);
+            }
+            '^' => {
+                let token_type = if self.match_char('=') {
+                    TokenType::CaretEqual
+                } else {
+                    TokenType::Caret
+                };
+                self.add_token(tokens, token_type);
             }
             ' ' | '\r' | '\t' => {
                 // Ignore whitespace
@@ -231,13 +373,23 @@ impl<'a> Lexer<'a> {
                 self.column = 1;
             }
             '"' => self.string(tokens)?,
+            '#' if self.keep_comments => {
+                while !self.is_at_end() && self.peek() != '\n' {
+                    self.advance();
+                }
+                self.add_token(tokens, TokenType::Directive);
+            }
             _ => {
                 if c.is_ascii_digit() {
                     self.number(tokens);
                 } else if c.is_ascii_alphabetic() || c == '_' {
                     self.identifier(tokens);
                 } else {
-                    return Err(LexError::InvalidCharacter(c, self.line, self.column));
+                    return Err(LexError::InvalidCharacter(
+                        c,
+                        self.start_line,
+                        self.start_column,
+                    ));
                 }
             }
         }
@@ -247,15 +399,17 @@ impl<'a> Lexer<'a> {
 
     fn string(&mut self, tokens: &mut Vec<Token<'a>>) -> Result<(), LexError> {
         while !self.is_at_end() && self.peek() != '"' {
-            if self.peek() == '\n' {
+            if self.advance() == '\n' {
                 self.line += 1;
                 self.column = 1;
             }
-            self.advance();
         }
 
         if self.is_at_end() {
-            return Err(LexError::UnterminatedString(self.line));
+            return Err(LexError::UnterminatedString(
+                self.start_line,
+                self.start_column,
+            ));
         }
 
         self.advance(); // Closing quote
@@ -263,7 +417,38 @@ impl<'a> Lexer<'a> {
         Ok(())
     }
 
+    /// `/* ... */`, which may span lines; an unterminated comment runs to the
+    /// end of the source.
+    fn block_comment(&mut self, tokens: &mut Vec<Token<'a>>) {
+        while !self.is_at_end() && !self.source[self.current..].starts_with("*/") {
+            if self.advance() == '\n' {
+                self.line += 1;
+                self.column = 1;
+            }
+        }
+        if !self.is_at_end() {
+            self.advance();
+            self.advance();
+        }
+        self.add_token(tokens, TokenType::Comment);
+    }
+
+    /// Scan a number. A `.` is its decimal point only with digits on both
+    /// sides, so `3.14` is a float while `1.b` and `3.` end at the `.`.
     fn number(&mut self, tokens: &mut Vec<Token<'a>>) {
+        // `0x` hex literals; the parser converts them to decimal
+        if self.source[self.start..].starts_with('0')
+            && matches!(self.peek(), 'x' | 'X')
+            && self.peek_next().is_ascii_hexdigit()
+        {
+            self.advance();
+            while !self.is_at_end() && self.peek().is_ascii_hexdigit() {
+                self.advance();
+            }
+            self.add_token(tokens, TokenType::NumberLiteral);
+            return;
+        }
+
         while !self.is_at_end() && self.peek().is_ascii_digit() {
             self.advance();
         }
@@ -273,6 +458,8 @@ impl<'a> Lexer<'a> {
             while !self.is_at_end() && self.peek().is_ascii_digit() {
                 self.advance();
             }
+            self.add_token(tokens, TokenType::FloatLiteral);
+            return;
         }
 
         self.add_token(tokens, TokenType::NumberLiteral);
@@ -297,10 +484,11 @@ impl<'a> Lexer<'a> {
         tokens.push(Token {
             token_type,
             lexeme,
-            line: self.line,
-            column: self.column,
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start,
+            end: self.current,
         });
-        self.column += lexeme.len();
     }
 
     fn advance(&mut self) -> char {
@@ -319,14 +507,53 @@ impl<'a> Lexer<'a> {
     }
 
     fn peek(&self) -> char {
-        self.source.chars().nth(self.current).unwrap_or('\0')
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn peek_next(&self) -> char {
-        self.source.chars().nth(self.current + 1).unwrap_or('\0')
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn is_at_end(&self) -> bool {
         self.current >= self.source.len()
     }
 }
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token<'a>, LexError>;
+
+    /// The next token, then `Eof` once the source is exhausted. Iteration
+    /// stops after `Eof` or the first error.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        loop {
+            if let Some(token) = self.pending.pop() {
+                return Some(Ok(token));
+            }
+            if self.is_at_end() {
+                self.finished = true;
+                return Some(Ok(Token {
+                    token_type: TokenType::Eof,
+                    lexeme: "",
+                    line: self.line,
+                    column: self.column,
+                    start: self.current,
+                    end: self.current,
+                }));
+            }
+
+            self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = self.column;
+            let mut pending = core::mem::take(&mut self.pending);
+            let scanned = self.scan_token(&mut pending);
+            self.pending = pending;
+            if let Err(error) = scanned {
+                self.finished = true;
+                return Some(Err(error));
+            }
+        }
+    }
+}
@@ -1,8 +1,44 @@
+pub mod analysis;
+pub mod asm;
 pub mod bpf_vm;
+pub mod btf;
+pub mod cfg;
+pub mod classes;
 pub mod codegen;
 pub mod compiler;
+pub mod disasm;
+pub mod elf;
+pub mod formatter;
 pub mod lexer;
+pub mod optimizer;
 pub mod parser;
+pub mod preprocessor;
+pub mod regalloc;
+#[cfg(feature = "std")]
+pub mod repl;
+pub mod resolve;
+#[cfg(feature = "riscv")]
+pub mod riscv;
 pub mod solana_bpf;
+pub mod typecheck;
+pub mod types;
+pub mod visit;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
-pub use compiler::{CompileOptions, CompileTarget, Compiler};
+/// Stand-ins for the parts of the `std` prelude and collections the
+/// compiler uses, so every module builds with only `alloc`.
+mod prelude {
+    pub use alloc::boxed::Box;
+    pub use alloc::string::{String, ToString};
+    pub use alloc::vec::Vec;
+    pub use alloc::{format, vec};
+    #[cfg(not(feature = "std"))]
+    pub use hashbrown::{HashMap, HashSet};
+    #[cfg(feature = "std")]
+    pub use std::collections::{HashMap, HashSet};
+}
+
+#[cfg(feature = "std")]
+pub use compiler::CompilerCache;
+pub use compiler::{CompileOptions, CompileTarget, Compiler, OutputFormat, ProgramType};
@@ -0,0 +1,202 @@
+use crate::pible::codegen::{self, BpfInstruction};
+use crate::pible::parser::{Node, NodeType};
+use crate::pible::prelude::*;
+use crate::pible::visit::{walk_transform, Transform};
+
+/// Fold arithmetic and comparisons whose operands are all integer literals.
+pub fn fold_constants(node: &Node) -> Node {
+    ConstantFolder.transform_node(node)
+}
+
+struct ConstantFolder;
+
+impl Transform for ConstantFolder {
+    fn transform_node(&mut self, node: &Node) -> Node {
+        let folded = walk_transform(self, node);
+        if folded.node_type != NodeType::Expression {
+            return folded;
+        }
+
+        let operator = folded.value.as_deref().unwrap_or("");
+        let operands: Option<Vec<i64>> = folded.children.iter().map(literal_value).collect();
+        let value = match (operator, operands.as_deref()) {
+            ("neg", Some(&[value])) => Some(value.wrapping_neg()),
+            ("!", Some(&[value])) => Some((value == 0) as i64),
+            (_, Some(&[left, right])) => fold_binary(operator, left, right),
+            _ => None,
+        };
+
+        match value {
+            Some(value) => {
+                let mut literal = Node::with_value(NodeType::Literal, value.to_string());
+                literal.position = folded.position;
+                literal
+            }
+            None => folded,
+        }
+    }
+}
+
+/// Value of an integer expression made of literals and the named
+/// `constants`, or `None` if it isn't known at compile time.
+pub fn constant_value(node: &Node, constants: &HashMap<String, i64>) -> Option<i64> {
+    let substituted = ConstantSubstitution { constants }.transform_node(node);
+    literal_value(&fold_constants(&substituted))
+}
+
+/// Replaces names of `constants` with their values.
+struct ConstantSubstitution<'a> {
+    constants: &'a HashMap<String, i64>,
+}
+
+impl Transform for ConstantSubstitution<'_> {
+    fn transform_node(&mut self, node: &Node) -> Node {
+        if node.node_type == NodeType::Identifier {
+            if let Some(value) = node
+                .value
+                .as_deref()
+                .and_then(|name| self.constants.get(name))
+            {
+                let mut literal = Node::with_value(NodeType::Literal, value.to_string());
+                literal.position = node.position;
+                return literal;
+            }
+        }
+        walk_transform(self, node)
+    }
+}
+
+fn fold_binary(operator: &str, left: i64, right: i64) -> Option<i64> {
+    let value = match operator {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        // Integer literals are I64, so division is signed. Leave division by
+        // zero for the runtime to report
+        "/" if right != 0 => left.wrapping_div(right),
+        "%" if right != 0 => left.wrapping_rem(right),
+        // Integer literals are I64, so `>>` is arithmetic
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "&" => left & right,
+        "|" => left | right,
+        "^" => left ^ right,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "<" => (left < right) as i64,
+        "<=" => (left <= right) as i64,
+        ">" => (left > right) as i64,
+        ">=" => (left >= right) as i64,
+        "&&" => (left != 0 && right != 0) as i64,
+        "||" => (left != 0 || right != 0) as i64,
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn literal_value(node: &Node) -> Option<i64> {
+    if node.node_type != NodeType::Literal {
+        return None;
+    }
+    match node.value.as_deref()? {
+        "true" => Some(1),
+        "false" => Some(0),
+        value => codegen::integer_value(value),
+    }
+}
+
+/// Remove redundant instructions, rewriting jump and call offsets to match.
+///
+/// Returns the optimized program and, for every original index (plus one past
+/// the end), the index it now corresponds to.
+pub fn peephole(instructions: &[BpfInstruction]) -> (Vec<BpfInstruction>, Vec<usize>) {
+    let mut keep = vec![true; instructions.len()];
+    for (i, instruction) in instructions.iter().enumerate() {
+        keep[i] = !is_redundant(instructions, i, instruction);
+    }
+
+    let mut index_map = Vec::with_capacity(instructions.len() + 1);
+    let mut kept = 0;
+    for &keep in &keep {
+        index_map.push(kept);
+        kept += keep as usize;
+    }
+    index_map.push(kept);
+
+    let mut optimized = Vec::with_capacity(kept);
+    for (i, instruction) in instructions.iter().enumerate() {
+        if !keep[i] {
+            continue;
+        }
+        let mut instruction = *instruction;
+        if let Some(target) = branch_target(instructions, i) {
+            let relative = index_map[target] as i64 - index_map[i] as i64 - 1;
+            if is_local_call(&instruction) {
+                instruction.immediate = relative as i32;
+            } else {
+                instruction.offset = relative as i16;
+            }
+        }
+        optimized.push(instruction);
+    }
+
+    (optimized, index_map)
+}
+
+fn is_redundant(instructions: &[BpfInstruction], i: usize, instruction: &BpfInstruction) -> bool {
+    // The second slot of `lddw` looks like opcode 0 and must stay put
+    if i > 0 && instructions[i - 1].opcode == 0x18 {
+        return false;
+    }
+
+    // mov rX, rX
+    if instruction.opcode == 0xbf && instruction.dst_reg == instruction.src_reg {
+        return true;
+    }
+
+    // ja +0
+    if instruction.opcode == 0x05 && instruction.offset == 0 {
+        return true;
+    }
+
+    // A move whose result is immediately overwritten by another move
+    match instructions.get(i + 1) {
+        Some(next) => is_move(instruction) && overwrites(next, instruction.dst_reg),
+        None => false,
+    }
+}
+
+fn is_move(instruction: &BpfInstruction) -> bool {
+    matches!(instruction.opcode, 0xb7 | 0xbf | 0xb4 | 0xbc)
+}
+
+/// Whether `instruction` writes `reg` without reading it first.
+fn overwrites(instruction: &BpfInstruction, reg: u8) -> bool {
+    match instruction.opcode {
+        0xb7 | 0xb4 => instruction.dst_reg == reg,
+        0xbf | 0xbc => instruction.dst_reg == reg && instruction.src_reg != reg,
+        _ => false,
+    }
+}
+
+fn is_local_call(instruction: &BpfInstruction) -> bool {
+    instruction.opcode == 0x85 && instruction.src_reg == 1
+}
+
+/// Absolute target of a jump or local call, if it lands inside the program or just past it.
+fn branch_target(instructions: &[BpfInstruction], i: usize) -> Option<usize> {
+    let instruction = &instructions[i];
+    if instruction.opcode & 0x07 != 0x05 {
+        return None;
+    }
+    let relative = match instruction.opcode & 0xf0 {
+        0x90 => return None,
+        0x80 if is_local_call(instruction) => instruction.immediate as i64,
+        0x80 => return None,
+        _ => instruction.offset as i64,
+    };
+    let target = i as i64 + 1 + relative;
+    (0..=instructions.len() as i64)
+        .contains(&target)
+        .then_some(target as usize)
+}
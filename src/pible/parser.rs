@@ -1,36 +1,105 @@
 use crate::pible::lexer::{Token, TokenType};
+use crate::pible::optimizer;
+use crate::pible::prelude::*;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 #[allow(dead_code)]
 pub enum ParseError {
-    #[error("Unexpected token: {0:?} at line {1}")]
-    UnexpectedToken(TokenType, usize),
-    #[error("Expected token: {expected:?}, found: {found:?} at line {line}")]
+    #[error("Unexpected token: {0:?} at line {1}, column {2}")]
+    UnexpectedToken(TokenType, usize, usize),
+    #[error("Expected token: {expected:?}, found: {found:?} at line {line}, column {column}")]
     ExpectedToken {
         expected: TokenType,
         found: TokenType,
         line: usize,
+        column: usize,
     },
     #[error("Unexpected end of file")]
     UnexpectedEof,
+    #[error("Nesting too deep at line {0}, column {1}")]
+    NestingTooDeep(usize, usize),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl ParseError {
+    /// Line and column the error points at, when known.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match *self {
+            ParseError::UnexpectedToken(_, line, column) => Some((line, column)),
+            ParseError::ExpectedToken { line, column, .. } => Some((line, column)),
+            ParseError::NestingTooDeep(line, column) => Some((line, column)),
+            ParseError::UnexpectedEof => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum NodeType {
     Program,
     FunctionDecl,
+    StructDecl,
+    MapDecl,
     Block,
     Statement,
     Expression,
     Identifier,
     Literal,
+    /// A literal with a fractional part, such as `1.5`
+    FloatLiteral,
+    /// `cond ? a : b`, with the condition and both branches as children
+    Ternary,
+    /// `sizeof(Type)`, with the type as its value, or `sizeof(expr)`, with
+    /// the expression as its child
+    Sizeof,
+    /// `const Type name = value;` at file scope, with a "Type:name" value
+    /// and the initializer as its child
+    ConstDecl,
+    /// `enum Name { A, B = 5, ... };`, with its name as the value and an
+    /// `I64` ConstDecl holding each variant's value as its children
+    EnumDecl,
+    /// `(Type)expr`, with the target type as its value and the operand as
+    /// its child
+    Cast,
+    /// `class Name { ... }`, with the fields ("Type:name" Identifiers) and
+    /// methods (FunctionDecls taking `Name* this` first) as its children
+    ClassDecl,
+    /// `{a, b, ...}` initializing an array, with the elements as its children
+    InitializerList,
+}
+
+/// Source line and column of the token a node starts at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    fn of(token: &Token) -> Self {
+        Self {
+            line: token.line,
+            column: token.column,
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Node {
     pub node_type: NodeType,
     pub value: Option<String>,
+    /// Where the node starts; `None` for synthesized nodes such as the program root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<Position>,
+    /// Set on functions declared with `export`
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub exported: bool,
+    /// Set on functions whose parameter list ends in `...`
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub variadic: bool,
+    /// Set on class members declared `private`
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub private: bool,
     pub children: Vec<Node>,
 }
 
@@ -39,6 +108,10 @@ impl Node {
         Self {
             node_type,
             value: None,
+            position: None,
+            exported: false,
+            variadic: false,
+            private: false,
             children: Vec::new(),
         }
     }
@@ -47,6 +120,10 @@ impl Node {
         Self {
             node_type,
             value: Some(value),
+            position: None,
+            exported: false,
+            variadic: false,
+            private: false,
             children: Vec::new(),
         }
     }
@@ -54,27 +131,90 @@ impl Node {
     pub fn add_child(&mut self, child: Node) {
         self.children.push(child);
     }
+
+    /// Serialize the tree as pretty-printed JSON, for `--dump-ast` and tooling.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("AST nodes always serialize")
+    }
 }
 
 pub struct Parser<'a> {
     tokens: Vec<Token<'a>>,
     current: usize,
+    /// Values of the `const`s declared so far, for array sizes
+    constants: HashMap<String, i64>,
+    /// `typedef` names and the types they stand for, e.g. `Lamports` -> `U64`
+    typedefs: HashMap<String, String>,
+    /// Current and maximum nesting of recursive rules
+    depth: usize,
+    max_depth: usize,
+    /// Declarations after the first of `U64 a, b;`, added to the enclosing
+    /// block once the statement ends
+    pending: Vec<Node>,
 }
 
+/// Nesting of statements and expressions allowed by default: far more than
+/// real programs use, and shallow enough for the recursive descent to stay
+/// well inside a thread's stack.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+/// Depth of the syntax tree of one declaration that later passes, which
+/// recurse over it, are known to handle.
+pub const MAX_TREE_DEPTH: usize = 160;
+
 impl<'a> Parser<'a> {
-    pub fn new(tokens: Vec<Token<'a>>) -> Self {
-        Self { tokens, current: 0 }
+    /// A parser over `tokens`, which gain a closing `Eof` if they lack one.
+    pub fn new(mut tokens: Vec<Token<'a>>) -> Self {
+        if tokens
+            .last()
+            .is_none_or(|token| token.token_type != TokenType::Eof)
+        {
+            let (line, column, end) = tokens
+                .last()
+                .map_or((1, 1, 0), |token| (token.line, token.column, token.end));
+            tokens.push(Token {
+                token_type: TokenType::Eof,
+                lexeme: "",
+                line,
+                column,
+                start: end,
+                end,
+            });
+        }
+        Self {
+            tokens,
+            current: 0,
+            constants: HashMap::new(),
+            typedefs: HashMap::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Limit how deeply statements and expressions may nest; deeper input
+    /// fails with [`ParseError::NestingTooDeep`].
+    #[allow(dead_code)]
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
     }
 
+    /// Parse a whole program, failing with the first declaration that
+    /// doesn't parse.
     pub fn parse(&mut self) -> Result<Node, ParseError> {
         let mut program = Node::new(NodeType::Program);
 
         while !self.is_at_end() {
-            if let Ok(declaration) = self.declaration() {
+            let declaration = self.declaration()?;
+            for declaration in core::iter::once(declaration).chain(self.pending.drain(..)) {
+                if tree_depth(&declaration) > MAX_TREE_DEPTH {
+                    let (line, column) = declaration
+                        .position
+                        .map_or((1, 1), |position| (position.line, position.column));
+                    return Err(ParseError::NestingTooDeep(line, column));
+                }
                 program.add_child(declaration);
-            } else {
-                // Skip to next declaration on error
-                self.synchronize();
             }
         }
 
@@ -82,45 +222,149 @@ impl<'a> Parser<'a> {
     }
 
     fn declaration(&mut self) -> Result<Node, ParseError> {
-        if self.match_token(&[TokenType::Export])
-            || self.check(&TokenType::U0)
-            || self.check(&TokenType::U8)
-            || self.check(&TokenType::U16)
-            || self.check(&TokenType::U32)
-            || self.check(&TokenType::U64)
+        if self.check(&TokenType::Export) || self.is_function_start() {
+            let function = self.function_signature()?;
+            self.function_body(function)
+        } else if self.check(&TokenType::Struct)
+            && self.token_at(self.current + 2) == TokenType::LeftBrace
         {
-            self.function_declaration()
+            self.struct_declaration()
+        } else if self.check(&TokenType::Class) {
+            self.class_declaration()
+        } else if self.check(&TokenType::Map) {
+            self.map_declaration()
+        } else if self.check(&TokenType::Const) {
+            self.const_declaration()
+        } else if self.check(&TokenType::Enum) {
+            self.enum_declaration()
+        } else if self.check(&TokenType::Typedef) {
+            self.typedef_declaration()
+        } else if self.type_length(self.current).is_some() {
+            self.var_declaration()
         } else {
             self.statement()
         }
     }
 
-    fn function_declaration(&mut self) -> Result<Node, ParseError> {
+    fn is_type(&self, token_type: &TokenType) -> bool {
+        matches!(
+            token_type,
+            TokenType::U0
+                | TokenType::U8
+                | TokenType::U16
+                | TokenType::U32
+                | TokenType::U64
+                | TokenType::I8
+                | TokenType::I16
+                | TokenType::I32
+                | TokenType::I64
+                | TokenType::F64
+                | TokenType::Bool
+        )
+    }
+
+    /// Number of tokens spelling the base type at `index`: one for a builtin
+    /// type or `typedef` name, two for `struct Name`.
+    fn type_length(&self, index: usize) -> Option<usize> {
+        match self.token_at(index) {
+            TokenType::Struct if self.token_at(index + 1) == TokenType::Identifier => Some(2),
+            TokenType::Identifier if self.typedefs.contains_key(self.tokens[index].lexeme) => {
+                Some(1)
+            }
+            token_type if self.is_type(&token_type) => Some(1),
+            _ => None,
+        }
+    }
+
+    /// A type, optional `*`s and an identifier followed by `(`.
+    fn is_function_start(&self) -> bool {
+        let Some(length) = self.type_length(self.current) else {
+            return false;
+        };
+        let mut index = self.current + length;
+        while self.token_at(index) == TokenType::Star {
+            index += 1;
+        }
+        self.token_at(index) == TokenType::Identifier
+            && self.token_at(index + 1) == TokenType::LeftParen
+    }
+
+    /// A type, optional `*`s and `(*`, as in `U0 (*callback)(U64)`.
+    fn is_function_pointer_start(&self) -> bool {
+        let Some(length) = self.type_length(self.current) else {
+            return false;
+        };
+        let mut index = self.current + length;
+        while self.token_at(index) == TokenType::Star {
+            index += 1;
+        }
+        self.token_at(index) == TokenType::LeftParen && self.token_at(index + 1) == TokenType::Star
+    }
+
+    fn position_at(&self, index: usize) -> Option<Position> {
+        self.tokens.get(index).map(Position::of)
+    }
+
+    fn token_at(&self, index: usize) -> TokenType {
+        self.tokens
+            .get(index)
+            .map_or(TokenType::Eof, |token| token.token_type)
+    }
+
+    /// Parse a type name including pointer stars, e.g. `U8*` or `struct Account*`.
+    /// A `typedef` name is replaced by the type it stands for.
+    fn type_name(&mut self) -> Result<String, ParseError> {
+        let mut type_str = self.advance().lexeme.to_string();
+        if self.previous().token_type == TokenType::Struct {
+            type_str = format!("struct {}", self.advance().lexeme);
+        } else if let Some(aliased) = self.typedefs.get(&type_str) {
+            type_str = aliased.clone();
+        }
+        self.pointer_stars(type_str)
+    }
+
+    /// Append the `*`s following a type name to `type_str`.
+    fn pointer_stars(&mut self, mut type_str: String) -> Result<String, ParseError> {
+        let mut stars = 0;
+        while self.match_token(&[TokenType::Star]) {
+            stars = self.chain_link(stars, 0)?;
+            type_str.push('*');
+        }
+        Ok(type_str)
+    }
+
+    /// Parse an optional `export`, return type, name and parameters, up to and
+    /// including the `{` that opens the body.
+    fn function_signature(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        let exported = self.match_token(&[TokenType::Export]);
+
         // Parse return type
-        let return_type_str = self.advance().lexeme.to_string();
+        let return_type_str = self.type_name()?;
 
         // Parse function name
         let name_token = self.consume(TokenType::Identifier, "Expected function name")?;
         let name_str = name_token.lexeme.to_string();
 
+        let mut function = Node::with_value(
+            NodeType::FunctionDecl,
+            format!("{}:{}", return_type_str, name_str),
+        );
+        function.position = self.position_at(start);
+        function.exported = exported;
+
         // Parse parameters
         self.consume(TokenType::LeftParen, "Expected '(' after function name")?;
-        let mut params = Vec::new();
 
         if !self.check(&TokenType::RightParen) {
             loop {
-                // Parse parameter type and name
-                let param_type_str = self.advance().lexeme.to_string();
-                if self.previous().token_type == TokenType::Star {
-                    // Handle pointer types like U8*
-                    let _ = self.advance(); // consume the base type
+                // `...` must be last; the `)` check below rejects anything after it
+                if self.match_token(&[TokenType::Ellipsis]) {
+                    function.variadic = true;
+                    break;
                 }
-                if self.check(&TokenType::Identifier) {
-                    let param_name_token = self.advance();
-                    let mut param_node =
-                        Node::with_value(NodeType::Identifier, param_name_token.lexeme.to_string());
-                    param_node.value = Some(param_type_str);
-                    params.push(param_node);
+                if let Some(param) = self.parameter() {
+                    function.add_child(param);
                 }
 
                 if !self.match_token(&[TokenType::Comma]) {
@@ -129,25 +373,528 @@ impl<'a> Parser<'a> {
             }
         }
         self.consume(TokenType::RightParen, "Expected ')' after parameters")?;
+        self.consume(TokenType::LeftBrace, "Expected '{' before function body")?;
 
-        // Parse function body
-        let body = self.block_statement()?;
+        Ok(function)
+    }
 
-        let mut function = Node::with_value(
-            NodeType::FunctionDecl,
-            format!("{}:{}", return_type_str, name_str),
-        );
-        for param in params {
-            function.add_child(param);
+    /// Parse one parameter, recorded as an Identifier with value "Type:name".
+    ///
+    /// Everything before the trailing identifier is the type, so spellings
+    /// such as `struct Account* accounts` are accepted. Returns `None` for an
+    /// unnamed parameter.
+    fn parameter(&mut self) -> Option<Node> {
+        if self.is_function_pointer_start() {
+            let base = self.type_name().ok()?;
+            let (type_str, name, position) = self.declarator(base).ok()?;
+            let mut param =
+                Node::with_value(NodeType::Identifier, format!("{}:{}", type_str, name));
+            param.position = Some(position);
+            return Some(param);
         }
-        function.add_child(body);
+        let start = self.current;
+        while !matches!(
+            self.peek().token_type,
+            TokenType::Comma
+                | TokenType::RightParen
+                | TokenType::LeftBrace
+                | TokenType::Semicolon
+                | TokenType::Eof
+        ) {
+            self.advance();
+        }
+
+        // An array parameter such as `U8 data[32]` is a pointer
+        let mut tokens = &self.tokens[start..self.current];
+        let is_array = tokens.last()?.token_type == TokenType::RightBracket;
+        if let Some(open) = tokens
+            .iter()
+            .position(|token| token.token_type == TokenType::LeftBracket)
+        {
+            tokens = &tokens[..open];
+        }
+
+        let (name, type_tokens) = tokens.split_last()?;
+        if name.token_type != TokenType::Identifier || type_tokens.is_empty() {
+            return None;
+        }
+
+        let mut type_str = String::new();
+        for (i, token) in type_tokens.iter().enumerate() {
+            if !type_str.is_empty() && token.token_type != TokenType::Star {
+                type_str.push(' ');
+            }
+            let after_struct = i > 0 && type_tokens[i - 1].token_type == TokenType::Struct;
+            match self.typedefs.get(token.lexeme) {
+                Some(aliased) if !after_struct => type_str.push_str(aliased),
+                _ => type_str.push_str(token.lexeme),
+            }
+        }
+        if is_array {
+            type_str.push('*');
+        }
+        let mut param = Node::with_value(
+            NodeType::Identifier,
+            format!("{}:{}", type_str, name.lexeme),
+        );
+        param.position = Some(Position::of(name));
+        Some(param)
+    }
 
+    /// Parse the body of a function whose signature has been parsed.
+    fn function_body(&mut self, mut function: Node) -> Result<Node, ParseError> {
+        let body = self.block_statement()?;
+        function.add_child(body);
         Ok(function)
     }
 
+    /// Parse `struct Name { Type field; ... };` into a StructDecl whose
+    /// children are the fields as "Type:name" identifiers.
+    fn struct_declaration(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        self.consume(TokenType::Struct, "Expected 'struct'")?;
+        let name = self
+            .consume(TokenType::Identifier, "Expected struct name")?
+            .lexeme
+            .to_string();
+        // As for a class, the bare name also names the type
+        self.typedefs
+            .insert(name.clone(), format!("struct {}", name));
+        let node = self.struct_fields(name, start)?;
+        self.match_token(&[TokenType::Semicolon]);
+        Ok(node)
+    }
+
+    /// Parse the `{ Type field; ... }` of the struct `name` declared at token `start`.
+    fn struct_fields(&mut self, name: String, start: usize) -> Result<Node, ParseError> {
+        self.consume(TokenType::LeftBrace, "Expected '{' after struct name")?;
+
+        let mut node = Node::with_value(NodeType::StructDecl, name);
+        node.position = self.position_at(start);
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            node.add_child(self.struct_field()?);
+        }
+
+        self.consume(TokenType::RightBrace, "Expected '}' after struct fields")?;
+        Ok(node)
+    }
+
+    /// Parse one `Type field;` of a struct or class body.
+    fn struct_field(&mut self) -> Result<Node, ParseError> {
+        if self.type_length(self.current).is_none() {
+            return Err(ParseError::UnexpectedToken(
+                self.peek().token_type,
+                self.peek().line,
+                self.peek().column,
+            ));
+        }
+        let base = self.type_name()?;
+        let (type_str, field, position) = self.declarator(base)?;
+        let mut field = Node::with_value(NodeType::Identifier, format!("{}:{}", type_str, field));
+        field.position = Some(position);
+        self.consume(TokenType::Semicolon, "Expected ';' after field")?;
+        Ok(field)
+    }
+
+    /// Parse `class Name { ... }`, whose members are fields or methods, each
+    /// optionally marked `public` or `private`. The class name becomes an
+    /// alias for `struct Name`, and every method gets a leading `Name* this`
+    /// parameter.
+    fn class_declaration(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        self.consume(TokenType::Class, "Expected 'class'")?;
+        let name = self
+            .consume(TokenType::Identifier, "Expected class name")?
+            .lexeme
+            .to_string();
+        // Registered first so fields and methods can refer to the class
+        let struct_type = format!("struct {}", name);
+        self.typedefs.insert(name.clone(), struct_type.clone());
+        self.consume(TokenType::LeftBrace, "Expected '{' after class name")?;
+
+        let mut node = Node::with_value(NodeType::ClassDecl, name);
+        node.position = self.position_at(start);
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let private = self.match_token(&[TokenType::Private]);
+            if !private {
+                self.match_token(&[TokenType::Public]);
+            }
+
+            let mut member = if self.is_function_start() {
+                let mut method = self.function_signature()?;
+                let mut this =
+                    Node::with_value(NodeType::Identifier, format!("{}*:this", struct_type));
+                this.position = method.position;
+                method.children.insert(0, this);
+                self.function_body(method)?
+            } else {
+                self.struct_field()?
+            };
+            member.private = private;
+            node.add_child(member);
+        }
+
+        self.consume(TokenType::RightBrace, "Expected '}' after class members")?;
+        self.match_token(&[TokenType::Semicolon]);
+        Ok(node)
+    }
+
+    /// Parse `typedef Type Name;` or `typedef struct [Tag] { ... } Name;`,
+    /// recording `Name` as an alias. A struct body becomes a StructDecl, named
+    /// after the alias when it has no tag; any other typedef leaves an empty
+    /// block, as aliases are resolved while parsing.
+    fn typedef_declaration(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        self.consume(TokenType::Typedef, "Expected 'typedef'")?;
+
+        let is_struct_body = self.check(&TokenType::Struct)
+            && matches!(
+                (
+                    self.token_at(self.current + 1),
+                    self.token_at(self.current + 2)
+                ),
+                (TokenType::LeftBrace, _) | (TokenType::Identifier, TokenType::LeftBrace)
+            );
+        let (type_str, node) = if is_struct_body {
+            self.advance();
+            let tag = self
+                .match_token(&[TokenType::Identifier])
+                .then(|| self.previous().lexeme.to_string());
+            // An untagged struct takes the alias's name
+            let name = match tag {
+                Some(tag) => tag,
+                None => self.peek_struct_alias()?,
+            };
+            let node = self.struct_fields(name.clone(), start)?;
+            (format!("struct {}", name), node)
+        } else if self.type_length(self.current).is_some() {
+            (self.type_name()?, Node::new(NodeType::Block))
+        } else {
+            let token = self.peek();
+            return Err(ParseError::UnexpectedToken(
+                token.token_type,
+                token.line,
+                token.column,
+            ));
+        };
+
+        let alias = self
+            .consume(TokenType::Identifier, "Expected typedef name")?
+            .lexeme
+            .to_string();
+        self.consume(TokenType::Semicolon, "Expected ';' after typedef")?;
+        self.typedefs.insert(alias, type_str);
+        Ok(node)
+    }
+
+    /// Name following the `}` that closes the struct body starting at the
+    /// current `{`.
+    fn peek_struct_alias(&self) -> Result<String, ParseError> {
+        let mut depth = 0;
+        for (index, token) in self.tokens.iter().enumerate().skip(self.current) {
+            match token.token_type {
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return match self.tokens.get(index + 1) {
+                            Some(name) if name.token_type == TokenType::Identifier => {
+                                Ok(name.lexeme.to_string())
+                            }
+                            Some(other) => Err(ParseError::ExpectedToken {
+                                expected: TokenType::Identifier,
+                                found: other.token_type,
+                                line: other.line,
+                                column: other.column,
+                            }),
+                            None => Err(ParseError::UnexpectedEof),
+                        };
+                    }
+                }
+                _ => {}
+            }
+        }
+        Err(ParseError::UnexpectedEof)
+    }
+
+    /// Parse `map hash<K, V> name[max_entries];` into a MapDecl named `name`
+    /// whose children are the map kind, key type, value type and maximum
+    /// number of entries.
+    fn map_declaration(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        self.consume(TokenType::Map, "Expected 'map'")?;
+        let kind = self.consume(TokenType::Identifier, "Expected map kind")?;
+        let kind = Node::with_value(NodeType::Identifier, kind.lexeme.to_string());
+        self.consume(TokenType::Less, "Expected '<' after map kind")?;
+        let key = self.map_type()?;
+        self.consume(TokenType::Comma, "Expected ',' after map key type")?;
+        let value = self.map_type()?;
+        self.consume(TokenType::Greater, "Expected '>' after map value type")?;
+        let name = self
+            .consume(TokenType::Identifier, "Expected map name")?
+            .lexeme
+            .to_string();
+        self.consume(TokenType::LeftBracket, "Expected '[' after map name")?;
+        let entries = self.consume(TokenType::NumberLiteral, "Expected max entries")?;
+        let entries = Node::with_value(NodeType::Literal, number_value(entries.lexeme));
+        self.consume(TokenType::RightBracket, "Expected ']' after max entries")?;
+        self.consume(TokenType::Semicolon, "Expected ';' after map declaration")?;
+
+        let mut node = Node::with_value(NodeType::MapDecl, name);
+        node.position = self.position_at(start);
+        for child in [kind, key, value, entries] {
+            node.add_child(child);
+        }
+        Ok(node)
+    }
+
+    /// A map key or value type: a builtin or `struct` type, or a declared
+    /// struct's bare name.
+    fn map_type(&mut self) -> Result<Node, ParseError> {
+        let type_str = if self.type_length(self.current).is_some() {
+            self.type_name()?
+        } else {
+            self.consume(TokenType::Identifier, "Expected map key or value type")?
+                .lexeme
+                .to_string()
+        };
+        Ok(Node::with_value(NodeType::Identifier, type_str))
+    }
+
+    /// Parse `const Type name = value;`. Constants with an integer value
+    /// known here can size arrays declared after them.
+    fn const_declaration(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        self.consume(TokenType::Const, "Expected 'const'")?;
+        if self.type_length(self.current).is_none() {
+            let token = self.peek();
+            return Err(ParseError::UnexpectedToken(
+                token.token_type,
+                token.line,
+                token.column,
+            ));
+        }
+        let type_str = self.type_name()?;
+        let name = self
+            .consume(TokenType::Identifier, "Expected constant name")?
+            .lexeme
+            .to_string();
+        self.consume(TokenType::Equal, "Expected '=' after constant name")?;
+        let value = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expected ';' after constant")?;
+
+        if let Some(value) = optimizer::constant_value(&value, &self.constants) {
+            self.constants.insert(name.clone(), value);
+        }
+        let mut node = Node::with_value(NodeType::ConstDecl, format!("{}:{}", type_str, name));
+        node.position = self.position_at(start);
+        node.add_child(value);
+        Ok(node)
+    }
+
+    /// Parse `enum Name { A, B = 5, C };`. A variant without a value is one
+    /// more than the one before it, and the first defaults to 0; a value
+    /// must be a constant expression. `Name` becomes a type, an alias of `I64`.
+    fn enum_declaration(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        self.consume(TokenType::Enum, "Expected 'enum'")?;
+        let name = self
+            .consume(TokenType::Identifier, "Expected enum name")?
+            .lexeme
+            .to_string();
+        self.consume(TokenType::LeftBrace, "Expected '{' after enum name")?;
+        self.typedefs.insert(name.clone(), "I64".to_string());
+
+        let mut node = Node::with_value(NodeType::EnumDecl, name);
+        node.position = self.position_at(start);
+        let mut next = 0i64;
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            let variant_start = self.current;
+            let variant = self
+                .consume(TokenType::Identifier, "Expected enum variant name")?
+                .lexeme
+                .to_string();
+            if self.match_token(&[TokenType::Equal]) {
+                let token = self.peek();
+                let (token_type, line, column) = (token.token_type, token.line, token.column);
+                let value = self.expression()?;
+                next = optimizer::constant_value(&value, &self.constants)
+                    .ok_or(ParseError::UnexpectedToken(token_type, line, column))?;
+            }
+            self.constants.insert(variant.clone(), next);
+
+            let mut constant = Node::with_value(NodeType::ConstDecl, format!("I64:{}", variant));
+            constant.position = self.position_at(variant_start);
+            constant.add_child(Node::with_value(NodeType::Literal, next.to_string()));
+            node.add_child(constant);
+            next = next.wrapping_add(1);
+
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' after enum variants")?;
+        self.consume(TokenType::Semicolon, "Expected ';' after enum")?;
+        Ok(node)
+    }
+
+    /// Parse `[N]` suffixes after a declared name, returned as e.g. `"[4][8]"`.
+    /// A size may name a `const` declared earlier.
+    fn array_dimensions(&mut self) -> Result<String, ParseError> {
+        let mut dimensions = String::new();
+        let mut count = 0;
+        while self.match_token(&[TokenType::LeftBracket]) {
+            count = self.chain_link(count, 0)?;
+            let constant = self.constants.get(self.peek().lexeme).copied();
+            let count = match constant {
+                Some(count) if self.check(&TokenType::Identifier) => {
+                    self.advance();
+                    count.to_string()
+                }
+                _ => number_value(
+                    self.consume(TokenType::NumberLiteral, "Expected array size")?
+                        .lexeme,
+                ),
+            };
+            dimensions.push_str(&format!("[{}]", count));
+            self.consume(TokenType::RightBracket, "Expected ']' after array size")?;
+        }
+        Ok(dimensions)
+    }
+
+    /// `Type a = 1, *b[2];`: the first declaration, with any others queued
+    /// in `pending`. Each declared name has its own `*`s, as in C.
+    fn var_declaration(&mut self) -> Result<Node, ParseError> {
+        let start = self.current;
+        let base = self.type_name()?;
+        let stmt = self.var_declarator(base.clone(), start)?;
+        let scalar = base.trim_end_matches('*').to_string();
+        while self.match_token(&[TokenType::Comma]) {
+            let start = self.current;
+            let base = self.pointer_stars(scalar.clone())?;
+            let declaration = self.var_declarator(base, start)?;
+            self.pending.push(declaration);
+        }
+
+        self.consume(
+            TokenType::Semicolon,
+            "Expected ';' after variable declaration",
+        )?;
+        Ok(stmt)
+    }
+
+    /// One declared name of a variable declaration starting at token
+    /// `start`, with its initializer.
+    fn var_declarator(&mut self, base: String, start: usize) -> Result<Node, ParseError> {
+        // `U8 key[] = {...}` takes its length from the initializer list
+        let unsized_array = self.token_at(self.current + 1) == TokenType::LeftBracket
+            && self.token_at(self.current + 2) == TokenType::RightBracket;
+
+        let mut initializer = None;
+        let (type_str, name_str, position) = if unsized_array {
+            let name_token = self.consume(TokenType::Identifier, "Expected variable name")?;
+            let (name, position) = (name_token.lexeme.to_string(), Position::of(name_token));
+            self.current += 2;
+            let dimensions = self.array_dimensions()?;
+            self.consume(
+                TokenType::Equal,
+                "Expected initializer for array without a size",
+            )?;
+            let list = self.initializer_list()?;
+            let type_str = format!("{}[{}]{}", base, list.children.len(), dimensions);
+            initializer = Some(list);
+            (type_str, name, position)
+        } else {
+            self.declarator(base)?
+        };
+        if initializer.is_none() && self.match_token(&[TokenType::Equal]) {
+            initializer = Some(if self.check(&TokenType::LeftBrace) {
+                self.initializer_list()?
+            } else {
+                self.expression()?
+            });
+        }
+
+        let mut name = Node::with_value(NodeType::Identifier, format!("{}:{}", type_str, name_str));
+        name.position = Some(position);
+
+        let mut stmt = Node::with_value(NodeType::Statement, "var".to_string());
+        stmt.position = self.position_at(start);
+        stmt.add_child(name);
+        if let Some(initializer) = initializer {
+            stmt.add_child(initializer);
+        }
+        Ok(stmt)
+    }
+
+    /// Parse a declared name after its base type: `name[N]...`, or
+    /// `(*name[N]...)(Params)` for a function pointer, whose type is spelled
+    /// `Base(*)(Params)[N]...`. Gives the type, the name and its position.
+    fn declarator(&mut self, base: String) -> Result<(String, String, Position), ParseError> {
+        let function_pointer = self.match_token(&[TokenType::LeftParen]);
+        if function_pointer {
+            self.consume(TokenType::Star, "Expected '*' in function pointer")?;
+        }
+        let name_token = self.consume(TokenType::Identifier, "Expected name")?;
+        let (name, position) = (name_token.lexeme.to_string(), Position::of(name_token));
+        let dimensions = self.array_dimensions()?;
+        if !function_pointer {
+            return Ok((base + &dimensions, name, position));
+        }
+
+        self.consume(TokenType::RightParen, "Expected ')' after function pointer")?;
+        self.consume(TokenType::LeftParen, "Expected '(' before parameter types")?;
+        let mut params = Vec::new();
+        while !self.check(&TokenType::RightParen) {
+            if self.type_length(self.current).is_none() {
+                return Err(ParseError::UnexpectedToken(
+                    self.peek().token_type,
+                    self.peek().line,
+                    self.peek().column,
+                ));
+            }
+            params.push(self.type_name()?);
+            // Parameter names are optional and ignored
+            self.match_token(&[TokenType::Identifier]);
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightParen, "Expected ')' after parameter types")?;
+        let type_str = format!("{}(*)({}){}", base, params.join(","), dimensions);
+        Ok((type_str, name, position))
+    }
+
+    /// `{a, b, ...}`, with an optional trailing comma.
+    fn initializer_list(&mut self) -> Result<Node, ParseError> {
+        let brace = self.consume(TokenType::LeftBrace, "Expected '{'")?;
+        let mut list = Node::new(NodeType::InitializerList);
+        list.position = Some(Position::of(brace));
+        while !self.check(&TokenType::RightBrace) {
+            list.add_child(self.expression()?);
+            if !self.match_token(&[TokenType::Comma]) {
+                break;
+            }
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' after initializer list")?;
+        Ok(list)
+    }
+
     fn statement(&mut self) -> Result<Node, ParseError> {
+        self.nested(Self::statement_kind)
+    }
+
+    fn statement_kind(&mut self) -> Result<Node, ParseError> {
         if self.match_token(&[TokenType::Return]) {
             self.return_statement()
+        } else if self.match_token(&[TokenType::If]) {
+            self.if_statement()
+        } else if self.match_token(&[TokenType::While]) {
+            self.while_statement()
+        } else if self.match_token(&[TokenType::For]) {
+            self.for_statement()
+        } else if self.match_token(&[TokenType::Break, TokenType::Continue]) {
+            self.loop_jump_statement()
         } else if self.match_token(&[TokenType::LeftBrace]) {
             self.block_statement()
         } else {
@@ -157,6 +904,7 @@ impl<'a> Parser<'a> {
 
     fn return_statement(&mut self) -> Result<Node, ParseError> {
         let mut stmt = Node::with_value(NodeType::Statement, "return".to_string());
+        stmt.position = Some(Position::of(self.previous()));
 
         if !self.check(&TokenType::Semicolon) {
             let expr = self.expression()?;
@@ -167,12 +915,114 @@ impl<'a> Parser<'a> {
         Ok(stmt)
     }
 
+    /// `break;` or `continue;`, with the keyword as the statement's value.
+    fn loop_jump_statement(&mut self) -> Result<Node, ParseError> {
+        let keyword = self.previous();
+        let mut stmt = Node::with_value(NodeType::Statement, keyword.lexeme.to_string());
+        stmt.position = Some(Position::of(keyword));
+        self.consume(
+            TokenType::Semicolon,
+            "Expected ';' after 'break' or 'continue'",
+        )?;
+        Ok(stmt)
+    }
+
+    /// An `if` node holds a condition and body for each arm, `else if`s
+    /// included, then the `else` body if there is one.
+    fn if_statement(&mut self) -> Result<Node, ParseError> {
+        let position = Position::of(self.previous());
+        self.consume(TokenType::LeftParen, "Expected '(' after 'if'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after if condition")?;
+
+        let mut stmt = Node::with_value(NodeType::Statement, "if".to_string());
+        stmt.position = Some(position);
+        stmt.add_child(condition);
+        stmt.add_child(self.statement()?);
+
+        // `else if` arms join this statement rather than nesting in its else
+        while self.match_token(&[TokenType::Else]) {
+            if !self.match_token(&[TokenType::If]) {
+                stmt.add_child(self.statement()?);
+                break;
+            }
+            self.consume(TokenType::LeftParen, "Expected '(' after 'if'")?;
+            stmt.add_child(self.expression()?);
+            self.consume(TokenType::RightParen, "Expected ')' after if condition")?;
+            stmt.add_child(self.statement()?);
+        }
+
+        Ok(stmt)
+    }
+
+    fn while_statement(&mut self) -> Result<Node, ParseError> {
+        let position = Position::of(self.previous());
+        self.consume(TokenType::LeftParen, "Expected '(' after 'while'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "Expected ')' after while condition")?;
+
+        let mut stmt = Node::with_value(NodeType::Statement, "while".to_string());
+        stmt.position = Some(position);
+        stmt.add_child(condition);
+        stmt.add_child(self.statement()?);
+        Ok(stmt)
+    }
+
+    /// `for (init; condition; step) body`; omitted clauses become empty blocks.
+    fn for_statement(&mut self) -> Result<Node, ParseError> {
+        let position = Position::of(self.previous());
+        self.consume(TokenType::LeftParen, "Expected '(' after 'for'")?;
+
+        let init = if self.match_token(&[TokenType::Semicolon]) {
+            Node::new(NodeType::Block)
+        } else if self.type_length(self.current).is_some() {
+            let init = self.var_declaration()?;
+            // The loop has room for one declaration only
+            if let Some(extra) = self.pending.pop() {
+                let position = extra.position.map_or((0, 0), |p| (p.line, p.column));
+                return Err(ParseError::UnexpectedToken(
+                    TokenType::Comma,
+                    position.0,
+                    position.1,
+                ));
+            }
+            init
+        } else {
+            self.expression_statement()?
+        };
+
+        let condition = if self.check(&TokenType::Semicolon) {
+            Node::new(NodeType::Block)
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expected ';' after loop condition")?;
+
+        let step = if self.check(&TokenType::RightParen) {
+            Node::new(NodeType::Block)
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::RightParen, "Expected ')' after for clauses")?;
+
+        let mut stmt = Node::with_value(NodeType::Statement, "for".to_string());
+        stmt.position = Some(position);
+        stmt.add_child(init);
+        stmt.add_child(condition);
+        stmt.add_child(step);
+        stmt.add_child(self.statement()?);
+        Ok(stmt)
+    }
+
     fn block_statement(&mut self) -> Result<Node, ParseError> {
         let mut block = Node::new(NodeType::Block);
+        // The opening brace has already been consumed
+        block.position = Some(Position::of(self.previous()));
 
         while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
             let stmt = self.declaration()?;
             block.add_child(stmt);
+            block.children.append(&mut self.pending);
         }
 
         self.consume(TokenType::RightBrace, "Expected '}' after block")?;
@@ -186,21 +1036,327 @@ impl<'a> Parser<'a> {
     }
 
     fn expression(&mut self) -> Result<Node, ParseError> {
+        self.nested(Self::assignment)
+    }
+
+    fn assignment(&mut self) -> Result<Node, ParseError> {
+        let target = self.conditional()?;
+
+        if self.match_token(&[TokenType::Equal]) {
+            let value = self.nested(Self::assignment)?;
+            return Ok(Self::binary("=", target, value));
+        }
+        if let Some(operator) = compound_operator(self.peek().token_type) {
+            self.check_updatable(&target, self.current)?;
+            self.advance();
+            let value = self.nested(Self::assignment)?;
+            return Ok(Self::compound_assignment(target, operator, value));
+        }
+
+        Ok(target)
+    }
+
+    /// `target op= value` as `target = target op value`, so `target` is
+    /// evaluated twice.
+    fn compound_assignment(target: Node, operator: &str, value: Node) -> Node {
+        let result = Self::binary(operator, target.clone(), value);
+        Self::binary("=", target, result)
+    }
+
+    /// Fail at the operator token `operator` unless `target` is a variable,
+    /// element or field whose evaluation has no side effects, since
+    /// `++`, `--` and compound assignments evaluate it twice.
+    fn check_updatable(&self, target: &Node, operator: usize) -> Result<(), ParseError> {
+        let place = match target.node_type {
+            NodeType::Identifier => true,
+            NodeType::Expression => matches!(target.value.as_deref(), Some("index" | "." | "->")),
+            _ => false,
+        };
+        let mut pending = vec![target];
+        let mut pure = true;
+        while let Some(node) = pending.pop() {
+            pure &= !(node.node_type == NodeType::Expression
+                && matches!(node.value.as_deref(), Some("=" | "call")));
+            pending.extend(&node.children);
+        }
+        if place && pure {
+            return Ok(());
+        }
+        let token = &self.tokens[operator];
+        Err(ParseError::UnexpectedToken(
+            token.token_type,
+            token.line,
+            token.column,
+        ))
+    }
+
+    /// `++target` or `--target` as `target += 1`, or `target++` and
+    /// `target--` as `(target += 1) - 1`: the result is computed in 64 bits,
+    /// so the old value of a narrow `target` that wrapped isn't recovered.
+    fn increment(&self, target: Node, operator: &str, postfix: bool) -> Node {
+        let mut one = Node::with_value(NodeType::Literal, "1".to_string());
+        one.position = Some(Position::of(self.previous()));
+        let updated = Self::compound_assignment(target, operator, one.clone());
+        if !postfix {
+            return updated;
+        }
+        let undo = if operator == "+" { "-" } else { "+" };
+        Self::binary(undo, updated, one)
+    }
+
+    /// `cond ? a : b`, binding looser than `||` and nesting to the right.
+    fn conditional(&mut self) -> Result<Node, ParseError> {
+        let condition = self.logical_or()?;
+        if !self.match_token(&[TokenType::Question]) {
+            return Ok(condition);
+        }
+        let then = self.expression()?;
+        self.consume(TokenType::Colon, "Expected ':' in conditional expression")?;
+        let otherwise = self.nested(Self::conditional)?;
+
+        let mut node = Node::new(NodeType::Ternary);
+        node.position = condition.position;
+        for child in [condition, then, otherwise] {
+            node.add_child(child);
+        }
+        Ok(node)
+    }
+
+    fn logical_or(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[TokenType::Or], Self::logical_and)
+    }
+
+    fn logical_and(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[TokenType::And], Self::bitwise_or)
+    }
+
+    fn bitwise_or(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[TokenType::Pipe], Self::bitwise_xor)
+    }
+
+    fn bitwise_xor(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[TokenType::Caret], Self::bitwise_and)
+    }
+
+    fn bitwise_and(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[TokenType::Ampersand], Self::equality)
+    }
+
+    fn equality(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(
+            &[TokenType::EqualEqual, TokenType::BangEqual],
+            Self::comparison,
+        )
+    }
+
+    fn comparison(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(
+            &[
+                TokenType::Less,
+                TokenType::LessEqual,
+                TokenType::Greater,
+                TokenType::GreaterEqual,
+            ],
+            Self::shift,
+        )
+    }
+
+    fn shift(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[TokenType::ShiftLeft, TokenType::ShiftRight], Self::term)
+    }
+
+    fn term(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(&[TokenType::Plus, TokenType::Minus], Self::factor)
+    }
+
+    fn factor(&mut self) -> Result<Node, ParseError> {
+        self.binary_level(
+            &[TokenType::Star, TokenType::Slash, TokenType::Percent],
+            Self::unary,
+        )
+    }
+
+    /// Parse a left-associative chain of `operators` over `operand`.
+    fn binary_level(
+        &mut self,
+        operators: &[TokenType],
+        operand: fn(&mut Self) -> Result<Node, ParseError>,
+    ) -> Result<Node, ParseError> {
+        let mut expr = operand(self)?;
+        let mut depth = tree_depth(&expr);
+
+        while self.match_token(operators) {
+            let operator = self.previous().lexeme.to_string();
+            let right = operand(self)?;
+            depth = self.chain_link(depth, tree_depth(&right))?;
+            expr = Self::binary(&operator, expr, right);
+        }
+
+        Ok(expr)
+    }
+
+    fn binary(operator: &str, left: Node, right: Node) -> Node {
+        let mut node = Node::with_value(NodeType::Expression, operator.to_string());
+        node.position = left.position;
+        node.add_child(left);
+        node.add_child(right);
+        node
+    }
+
+    fn unary(&mut self) -> Result<Node, ParseError> {
+        if self.match_token(&[TokenType::Minus, TokenType::Bang]) {
+            let operator = match self.previous().token_type {
+                TokenType::Minus => "neg",
+                _ => "!",
+            };
+            let mut node = Node::with_value(NodeType::Expression, operator.to_string());
+            node.position = Some(Position::of(self.previous()));
+            node.add_child(self.nested(Self::unary)?);
+            return Ok(node);
+        }
+        if self.match_token(&[TokenType::Star]) {
+            // `*p` is `p[0]`
+            let position = Position::of(self.previous());
+            let pointer = self.nested(Self::unary)?;
+            let mut zero = Node::with_value(NodeType::Literal, "0".to_string());
+            zero.position = Some(position);
+            let mut node = Self::binary("index", pointer, zero);
+            node.position = Some(position);
+            return Ok(node);
+        }
+        if self.match_token(&[TokenType::Ampersand]) {
+            let mut node = Node::with_value(NodeType::Expression, "addr".to_string());
+            node.position = Some(Position::of(self.previous()));
+            node.add_child(self.nested(Self::unary)?);
+            return Ok(node);
+        }
+        if self.match_token(&[TokenType::PlusPlus, TokenType::MinusMinus]) {
+            let token = self.current - 1;
+            let operator = increment_operator(self.previous().token_type);
+            let target = self.nested(Self::unary)?;
+            self.check_updatable(&target, token)?;
+            return Ok(self.increment(target, operator, false));
+        }
+        if self.match_token(&[TokenType::Sizeof]) {
+            return self.sizeof();
+        }
+        if self.is_parenthesized_type() {
+            let mut node = Node::new(NodeType::Cast);
+            node.position = Some(Position::of(self.peek()));
+            node.value = Some(self.parenthesized_type()?);
+            node.add_child(self.nested(Self::unary)?);
+            return Ok(node);
+        }
+
         self.call()
     }
 
+    /// The operand of `sizeof`: a parenthesized type such as `U8*`,
+    /// `struct Account` or `U64[4]`, or an expression.
+    fn sizeof(&mut self) -> Result<Node, ParseError> {
+        let mut node = Node::new(NodeType::Sizeof);
+        node.position = Some(Position::of(self.previous()));
+
+        if !self.is_parenthesized_type() {
+            node.add_child(self.unary()?);
+            return Ok(node);
+        }
+        node.value = Some(self.parenthesized_type()?);
+        Ok(node)
+    }
+
+    /// Parse a type in parentheses, as [`Self::is_parenthesized_type`] found.
+    fn parenthesized_type(&mut self) -> Result<String, ParseError> {
+        self.consume(TokenType::LeftParen, "Expected '(' before type")?;
+        let type_str = if self.type_length(self.current).is_some() {
+            self.type_name()?
+        } else {
+            // A declared struct's bare name followed by `*`s
+            let name = self.advance().lexeme.to_string();
+            self.pointer_stars(name)?
+        };
+        let type_str = type_str + &self.array_dimensions()?;
+        self.consume(TokenType::RightParen, "Expected ')' after type")?;
+        Ok(type_str)
+    }
+
+    /// Whether a `(` follows that opens a type rather than an expression. A
+    /// bare identifier is parsed as an expression; codegen resolves it to a
+    /// struct when no variable has that name.
+    fn is_parenthesized_type(&self) -> bool {
+        if self.token_at(self.current) != TokenType::LeftParen {
+            return false;
+        }
+        if self.type_length(self.current + 1).is_some() {
+            return true;
+        }
+        let mut index = self.current + 2;
+        while self.token_at(index) == TokenType::Star {
+            index += 1;
+        }
+        self.token_at(self.current + 1) == TokenType::Identifier
+            && index > self.current + 2
+            && self.token_at(index) == TokenType::RightParen
+    }
+
     fn call(&mut self) -> Result<Node, ParseError> {
         let mut expr = self.primary()?;
+        let mut depth = tree_depth(&expr);
 
-        while self.match_token(&[TokenType::LeftParen]) {
-            expr = self.finish_call(expr)?;
+        loop {
+            if self.match_token(&[TokenType::LeftParen]) {
+                expr = if Self::is_member_access(&expr) {
+                    // `obj.method(args)` calls `method(obj, args)`: a struct
+                    // evaluates to its address, so `.` and `->` both pass a pointer
+                    let [object, method]: [Node; 2] = expr
+                        .children
+                        .try_into()
+                        .expect("member access has two children");
+                    let mut call = self.finish_call(method)?;
+                    call.children.insert(1, object);
+                    call
+                } else {
+                    self.finish_call(expr)?
+                };
+                let args = expr.children[1..].iter().map(tree_depth).max();
+                depth = self.chain_link(depth, args.unwrap_or(0))?;
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                // Indexing, recorded as "index" [base, index]
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "Expected ']' after index")?;
+                depth = self.chain_link(depth, tree_depth(&index))?;
+                expr = Self::binary("index", expr, index);
+            } else if self.check(&TokenType::PlusPlus) || self.check(&TokenType::MinusMinus) {
+                self.check_updatable(&expr, self.current)?;
+                let operator = increment_operator(self.advance().token_type);
+                expr = self.increment(expr, operator, true);
+            } else if self.match_token(&[TokenType::Dot, TokenType::Arrow]) {
+                // Member access, recorded as "." or "->" [object, field]
+                let operator = self.previous().lexeme.to_string();
+                let field_token = self.consume(TokenType::Identifier, "Expected field name")?;
+                let mut field =
+                    Node::with_value(NodeType::Identifier, field_token.lexeme.to_string());
+                field.position = Some(Position::of(field_token));
+                depth = self.chain_link(depth, 1)?;
+                expr = Self::binary(&operator, expr, field);
+            } else {
+                break;
+            }
         }
 
         Ok(expr)
     }
 
+    /// Whether `expr` is a member access `object.field` or `object->field`.
+    fn is_member_access(expr: &Node) -> bool {
+        expr.node_type == NodeType::Expression
+            && matches!(expr.value.as_deref(), Some(".") | Some("->"))
+            && expr.children.len() == 2
+    }
+
     fn finish_call(&mut self, callee: Node) -> Result<Node, ParseError> {
         let mut call = Node::with_value(NodeType::Expression, "call".to_string());
+        call.position = callee.position;
         call.add_child(callee);
 
         if !self.check(&TokenType::RightParen) {
@@ -225,14 +1381,21 @@ impl<'a> Parser<'a> {
             TokenType::NumberLiteral,
             TokenType::StringLiteral,
         ]) {
-            let token_str = self.previous().lexeme.to_string();
+            let token_str = number_value(self.previous().lexeme);
             let mut node = Node::new(NodeType::Literal);
             node.value = Some(token_str);
+            node.position = Some(Position::of(self.previous()));
+            Ok(node)
+        } else if self.match_token(&[TokenType::FloatLiteral]) {
+            let mut node =
+                Node::with_value(NodeType::FloatLiteral, self.previous().lexeme.to_string());
+            node.position = Some(Position::of(self.previous()));
             Ok(node)
         } else if self.match_token(&[TokenType::Identifier, TokenType::PrintF]) {
             let token_str = self.previous().lexeme.to_string();
             let mut node = Node::new(NodeType::Identifier);
             node.value = Some(token_str);
+            node.position = Some(Position::of(self.previous()));
             Ok(node)
         } else if self.match_token(&[TokenType::LeftParen]) {
             let expr = self.expression()?;
@@ -242,10 +1405,40 @@ impl<'a> Parser<'a> {
             Err(ParseError::UnexpectedToken(
                 self.peek().token_type,
                 self.peek().line,
+                self.peek().column,
             ))
         }
     }
 
+    /// Run a rule one nesting level deeper, failing instead of recursing
+    /// past `max_depth`.
+    fn nested(
+        &mut self,
+        rule: fn(&mut Self) -> Result<Node, ParseError>,
+    ) -> Result<Node, ParseError> {
+        if self.depth >= self.max_depth {
+            let token = self.peek();
+            return Err(ParseError::NestingTooDeep(token.line, token.column));
+        }
+        self.depth += 1;
+        let result = rule(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Depth of a chain such as `a + b + c`, `x[0].y` or `U8**`, `depth` levels
+    /// deep, once extended with an operand `operand_depth` levels deep. The
+    /// loops building chains don't recurse, but later passes do, so the tree
+    /// may not grow past [`MAX_TREE_DEPTH`].
+    fn chain_link(&self, depth: usize, operand_depth: usize) -> Result<usize, ParseError> {
+        let depth = 1 + depth.max(operand_depth);
+        if depth > MAX_TREE_DEPTH {
+            let token = self.previous();
+            return Err(ParseError::NestingTooDeep(token.line, token.column));
+        }
+        Ok(depth)
+    }
+
     fn match_token(&mut self, types: &[TokenType]) -> bool {
         for token_type in types {
             if self.check(token_type) {
@@ -279,8 +1472,9 @@ impl<'a> Parser<'a> {
         &self.tokens[self.current]
     }
 
+    /// The token just consumed, or the first one before any is.
     fn previous(&self) -> &Token<'a> {
-        &self.tokens[self.current - 1]
+        &self.tokens[self.current.saturating_sub(1)]
     }
 
     fn consume(&mut self, token_type: TokenType, _message: &str) -> Result<&Token<'a>, ParseError> {
@@ -291,28 +1485,54 @@ impl<'a> Parser<'a> {
                 expected: token_type,
                 found: self.peek().token_type,
                 line: self.peek().line,
+                column: self.peek().column,
             })
         }
     }
+}
 
-    fn synchronize(&mut self) {
-        self.advance();
-
-        while !self.is_at_end() {
-            if self.previous().token_type == TokenType::Semicolon {
-                return;
-            }
+/// Levels of `node`'s tree, counted without recursing.
+fn tree_depth(node: &Node) -> usize {
+    let mut deepest = 0;
+    let mut pending = vec![(node, 1)];
+    while let Some((node, depth)) = pending.pop() {
+        deepest = deepest.max(depth);
+        pending.extend(node.children.iter().map(|child| (child, depth + 1)));
+    }
+    deepest
+}
 
-            match self.peek().token_type {
-                TokenType::Class
-                | TokenType::For
-                | TokenType::If
-                | TokenType::While
-                | TokenType::Return => return,
-                _ => {}
-            }
+/// Arithmetic operator of a compound assignment token such as `+=`.
+fn compound_operator(token_type: TokenType) -> Option<&'static str> {
+    match token_type {
+        TokenType::PlusEqual => Some("+"),
+        TokenType::MinusEqual => Some("-"),
+        TokenType::StarEqual => Some("*"),
+        TokenType::SlashEqual => Some("/"),
+        TokenType::PercentEqual => Some("%"),
+        TokenType::ShiftLeftEqual => Some("<<"),
+        TokenType::ShiftRightEqual => Some(">>"),
+        TokenType::AmpersandEqual => Some("&"),
+        TokenType::PipeEqual => Some("|"),
+        TokenType::CaretEqual => Some("^"),
+        _ => None,
+    }
+}
 
-            self.advance();
-        }
+/// `+` for `++`, `-` for `--`.
+fn increment_operator(token_type: TokenType) -> &'static str {
+    match token_type {
+        TokenType::PlusPlus => "+",
+        _ => "-",
     }
 }
+
+/// Decimal text of a number literal, converting `0x` hex literals so later
+/// passes only see decimal.
+fn number_value(lexeme: &str) -> String {
+    lexeme
+        .strip_prefix("0x")
+        .or_else(|| lexeme.strip_prefix("0X"))
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())
+        .map_or_else(|| lexeme.to_string(), |value| value.to_string())
+}
@@ -0,0 +1,428 @@
+use crate::pible::prelude::*;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PreprocessError {
+    #[error("{file}:{line}: cannot read included file \"{path}\": {reason}")]
+    MissingInclude {
+        file: String,
+        line: usize,
+        path: String,
+        reason: String,
+    },
+    #[error("{file}:{line}: malformed #include, expected #include \"file.hc\"")]
+    MalformedInclude { file: String, line: usize },
+    #[error("include cycle: {}", .0.join(" -> "))]
+    IncludeCycle(Vec<String>),
+    #[error("{file}:{line}: malformed #{directive}")]
+    MalformedDirective {
+        file: String,
+        line: usize,
+        directive: String,
+    },
+    #[error("{file}:{line}: macro {name} takes {expected} argument(s), found {found}")]
+    MacroArguments {
+        file: String,
+        line: usize,
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+}
+
+/// Where a line of preprocessed source came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    /// File the line was read from; `None` for source passed in directly
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+/// Source with every `#include` spliced in and macros expanded, remembering
+/// the origin of each line.
+#[derive(Debug, Clone)]
+pub struct Preprocessed {
+    pub source: String,
+    lines: Vec<SourceLine>,
+    pub pragmas: Pragmas,
+    /// Every file spliced in by `#include`, directly or through another
+    /// include, in the order first reached
+    pub includes: Vec<String>,
+}
+
+/// Settings from `#pragma name("value")` lines; a later pragma overrides an
+/// earlier one.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Pragmas {
+    /// `#pragma license("GPL")`: the license recorded in a Linux BPF object
+    pub license: Option<String>,
+    /// `#pragma section("xdp")`: the ELF section holding the program
+    pub section: Option<String>,
+}
+
+impl Preprocessed {
+    /// File and line that line `line` (1-based) of [`Self::source`] came from.
+    pub fn origin(&self, line: usize) -> SourceLine {
+        line.checked_sub(1)
+            .and_then(|index| self.lines.get(index))
+            .or(self.lines.last())
+            .cloned()
+            .unwrap_or(SourceLine { file: None, line })
+    }
+}
+
+/// A `#define`: function-like macros have a parameter list.
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Option<Vec<String>>,
+    body: String,
+}
+
+/// Preprocess `source` read from `path`, or given directly when `path` is
+/// `None`, in which case includes resolve relative to the working directory.
+/// Without the `std` feature any `#include` fails as a missing file.
+pub fn preprocess(source: &str, path: Option<&str>) -> Result<Preprocessed, PreprocessError> {
+    let mut preprocessor = Preprocessor {
+        macros: HashMap::new(),
+        stack: Vec::new(),
+        output: Preprocessed {
+            source: String::new(),
+            lines: Vec::new(),
+            pragmas: Pragmas::default(),
+            includes: Vec::new(),
+        },
+    };
+    if let Some(path) = path {
+        preprocessor.stack.push(canonical(path));
+    }
+    preprocessor.expand(source, path)?;
+    Ok(preprocessor.output)
+}
+
+struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    /// Canonical paths of the files currently being expanded, for cycle detection
+    stack: Vec<String>,
+    output: Preprocessed,
+}
+
+impl Preprocessor {
+    fn expand(&mut self, source: &str, path: Option<&str>) -> Result<(), PreprocessError> {
+        let file_name = path.unwrap_or("<input>").to_string();
+
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let Some(directive) = text.trim_start().strip_prefix('#') else {
+                let expanded =
+                    self.substitute(text, &HashSet::new())
+                        .map_err(|(name, expected, found)| PreprocessError::MacroArguments {
+                            file: file_name.clone(),
+                            line,
+                            name,
+                            expected,
+                            found,
+                        })?;
+                self.output.source.push_str(&expanded);
+                self.output.source.push('\n');
+                self.output.lines.push(SourceLine {
+                    file: path.map(str::to_string),
+                    line,
+                });
+                continue;
+            };
+
+            let (name, rest) = split_identifier(directive.trim_start());
+            let malformed = || PreprocessError::MalformedDirective {
+                file: file_name.clone(),
+                line,
+                directive: name.to_string(),
+            };
+            match name {
+                "include" => self.include(rest, path, &file_name, line)?,
+                "define" => {
+                    let (name, definition) = split_identifier(rest.trim_start());
+                    if name.is_empty() {
+                        return Err(malformed());
+                    }
+                    let definition = parse_definition(definition).ok_or_else(malformed)?;
+                    self.macros.insert(name.to_string(), definition);
+                }
+                "undef" => {
+                    let (name, rest) = split_identifier(rest.trim_start());
+                    if name.is_empty() || !rest.trim().is_empty() {
+                        return Err(malformed());
+                    }
+                    self.macros.remove(name);
+                }
+                "pragma" => {
+                    let (name, argument) = split_identifier(rest.trim_start());
+                    let value = parse_pragma_argument(argument).ok_or_else(malformed)?;
+                    let pragmas = &mut self.output.pragmas;
+                    match name {
+                        "license" => pragmas.license = Some(value),
+                        "section" => pragmas.section = Some(value),
+                        _ => return Err(malformed()),
+                    }
+                }
+                _ => return Err(malformed()),
+            }
+
+            // Keep line numbers stable for the lines that follow
+            if name != "include" {
+                self.output.source.push('\n');
+                self.output.lines.push(SourceLine {
+                    file: path.map(str::to_string),
+                    line,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn include(
+        &mut self,
+        directive: &str,
+        path: Option<&str>,
+        file_name: &str,
+        line: usize,
+    ) -> Result<(), PreprocessError> {
+        let included = directive
+            .trim()
+            .strip_prefix('"')
+            .and_then(|rest| rest.strip_suffix('"'))
+            .ok_or_else(|| PreprocessError::MalformedInclude {
+                file: file_name.to_string(),
+                line,
+            })?;
+
+        let (include_path, content) =
+            read_include(path, included).map_err(|reason| PreprocessError::MissingInclude {
+                file: file_name.to_string(),
+                line,
+                path: included.to_string(),
+                reason,
+            })?;
+
+        let key = canonical(&include_path);
+        if let Some(start) = self.stack.iter().position(|open| *open == key) {
+            let mut cycle = self.stack[start..].to_vec();
+            cycle.push(key);
+            return Err(PreprocessError::IncludeCycle(cycle));
+        }
+
+        if !self.output.includes.contains(&include_path) {
+            self.output.includes.push(include_path.clone());
+        }
+        self.stack.push(key);
+        self.expand(&content, Some(&include_path))?;
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Expand macros in `text`. Macros in `disabled` are mid-expansion and
+    /// left alone, so self-referential macros terminate. Fails with the
+    /// macro name and expected and found argument counts on a bad call.
+    fn substitute(
+        &self,
+        text: &str,
+        disabled: &HashSet<String>,
+    ) -> Result<String, (String, usize, usize)> {
+        let mut output = String::new();
+        let mut rest = text;
+
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("//") {
+                output.push_str(rest);
+                break;
+            }
+            if c == '"' {
+                // String literals are copied verbatim
+                let end = rest[1..].find('"').map_or(rest.len(), |end| end + 2);
+                output.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            if c.is_ascii_digit() {
+                // Numbers, including any suffix letters, are never macro names
+                let end = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                    .unwrap_or(rest.len());
+                output.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            if !(c.is_ascii_alphabetic() || c == '_') {
+                output.push(c);
+                rest = &rest[c.len_utf8()..];
+                continue;
+            }
+
+            let (name, after) = split_identifier(rest);
+            rest = after;
+            let Some(definition) = self.macros.get(name).filter(|_| !disabled.contains(name))
+            else {
+                output.push_str(name);
+                continue;
+            };
+
+            let body = match &definition.params {
+                None => definition.body.clone(),
+                Some(params) => {
+                    // A function-like macro name without arguments is left as is
+                    let Some((args, after)) = split_arguments(rest) else {
+                        output.push_str(name);
+                        continue;
+                    };
+                    rest = after;
+                    if args.len() != params.len() {
+                        return Err((name.to_string(), params.len(), args.len()));
+                    }
+                    let args: Vec<String> = args
+                        .iter()
+                        .map(|arg| self.substitute(arg.trim(), disabled))
+                        .collect::<Result<_, _>>()?;
+                    replace_parameters(&definition.body, params, &args)
+                }
+            };
+
+            let mut disabled = disabled.clone();
+            disabled.insert(name.to_string());
+            output.push_str(&self.substitute(&body, &disabled)?);
+        }
+
+        Ok(output)
+    }
+}
+
+/// Parse what follows a macro name: `(a, b) body` immediately after the name
+/// makes a function-like macro, anything else is an object-like body.
+fn parse_definition(definition: &str) -> Option<Macro> {
+    let Some(rest) = definition.strip_prefix('(') else {
+        return Some(Macro {
+            params: None,
+            body: definition.trim().to_string(),
+        });
+    };
+
+    let close = rest.find(')')?;
+    let params: Vec<String> = rest[..close]
+        .split(',')
+        .map(|param| param.trim().to_string())
+        .filter(|param| !param.is_empty())
+        .collect();
+    if params
+        .iter()
+        .any(|param| split_identifier(param).0.len() != param.len())
+    {
+        return None;
+    }
+    Some(Macro {
+        params: Some(params),
+        body: rest[close + 1..].trim().to_string(),
+    })
+}
+
+/// The string in a pragma's `("value")` argument.
+fn parse_pragma_argument(argument: &str) -> Option<String> {
+    let value = argument
+        .trim()
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .trim()
+        .strip_prefix('"')?
+        .strip_suffix('"')?;
+    (!value.is_empty() && !value.contains('"')).then(|| value.to_string())
+}
+
+/// Split `(a, f(b, c))` off the start of `text` (after optional spaces) into
+/// its top-level arguments and the text that follows.
+fn split_arguments(text: &str) -> Option<(Vec<&str>, &str)> {
+    let start = text.len() - text.trim_start().len();
+    let text = &text[start..];
+    if !text.starts_with('(') {
+        return None;
+    }
+
+    let mut args = Vec::new();
+    let mut depth = 0;
+    let mut arg_start = 1;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    let last = &text[arg_start..i];
+                    if !(args.is_empty() && last.trim().is_empty()) {
+                        args.push(last);
+                    }
+                    return Some((args, &text[i + 1..]));
+                }
+            }
+            ',' if depth == 1 => {
+                args.push(&text[arg_start..i]);
+                arg_start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Replace whole-identifier occurrences of `params` in `body` with `args`.
+fn replace_parameters(body: &str, params: &[String], args: &[String]) -> String {
+    let mut output = String::new();
+    let mut rest = body;
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphabetic() || c == '_' {
+            let (name, after) = split_identifier(rest);
+            match params.iter().position(|param| param == name) {
+                Some(index) => output.push_str(&args[index]),
+                None => output.push_str(name),
+            }
+            rest = after;
+        } else {
+            output.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    output
+}
+
+/// Split a leading identifier off `text`; the identifier is empty if `text`
+/// doesn't start with one.
+fn split_identifier(text: &str) -> (&str, &str) {
+    let end = text
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_alphanumeric() || c == '_') || (i == 0 && c.is_ascii_digit()))
+        .map_or(text.len(), |(i, _)| i);
+    text.split_at(end)
+}
+
+/// Read the file `included` names, relative to the directory of the
+/// including file `from`. Returns its path and contents.
+#[cfg(feature = "std")]
+fn read_include(from: Option<&str>, included: &str) -> Result<(String, String), String> {
+    use std::path::Path;
+
+    let directory = from.and_then(|from| Path::new(from).parent());
+    let path = directory.unwrap_or(Path::new("")).join(included);
+    let content = std::fs::read_to_string(&path).map_err(|e| e.to_string())?;
+    Ok((path.display().to_string(), content))
+}
+
+#[cfg(not(feature = "std"))]
+fn read_include(_from: Option<&str>, _included: &str) -> Result<(String, String), String> {
+    Err("includes need the std feature".to_string())
+}
+
+#[cfg(feature = "std")]
+fn canonical(path: &str) -> String {
+    std::fs::canonicalize(path).map_or_else(|_| path.to_string(), |path| path.display().to_string())
+}
+
+#[cfg(not(feature = "std"))]
+fn canonical(path: &str) -> String {
+    path.to_string()
+}
@@ -0,0 +1,364 @@
+use crate::pible::codegen::{bpf_opcodes, BpfInstruction};
+use crate::pible::prelude::*;
+use alloc::collections::BTreeSet;
+
+/// A value in a [`Block`] before it's given a register.
+pub type VReg = usize;
+
+const FRAME_POINTER: u8 = 10;
+
+/// One operation of the allocator's IR. Operations are two-address like
+/// BPF's own: an ALU operation reads and overwrites `dst`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inst {
+    /// `dst = value`
+    Const { dst: VReg, value: i64 },
+    /// `dst = *(u64 *)(r10 + offset)`
+    Load { dst: VReg, offset: i16 },
+    /// `dst op= src`; `offset` selects signed division as in the instruction
+    Alu {
+        op: u8,
+        dst: VReg,
+        src: VReg,
+        offset: i16,
+    },
+    /// `dst op= value`
+    AluImm {
+        op: u8,
+        dst: VReg,
+        value: i32,
+        offset: i16,
+    },
+    /// `dst = -dst`
+    Neg { dst: VReg },
+}
+
+impl Inst {
+    /// Values the operation reads.
+    fn uses(&self) -> Vec<VReg> {
+        match *self {
+            Inst::Const { .. } | Inst::Load { .. } => vec![],
+            Inst::Alu { dst, src, .. } => vec![dst, src],
+            Inst::AluImm { dst, .. } | Inst::Neg { dst } => vec![dst],
+        }
+    }
+
+    /// The value the operation writes.
+    fn def(&self) -> VReg {
+        match *self {
+            Inst::Const { dst, .. }
+            | Inst::Load { dst, .. }
+            | Inst::Alu { dst, .. }
+            | Inst::AluImm { dst, .. }
+            | Inst::Neg { dst } => dst,
+        }
+    }
+}
+
+/// Straight-line code over virtual registers, such as an arithmetic
+/// expression with no calls or branches.
+#[derive(Debug, Clone, Default)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    vregs: usize,
+}
+
+impl Block {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A virtual register no operation has used yet.
+    pub fn vreg(&mut self) -> VReg {
+        self.vregs += 1;
+        self.vregs - 1
+    }
+
+    pub fn push(&mut self, inst: Inst) {
+        self.insts.push(inst);
+    }
+}
+
+/// Where a value is live: from the operation defining it to the last one
+/// reading it, or past the end of the block if it's live out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The live range of each virtual register of `block`, `None` for one that's
+/// never defined. Values in `live_out` are read after the block ends. In
+/// straight-line code a value is live from its first definition to its last
+/// read.
+pub fn live_ranges(block: &Block, live_out: &[VReg]) -> Vec<Option<LiveRange>> {
+    let mut ranges: Vec<Option<LiveRange>> = vec![None; block.vregs];
+    for (index, inst) in block.insts.iter().enumerate() {
+        for vreg in inst.uses() {
+            if let Some(range) = &mut ranges[vreg] {
+                range.end = index;
+            }
+        }
+        ranges[inst.def()].get_or_insert(LiveRange {
+            start: index,
+            end: index,
+        });
+    }
+    for &vreg in live_out {
+        if let Some(range) = &mut ranges[vreg] {
+            range.end = block.insts.len();
+        }
+    }
+    ranges
+}
+
+/// Where a value ends up once its block has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Register(u8),
+    /// Index of a stack slot, which the caller maps to a frame offset
+    Spill(usize),
+}
+
+/// A stretch of instructions over which one register holds a value.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Piece {
+    vreg: VReg,
+    start: usize,
+    end: usize,
+    reg: u8,
+    /// Whether the value is loaded from its stack slot before `start`
+    reloaded: bool,
+    /// Whether the value is stored to its stack slot after `end`, to free
+    /// the register for another
+    evicted: bool,
+}
+
+/// The registers a block's values are held in as it runs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    pieces: Vec<Piece>,
+    /// Stack slot of each value evicted from its register at some point
+    slots: Vec<Option<usize>>,
+    /// Number of stack slots the evicted values use
+    pub spill_slots: usize,
+    len: usize,
+}
+
+impl Allocation {
+    /// The register holding `vreg` while instruction `index` runs.
+    pub fn register(&self, vreg: VReg, index: usize) -> Option<u8> {
+        self.pieces
+            .iter()
+            .find(|piece| piece.vreg == vreg && (piece.start..=piece.end).contains(&index))
+            .map(|piece| piece.reg)
+    }
+
+    /// Where the live-out value `vreg` is after the block.
+    pub fn result(&self, vreg: VReg) -> Location {
+        match self.register(vreg, self.len) {
+            Some(reg) => Location::Register(reg),
+            None => Location::Spill(self.slots[vreg].unwrap_or(0)),
+        }
+    }
+
+    /// Number of values stored to free a register, and of loads bringing
+    /// them back.
+    pub fn spills(&self) -> (usize, usize) {
+        let count = |f: fn(&Piece) -> bool| self.pieces.iter().filter(|p| f(p)).count();
+        (count(|piece| piece.evicted), count(|piece| piece.reloaded))
+    }
+}
+
+/// Assign the values of `block` to `registers` by linear scan over their
+/// live ranges. When more values are live than there are registers, the one
+/// read again furthest away is stored to the stack and loaded back into a
+/// free register just before that read, splitting its range. Ties go to the
+/// lower virtual register and free registers are taken in the order given,
+/// so the result is deterministic.
+///
+/// An instruction reads at most two values, so three registers are enough.
+pub fn allocate(block: &Block, registers: &[u8], live_out: &[VReg]) -> Allocation {
+    assert!(
+        registers.len() >= 3,
+        "register allocation needs three registers"
+    );
+    let len = block.insts.len();
+    let ranges = live_ranges(block, live_out);
+
+    // Instructions reading each value, ending with the block's end for a
+    // live-out one
+    let mut reads: Vec<Vec<usize>> = vec![Vec::new(); ranges.len()];
+    for (index, inst) in block.insts.iter().enumerate() {
+        for vreg in inst.uses() {
+            reads[vreg].push(index);
+        }
+    }
+    for &vreg in live_out {
+        reads[vreg].push(len);
+    }
+    let next_read = |vreg: VReg, from: usize| {
+        reads[vreg]
+            .iter()
+            .copied()
+            .find(|&index| index >= from)
+            .unwrap_or(len)
+    };
+
+    // Stretches waiting for a register, as (start, vreg, reloaded)
+    let mut unhandled: BTreeSet<(usize, VReg, bool)> = ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(vreg, range)| range.map(|range| (range.start, vreg, false)))
+        .collect();
+    let mut allocation = Allocation {
+        pieces: Vec::new(),
+        slots: vec![None; ranges.len()],
+        spill_slots: 0,
+        len,
+    };
+    // Free registers by their position in `registers`
+    let mut free: BTreeSet<usize> = (0..registers.len()).collect();
+    // Pieces holding a register, as (end, piece)
+    let mut active: BTreeSet<(usize, usize)> = BTreeSet::new();
+
+    while let Some((start, vreg, reloaded)) = unhandled.pop_first() {
+        // Registers of values dead before this stretch starts come free again
+        while let Some(&(end, piece)) = active.first() {
+            if end >= start {
+                break;
+            }
+            active.remove(&(end, piece));
+            let reg = allocation.pieces[piece].reg;
+            free.extend(registers.iter().position(|&r| r == reg));
+        }
+
+        let reg = match free.pop_first() {
+            Some(index) => registers[index],
+            None => {
+                // Evict the value read again furthest away, never one this
+                // instruction reads
+                let (next, victim, end, piece) = active
+                    .iter()
+                    .map(|&(end, piece)| {
+                        let vreg = allocation.pieces[piece].vreg;
+                        (next_read(vreg, start), vreg, end, piece)
+                    })
+                    .filter(|&(next, ..)| next > start)
+                    .max()
+                    .expect("an instruction reads at most two values");
+                active.remove(&(end, piece));
+                allocation.pieces[piece].end = start - 1;
+                allocation.pieces[piece].evicted = true;
+                if allocation.slots[victim].is_none() {
+                    allocation.slots[victim] = Some(allocation.spill_slots);
+                    allocation.spill_slots += 1;
+                }
+                // A value only read after the block stays in its slot
+                if next < len {
+                    unhandled.insert((next, victim, true));
+                }
+                allocation.pieces[piece].reg
+            }
+        };
+
+        let end = ranges[vreg].map_or(start, |range| range.end);
+        active.insert((end, allocation.pieces.len()));
+        allocation.pieces.push(Piece {
+            vreg,
+            start,
+            end,
+            reg,
+            reloaded,
+            evicted: false,
+        });
+    }
+    allocation
+}
+
+/// Generate BPF for `block` as allocated. Evicted values are kept in the
+/// stack slot at `slot_offset(index)` from r10.
+pub fn lower(
+    block: &Block,
+    allocation: &Allocation,
+    slot_offset: impl Fn(usize) -> i16,
+) -> Vec<BpfInstruction> {
+    let mut output = Vec::new();
+    let alu = |op: u8, dst, src, offset, immediate| {
+        BpfInstruction::new(bpf_opcodes::BPF_ALU64 | op, dst, src, offset, immediate)
+    };
+    let slot = |vreg: VReg| slot_offset(allocation.slots[vreg].unwrap_or(0));
+    let register = |vreg: VReg, index: usize| allocation.register(vreg, index).unwrap_or(0);
+
+    for (index, inst) in block.insts.iter().enumerate() {
+        // Free the registers this instruction's values need, then load the
+        // values it reads that were evicted
+        for piece in &allocation.pieces {
+            if piece.evicted && piece.end + 1 == index {
+                output.push(BpfInstruction::new(
+                    bpf_opcodes::BPF_STX | bpf_opcodes::BPF_MEM | bpf_opcodes::BPF_DW,
+                    FRAME_POINTER,
+                    piece.reg,
+                    slot(piece.vreg),
+                    0,
+                ));
+            }
+        }
+        for piece in &allocation.pieces {
+            if piece.reloaded && piece.start == index {
+                output.push(BpfInstruction::new(
+                    bpf_opcodes::BPF_LDX | bpf_opcodes::BPF_MEM | bpf_opcodes::BPF_DW,
+                    piece.reg,
+                    FRAME_POINTER,
+                    slot(piece.vreg),
+                    0,
+                ));
+            }
+        }
+
+        let dst = register(inst.def(), index);
+        match *inst {
+            Inst::Const { value, .. } => match i32::try_from(value) {
+                Ok(immediate) => output.push(alu(
+                    bpf_opcodes::BPF_MOV | bpf_opcodes::BPF_K,
+                    dst,
+                    0,
+                    0,
+                    immediate,
+                )),
+                Err(_) => {
+                    output.push(BpfInstruction::new(
+                        bpf_opcodes::BPF_LD | bpf_opcodes::BPF_IMM | bpf_opcodes::BPF_DW,
+                        dst,
+                        0,
+                        0,
+                        value as i32,
+                    ));
+                    output.push(BpfInstruction::new(0, 0, 0, 0, (value >> 32) as i32));
+                }
+            },
+            Inst::Load { offset, .. } => output.push(BpfInstruction::new(
+                bpf_opcodes::BPF_LDX | bpf_opcodes::BPF_MEM | bpf_opcodes::BPF_DW,
+                dst,
+                FRAME_POINTER,
+                offset,
+                0,
+            )),
+            Inst::Alu {
+                op, src, offset, ..
+            } => output.push(alu(
+                op | bpf_opcodes::BPF_X,
+                dst,
+                register(src, index),
+                offset,
+                0,
+            )),
+            Inst::AluImm {
+                op, value, offset, ..
+            } => output.push(alu(op | bpf_opcodes::BPF_K, dst, 0, offset, value)),
+            Inst::Neg { .. } => output.push(alu(bpf_opcodes::BPF_NEG, dst, 0, 0, 0)),
+        }
+    }
+
+    output
+}
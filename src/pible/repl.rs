@@ -0,0 +1,114 @@
+use crate::pible::codegen;
+use crate::pible::compiler::{CompileError, CompileOptions, Compiler};
+use crate::pible::parser::NodeType;
+use std::io::{self, BufRead, Write};
+
+/// An interactive session: definitions and statements entered so far, which
+/// every later line is compiled after.
+///
+/// Each expression compiles to a fresh VM program whose `main` replays the
+/// session's statements and returns the expression's value, so statements
+/// must be cheap to run again.
+#[derive(Default)]
+pub struct Session {
+    /// Functions, structs, constants and classes
+    definitions: String,
+    /// Statements making up the body of `main`, such as variable declarations
+    statements: String,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluate one line of input. A definition or a statement ending in `;`
+    /// or `}` joins the session and gives `None`; anything else is an
+    /// expression whose value is returned. Lines that fail to compile leave
+    /// the session unchanged.
+    pub fn eval(&mut self, line: &str) -> Result<Option<i32>, CompileError> {
+        let line = line.trim();
+        if line.is_empty() {
+            return Ok(None);
+        }
+
+        if is_definition(line) {
+            let definitions = format!("{}{}\n", self.definitions, line);
+            self.run(&definitions, &self.statements, "0")?;
+            self.definitions = definitions;
+            return Ok(None);
+        }
+        if line.ends_with(';') || line.ends_with('}') {
+            let statements = format!("{}{}\n", self.statements, line);
+            self.run(&self.definitions, &statements, "0")?;
+            self.statements = statements;
+            return Ok(None);
+        }
+        self.run(&self.definitions, &self.statements, line)
+            .map(Some)
+    }
+
+    fn run(&self, definitions: &str, statements: &str, value: &str) -> Result<i32, CompileError> {
+        let source = format!(
+            "{}I64 main() {{\n{}return ({});\n}}\n",
+            definitions, statements, value
+        );
+        // The parser skips a function it can't parse, so check `main` survived
+        let compiler = Compiler::new();
+        let program = compiler.parse(&source)?;
+        if !program
+            .children
+            .iter()
+            .any(|child| codegen::function_name(child) == Some("main"))
+        {
+            return Err(CompileError::InvalidSyntax(
+                "the line doesn't parse".to_string(),
+            ));
+        }
+        compiler
+            .compile_and_run(&source, &CompileOptions::default())
+            .map(|result| result.exit_code)
+    }
+}
+
+/// Whether `line` holds only top-level declarations other than variables.
+fn is_definition(line: &str) -> bool {
+    Compiler::new().parse(line).is_ok_and(|program| {
+        !program.children.is_empty()
+            && program.children.iter().all(|child| {
+                matches!(
+                    child.node_type,
+                    NodeType::FunctionDecl
+                        | NodeType::StructDecl
+                        | NodeType::ConstDecl
+                        | NodeType::EnumDecl
+                        | NodeType::ClassDecl
+                )
+            })
+    })
+}
+
+/// Read lines from `input` until it ends, writing each expression's value
+/// or error to `output`. `prompt` shows `> ` before each line, for a terminal.
+pub fn run(input: impl BufRead, mut output: impl Write, prompt: bool) -> io::Result<()> {
+    let mut session = Session::new();
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+        let Some(line) = lines.next().transpose()? else {
+            break;
+        };
+        match session.eval(&line) {
+            Ok(Some(value)) => writeln!(output, "{}", value)?,
+            Ok(None) => {}
+            Err(error) => writeln!(output, "error: {}", error)?,
+        }
+    }
+    if prompt {
+        writeln!(output)?;
+    }
+    Ok(())
+}
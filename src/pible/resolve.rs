@@ -0,0 +1,170 @@
+use crate::pible::prelude::*;
+use thiserror::Error;
+
+use crate::pible::codegen;
+use crate::pible::parser::{Node, NodeType};
+use crate::pible::visit::{walk_node, Visitor};
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ResolveError {
+    #[error("Undefined variable: {0}")]
+    UndefinedVariable(String),
+    #[error("Undefined function: {0}")]
+    UndefinedFunction(String),
+}
+
+/// Check that every name `ast` reads is a variable in scope, a constant or a
+/// map, and that every call targets a declared function or a builtin.
+/// Scoping follows code generation: top-level variables are only visible to
+/// top-level statements, and each function starts with just its parameters.
+pub fn resolve(ast: &Node) -> Result<(), ResolveError> {
+    let mut resolver = Resolver::default();
+    for child in &ast.children {
+        let name = child.value.as_deref().unwrap_or("");
+        match child.node_type {
+            NodeType::FunctionDecl => {
+                if let Some(name) = codegen::function_name(child) {
+                    resolver.functions.insert(name.to_string());
+                }
+            }
+            NodeType::ConstDecl => {
+                resolver.globals.insert(declared_name(name).to_string());
+            }
+            NodeType::EnumDecl => {
+                for variant in &child.children {
+                    let name = variant.value.as_deref().unwrap_or("");
+                    resolver.globals.insert(declared_name(name).to_string());
+                }
+            }
+            NodeType::MapDecl => {
+                resolver.globals.insert(name.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    resolver.scopes.push(HashSet::new());
+    for child in &ast.children {
+        match child.node_type {
+            NodeType::FunctionDecl => {}
+            NodeType::StructDecl | NodeType::MapDecl | NodeType::EnumDecl => {}
+            _ => resolver.visit_node(child)?,
+        }
+    }
+
+    for function in ast
+        .children
+        .iter()
+        .filter(|child| child.node_type == NodeType::FunctionDecl)
+    {
+        resolver.scopes = vec![HashSet::new()];
+        if function.variadic {
+            for name in ["argc", "argv"] {
+                resolver.scopes[0].insert(name.to_string());
+            }
+        }
+        for child in &function.children {
+            match child.node_type {
+                NodeType::Identifier => resolver.declare(child),
+                _ => resolver.visit_node(child)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Resolver {
+    /// Constants and maps, visible everywhere
+    globals: HashSet<String>,
+    functions: HashSet<String>,
+    scopes: Vec<HashSet<String>>,
+}
+
+impl Visitor for Resolver {
+    type Error = ResolveError;
+
+    fn visit_node(&mut self, node: &Node) -> Result<(), ResolveError> {
+        let value = node.value.as_deref().unwrap_or("");
+        match (&node.node_type, value) {
+            (NodeType::Block, _) => self.scoped(&node.children),
+            (NodeType::Statement, "for") => self.scoped(&node.children),
+            (NodeType::Statement, "var") => {
+                for initializer in node.children.iter().skip(1) {
+                    self.visit_node(initializer)?;
+                }
+                if let Some(name) = node.children.first() {
+                    self.declare(name);
+                }
+                Ok(())
+            }
+            (NodeType::ConstDecl, _) => self.children(&node.children),
+            // A function's name is a function pointer
+            (NodeType::Identifier, name) => {
+                if self.is_defined(name) || self.functions.contains(name) {
+                    Ok(())
+                } else {
+                    Err(ResolveError::UndefinedVariable(name.to_string()))
+                }
+            }
+            (NodeType::Expression, "call") => {
+                let Some(callee) = node.children.first() else {
+                    return Ok(());
+                };
+                // An indirect call through a computed function pointer
+                if callee.node_type != NodeType::Identifier {
+                    return self.children(&node.children);
+                }
+                let callee = callee.value.as_deref().unwrap_or("");
+                // Calling a variable is left for the type checker to report
+                if !self.functions.contains(callee)
+                    && !codegen::is_builtin(callee)
+                    && !self.is_defined(callee)
+                {
+                    return Err(ResolveError::UndefinedFunction(callee.to_string()));
+                }
+                self.children(node.children.get(1..).unwrap_or(&[]))
+            }
+            // The right-hand side is a field name
+            (NodeType::Expression, "." | "->") => self.children(&node.children[..1]),
+            // A bare name may be a struct, as in `sizeof(Account)`
+            (NodeType::Sizeof, _) => match node.children.first() {
+                Some(operand) if operand.node_type != NodeType::Identifier => {
+                    self.visit_node(operand)
+                }
+                _ => Ok(()),
+            },
+            _ => walk_node(self, node),
+        }
+    }
+}
+
+impl Resolver {
+    fn children(&mut self, children: &[Node]) -> Result<(), ResolveError> {
+        children.iter().try_for_each(|child| self.visit_node(child))
+    }
+
+    fn scoped(&mut self, children: &[Node]) -> Result<(), ResolveError> {
+        self.scopes.push(HashSet::new());
+        let result = self.children(children);
+        self.scopes.pop();
+        result
+    }
+
+    /// Bring the `Type:name` identifier `node` into the innermost scope.
+    fn declare(&mut self, node: &Node) {
+        let name = declared_name(node.value.as_deref().unwrap_or(""));
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string());
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.globals.contains(name) || self.scopes.iter().any(|scope| scope.contains(name))
+    }
+}
+
+/// Name from a `Type:name` declaration value.
+fn declared_name(value: &str) -> &str {
+    value.split_once(':').map_or(value, |(_, name)| name)
+}
@@ -0,0 +1,1236 @@
+//! Experimental RV64I backend.
+//!
+//! Lowers the integer subset of HolyC (functions, locals, arithmetic,
+//! comparisons, control flow and calls) to RISC-V machine code following the
+//! standard calling convention: arguments in a0-a7, the result in a0, and a
+//! frame pointer in s0. Every value lives in a 64-bit register or stack slot,
+//! so narrower integer types are not truncated. RV64I has no multiply or
+//! divide, so `*`, `/` and `%` are rejected, as are pointers, arrays, structs,
+//! strings and `F64`.
+
+use crate::pible::codegen::{self, CodeGen, CodeGenError, LoopJumps};
+use crate::pible::parser::{Node, NodeType};
+use crate::pible::prelude::*;
+use crate::pible::types::Type;
+
+/// Integer registers by ABI name.
+pub mod registers {
+    pub const ZERO: u8 = 0;
+    pub const RA: u8 = 1;
+    pub const SP: u8 = 2;
+    pub const T0: u8 = 5;
+    pub const S0: u8 = 8;
+    pub const A0: u8 = 10;
+}
+
+use registers::*;
+
+/// Expression temporaries: s1-s11, callee-saved so they survive calls.
+/// Functions save and restore the ones they use.
+const TEMP_REGS: [u8; 11] = [9, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27];
+
+/// Argument registers a0-a7.
+const ARG_REGS: usize = 8;
+
+/// The return address and caller's s0 sit just below the frame pointer.
+const FRAME_HEADER: i32 = 16;
+
+/// Register-register and register-immediate ALU operations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+    Add,
+    /// No immediate form; subtract by adding the negated immediate
+    Sub,
+    Sll,
+    Slt,
+    Sltu,
+    Xor,
+    Srl,
+    Sra,
+    Or,
+    And,
+}
+
+impl AluOp {
+    fn funct3(self) -> u32 {
+        match self {
+            AluOp::Add | AluOp::Sub => 0,
+            AluOp::Sll => 1,
+            AluOp::Slt => 2,
+            AluOp::Sltu => 3,
+            AluOp::Xor => 4,
+            AluOp::Srl | AluOp::Sra => 5,
+            AluOp::Or => 6,
+            AluOp::And => 7,
+        }
+    }
+
+    fn funct7(self) -> u32 {
+        match self {
+            AluOp::Sub | AluOp::Sra => 0x20,
+            _ => 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    fn from_funct(funct3: u32, alternate: bool) -> Option<Self> {
+        Some(match (funct3, alternate) {
+            (0, false) => AluOp::Add,
+            (0, true) => AluOp::Sub,
+            (1, false) => AluOp::Sll,
+            (2, false) => AluOp::Slt,
+            (3, false) => AluOp::Sltu,
+            (4, false) => AluOp::Xor,
+            (5, false) => AluOp::Srl,
+            (5, true) => AluOp::Sra,
+            (6, false) => AluOp::Or,
+            (7, false) => AluOp::And,
+            _ => return None,
+        })
+    }
+}
+
+/// Conditional branch comparisons.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum BranchKind {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl BranchKind {
+    fn funct3(self) -> u32 {
+        match self {
+            BranchKind::Eq => 0,
+            BranchKind::Ne => 1,
+            BranchKind::Lt => 4,
+            BranchKind::Ge => 5,
+            BranchKind::Ltu => 6,
+            BranchKind::Geu => 7,
+        }
+    }
+}
+
+/// The RV64I instructions this backend emits. Offsets are in bytes; `Lui`
+/// holds the 20-bit upper immediate, sign-extended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Lui {
+        rd: u8,
+        imm: i32,
+    },
+    Jal {
+        rd: u8,
+        offset: i32,
+    },
+    Jalr {
+        rd: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    Branch {
+        kind: BranchKind,
+        rs1: u8,
+        rs2: u8,
+        offset: i32,
+    },
+    Ld {
+        rd: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    Sd {
+        rs2: u8,
+        rs1: u8,
+        offset: i32,
+    },
+    /// `addi`, `slti`, `xori` and friends; shifts take a 6-bit amount
+    OpImm {
+        op: AluOp,
+        rd: u8,
+        rs1: u8,
+        imm: i32,
+    },
+    Addiw {
+        rd: u8,
+        rs1: u8,
+        imm: i32,
+    },
+    Op {
+        op: AluOp,
+        rd: u8,
+        rs1: u8,
+        rs2: u8,
+    },
+}
+
+const OPCODE_LUI: u32 = 0x37;
+const OPCODE_JAL: u32 = 0x6f;
+const OPCODE_JALR: u32 = 0x67;
+const OPCODE_BRANCH: u32 = 0x63;
+const OPCODE_LOAD: u32 = 0x03;
+const OPCODE_STORE: u32 = 0x23;
+const OPCODE_OP_IMM: u32 = 0x13;
+const OPCODE_OP_IMM_32: u32 = 0x1b;
+const OPCODE_OP: u32 = 0x33;
+/// funct3 of `ld` and `sd`
+const DOUBLEWORD: u32 = 3;
+
+impl Instruction {
+    /// Encode as a 32-bit instruction word.
+    pub fn encode(self) -> u32 {
+        let reg = |r: u8| (r & 0x1f) as u32;
+        let i_type = |imm: i32, rs1: u8, funct3: u32, rd: u8, opcode: u32| {
+            ((imm as u32 & 0xfff) << 20)
+                | (reg(rs1) << 15)
+                | (funct3 << 12)
+                | (reg(rd) << 7)
+                | opcode
+        };
+        match self {
+            Instruction::Lui { rd, imm } => {
+                ((imm as u32 & 0xfffff) << 12) | (reg(rd) << 7) | OPCODE_LUI
+            }
+            Instruction::Jal { rd, offset } => {
+                let imm = offset as u32;
+                (((imm >> 20) & 1) << 31)
+                    | (((imm >> 1) & 0x3ff) << 21)
+                    | (((imm >> 11) & 1) << 20)
+                    | (((imm >> 12) & 0xff) << 12)
+                    | (reg(rd) << 7)
+                    | OPCODE_JAL
+            }
+            Instruction::Jalr { rd, rs1, offset } => i_type(offset, rs1, 0, rd, OPCODE_JALR),
+            Instruction::Branch {
+                kind,
+                rs1,
+                rs2,
+                offset,
+            } => {
+                let imm = offset as u32;
+                (((imm >> 12) & 1) << 31)
+                    | (((imm >> 5) & 0x3f) << 25)
+                    | (reg(rs2) << 20)
+                    | (reg(rs1) << 15)
+                    | (kind.funct3() << 12)
+                    | (((imm >> 1) & 0xf) << 8)
+                    | (((imm >> 11) & 1) << 7)
+                    | OPCODE_BRANCH
+            }
+            Instruction::Ld { rd, rs1, offset } => i_type(offset, rs1, DOUBLEWORD, rd, OPCODE_LOAD),
+            Instruction::Sd { rs2, rs1, offset } => {
+                let imm = offset as u32;
+                (((imm >> 5) & 0x7f) << 25)
+                    | (reg(rs2) << 20)
+                    | (reg(rs1) << 15)
+                    | (DOUBLEWORD << 12)
+                    | ((imm & 0x1f) << 7)
+                    | OPCODE_STORE
+            }
+            Instruction::OpImm { op, rd, rs1, imm } => {
+                debug_assert_ne!(op, AluOp::Sub, "subi doesn't exist");
+                let imm = match op {
+                    AluOp::Sll | AluOp::Srl => imm & 0x3f,
+                    AluOp::Sra => (imm & 0x3f) | 0x400,
+                    _ => imm,
+                };
+                i_type(imm, rs1, op.funct3(), rd, OPCODE_OP_IMM)
+            }
+            Instruction::Addiw { rd, rs1, imm } => i_type(imm, rs1, 0, rd, OPCODE_OP_IMM_32),
+            Instruction::Op { op, rd, rs1, rs2 } => {
+                (op.funct7() << 25)
+                    | (reg(rs2) << 20)
+                    | (reg(rs1) << 15)
+                    | (op.funct3() << 12)
+                    | (reg(rd) << 7)
+                    | OPCODE_OP
+            }
+        }
+    }
+
+    /// Decode an instruction word written by [`Self::encode`]; `None` for
+    /// anything outside the subset this backend emits.
+    #[allow(dead_code)]
+    pub fn decode(word: u32) -> Option<Self> {
+        let rd = ((word >> 7) & 0x1f) as u8;
+        let rs1 = ((word >> 15) & 0x1f) as u8;
+        let rs2 = ((word >> 20) & 0x1f) as u8;
+        let funct3 = (word >> 12) & 0x7;
+        let i_imm = sign_extend(word >> 20, 12);
+
+        Some(match word & 0x7f {
+            OPCODE_LUI => Instruction::Lui {
+                rd,
+                imm: sign_extend(word >> 12, 20),
+            },
+            OPCODE_JAL => {
+                let imm = (((word >> 31) & 1) << 20)
+                    | (((word >> 12) & 0xff) << 12)
+                    | (((word >> 20) & 1) << 11)
+                    | (((word >> 21) & 0x3ff) << 1);
+                Instruction::Jal {
+                    rd,
+                    offset: sign_extend(imm, 21),
+                }
+            }
+            OPCODE_JALR if funct3 == 0 => Instruction::Jalr {
+                rd,
+                rs1,
+                offset: i_imm,
+            },
+            OPCODE_BRANCH => {
+                let kind = match funct3 {
+                    0 => BranchKind::Eq,
+                    1 => BranchKind::Ne,
+                    4 => BranchKind::Lt,
+                    5 => BranchKind::Ge,
+                    6 => BranchKind::Ltu,
+                    7 => BranchKind::Geu,
+                    _ => return None,
+                };
+                let imm = (((word >> 31) & 1) << 12)
+                    | (((word >> 7) & 1) << 11)
+                    | (((word >> 25) & 0x3f) << 5)
+                    | (((word >> 8) & 0xf) << 1);
+                Instruction::Branch {
+                    kind,
+                    rs1,
+                    rs2,
+                    offset: sign_extend(imm, 13),
+                }
+            }
+            OPCODE_LOAD if funct3 == DOUBLEWORD => Instruction::Ld {
+                rd,
+                rs1,
+                offset: i_imm,
+            },
+            OPCODE_STORE if funct3 == DOUBLEWORD => Instruction::Sd {
+                rs2,
+                rs1,
+                offset: sign_extend(((word >> 25) << 5) | ((word >> 7) & 0x1f), 12),
+            },
+            OPCODE_OP_IMM => {
+                let shift = funct3 == 1 || funct3 == 5;
+                let alternate = shift && word & (1 << 30) != 0;
+                let op = AluOp::from_funct(funct3, alternate)?;
+                let imm = if shift {
+                    (word >> 20) as i32 & 0x3f
+                } else {
+                    i_imm
+                };
+                Instruction::OpImm { op, rd, rs1, imm }
+            }
+            OPCODE_OP_IMM_32 if funct3 == 0 => Instruction::Addiw {
+                rd,
+                rs1,
+                imm: i_imm,
+            },
+            OPCODE_OP => {
+                let alternate = match word >> 25 {
+                    0 => false,
+                    0x20 => true,
+                    _ => return None,
+                };
+                Instruction::Op {
+                    op: AluOp::from_funct(funct3, alternate)?,
+                    rd,
+                    rs1,
+                    rs2,
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+/// Sign-extend the low `bits` bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> i32 {
+    ((value << (32 - bits)) as i32) >> (32 - bits)
+}
+
+/// A local variable's offset from the frame pointer and its declared type.
+#[derive(Debug, Clone)]
+struct Variable {
+    offset: i32,
+    ty: Type,
+}
+
+pub struct RiscVCodeGen {
+    instructions: Vec<Instruction>,
+    functions: HashMap<String, usize>,
+    pending_calls: Vec<(usize, String)>,
+    return_types: HashMap<String, Type>,
+    scopes: Vec<HashMap<String, Variable>>,
+    /// Bytes of the current frame used by the header and locals
+    frame_size: i32,
+    current_temp: usize,
+    /// Temporaries the current function has used, and must save
+    temps_used: usize,
+    /// Jumps to the current function's epilogue
+    returns: Vec<usize>,
+    /// Enclosing loops, innermost last
+    loops: Vec<LoopJumps>,
+}
+
+impl Default for RiscVCodeGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RiscVCodeGen {
+    pub fn new() -> Self {
+        Self {
+            instructions: Vec::new(),
+            functions: HashMap::new(),
+            pending_calls: Vec::new(),
+            return_types: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            frame_size: FRAME_HEADER,
+            current_temp: 0,
+            temps_used: 0,
+            returns: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    /// Generate the program for `ast`, entry function first.
+    pub fn generate(&mut self, ast: &Node) -> Result<Vec<Instruction>, CodeGenError> {
+        let mut functions = Vec::new();
+        for child in &ast.children {
+            if child.node_type != NodeType::FunctionDecl {
+                return Err(CodeGenError::UnsupportedNodeType(child.node_type.clone()));
+            }
+            if let Some((ret, name)) = child.value.as_deref().and_then(|v| v.split_once(':')) {
+                let ret = Type::parse(ret).unwrap_or(Type::I64);
+                self.return_types.insert(name.to_string(), ret);
+            }
+            functions.push(child);
+        }
+
+        let entry = CodeGen::entry_index(&functions);
+        if let Some(entry) = entry {
+            self.generate_function(functions[entry])?;
+        }
+        for (i, function) in functions.iter().enumerate() {
+            if Some(i) != entry {
+                self.generate_function(function)?;
+            }
+        }
+
+        self.resolve_calls()?;
+        Ok(core::mem::take(&mut self.instructions))
+    }
+
+    /// Instruction index where each generated function starts.
+    #[allow(dead_code)]
+    pub fn functions(&self) -> &HashMap<String, usize> {
+        &self.functions
+    }
+
+    fn generate_function(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let start = self.instructions.len();
+        if let Some(name) = codegen::function_name(node) {
+            self.functions.insert(name.to_string(), start);
+        }
+        let first_call = self.pending_calls.len();
+        self.scopes = vec![HashMap::new()];
+        self.frame_size = FRAME_HEADER;
+        self.current_temp = 0;
+        self.temps_used = 0;
+
+        // Parameters arrive in a0-a7; spill them to stack slots
+        let params = &node.children[..node.children.len().saturating_sub(1)];
+        if node.variadic {
+            return Err(CodeGenError::InvalidInstruction(
+                "RISC-V backend doesn't support variadic functions".to_string(),
+            ));
+        }
+        if params.len() > ARG_REGS {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "{} parameters, RISC-V passes at most {} in registers",
+                params.len(),
+                ARG_REGS
+            )));
+        }
+        for (i, param) in params.iter().enumerate() {
+            if let Some((ty, name)) = param.value.as_deref().and_then(|v| v.split_once(':')) {
+                let offset = self.declare_variable(name, ty)?;
+                self.emit(Instruction::Sd {
+                    rs2: A0 + i as u8,
+                    rs1: S0,
+                    offset,
+                });
+            }
+        }
+
+        if let Some(body) = node.children.last() {
+            self.visit_node(body)?;
+        }
+
+        // Implicit `return 0` for bodies that fall off the end
+        self.emit_move_immediate(A0, 0);
+
+        // Saved temporaries go below the locals; the frame keeps sp 16-byte aligned
+        let saved: Vec<(u8, i32)> = TEMP_REGS[..self.temps_used]
+            .iter()
+            .enumerate()
+            .map(|(i, &reg)| (reg, -(self.frame_size + 8 * (i as i32 + 1))))
+            .collect();
+        let total = (self.frame_size + 8 * saved.len() as i32 + 15) & !15;
+        if total > 2048 {
+            return Err(CodeGenError::InvalidInstruction(
+                "stack frame too large for a 12-bit offset".to_string(),
+            ));
+        }
+
+        let epilogue = self.instructions.len();
+        for index in core::mem::take(&mut self.returns) {
+            self.patch_jump(index, epilogue)?;
+        }
+        for &(reg, offset) in &saved {
+            self.emit(Instruction::Ld {
+                rd: reg,
+                rs1: S0,
+                offset,
+            });
+        }
+        self.emit(Instruction::Ld {
+            rd: RA,
+            rs1: S0,
+            offset: -8,
+        });
+        self.emit_move_register(T0, S0);
+        self.emit(Instruction::Ld {
+            rd: S0,
+            rs1: S0,
+            offset: -16,
+        });
+        self.emit_move_register(SP, T0);
+        self.emit(Instruction::Jalr {
+            rd: ZERO,
+            rs1: RA,
+            offset: 0,
+        });
+
+        let mut prologue = vec![
+            Instruction::OpImm {
+                op: AluOp::Add,
+                rd: SP,
+                rs1: SP,
+                imm: -total,
+            },
+            Instruction::Sd {
+                rs2: RA,
+                rs1: SP,
+                offset: total - 8,
+            },
+            Instruction::Sd {
+                rs2: S0,
+                rs1: SP,
+                offset: total - 16,
+            },
+            Instruction::OpImm {
+                op: AluOp::Add,
+                rd: S0,
+                rs1: SP,
+                imm: total,
+            },
+        ];
+        prologue.extend(saved.iter().map(|&(reg, offset)| Instruction::Sd {
+            rs2: reg,
+            rs1: S0,
+            offset,
+        }));
+
+        // Branches are relative, so only call sites move with the body
+        let shift = prologue.len();
+        self.instructions.splice(start..start, prologue);
+        for (index, _) in &mut self.pending_calls[first_call..] {
+            *index += shift;
+        }
+        Ok(())
+    }
+
+    fn visit_node(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        match node.node_type {
+            NodeType::Block => {
+                self.scopes.push(HashMap::new());
+                for child in &node.children {
+                    self.visit_node(child)?;
+                }
+                self.scopes.pop();
+            }
+            NodeType::Statement => match node.value.as_deref().unwrap_or("") {
+                "return" => {
+                    match node.children.first() {
+                        Some(value) => {
+                            let reg = self.generate_expression(value)?;
+                            self.emit_move_register(A0, reg);
+                            self.release_register();
+                        }
+                        None => self.emit_move_immediate(A0, 0),
+                    }
+                    let jump = self.emit_jump_placeholder();
+                    self.returns.push(jump);
+                }
+                "var" => self.generate_var_declaration(node)?,
+                "if" => self.generate_if(node)?,
+                "while" => self.generate_while(node)?,
+                "for" => self.generate_for(node)?,
+                kind @ ("break" | "continue") => self.generate_loop_jump(kind)?,
+                _ => {
+                    for child in &node.children {
+                        self.visit_node(child)?;
+                    }
+                }
+            },
+            NodeType::Expression
+            | NodeType::Identifier
+            | NodeType::Literal
+            | NodeType::Ternary
+            | NodeType::Cast => {
+                // Expression statement - evaluate for side effects
+                self.generate_expression(node)?;
+                self.release_register();
+            }
+            _ => return Err(CodeGenError::UnsupportedNodeType(node.node_type.clone())),
+        }
+        Ok(())
+    }
+
+    fn generate_var_declaration(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let Some((ty, name)) = node
+            .children
+            .first()
+            .and_then(|child| child.value.as_deref())
+            .and_then(|value| value.split_once(':'))
+        else {
+            return Ok(());
+        };
+        let offset = self.declare_variable(name, ty)?;
+
+        if let Some(initializer) = node.children.get(1) {
+            let reg = self.generate_expression(initializer)?;
+            self.emit(Instruction::Sd {
+                rs2: reg,
+                rs1: S0,
+                offset,
+            });
+            self.release_register();
+        }
+        Ok(())
+    }
+
+    /// An `if` and its `else if` arms, each body jumping to one shared exit.
+    fn generate_if(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let arms = node.children.chunks_exact(2);
+        let otherwise = arms.remainder().first();
+        let count = arms.len();
+        let mut exits = Vec::new();
+        for (i, arm) in arms.enumerate() {
+            let skip = self.generate_condition_jump(&arm[0])?;
+            self.visit_node(&arm[1])?;
+            if i + 1 < count || otherwise.is_some() {
+                exits.push(self.emit_jump_placeholder());
+            }
+            self.patch_jump(skip, self.instructions.len())?;
+        }
+        if let Some(otherwise) = otherwise {
+            self.visit_node(otherwise)?;
+        }
+        for exit in exits {
+            self.patch_jump(exit, self.instructions.len())?;
+        }
+        Ok(())
+    }
+
+    fn generate_while(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let loop_start = self.instructions.len();
+        let exit_jump = self.generate_condition_jump(&node.children[0])?;
+        let jumps = self.generate_loop_body(&node.children[1])?;
+        for jump in jumps.continues {
+            self.patch_jump(jump, loop_start)?;
+        }
+        self.emit_jump_to(loop_start);
+        self.patch_jump(exit_jump, self.instructions.len())?;
+        for jump in jumps.breaks {
+            self.patch_jump(jump, self.instructions.len())?;
+        }
+        Ok(())
+    }
+
+    fn generate_for(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        // The init clause may declare a variable scoped to the loop
+        self.scopes.push(HashMap::new());
+        self.visit_node(&node.children[0])?;
+
+        let loop_start = self.instructions.len();
+        let condition = &node.children[1];
+        let exit_jump = if codegen::is_empty(condition) {
+            None
+        } else {
+            Some(self.generate_condition_jump(condition)?)
+        };
+
+        let jumps = self.generate_loop_body(&node.children[3])?;
+        // `continue` runs the step before testing the condition again
+        for jump in jumps.continues {
+            self.patch_jump(jump, self.instructions.len())?;
+        }
+        if !codegen::is_empty(&node.children[2]) {
+            self.visit_node(&node.children[2])?;
+        }
+        self.emit_jump_to(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump, self.instructions.len())?;
+        }
+        for jump in jumps.breaks {
+            self.patch_jump(jump, self.instructions.len())?;
+        }
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// Generate a loop body, returning the `break` and `continue` jumps in it
+    /// that belong to this loop.
+    fn generate_loop_body(&mut self, body: &Node) -> Result<LoopJumps, CodeGenError> {
+        self.loops.push(LoopJumps::default());
+        let generated = self.visit_node(body);
+        let jumps = self.loops.pop().unwrap_or_default();
+        generated.map(|()| jumps)
+    }
+
+    /// `break` or `continue`: a jump patched by the innermost loop.
+    fn generate_loop_jump(&mut self, kind: &str) -> Result<(), CodeGenError> {
+        if self.loops.is_empty() {
+            return Err(CodeGenError::OutsideLoop(kind.to_string()));
+        }
+        let jump = self.emit_jump_placeholder();
+        if let Some(jumps) = self.loops.last_mut() {
+            if kind == "break" {
+                jumps.breaks.push(jump);
+            } else {
+                jumps.continues.push(jump);
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate `condition` and emit a branch, to be patched, taken when it is zero.
+    fn generate_condition_jump(&mut self, condition: &Node) -> Result<usize, CodeGenError> {
+        let reg = self.generate_expression(condition)?;
+        let jump = self.emit(Instruction::Branch {
+            kind: BranchKind::Eq,
+            rs1: reg,
+            rs2: ZERO,
+            offset: 0,
+        });
+        self.release_register();
+        Ok(jump)
+    }
+
+    fn generate_expression(&mut self, node: &Node) -> Result<u8, CodeGenError> {
+        let value = node.value.as_deref().unwrap_or("");
+        match node.node_type {
+            NodeType::Literal => {
+                let number = match value {
+                    "true" => 1,
+                    "false" => 0,
+                    _ => codegen::integer_value(value).ok_or_else(|| {
+                        CodeGenError::LiteralOverflow(format!("{} doesn't fit in U64", value))
+                    })?,
+                };
+                let reg = self.allocate_register()?;
+                self.emit_load_immediate(reg, number);
+                Ok(reg)
+            }
+            NodeType::Identifier => {
+                let offset = self.variable(value)?.offset;
+                let reg = self.allocate_register()?;
+                self.emit(Instruction::Ld {
+                    rd: reg,
+                    rs1: S0,
+                    offset,
+                });
+                Ok(reg)
+            }
+            NodeType::Cast => {
+                let to = Type::parse(value)
+                    .filter(|ty| matches!(ty, Type::Int { .. } | Type::Bool))
+                    .ok_or_else(|| {
+                        CodeGenError::InvalidInstruction(format!(
+                            "RISC-V backend has no {} values",
+                            value
+                        ))
+                    })?;
+                let operand = &node.children[0];
+                let from = self.expression_type(operand);
+                let reg = self.generate_expression(operand)?;
+                match (&from, &to) {
+                    (Type::Int { .. }, Type::Bool) => {
+                        self.emit_set_if_nonzero(reg);
+                    }
+                    (
+                        Type::Int {
+                            bits: from_bits, ..
+                        },
+                        Type::Int { bits, .. },
+                    ) => {
+                        if from_bits < bits {
+                            self.emit_extend(reg, &from);
+                        }
+                        self.emit_extend(reg, &to);
+                    }
+                    _ => {}
+                }
+                Ok(reg)
+            }
+            NodeType::Ternary => {
+                let skip_then = self.generate_condition_jump(&node.children[0])?;
+                let reg = self.generate_expression(&node.children[1])?;
+                let skip_else = self.emit_jump_placeholder();
+                self.release_register();
+                self.patch_jump(skip_then, self.instructions.len())?;
+                let else_reg = self.generate_expression(&node.children[2])?;
+                debug_assert_eq!(reg, else_reg);
+                self.patch_jump(skip_else, self.instructions.len())?;
+                Ok(reg)
+            }
+            NodeType::Expression => match (value, node.children.as_slice()) {
+                ("call", [callee, args @ ..]) => {
+                    self.generate_call(callee.value.as_deref().unwrap_or(""), args)
+                }
+                ("=", [target, value]) => {
+                    let offset = match target.node_type {
+                        NodeType::Identifier => {
+                            self.variable(target.value.as_deref().unwrap_or(""))?.offset
+                        }
+                        _ => {
+                            return Err(CodeGenError::InvalidInstruction(
+                                "RISC-V backend can only assign to variables".to_string(),
+                            ))
+                        }
+                    };
+                    let reg = self.generate_expression(value)?;
+                    self.emit(Instruction::Sd {
+                        rs2: reg,
+                        rs1: S0,
+                        offset,
+                    });
+                    Ok(reg)
+                }
+                ("neg", [operand]) => {
+                    let reg = self.generate_expression(operand)?;
+                    self.emit(Instruction::Op {
+                        op: AluOp::Sub,
+                        rd: reg,
+                        rs1: ZERO,
+                        rs2: reg,
+                    });
+                    Ok(reg)
+                }
+                ("!", [operand]) => {
+                    let reg = self.generate_expression(operand)?;
+                    self.emit_set_if_zero(reg);
+                    Ok(reg)
+                }
+                (_, [left, right]) => self.generate_binary(value, left, right),
+                _ => Err(CodeGenError::InvalidInstruction(format!(
+                    "RISC-V backend has no `{}` expressions",
+                    value
+                ))),
+            },
+            _ => Err(CodeGenError::UnsupportedNodeType(node.node_type.clone())),
+        }
+    }
+
+    fn generate_binary(
+        &mut self,
+        operator: &str,
+        left: &Node,
+        right: &Node,
+    ) -> Result<u8, CodeGenError> {
+        if matches!(operator, "*" | "/" | "%") {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "`{}` needs the RISC-V M extension",
+                operator
+            )));
+        }
+        if matches!(operator, "&&" | "||") {
+            return self.generate_logical(operator, left, right);
+        }
+        let left_type = self.expression_type(left);
+        let signed = Type::common(&left_type, &self.expression_type(right)).is_signed();
+        let less = if signed { AluOp::Slt } else { AluOp::Sltu };
+
+        let dst = self.generate_expression(left)?;
+        let src = self.generate_expression(right)?;
+        let op = |op: AluOp, rs1: u8, rs2: u8| Instruction::Op {
+            op,
+            rd: dst,
+            rs1,
+            rs2,
+        };
+
+        match operator {
+            "+" => self.emit(op(AluOp::Add, dst, src)),
+            "-" => self.emit(op(AluOp::Sub, dst, src)),
+            "&" => self.emit(op(AluOp::And, dst, src)),
+            "|" => self.emit(op(AluOp::Or, dst, src)),
+            "^" => self.emit(op(AluOp::Xor, dst, src)),
+            "<<" => self.emit(op(AluOp::Sll, dst, src)),
+            ">>" if left_type.is_signed() => self.emit(op(AluOp::Sra, dst, src)),
+            ">>" => self.emit(op(AluOp::Srl, dst, src)),
+            "==" => {
+                self.emit(op(AluOp::Sub, dst, src));
+                self.emit_set_if_zero(dst)
+            }
+            "!=" => {
+                self.emit(op(AluOp::Sub, dst, src));
+                self.emit_set_if_nonzero(dst)
+            }
+            "<" => self.emit(op(less, dst, src)),
+            ">" => self.emit(op(less, src, dst)),
+            "<=" => {
+                self.emit(op(less, src, dst));
+                self.emit_flip(dst)
+            }
+            ">=" => {
+                self.emit(op(less, dst, src));
+                self.emit_flip(dst)
+            }
+            _ => {
+                return Err(CodeGenError::InvalidInstruction(format!(
+                    "Unsupported operator: {}",
+                    operator
+                )))
+            }
+        };
+
+        self.release_register();
+        Ok(dst)
+    }
+
+    /// `&&` and `||`, skipping the right operand when the left one decides
+    /// the result.
+    fn generate_logical(
+        &mut self,
+        operator: &str,
+        left: &Node,
+        right: &Node,
+    ) -> Result<u8, CodeGenError> {
+        // `&&` is decided by a zero operand, `||` by a non-zero one
+        let (kind, decided_value, otherwise) = match operator {
+            "&&" => (BranchKind::Eq, 0, 1),
+            _ => (BranchKind::Ne, 1, 0),
+        };
+        let branch = |rs1| Instruction::Branch {
+            kind,
+            rs1,
+            rs2: ZERO,
+            offset: 0,
+        };
+
+        let dst = self.generate_expression(left)?;
+        let skip_left = self.emit(branch(dst));
+        let src = self.generate_expression(right)?;
+        let skip_right = self.emit(branch(src));
+        self.release_register();
+
+        self.emit_move_immediate(dst, otherwise);
+        let skip_decided = self.emit_jump_placeholder();
+        let decided_at = self.instructions.len();
+        self.patch_jump(skip_left, decided_at)?;
+        self.patch_jump(skip_right, decided_at)?;
+        self.emit_move_immediate(dst, decided_value);
+        self.patch_jump(skip_decided, self.instructions.len())?;
+        Ok(dst)
+    }
+
+    fn generate_call(&mut self, callee: &str, args: &[Node]) -> Result<u8, CodeGenError> {
+        if args.len() > ARG_REGS {
+            return Err(CodeGenError::InvalidInstruction(format!(
+                "call to {} passes {} arguments, RISC-V passes at most {} in registers",
+                callee,
+                args.len(),
+                ARG_REGS
+            )));
+        }
+
+        // Evaluate every argument before loading a0-a7, so a call nested in
+        // a later argument can't clobber an earlier one
+        let mut regs = Vec::with_capacity(args.len());
+        for arg in args {
+            regs.push(self.generate_expression(arg)?);
+        }
+        for (i, &reg) in regs.iter().enumerate() {
+            self.emit_move_register(A0 + i as u8, reg);
+        }
+        for _ in &regs {
+            self.release_register();
+        }
+
+        // Resolved once all functions are laid out
+        self.pending_calls
+            .push((self.instructions.len(), callee.to_string()));
+        self.emit(Instruction::Jal { rd: RA, offset: 0 });
+
+        let reg = self.allocate_register()?;
+        self.emit_move_register(reg, A0);
+        Ok(reg)
+    }
+
+    /// Static type of an expression, used to pick signed or unsigned instructions.
+    fn expression_type(&self, node: &Node) -> Type {
+        let value = node.value.as_deref().unwrap_or("");
+        match node.node_type {
+            NodeType::Literal if value == "true" || value == "false" => Type::Bool,
+            NodeType::Identifier => self
+                .lookup_variable(value)
+                .map_or(Type::I64, |variable| variable.ty.clone()),
+            NodeType::Cast => Type::parse(value).unwrap_or(Type::I64),
+            NodeType::Ternary => match node.children.as_slice() {
+                [_, then, otherwise] => Type::common(
+                    &self.expression_type(then),
+                    &self.expression_type(otherwise),
+                ),
+                _ => Type::I64,
+            },
+            NodeType::Expression => match value {
+                "==" | "!=" | "<" | "<=" | ">" | ">=" | "&&" | "||" | "!" => Type::Bool,
+                "call" => node
+                    .children
+                    .first()
+                    .and_then(|callee| callee.value.as_deref())
+                    .and_then(|name| self.return_types.get(name))
+                    .cloned()
+                    .unwrap_or(Type::I64),
+                "=" | "neg" | "<<" | ">>" => node
+                    .children
+                    .first()
+                    .map_or(Type::I64, |operand| self.expression_type(operand)),
+                _ => match node.children.as_slice() {
+                    [left, right] => {
+                        Type::common(&self.expression_type(left), &self.expression_type(right))
+                    }
+                    _ => Type::I64,
+                },
+            },
+            _ => Type::I64,
+        }
+    }
+
+    /// Point every call at its function.
+    fn resolve_calls(&mut self) -> Result<(), CodeGenError> {
+        for (index, name) in core::mem::take(&mut self.pending_calls) {
+            let target = *self
+                .functions
+                .get(&name)
+                .ok_or(CodeGenError::FunctionNotFound(name))?;
+            self.instructions[index] = Instruction::Jal {
+                rd: RA,
+                offset: (target as i32 - index as i32) * 4,
+            };
+        }
+        Ok(())
+    }
+
+    /// Give `name` a stack slot; only integer and `Bool` values are supported.
+    fn declare_variable(&mut self, name: &str, type_name: &str) -> Result<i32, CodeGenError> {
+        let ty = Type::parse(type_name)
+            .filter(|ty| matches!(ty, Type::Int { .. } | Type::Bool))
+            .ok_or_else(|| {
+                CodeGenError::InvalidInstruction(format!(
+                    "RISC-V backend has no {} values",
+                    type_name
+                ))
+            })?;
+        self.frame_size += 8;
+        let offset = -self.frame_size;
+        if offset < -2048 {
+            return Err(CodeGenError::InvalidInstruction(
+                "stack frame too large for a 12-bit offset".to_string(),
+            ));
+        }
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Variable { offset, ty });
+        }
+        Ok(offset)
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn variable(&self, name: &str) -> Result<&Variable, CodeGenError> {
+        self.lookup_variable(name)
+            .ok_or_else(|| CodeGenError::InvalidInstruction(format!("undefined variable {}", name)))
+    }
+
+    fn allocate_register(&mut self) -> Result<u8, CodeGenError> {
+        let reg = *TEMP_REGS
+            .get(self.current_temp)
+            .ok_or(CodeGenError::OutOfRegisters)?;
+        self.current_temp += 1;
+        self.temps_used = self.temps_used.max(self.current_temp);
+        Ok(reg)
+    }
+
+    fn release_register(&mut self) {
+        self.current_temp = self.current_temp.saturating_sub(1);
+    }
+
+    /// Append `instruction`, returning its index.
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    fn emit_move_register(&mut self, rd: u8, rs1: u8) {
+        self.emit(Instruction::OpImm {
+            op: AluOp::Add,
+            rd,
+            rs1,
+            imm: 0,
+        });
+    }
+
+    fn emit_move_immediate(&mut self, rd: u8, imm: i32) {
+        self.emit(Instruction::OpImm {
+            op: AluOp::Add,
+            rd,
+            rs1: ZERO,
+            imm,
+        });
+    }
+
+    /// Materialize any 64-bit constant: `addi` for 12 bits, `lui` and
+    /// `addiw` for 32, and for wider values the upper bits recursively
+    /// followed by a shift and an add of the low 12.
+    fn emit_load_immediate(&mut self, rd: u8, value: i64) {
+        let low = ((value << 52) >> 52) as i32;
+        if (-2048..2048).contains(&value) {
+            self.emit_move_immediate(rd, low);
+        } else if i32::try_from(value).is_ok() {
+            // `lui` sign-extends, and `addiw` wraps back into 32 bits
+            let upper = sign_extend((value.wrapping_sub(low as i64) >> 12) as u32, 20);
+            self.emit(Instruction::Lui { rd, imm: upper });
+            if low != 0 {
+                self.emit(Instruction::Addiw {
+                    rd,
+                    rs1: rd,
+                    imm: low,
+                });
+            }
+        } else {
+            let upper = value.wrapping_sub(low as i64) >> 12;
+            let shift = 12 + upper.trailing_zeros();
+            self.emit_load_immediate(rd, upper >> (shift - 12));
+            self.emit(Instruction::OpImm {
+                op: AluOp::Sll,
+                rd,
+                rs1: rd,
+                imm: shift as i32,
+            });
+            if low != 0 {
+                self.emit(Instruction::OpImm {
+                    op: AluOp::Add,
+                    rd,
+                    rs1: rd,
+                    imm: low,
+                });
+            }
+        }
+    }
+
+    /// Sign- or zero-extend the low bits of `reg` that hold an integer of `ty`.
+    fn emit_extend(&mut self, reg: u8, ty: &Type) {
+        let Type::Int { bits, signed } = *ty else {
+            return;
+        };
+        if bits >= 64 {
+            return;
+        }
+        let shift = 64 - bits as i32;
+        let right = if signed { AluOp::Sra } else { AluOp::Srl };
+        for op in [AluOp::Sll, right] {
+            self.emit(Instruction::OpImm {
+                op,
+                rd: reg,
+                rs1: reg,
+                imm: shift,
+            });
+        }
+    }
+
+    /// `seqz reg, reg`
+    fn emit_set_if_zero(&mut self, reg: u8) -> usize {
+        self.emit(Instruction::OpImm {
+            op: AluOp::Sltu,
+            rd: reg,
+            rs1: reg,
+            imm: 1,
+        })
+    }
+
+    /// `snez reg, reg`
+    fn emit_set_if_nonzero(&mut self, reg: u8) -> usize {
+        self.emit(Instruction::Op {
+            op: AluOp::Sltu,
+            rd: reg,
+            rs1: ZERO,
+            rs2: reg,
+        })
+    }
+
+    /// Turn a 0/1 in `reg` into 1/0.
+    fn emit_flip(&mut self, reg: u8) -> usize {
+        self.emit(Instruction::OpImm {
+            op: AluOp::Xor,
+            rd: reg,
+            rs1: reg,
+            imm: 1,
+        })
+    }
+
+    fn emit_jump_placeholder(&mut self) -> usize {
+        self.emit(Instruction::Jal {
+            rd: ZERO,
+            offset: 0,
+        })
+    }
+
+    fn emit_jump_to(&mut self, target: usize) {
+        let offset = (target as i32 - self.instructions.len() as i32) * 4;
+        self.emit(Instruction::Jal { rd: ZERO, offset });
+    }
+
+    /// Point the jump or branch at `index` to instruction `target`.
+    fn patch_jump(&mut self, index: usize, target: usize) -> Result<(), CodeGenError> {
+        let distance = (target as i32 - index as i32) * 4;
+        match &mut self.instructions[index] {
+            Instruction::Branch { offset, .. } if (-4096..4096).contains(&distance) => {
+                *offset = distance
+            }
+            Instruction::Jal { offset, .. } if (-(1 << 20)..1 << 20).contains(&distance) => {
+                *offset = distance
+            }
+            _ => return Err(CodeGenError::JumpTooFar(distance as i64 / 4)),
+        }
+        Ok(())
+    }
+}
+
+/// Concatenate instruction words, little-endian.
+pub fn to_bytes(instructions: &[Instruction]) -> Vec<u8> {
+    instructions
+        .iter()
+        .flat_map(|instruction| instruction.encode().to_le_bytes())
+        .collect()
+}
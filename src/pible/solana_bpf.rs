@@ -1,4 +1,7 @@
+use crate::pible::cfg;
 use crate::pible::codegen::{BpfInstruction, CodeGen};
+use crate::pible::prelude::*;
+use crate::pible::types::Type;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,43 +13,488 @@ pub enum SolanaError {
     InvalidProgram(String),
 }
 
+/// A rule of the Solana verifier broken by the instruction at `index`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    #[error("instruction {index}: r{register} is not a register")]
+    InvalidRegister { index: usize, register: u8 },
+    #[error("instruction {index}: writes the read-only frame pointer r10")]
+    FramePointerWrite { index: usize },
+    #[error("instruction {index}: reads r{register} before it is set")]
+    UninitializedRead { index: usize, register: u8 },
+    #[error("instruction {index}: jumps to {target}, which is not the start of an instruction")]
+    BadJumpTarget { index: usize, target: i64 },
+    #[error("instruction {index}: {size}-byte stack access at r10{offset:+} is outside the frame")]
+    StackOutOfBounds {
+        index: usize,
+        offset: i16,
+        size: usize,
+    },
+    #[error("instruction {index}: execution can run past it without reaching an exit")]
+    FallsOffEnd { index: usize },
+}
+
+/// Bytes of stack each call frame gets below r10.
+pub const STACK_FRAME_SIZE: i64 = 4096;
+
+/// HolyC builtins that lower to Solana syscalls, with the symbol each one
+/// calls. The runtime identifies syscalls by [`syscall_id`] of the symbol.
+pub const SYSCALLS: &[(&str, &str)] = &[
+    ("PrintF", "sol_log_"),
+    ("sol_log", "sol_log_"),
+    ("sol_log_64", "sol_log_64_"),
+    // sol_invoke is sol_invoke_signed with no signer seeds
+    ("sol_invoke", "sol_invoke_signed_c"),
+    ("sol_invoke_signed", "sol_invoke_signed_c"),
+    ("sol_create_program_address", "sol_create_program_address"),
+    (
+        "sol_try_find_program_address",
+        "sol_try_find_program_address",
+    ),
+    ("MemCmp", "sol_memcmp_"),
+    ("MemSet", "sol_memset_"),
+    ("MemCpy", "sol_memcpy_"),
+];
+
+/// Syscall symbol a builtin lowers to on the Solana target.
+pub fn builtin_syscall(builtin: &str) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .find(|(name, _)| *name == builtin)
+        .map(|(_, symbol)| *symbol)
+}
+
+/// Compute units the runtime charges for a syscall, on top of the call
+/// instruction itself.
+const SYSCALL_COSTS: &[(&str, u64)] = &[
+    ("sol_log_", 100),
+    ("sol_log_64_", 100),
+    ("sol_invoke_signed_c", 1000),
+    ("sol_create_program_address", 1500),
+    ("sol_try_find_program_address", 1500),
+    ("sol_memcmp_", 10),
+    ("sol_memset_", 10),
+    ("sol_memcpy_", 10),
+];
+
+/// Default compute-unit limit for a single instruction.
+pub const DEFAULT_COMPUTE_UNIT_LIMIT: u64 = 200_000;
+
+/// Iterations assumed for every loop when estimating compute units.
+pub const ASSUMED_LOOP_ITERATIONS: u64 = 100;
+
+/// Conservative static estimate of the compute units `instructions` consume.
+///
+/// Every instruction costs one unit and syscalls add their runtime price.
+/// Code inside a loop (a backward jump) counts [`ASSUMED_LOOP_ITERATIONS`]
+/// times, compounding for nested loops.
+pub fn estimate_compute_units(instructions: &[BpfInstruction]) -> u64 {
+    let mut weights = vec![1u64; instructions.len()];
+    for (pc, instruction) in instructions.iter().enumerate() {
+        let is_jump =
+            instruction.opcode & 0x07 == 0x05 && !matches!(instruction.opcode & 0xf0, 0x80 | 0x90);
+        if is_jump && instruction.offset < 0 {
+            let start = (pc as i64 + 1 + instruction.offset as i64).max(0) as usize;
+            for weight in &mut weights[start..=pc] {
+                *weight = weight.saturating_mul(ASSUMED_LOOP_ITERATIONS);
+            }
+        }
+    }
+
+    instructions
+        .iter()
+        .zip(weights)
+        .map(|(instruction, weight)| {
+            let syscall = match (instruction.opcode, instruction.src_reg) {
+                (0x85, 0) => syscall_cost(instruction.immediate as u32),
+                _ => 0,
+            };
+            weight.saturating_mul(1 + syscall)
+        })
+        .fold(0, u64::saturating_add)
+}
+
+fn syscall_cost(id: u32) -> u64 {
+    SYSCALL_COSTS
+        .iter()
+        .find(|(symbol, _)| syscall_id(symbol) == id)
+        .map_or(0, |(_, cost)| *cost)
+}
+
+/// Symbol of the syscall whose [`syscall_id`] is `id`, if it's one we emit.
+pub fn syscall_symbol(id: u32) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .map(|(_, symbol)| *symbol)
+        .find(|symbol| syscall_id(symbol) == id)
+}
+
+/// Instruction indices of syscall `call`s in `instructions`, with the symbol
+/// each one calls, for relocations the loader binds by name.
+pub fn syscall_call_sites(instructions: &[BpfInstruction]) -> Vec<(usize, String)> {
+    instructions
+        .iter()
+        .enumerate()
+        .filter(|(_, instruction)| instruction.opcode == 0x85 && instruction.src_reg == 0)
+        .filter_map(|(index, instruction)| {
+            syscall_symbol(instruction.immediate as u32).map(|symbol| (index, symbol.to_string()))
+        })
+        .collect()
+}
+
+/// Call immediate for a syscall: the murmur3 hash of its symbol, as the
+/// loader registers it.
+pub fn syscall_id(symbol: &str) -> u32 {
+    murmur3_32(symbol.as_bytes(), 0)
+}
+
+fn murmur3_32(data: &[u8], seed: u32) -> u32 {
+    const C1: u32 = 0xcc9e2d51;
+    const C2: u32 = 0x1b873593;
+
+    let mut hash = seed;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe6546b64);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        let mut k = 0u32;
+        for (i, &byte) in tail.iter().enumerate() {
+            k |= (byte as u32) << (8 * i);
+        }
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+
+    hash ^= data.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85ebca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2ae35);
+    hash ^ (hash >> 16)
+}
+
+/// Anchor's 8-byte instruction discriminator for the handler `name`: the
+/// start of `sha256("global:<name>")`. Clients put it in front of the
+/// instruction data to select the handler.
+pub fn anchor_discriminator(name: &str) -> [u8; 8] {
+    let hash = sha256(format!("global:{}", name).as_bytes());
+    let mut discriminator = [0; 8];
+    discriminator.copy_from_slice(&hash[..8]);
+    discriminator
+}
+
+/// SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    // Pad with a 1 bit, zeros and the bit length to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+
+    let mut hash = [0; 32];
+    for (bytes, word) in hash.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    hash
+}
+
+/// Most accounts the entrypoint deserializes; their `SolAccountInfo`s fill
+/// most of the entrypoint's 4KB stack frame.
+pub const MAX_ACCOUNTS: i32 = 64;
+
+/// Size of the `SolAccountInfo` records handed to the user entrypoint:
+///
+/// | offset | field                        |
+/// |--------|------------------------------|
+/// | 0      | `U8* key`                    |
+/// | 8      | `U64* lamports`              |
+/// | 16     | `U64 data_len`               |
+/// | 24     | `U8* data`                   |
+/// | 32     | `U8* owner`                  |
+/// | 40     | `U64 rent_epoch`             |
+/// | 48     | `Bool is_signer`             |
+/// | 49     | `Bool is_writable`           |
+/// | 50     | `Bool executable`            |
+pub const ACCOUNT_INFO_SIZE: i32 = 56;
+
+/// Spare bytes the runtime reserves after each account's data for realloc.
+pub const MAX_PERMITTED_DATA_INCREASE: i32 = 10 * 1024;
+
+/// `ProgramError::InvalidArgument`, returned when the input holds more than
+/// [`MAX_ACCOUNTS`] accounts.
+const ERROR_INVALID_ARGUMENT: i32 = 2;
+
+/// Anchor's `InstructionMissing`: the instruction data is too short to hold
+/// a discriminator.
+pub const ANCHOR_INSTRUCTION_MISSING: i32 = 100;
+
+/// Anchor's `InstructionFallbackNotFound`: no handler has the discriminator.
+pub const ANCHOR_FALLBACK_NOT_FOUND: i32 = 101;
+
+/// Builds the program entrypoint that turns Solana's serialized input into
+/// arguments for a HolyC function.
+///
+/// The instructions returned by [`SolanaBpf::get_instructions`] go in front
+/// of the code generated by the `CodeGen` this was created from.
 #[allow(dead_code)]
 pub struct SolanaBpf {
     codegen: Vec<BpfInstruction>,
+    /// Start of each generated function, and whether it returns a value
+    functions: HashMap<String, (usize, bool)>,
+    /// Entrypoint calls and the function address each one targets
+    calls: Vec<(usize, usize)>,
 }
 
 #[allow(dead_code)]
 impl SolanaBpf {
-    pub fn new(_codegen: &mut CodeGen) -> Self {
+    pub fn new(codegen: &mut CodeGen) -> Self {
+        let functions = codegen
+            .functions()
+            .iter()
+            .map(|(name, &address)| {
+                // A failure to decode arguments is reported even from void functions
+                let returns_value = !matches!(codegen.return_type(name), None | Some(Type::Void))
+                    || codegen.decodes_arguments(name);
+                (name.clone(), (address, returns_value))
+            })
+            .collect();
         Self {
             codegen: Vec::new(),
+            functions,
+            calls: Vec::new(),
         }
     }
 
+    /// Emit an entrypoint that deserializes the input region at R1 and calls
+    /// `name(accounts, accounts_len, data, data_len, program_id)`, where
+    /// `accounts` points at an array of `SolAccountInfo` records (see
+    /// [`ACCOUNT_INFO_SIZE`]). Void functions report success; otherwise the
+    /// function's result is the program's.
+    ///
+    /// An unknown `name` calls the first instruction of the generated code.
     pub fn generate_entrypoint(&mut self, name: &str) -> Result<(), SolanaError> {
-        // Generate real Solana BPF program entrypoint
-        // 1. Load program accounts from instruction data
-        // R1 = program_id pointer, R2 = accounts pointer, R3 = instruction_data pointer
+        let too_many = self.emit_deserialize();
+        let returns_value = self.emit_call_function(name)?;
+        self.emit_return(returns_value);
+        self.emit_too_many_accounts(too_many);
 
-        // Load accounts array pointer to R6
-        self.emit_load_reg(6, 2, 0); // r6 = accounts
+        // Earlier entrypoints' calls must still reach past everything emitted
+        self.resolve_calls();
+        Ok(())
+    }
 
-        // Load instruction data pointer to R7
-        self.emit_load_reg(7, 3, 0); // r7 = instruction_data
+    /// Emit an Anchor-compatible entrypoint that picks the handler among
+    /// `names` whose [`anchor_discriminator`] starts the instruction data.
+    ///
+    /// The handler is called like [`Self::generate_entrypoint`]'s function,
+    /// with the data following the discriminator. Unknown discriminators fail
+    /// with [`ANCHOR_FALLBACK_NOT_FOUND`] and data too short to hold one with
+    /// [`ANCHOR_INSTRUCTION_MISSING`].
+    pub fn generate_dispatcher(&mut self, names: &[&str]) -> Result<(), SolanaError> {
+        let too_many = self.emit_deserialize();
 
-        // Set up stack frame for program execution
-        self.emit_move_immediate(10, 512); // r10 = stack_ptr (512 bytes)
+        let missing = self.emit(0xa5, 4, 0, 0, 8); // jlt r4, 8
+        self.emit_load_reg(8, 3, 0); // r8 = discriminator
+        self.emit_add_immediate(3, 8);
+        self.emit_add_immediate(4, -8);
 
-        // Call the main program logic
-        self.emit_call_function(name)?;
+        for name in names {
+            let discriminator = i64::from_le_bytes(anchor_discriminator(name));
+            self.emit(0x18, 9, 0, 0, discriminator as i32); // lddw r9, discriminator
+            self.emit(0, 0, 0, 0, (discriminator >> 32) as i32);
+            let next = self.emit(0x5d, 8, 9, 0, 0); // jne r8, r9
+            let returns_value = self.emit_call_function(name)?;
+            self.emit_return(returns_value);
+            self.patch_jump(next, self.codegen.len());
+        }
+        self.emit_move_immediate(0, ANCHOR_FALLBACK_NOT_FOUND);
+        self.emit_exit();
 
-        // Return success (0) on R0
-        self.emit_move_immediate(0, 0);
+        self.patch_jump(missing, self.codegen.len());
+        self.emit_move_immediate(0, ANCHOR_INSTRUCTION_MISSING);
         self.emit_exit();
 
+        self.emit_too_many_accounts(too_many);
+        self.resolve_calls();
         Ok(())
     }
 
+    /// Deserialize the input region at R1 into the entrypoint arguments in
+    /// R1-R5, returning the jump taken when there are too many accounts.
+    fn emit_deserialize(&mut self) -> usize {
+        let infos = -(MAX_ACCOUNTS * ACCOUNT_INFO_SIZE);
+
+        // r6 walks the input; r7 = number of accounts
+        self.emit(0xbf, 6, 1, 0, 0); // mov r6, r1
+        self.emit_load_reg(7, 6, 0); // ldxdw r7, [r6+0]
+        self.emit_add_immediate(6, 8);
+        let too_many = self.emit(0x25, 7, 0, 0, MAX_ACCOUNTS); // jgt r7, MAX_ACCOUNTS
+
+        // r8 = account index, r9 = its SolAccountInfo
+        self.emit_move_immediate(8, 0);
+        self.emit(0xbf, 9, 10, 0, 0); // mov r9, r10
+        self.emit_add_immediate(9, infos);
+
+        let loop_start = self.codegen.len();
+        let done = self.emit(0x3d, 8, 7, 0, 0); // jge r8, r7
+        self.emit(0x71, 2, 6, 0, 0); // ldxb r2, [r6+0] (duplicate marker)
+        let duplicate = self.emit(0x55, 2, 0, 0, 0xff); // jne r2, 0xff
+
+        // A fresh account: flags, key, owner, lamports and data live in the input
+        for (input, info) in [(1, 48), (2, 49), (3, 50)] {
+            self.emit(0x71, 3, 6, input, 0); // ldxb r3, [r6+input]
+            self.emit(0x73, 9, 3, info, 0); // stxb [r9+info], r3
+        }
+        for (input, info) in [(8, 0), (72, 8), (88, 24), (40, 32)] {
+            self.emit(0xbf, 3, 6, 0, 0); // mov r3, r6
+            self.emit_add_immediate(3, input);
+            self.emit(0x7b, 9, 3, info, 0); // stxdw [r9+info], r3
+        }
+        self.emit_load_reg(4, 6, 80); // data_len
+        self.emit(0x7b, 9, 4, 16, 0);
+
+        // Skip the data and realloc padding, align to 8, then read rent_epoch
+        self.emit(0x0f, 6, 4, 0, 0); // add r6, r4
+        self.emit_add_immediate(6, 88 + MAX_PERMITTED_DATA_INCREASE + 7);
+        self.emit(0x77, 6, 0, 0, 3); // rsh r6, 3
+        self.emit(0x67, 6, 0, 0, 3); // lsh r6, 3
+        self.emit_load_reg(3, 6, 0);
+        self.emit(0x7b, 9, 3, 40, 0);
+        self.emit_add_immediate(6, 8);
+        let fresh_done = self.emit(0x05, 0, 0, 0, 0); // ja next
+
+        // A duplicate: copy the SolAccountInfo of the account it repeats
+        self.patch_jump(duplicate, self.codegen.len());
+        self.emit_move_immediate(3, ACCOUNT_INFO_SIZE);
+        self.emit(0x2f, 2, 3, 0, 0); // mul r2, r3
+        self.emit(0xbf, 3, 10, 0, 0); // mov r3, r10
+        self.emit_add_immediate(3, infos);
+        self.emit(0x0f, 3, 2, 0, 0); // add r3, r2
+        for offset in (0..ACCOUNT_INFO_SIZE as i16).step_by(8) {
+            self.emit_load_reg(4, 3, offset);
+            self.emit(0x7b, 9, 4, offset, 0);
+        }
+        self.emit_add_immediate(6, 8);
+
+        self.patch_jump(fresh_done, self.codegen.len());
+        self.emit_add_immediate(8, 1);
+        self.emit_add_immediate(9, ACCOUNT_INFO_SIZE);
+        let back = self.emit(0x05, 0, 0, 0, 0);
+        self.patch_jump(back, loop_start);
+
+        // Instruction data and program id follow the accounts
+        self.patch_jump(done, self.codegen.len());
+        self.emit_load_reg(4, 6, 0); // r4 = data_len
+        self.emit(0xbf, 3, 6, 0, 0);
+        self.emit_add_immediate(3, 8); // r3 = data
+        self.emit(0xbf, 5, 3, 0, 0);
+        self.emit(0x0f, 5, 4, 0, 0); // r5 = program_id
+        self.emit(0xbf, 2, 7, 0, 0); // r2 = accounts_len
+        self.emit(0xbf, 1, 10, 0, 0);
+        self.emit_add_immediate(1, infos); // r1 = accounts
+        too_many
+    }
+
+    /// Exit with the called function's result, or success for a void one.
+    fn emit_return(&mut self, returns_value: bool) {
+        if !returns_value {
+            // Return success (0) on R0
+            self.emit_move_immediate(0, 0);
+        }
+        self.emit_exit();
+    }
+
+    fn emit_too_many_accounts(&mut self, too_many: usize) {
+        self.patch_jump(too_many, self.codegen.len());
+        self.emit_move_immediate(0, ERROR_INVALID_ARGUMENT);
+        self.emit(0x67, 0, 0, 0, 32); // builtin program errors live in the high word
+        self.emit_exit();
+    }
+
+    /// Check `instructions` against the verifier's rules: r10 is only
+    /// adjusted by an immediate, no register is read before it is set on
+    /// every path, jumps land on instruction boundaries (not inside an
+    /// `lddw`), stack accesses stay within the frame and every path exits.
+    pub fn verify(instructions: &[BpfInstruction]) -> Result<(), Vec<VerifyError>> {
+        let mut errors = check_instructions(instructions);
+        // Register and flow checks need a well-formed control-flow graph
+        if errors.is_empty() {
+            errors = check_flow(instructions);
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
     pub fn validate_solana_program(&self, instructions: &[BpfInstruction]) -> bool {
         // Real Solana BPF validation
         if instructions.is_empty() {
@@ -62,8 +510,7 @@ impl SolanaBpf {
         // Validate instruction sequence
         for (i, instruction) in instructions.iter().enumerate() {
             // Check for invalid opcodes
-            let class = instruction.opcode & 0x07;
-            match class {
+            match instruction.class() {
                 0x00 | 0x01 | 0x02 | 0x03 | 0x04 | 0x05 | 0x07 => {
                     // Valid instruction classes (LD, LDX, ST, STX, ALU, JMP, ALU64)
                 }
@@ -75,8 +522,15 @@ impl SolanaBpf {
                 return false;
             }
 
-            // Validate jump targets
-            if (instruction.opcode & 0xf0) == 0x50 || (instruction.opcode & 0xf0) == 0x60 {
+            // r10 is the read-only frame pointer; locals live below it
+            if registers(instruction).1 == Some(10) {
+                return false;
+            }
+
+            // Validate jump targets; calls and exits don't use the offset
+            let is_jump =
+                instruction.class() == 0x05 && !matches!(instruction.opcode & 0xf0, 0x80 | 0x90);
+            if is_jump {
                 let target = i as i32 + instruction.offset as i32 + 1;
                 if target < 0 || target >= instructions.len() as i32 {
                     return false;
@@ -93,33 +547,246 @@ impl SolanaBpf {
         true
     }
 
+    fn emit(&mut self, opcode: u8, dst_reg: u8, src_reg: u8, offset: i16, immediate: i32) -> usize {
+        self.codegen.push(BpfInstruction::new(
+            opcode, dst_reg, src_reg, offset, immediate,
+        ));
+        self.codegen.len() - 1
+    }
+
     fn emit_load_reg(&mut self, dst_reg: u8, src_reg: u8, offset: i16) {
         // BPF_LDX | BPF_MEM | BPF_DW (64-bit load)
-        let instruction = BpfInstruction::new(0x79, dst_reg, src_reg, offset, 0);
-        self.codegen.push(instruction);
+        self.emit(0x79, dst_reg, src_reg, offset, 0);
     }
 
     fn emit_move_immediate(&mut self, dst_reg: u8, immediate: i32) {
         // BPF_ALU64 | BPF_MOV | BPF_K (64-bit immediate move)
-        let instruction = BpfInstruction::new(0xb7, dst_reg, 0, 0, immediate);
-        self.codegen.push(instruction);
+        self.emit(0xb7, dst_reg, 0, 0, immediate);
     }
 
-    fn emit_call_function(&mut self, _name: &str) -> Result<(), SolanaError> {
-        // For now, emit a call to a generic program function
-        // This would be replaced with actual function resolution
-        let instruction = BpfInstruction::new(0x85, 0, 0, 0, 1);
-        self.codegen.push(instruction);
-        Ok(())
+    fn emit_add_immediate(&mut self, dst_reg: u8, immediate: i32) {
+        // BPF_ALU64 | BPF_ADD | BPF_K
+        self.emit(0x07, dst_reg, 0, 0, immediate);
+    }
+
+    /// Emit a local call to `name`, returning whether it produces a value.
+    fn emit_call_function(&mut self, name: &str) -> Result<bool, SolanaError> {
+        let (address, returns_value) = self.functions.get(name).copied().unwrap_or((0, false));
+        // BPF_JMP | BPF_CALL with BPF_PSEUDO_CALL; the offset is set by resolve_calls
+        let call = self.emit(0x85, 0, 1, 0, 0);
+        self.calls.push((call, address));
+        Ok(returns_value)
+    }
+
+    /// Point every call at its function, which starts `address` instructions
+    /// past the end of the entrypoint code.
+    fn resolve_calls(&mut self) {
+        let end = self.codegen.len();
+        for &(call, address) in &self.calls {
+            self.codegen[call].immediate = (end + address - call - 1) as i32;
+        }
+    }
+
+    fn patch_jump(&mut self, jump: usize, target: usize) {
+        self.codegen[jump].offset = (target as i64 - jump as i64 - 1) as i16;
     }
 
     fn emit_exit(&mut self) {
         // BPF_JMP | BPF_EXIT
-        let instruction = BpfInstruction::new(0x95, 0, 0, 0, 0);
-        self.codegen.push(instruction);
+        self.emit(0x95, 0, 0, 0, 0);
     }
 
     pub fn get_instructions(&self) -> &[BpfInstruction] {
         &self.codegen
     }
 }
+
+/// Registers set on entry to the program: the input pointer and r10.
+const ENTRY_REGISTERS: u16 = 1 << 1 | 1 << 10;
+
+/// Registers set on entry to a called function: its arguments and r10.
+const CALLEE_REGISTERS: u16 = 0b11111 << 1 | 1 << 10;
+
+/// Registers a call leaves unset: the argument registers r1-r5.
+const CLOBBERED_BY_CALL: u16 = 0b11111 << 1;
+
+/// Number of slots an instruction takes; `lddw` spans two.
+fn width(instruction: &BpfInstruction) -> usize {
+    if instruction.opcode == 0x18 {
+        2
+    } else {
+        1
+    }
+}
+
+/// Target of a jump or local call, if the instruction has one.
+fn branch_target(index: usize, instruction: &BpfInstruction) -> Option<i64> {
+    if !matches!(instruction.opcode & 0x07, 0x05 | 0x06) {
+        return None;
+    }
+    let relative = match instruction.opcode & 0xf0 {
+        0x90 => return None,
+        0x80 if instruction.src_reg == 1 => instruction.immediate as i64,
+        0x80 => return None,
+        _ => instruction.offset as i64,
+    };
+    Some(index as i64 + 1 + relative)
+}
+
+/// Registers an instruction reads and the one it writes.
+fn registers(instruction: &BpfInstruction) -> (Vec<u8>, Option<u8>) {
+    let (dst, src) = (instruction.dst_reg, instruction.src_reg);
+    let uses_src = instruction.opcode & 0x08 != 0;
+    let op = instruction.opcode & 0xf0;
+    match instruction.opcode & 0x07 {
+        // LD: only `lddw`, which loads a constant
+        0x00 => (vec![], Some(dst)),
+        0x01 => (vec![src], Some(dst)),
+        0x02 => (vec![dst], None),
+        0x03 => (vec![dst, src], None),
+        0x04 | 0x07 => match op {
+            0xb0 if uses_src => (vec![src], Some(dst)),
+            0xb0 => (vec![], Some(dst)),
+            0x80 | 0xd0 => (vec![dst], Some(dst)),
+            _ if uses_src => (vec![dst, src], Some(dst)),
+            _ => (vec![dst], Some(dst)),
+        },
+        _ => match op {
+            0x00 | 0x80 => (vec![], None),
+            0x90 => (vec![0], None),
+            _ if uses_src => (vec![dst, src], None),
+            _ => (vec![dst], None),
+        },
+    }
+}
+
+/// Rules that hold for each instruction on its own.
+fn check_instructions(instructions: &[BpfInstruction]) -> Vec<VerifyError> {
+    let mut starts = vec![false; instructions.len()];
+    let mut index = 0;
+    while index < instructions.len() {
+        starts[index] = true;
+        index += width(&instructions[index]);
+    }
+
+    let mut errors = Vec::new();
+    for (index, instruction) in instructions.iter().enumerate() {
+        if !starts[index] {
+            continue;
+        }
+        if let Some(register) = [instruction.dst_reg, instruction.src_reg]
+            .into_iter()
+            .find(|&register| register > 10)
+        {
+            errors.push(VerifyError::InvalidRegister { index, register });
+            continue;
+        }
+
+        // Growing or shrinking the frame by a constant is the only write allowed
+        let adjusts_frame = matches!(instruction.opcode, 0x07 | 0x17);
+        if registers(instruction).1 == Some(10) && !adjusts_frame {
+            errors.push(VerifyError::FramePointerWrite { index });
+        }
+
+        if let Some(target) = branch_target(index, instruction) {
+            if !usize::try_from(target).is_ok_and(|target| starts.get(target) == Some(&true)) {
+                errors.push(VerifyError::BadJumpTarget { index, target });
+            }
+        }
+
+        let base = match instruction.opcode & 0x07 {
+            0x01 => Some(instruction.src_reg),
+            0x02 | 0x03 => Some(instruction.dst_reg),
+            _ => None,
+        };
+        if base == Some(10) {
+            let size = match instruction.opcode & 0x18 {
+                0x00 => 4,
+                0x08 => 2,
+                0x10 => 1,
+                _ => 8,
+            };
+            let offset = instruction.offset as i64;
+            if offset < -STACK_FRAME_SIZE || offset + size as i64 > 0 {
+                errors.push(VerifyError::StackOutOfBounds {
+                    index,
+                    offset: instruction.offset,
+                    size,
+                });
+            }
+        }
+    }
+    errors
+}
+
+/// Rules over paths through the program: registers are set before they are
+/// read whichever way control arrives, and no path runs off the end.
+fn check_flow(instructions: &[BpfInstruction]) -> Vec<VerifyError> {
+    let blocks = cfg::basic_blocks(instructions);
+    let block_at = |start: usize| blocks.iter().position(|block| block.start == start);
+
+    // Registers set on entry to each block, on every path seen so far
+    let mut entry: Vec<Option<u16>> = vec![None; blocks.len()];
+    let mut pending = Vec::new();
+    if !blocks.is_empty() {
+        entry[0] = Some(ENTRY_REGISTERS);
+        pending.push(0);
+    }
+    for (index, instruction) in instructions.iter().enumerate() {
+        if instruction.opcode == 0x85 && instruction.src_reg == 1 {
+            let callee =
+                branch_target(index, instruction).and_then(|target| block_at(target as usize));
+            if let Some(block) = callee {
+                entry[block] =
+                    Some(entry[block].map_or(CALLEE_REGISTERS, |set| set & CALLEE_REGISTERS));
+                pending.push(block);
+            }
+        }
+    }
+
+    let run = |block: &cfg::BasicBlock, mut set: u16, errors: &mut Vec<VerifyError>| {
+        let mut index = block.start;
+        while index < block.end {
+            let instruction = &instructions[index];
+            let (reads, write) = registers(instruction);
+            for register in reads {
+                if set & 1 << register == 0 {
+                    errors.push(VerifyError::UninitializedRead { index, register });
+                }
+            }
+            if instruction.opcode == 0x85 {
+                set = (set & !CLOBBERED_BY_CALL) | 1;
+            }
+            if let Some(register) = write {
+                set |= 1 << register;
+            }
+            index += width(instruction);
+        }
+        set
+    };
+
+    while let Some(index) = pending.pop() {
+        let exit = run(&blocks[index], entry[index].unwrap_or(0), &mut Vec::new());
+        for &successor in &blocks[index].successors {
+            let merged = entry[successor].map_or(exit, |set| set & exit);
+            if entry[successor] != Some(merged) {
+                entry[successor] = Some(merged);
+                pending.push(successor);
+            }
+        }
+    }
+
+    let mut errors = Vec::new();
+    for (block, set) in blocks.iter().zip(&entry) {
+        let Some(set) = *set else {
+            continue;
+        };
+        run(block, set, &mut errors);
+        if block.falls_off {
+            errors.push(VerifyError::FallsOffEnd {
+                index: block.end - 1,
+            });
+        }
+    }
+    errors
+}
@@ -0,0 +1,512 @@
+use crate::pible::prelude::*;
+use thiserror::Error;
+
+use crate::pible::codegen;
+use crate::pible::parser::{Node, NodeType};
+use crate::pible::types::Type;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TypeError {
+    #[error("cannot use {found:?} where {expected:?} is expected")]
+    Incompatible { expected: Type, found: Type },
+    #[error("{0} is not a function")]
+    NotAFunction(String),
+    #[error("cannot index {0:?}")]
+    NotIndexable(Type),
+    #[error("{operator} needs a struct, found {found:?}")]
+    NotAStruct { operator: String, found: Type },
+    #[error("{function} takes {expected} arguments but {found} were given")]
+    Arity {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{function} takes at least {expected} arguments but {found} were given")]
+    VariadicArity {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    #[error("PrintF format has {expected} conversions but {found} arguments were given")]
+    FormatArity { expected: usize, found: usize },
+    #[error("%{conversion} in a PrintF format can't print {found:?}")]
+    FormatMismatch { conversion: char, found: Type },
+    #[error("unsupported PrintF conversion %{0}")]
+    UnknownConversion(String),
+}
+
+/// Check a resolved AST for category errors: structs used as scalars,
+/// calls to non-functions, indexing scalars and calls with the wrong number
+/// of arguments. Integers, pointers and booleans convert freely as in C,
+/// and expressions whose type can't be worked out are accepted.
+pub fn check(ast: &Node) -> Result<(), TypeError> {
+    let mut checker = Checker::default();
+    for child in &ast.children {
+        match child.node_type {
+            NodeType::StructDecl => {
+                let fields = child
+                    .children
+                    .iter()
+                    .filter_map(|field| field.value.as_deref()?.split_once(':'))
+                    .map(|(ty, name)| (name.to_string(), ty.to_string()))
+                    .collect();
+                checker
+                    .structs
+                    .insert(child.value.clone().unwrap_or_default(), fields);
+            }
+            NodeType::FunctionDecl => {
+                let Some((ret, name)) = child.value.as_deref().and_then(|v| v.split_once(':'))
+                else {
+                    continue;
+                };
+                let params = child
+                    .children
+                    .iter()
+                    .filter(|param| param.node_type == NodeType::Identifier)
+                    .filter_map(|param| param.value.as_deref()?.split_once(':'))
+                    .map(|(ty, _)| ty.to_string())
+                    .collect();
+                checker.functions.insert(
+                    name.to_string(),
+                    Signature {
+                        ret: ret.to_string(),
+                        params,
+                        variadic: child.variadic,
+                    },
+                );
+            }
+            _ => {}
+        }
+    }
+    // An enum's variants are constants too
+    let constants = ast.children.iter().flat_map(|child| match child.node_type {
+        NodeType::EnumDecl => child.children.iter().collect(),
+        _ => vec![child],
+    });
+    for child in constants {
+        if let Some((ty, name)) = child
+            .value
+            .as_deref()
+            .filter(|_| child.node_type == NodeType::ConstDecl)
+            .and_then(|value| value.split_once(':'))
+        {
+            let ty = checker.resolve_type(ty);
+            checker.globals.insert(name.to_string(), ty);
+        }
+    }
+
+    checker.scopes.push(HashMap::new());
+    for child in &ast.children {
+        match child.node_type {
+            NodeType::FunctionDecl
+            | NodeType::StructDecl
+            | NodeType::MapDecl
+            | NodeType::EnumDecl => {}
+            _ => checker.statement(child)?,
+        }
+    }
+
+    for function in ast
+        .children
+        .iter()
+        .filter(|child| child.node_type == NodeType::FunctionDecl)
+    {
+        checker.scopes = vec![HashMap::new()];
+        if function.variadic {
+            let scope = &mut checker.scopes[0];
+            scope.insert("argc".to_string(), Some(Type::I64));
+            scope.insert("argv".to_string(), Some(Type::Pointer(Box::new(Type::I64))));
+        }
+        checker.return_type = function
+            .value
+            .as_deref()
+            .and_then(|value| value.split_once(':'))
+            .and_then(|(ret, _)| checker.resolve_type(ret));
+        for child in &function.children {
+            match child.node_type {
+                NodeType::Identifier => checker.declare(child),
+                _ => checker.statement(child)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Checker {
+    /// Field names and declared types of each struct
+    structs: HashMap<String, Vec<(String, String)>>,
+    functions: HashMap<String, Signature>,
+    globals: HashMap<String, Option<Type>>,
+    scopes: Vec<HashMap<String, Option<Type>>>,
+    return_type: Option<Type>,
+}
+
+/// A function's return and parameter types, as declared.
+#[derive(Clone)]
+struct Signature {
+    ret: String,
+    params: Vec<String>,
+    /// Whether further arguments may follow `params`
+    variadic: bool,
+}
+
+impl Checker {
+    fn statement(&mut self, node: &Node) -> Result<(), TypeError> {
+        match (&node.node_type, node.value.as_deref().unwrap_or("")) {
+            (NodeType::Block, _) | (NodeType::Statement, "for") => {
+                self.scopes.push(HashMap::new());
+                let result = node
+                    .children
+                    .iter()
+                    .try_for_each(|child| self.statement(child));
+                self.scopes.pop();
+                result
+            }
+            (NodeType::Statement, "var") => {
+                let Some(name) = node.children.first() else {
+                    return Ok(());
+                };
+                if let Some(initializer) = node.children.get(1) {
+                    let declared = name
+                        .value
+                        .as_deref()
+                        .and_then(|value| value.split_once(':'))
+                        .and_then(|(ty, _)| self.resolve_type(ty));
+                    if initializer.node_type == NodeType::InitializerList {
+                        let element = declared.as_ref().and_then(Type::element);
+                        for value in &initializer.children {
+                            let found = self.expression(value)?;
+                            assignable(element, found.as_ref())?;
+                        }
+                    } else {
+                        let found = self.expression(initializer)?;
+                        assignable(declared.as_ref(), found.as_ref())?;
+                    }
+                }
+                self.declare(name);
+                Ok(())
+            }
+            (NodeType::Statement, "return") => {
+                if let Some(value) = node.children.first() {
+                    let found = self.expression(value)?;
+                    // `U0` functions returning a status code are common
+                    if self.return_type != Some(Type::Void) {
+                        assignable(self.return_type.as_ref(), found.as_ref())?;
+                    }
+                }
+                Ok(())
+            }
+            (NodeType::Statement, _) => node
+                .children
+                .iter()
+                .try_for_each(|child| self.statement(child)),
+            (NodeType::ConstDecl, _) => Ok(()),
+            _ => self.expression(node).map(|_| ()),
+        }
+    }
+
+    /// Check `node` and give its type, or `None` when it isn't known.
+    fn expression(&mut self, node: &Node) -> Result<Option<Type>, TypeError> {
+        let value = node.value.as_deref().unwrap_or("");
+        let ty = match (&node.node_type, value, node.children.as_slice()) {
+            (NodeType::Literal, _, _) if value.starts_with('"') => {
+                Some(Type::Pointer(Box::new(Type::Int {
+                    bits: 8,
+                    signed: false,
+                })))
+            }
+            (NodeType::Literal, "true" | "false", _) => Some(Type::Bool),
+            (NodeType::Literal, _, _) => Some(Type::I64),
+            (NodeType::FloatLiteral, _, _) => Some(Type::F64),
+            (NodeType::Sizeof, _, _) => Some(Type::Int {
+                bits: 64,
+                signed: false,
+            }),
+            (NodeType::Cast, _, [operand]) => {
+                self.expression(operand)?;
+                self.resolve_type(value)
+            }
+            (NodeType::Identifier, name, _) => match self.lookup(name) {
+                Some(ty) => ty,
+                None => self.function_pointer(name),
+            },
+            (NodeType::Expression, "call", [callee, args @ ..]) => self.call(callee, args)?,
+            (NodeType::Expression, "=", [target, value]) => {
+                let expected = self.expression(target)?;
+                let found = self.expression(value)?;
+                assignable(expected.as_ref(), found.as_ref())?;
+                expected
+            }
+            (NodeType::Expression, operator @ ("." | "->"), [object, field]) => {
+                let object = self.expression(object)?;
+                let name = match (operator, &object) {
+                    (".", Some(Type::Struct(name))) => name.clone(),
+                    ("->", Some(Type::Pointer(pointee))) => match pointee.as_ref() {
+                        Type::Struct(name) => name.clone(),
+                        _ => return Ok(None),
+                    },
+                    (".", Some(found)) if !found.is_aggregate() => {
+                        return Err(TypeError::NotAStruct {
+                            operator: operator.to_string(),
+                            found: found.clone(),
+                        })
+                    }
+                    _ => return Ok(None),
+                };
+                self.field_type(&name, field.value.as_deref().unwrap_or(""))
+            }
+            (NodeType::Expression, "index", [base, index]) => {
+                let base = self.expression(base)?;
+                self.expression(index)?;
+                match base {
+                    Some(Type::Array(element, _)) | Some(Type::Pointer(element)) => Some(*element),
+                    Some(found) => return Err(TypeError::NotIndexable(found)),
+                    None => None,
+                }
+            }
+            (NodeType::Expression, "addr", [place]) => self
+                .expression(place)?
+                .map(|ty| Type::Pointer(Box::new(ty))),
+            (
+                NodeType::Expression,
+                "==" | "!=" | "<" | "<=" | ">" | ">=" | "&&" | "||" | "!",
+                _,
+            ) => {
+                self.operands(&node.children)?;
+                Some(Type::Bool)
+            }
+            (NodeType::Expression, _, [_, _]) => match self.operands(&node.children)?.as_slice() {
+                [Some(left), Some(right)] => Some(Type::common(left, right)),
+                _ => None,
+            },
+            (NodeType::Expression, _, [_]) => self.operands(&node.children)?.pop().flatten(),
+            (NodeType::Ternary, _, [condition, then, otherwise]) => {
+                self.expression(condition)?;
+                let then = self.expression(then)?;
+                self.expression(otherwise)?;
+                then
+            }
+            _ => {
+                for child in &node.children {
+                    self.expression(child)?;
+                }
+                None
+            }
+        };
+        Ok(ty)
+    }
+
+    /// Check the operands of an arithmetic or logical operator, none of
+    /// which may be a struct.
+    fn operands(&mut self, operands: &[Node]) -> Result<Vec<Option<Type>>, TypeError> {
+        operands
+            .iter()
+            .map(|operand| match self.expression(operand)? {
+                Some(found @ Type::Struct(_)) => Err(TypeError::Incompatible {
+                    expected: Type::I64,
+                    found,
+                }),
+                ty => Ok(ty),
+            })
+            .collect()
+    }
+
+    fn call(&mut self, callee: &Node, args: &[Node]) -> Result<Option<Type>, TypeError> {
+        let name = callee.value.as_deref().unwrap_or("");
+        let arg_types = args
+            .iter()
+            .map(|arg| self.expression(arg))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // A variable or computed callee must be a function pointer
+        if callee.node_type != NodeType::Identifier || self.lookup(name).is_some() {
+            let name = match callee.node_type {
+                NodeType::Identifier => name,
+                _ => "expression",
+            };
+            let Some(ty) = self.expression(callee)? else {
+                return Ok(None);
+            };
+            let Some(Type::Function { ret, params }) = ty.element() else {
+                return Err(TypeError::NotAFunction(name.to_string()));
+            };
+            if params.len() != args.len() {
+                return Err(TypeError::Arity {
+                    function: name.to_string(),
+                    expected: params.len(),
+                    found: args.len(),
+                });
+            }
+            for (param, found) in params.iter().zip(&arg_types) {
+                assignable(Some(param), found.as_ref())?;
+            }
+            return Ok(Some(ret.as_ref().clone()));
+        }
+        if name == "PrintF" {
+            check_format(args, &arg_types)?;
+        }
+        let Some(signature) = self.functions.get(name).cloned() else {
+            // Builtins check their own arguments during code generation
+            return Ok(None);
+        };
+        let (expected, found) = (signature.params.len(), args.len());
+        if signature.variadic && found < expected {
+            return Err(TypeError::VariadicArity {
+                function: name.to_string(),
+                expected,
+                found,
+            });
+        }
+        if !signature.variadic && found != expected {
+            return Err(TypeError::Arity {
+                function: name.to_string(),
+                expected,
+                found,
+            });
+        }
+        for (param, found) in signature.params.iter().zip(&arg_types) {
+            assignable(self.resolve_type(param).as_ref(), found.as_ref())?;
+        }
+        Ok(self.resolve_type(&signature.ret))
+    }
+
+    /// Type of the function `name` used as a value: a pointer to it.
+    fn function_pointer(&self, name: &str) -> Option<Type> {
+        let signature = self.functions.get(name)?;
+        let function = Type::Function {
+            ret: Box::new(self.resolve_type(&signature.ret)?),
+            params: signature
+                .params
+                .iter()
+                .map(|param| self.resolve_type(param))
+                .collect::<Option<_>>()?,
+        };
+        Some(Type::Pointer(Box::new(function)))
+    }
+
+    fn field_type(&self, name: &str, field: &str) -> Option<Type> {
+        let (_, ty) = self.structs.get(name)?.iter().find(|(f, _)| f == field)?;
+        self.resolve_type(ty)
+    }
+
+    /// Bring the `Type:name` identifier `node` into the innermost scope.
+    fn declare(&mut self, node: &Node) {
+        let Some((ty, name)) = node.value.as_deref().and_then(|v| v.split_once(':')) else {
+            return;
+        };
+        let ty = self.resolve_type(ty);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), ty);
+        }
+    }
+
+    /// Type of the variable or constant `name`: `None` when nothing has that
+    /// name, `Some(None)` when its type is unknown.
+    fn lookup(&self, name: &str) -> Option<Option<Type>> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .or_else(|| self.globals.get(name))
+            .cloned()
+    }
+
+    /// Type a declaration spells, accepting a declared struct's bare name.
+    fn resolve_type(&self, name: &str) -> Option<Type> {
+        Type::parse(name).or_else(|| {
+            let base = name.trim_end_matches('*').trim();
+            self.structs
+                .contains_key(base)
+                .then(|| Type::parse(&format!("struct {}", name)))
+                .flatten()
+        })
+    }
+}
+
+/// Whether a value of type `found` may be stored where `expected` is
+/// declared. Only struct values are strict: they must match exactly and
+/// can't mix with scalars, except that a struct evaluates to its address
+/// where a pointer to it is expected.
+fn assignable(expected: Option<&Type>, found: Option<&Type>) -> Result<(), TypeError> {
+    let (Some(expected), Some(found)) = (expected, found) else {
+        return Ok(());
+    };
+    let mismatch = match (expected, found) {
+        (Type::Struct(expected), Type::Struct(found)) => expected != found,
+        (Type::Pointer(pointee), Type::Struct(_)) => pointee.as_ref() != found,
+        (Type::Struct(_), _) | (_, Type::Struct(_)) => true,
+        _ => false,
+    };
+    if mismatch {
+        return Err(TypeError::Incompatible {
+            expected: expected.clone(),
+            found: found.clone(),
+        });
+    }
+    Ok(())
+}
+
+/// Check a `PrintF` call's arguments against the conversions in its format
+/// string. Calls whose format isn't a string literal aren't checked.
+fn check_format(args: &[Node], arg_types: &[Option<Type>]) -> Result<(), TypeError> {
+    let Some(format) = args
+        .first()
+        .and_then(|format| format.value.as_deref())
+        .and_then(codegen::string_literal)
+    else {
+        return Ok(());
+    };
+    let conversions = conversions(&format)?;
+    if conversions.len() != args.len() - 1 {
+        return Err(TypeError::FormatArity {
+            expected: conversions.len(),
+            found: args.len() - 1,
+        });
+    }
+    for (&conversion, found) in conversions.iter().zip(&arg_types[1..]) {
+        let Some(found) = found else { continue };
+        let printable = match conversion {
+            's' => matches!(found, Type::Pointer(_) | Type::Array(..)),
+            'x' => matches!(found, Type::Int { .. } | Type::Bool | Type::Pointer(_)),
+            _ => matches!(found, Type::Int { .. } | Type::Bool),
+        };
+        if !printable {
+            return Err(TypeError::FormatMismatch {
+                conversion,
+                found: found.clone(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Conversion characters of each specifier in `format`, in order. Flags,
+/// widths and `l`/`h` length modifiers are accepted and skipped; `%%` is
+/// a literal percent sign.
+fn conversions(format: &str) -> Result<Vec<char>, TypeError> {
+    let mut conversions = Vec::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            continue;
+        }
+        let mut specifier = String::new();
+        let conversion = loop {
+            match chars.next() {
+                Some(c @ ('-' | '+' | ' ' | '#' | '0'..='9' | 'l' | 'h')) => specifier.push(c),
+                Some(c) => break c,
+                None => return Err(TypeError::UnknownConversion(specifier)),
+            }
+        };
+        match conversion {
+            '%' if specifier.is_empty() => {}
+            'd' | 'i' | 'u' | 'x' | 'c' | 's' => conversions.push(conversion),
+            other => {
+                specifier.push(other);
+                return Err(TypeError::UnknownConversion(specifier));
+            }
+        }
+    }
+    Ok(conversions)
+}
@@ -0,0 +1,322 @@
+use crate::pible::prelude::*;
+
+/// A HolyC value type, as far as code generation needs to know it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Void,
+    Int {
+        bits: u8,
+        signed: bool,
+    },
+    F64,
+    Bool,
+    Pointer(Box<Type>),
+    /// Fixed-size array; multi-dimensional arrays nest row-major, so
+    /// `U8[2][3]` is an array of two `U8[3]`s.
+    Array(Box<Type>, usize),
+    Struct(String),
+    /// What a function pointer points at, spelled `Ret(*)(Params)`
+    Function {
+        ret: Box<Type>,
+        params: Vec<Type>,
+    },
+}
+
+impl Type {
+    /// HolyC's default integer type, used for literals and undeclared values.
+    pub const I64: Type = Type::Int {
+        bits: 64,
+        signed: true,
+    };
+
+    /// Parse a declared type name such as `I64`, `U8*`, `U64[4]` or the
+    /// function pointer `U0(*)(U64)`.
+    pub fn parse(name: &str) -> Option<Type> {
+        let name = name.trim();
+        // Array dimensions follow a function pointer's parameter list
+        let params_end = name.rfind(')').map_or(0, |close| close + 1);
+        if let Some(open) = name[params_end..].find('[').map(|open| params_end + open) {
+            let mut ty = Type::parse(&name[..open])?;
+            let dimensions: Vec<usize> = name[open..]
+                .split(']')
+                .filter(|part| !part.is_empty())
+                .map(|part| part.trim_start_matches('[').trim().parse().ok())
+                .collect::<Option<_>>()?;
+            for &count in dimensions.iter().rev() {
+                ty = Type::Array(Box::new(ty), count);
+            }
+            return Some(ty);
+        }
+        if let Some(inner) = name.strip_suffix('*') {
+            return Type::parse(inner).map(|inner| Type::Pointer(Box::new(inner)));
+        }
+        if let Some((ret, params)) = name.split_once("(*)") {
+            let params = params.trim().strip_prefix('(')?.strip_suffix(')')?;
+            let function = Type::Function {
+                ret: Box::new(Type::parse(ret)?),
+                params: split_params(params)
+                    .into_iter()
+                    .map(Type::parse)
+                    .collect::<Option<_>>()?,
+            };
+            return Some(Type::Pointer(Box::new(function)));
+        }
+
+        if let Some(struct_name) = name.strip_prefix("struct ") {
+            return Some(Type::Struct(struct_name.trim().to_string()));
+        }
+
+        let (bits, signed) = match name {
+            "U0" => return Some(Type::Void),
+            "F64" => return Some(Type::F64),
+            "Bool" => return Some(Type::Bool),
+            "U8" => (8, false),
+            "U16" => (16, false),
+            "U32" => (32, false),
+            "U64" => (64, false),
+            "I8" => (8, true),
+            "I16" => (16, true),
+            "I32" => (32, true),
+            "I64" => (64, true),
+            _ => return None,
+        };
+        Some(Type::Int { bits, signed })
+    }
+
+    /// Whether comparisons and right shifts on this type are signed.
+    pub fn is_signed(&self) -> bool {
+        match self {
+            Type::Int { signed, .. } => *signed,
+            Type::F64 => true,
+            Type::Void
+            | Type::Bool
+            | Type::Pointer(_)
+            | Type::Array(..)
+            | Type::Struct(_)
+            | Type::Function { .. } => false,
+        }
+    }
+
+    /// Structs and arrays live in memory; as values they stand for their address.
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self, Type::Struct(_) | Type::Array(..))
+    }
+
+    /// The type an array decays to when used as a value.
+    pub fn decayed(&self) -> Type {
+        match self {
+            Type::Array(element, _) => Type::Pointer(element.clone()),
+            other => other.clone(),
+        }
+    }
+
+    /// Element type of an array or pointee of a pointer.
+    pub fn element(&self) -> Option<&Type> {
+        match self {
+            Type::Array(element, _) | Type::Pointer(element) => Some(element),
+            _ => None,
+        }
+    }
+
+    /// Size in bytes, looking struct layouts up in `structs`.
+    pub fn size(&self, structs: &HashMap<String, StructLayout>) -> Option<usize> {
+        match self {
+            Type::Void => Some(0),
+            Type::Bool => Some(1),
+            Type::Int { bits, .. } => Some(*bits as usize / 8),
+            Type::F64 | Type::Pointer(_) => Some(8),
+            Type::Array(element, count) => element.size(structs).map(|size| size * count),
+            Type::Struct(name) => structs.get(name).map(|layout| layout.size),
+            Type::Function { .. } => None,
+        }
+    }
+
+    /// Natural alignment in bytes; scalars align to their size.
+    pub fn align(&self, structs: &HashMap<String, StructLayout>) -> Option<usize> {
+        match self {
+            Type::Struct(name) => structs.get(name).map(|layout| layout.align),
+            Type::Array(element, _) => element.align(structs),
+            other => other.size(structs).map(|size| size.max(1)),
+        }
+    }
+
+    /// Result type of arithmetic on `left` and `right`: as in C, an `F64`
+    /// operand makes the result `F64` and an unsigned one makes it unsigned.
+    pub fn common(left: &Type, right: &Type) -> Type {
+        let (left, right) = (left.promoted(), right.promoted());
+        match (&left, &right) {
+            (Type::Pointer(_), _) => left,
+            (_, Type::Pointer(_)) => right,
+            (Type::F64, _) | (_, Type::F64) => Type::F64,
+            _ if left.is_signed() && !right.is_signed() => right,
+            _ => left,
+        }
+    }
+
+    /// Borsh encoding of a value of this type: scalars little-endian, struct
+    /// fields in declaration order without padding, and arrays as a `u32`
+    /// element count followed by the elements. Returns the pieces of the
+    /// encoding and its size, or `None` for types Borsh can't carry.
+    pub fn borsh_layout(
+        &self,
+        structs: &HashMap<String, StructLayout>,
+    ) -> Option<(Vec<BorshItem>, usize)> {
+        let mut items = Vec::new();
+        let size = self.borsh_items(structs, 0, 0, &mut items)?;
+        Some((items, size))
+    }
+
+    /// Append the items for a value at `memory` in memory and `encoded` in
+    /// the encoding, returning the encoded size.
+    fn borsh_items(
+        &self,
+        structs: &HashMap<String, StructLayout>,
+        memory: usize,
+        encoded: usize,
+        items: &mut Vec<BorshItem>,
+    ) -> Option<usize> {
+        match self {
+            Type::Int { .. } | Type::Bool => {
+                let size = self.size(structs)?;
+                items.push(BorshItem::Scalar {
+                    memory,
+                    encoded,
+                    size,
+                });
+                Some(size)
+            }
+            Type::Struct(name) => {
+                let mut size = 0;
+                for field in &structs.get(name)?.fields {
+                    size += field.ty.borsh_items(
+                        structs,
+                        memory + field.offset,
+                        encoded + size,
+                        items,
+                    )?;
+                }
+                Some(size)
+            }
+            Type::Array(element, count) => {
+                items.push(BorshItem::Length {
+                    encoded,
+                    count: *count,
+                });
+                let stride = element.size(structs)?;
+                let mut size = 4;
+                for i in 0..*count {
+                    size +=
+                        element.borsh_items(structs, memory + i * stride, encoded + size, items)?;
+                }
+                Some(size)
+            }
+            // F64 is fixed point here, not the IEEE double Borsh expects
+            Type::Void | Type::F64 | Type::Pointer(_) | Type::Function { .. } => None,
+        }
+    }
+
+    /// Bools and `U0` take part in arithmetic as `I64`; arrays as pointers.
+    fn promoted(&self) -> Type {
+        match self {
+            Type::Void | Type::Bool => Type::I64,
+            other => other.decayed(),
+        }
+    }
+}
+
+/// Split a function pointer's parameter list at the commas outside nested
+/// parentheses. `U0` or nothing at all means no parameters.
+fn split_params(params: &str) -> Vec<&str> {
+    let params = params.trim();
+    if params.is_empty() || params == "U0" {
+        return Vec::new();
+    }
+    let mut parts = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(&params[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&params[start..]);
+    parts
+}
+
+/// A struct field's position within its struct.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub name: String,
+    pub ty: Type,
+    pub offset: usize,
+    pub size: usize,
+}
+
+/// Byte layout of a struct: fields in declaration order, each at its natural
+/// alignment, with the total size padded to the struct's alignment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StructLayout {
+    pub name: String,
+    pub fields: Vec<FieldLayout>,
+    pub size: usize,
+    pub align: usize,
+}
+
+impl StructLayout {
+    /// Lay out `fields`; fails with the name of the first field whose type
+    /// has no known size.
+    pub fn new(
+        name: &str,
+        fields: &[(String, Type)],
+        structs: &HashMap<String, StructLayout>,
+    ) -> Result<Self, String> {
+        let mut layouts = Vec::with_capacity(fields.len());
+        let mut offset = 0usize;
+        let mut align = 1;
+
+        for (field_name, ty) in fields {
+            let (Some(size), Some(field_align)) = (ty.size(structs), ty.align(structs)) else {
+                return Err(field_name.clone());
+            };
+            offset = offset.next_multiple_of(field_align);
+            align = align.max(field_align);
+            layouts.push(FieldLayout {
+                name: field_name.clone(),
+                ty: ty.clone(),
+                offset,
+                size,
+            });
+            offset += size;
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            fields: layouts,
+            size: offset.next_multiple_of(align),
+            align,
+        })
+    }
+
+    pub fn field(&self, name: &str) -> Option<&FieldLayout> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+/// A piece of a value's Borsh encoding; offsets are from the start of the
+/// value in memory and in the encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorshItem {
+    /// A scalar of `size` bytes, laid out the same in memory and encoded
+    Scalar {
+        memory: usize,
+        encoded: usize,
+        size: usize,
+    },
+    /// The `u32` element count in front of an array of `count` elements
+    Length { encoded: usize, count: usize },
+}
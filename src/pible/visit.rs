@@ -0,0 +1,71 @@
+use crate::pible::parser::Node;
+
+/// A pass that walks an AST without changing it.
+///
+/// The default `visit_node` visits every child in order; a pass overrides it
+/// to act on the nodes it cares about, calling [`walk_node`] to carry on into
+/// their children.
+pub trait Visitor {
+    type Error;
+
+    fn visit_node(&mut self, node: &Node) -> Result<(), Self::Error> {
+        walk_node(self, node)
+    }
+}
+
+/// Visit each of `node`'s children with `visitor`, stopping at the first error.
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) -> Result<(), V::Error> {
+    node.children
+        .iter()
+        .try_for_each(|child| visitor.visit_node(child))
+}
+
+/// A pass that rewrites an AST into a new one.
+///
+/// The default `transform_node` copies the node with each child transformed;
+/// a pass overrides it to replace the nodes it cares about, calling
+/// [`walk_transform`] to rewrite their children first.
+pub trait Transform {
+    fn transform_node(&mut self, node: &Node) -> Node {
+        walk_transform(self, node)
+    }
+}
+
+/// Copy `node` with each of its children rewritten by `transform`.
+pub fn walk_transform<T: Transform + ?Sized>(transform: &mut T, node: &Node) -> Node {
+    Node {
+        node_type: node.node_type.clone(),
+        value: node.value.clone(),
+        position: node.position,
+        exported: node.exported,
+        variadic: node.variadic,
+        private: node.private,
+        children: node
+            .children
+            .iter()
+            .map(|child| transform.transform_node(child))
+            .collect(),
+    }
+}
+
+/// Counts the nodes in an AST, the smallest useful [`Visitor`].
+#[derive(Debug, Default)]
+pub struct NodeCounter {
+    pub count: usize,
+}
+
+impl Visitor for NodeCounter {
+    type Error = core::convert::Infallible;
+
+    fn visit_node(&mut self, node: &Node) -> Result<(), Self::Error> {
+        self.count += 1;
+        walk_node(self, node)
+    }
+}
+
+/// Number of nodes in the tree rooted at `node`, including `node` itself.
+pub fn count_nodes(node: &Node) -> usize {
+    let mut counter = NodeCounter::default();
+    let Ok(()) = counter.visit_node(node);
+    counter.count
+}
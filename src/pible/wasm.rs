@@ -0,0 +1,967 @@
+//! Experimental WebAssembly backend.
+//!
+//! Lowers the integer subset of HolyC (functions, locals, arithmetic,
+//! comparisons, control flow and calls) to a WebAssembly 1.0 module. Every
+//! value is an `i64` held in a Wasm local, parameters first; narrower integer
+//! types are not truncated except by casts. The entry function is exported
+//! as `main`. Pointers, arrays, structs, strings, `F64` and builtins such as
+//! `PrintF` are rejected.
+
+use crate::pible::codegen::{self, CodeGen, CodeGenError};
+use crate::pible::parser::{Node, NodeType};
+use crate::pible::prelude::*;
+use crate::pible::types::Type;
+use crate::pible::visit::{walk_node, Visitor};
+
+/// `\0asm`, followed by the format version.
+pub const MAGIC: [u8; 4] = *b"\0asm";
+pub const VERSION: [u8; 4] = [1, 0, 0, 0];
+
+const SECTION_TYPE: u8 = 1;
+const SECTION_FUNCTION: u8 = 3;
+const SECTION_EXPORT: u8 = 7;
+const SECTION_CODE: u8 = 10;
+
+const TYPE_FUNCTION: u8 = 0x60;
+const TYPE_I64: u8 = 0x7e;
+const EXPORT_FUNCTION: u8 = 0x00;
+
+/// Result of a `block`, `loop` or `if`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    Empty,
+    I64,
+}
+
+impl BlockType {
+    fn encode(self) -> u8 {
+        match self {
+            BlockType::Empty => 0x40,
+            BlockType::I64 => TYPE_I64,
+        }
+    }
+}
+
+/// Instructions without immediates, other than control flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    I32Eqz,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtS,
+    I64GtU,
+    I64LeS,
+    I64LeU,
+    I64GeS,
+    I64GeU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64DivU,
+    I64RemS,
+    I64RemU,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrS,
+    I64ShrU,
+    /// Widen a comparison's `i32` result
+    I64ExtendI32U,
+}
+
+impl Op {
+    const ALL: [Op; 26] = [
+        Op::I32Eqz,
+        Op::I64Eqz,
+        Op::I64Eq,
+        Op::I64Ne,
+        Op::I64LtS,
+        Op::I64LtU,
+        Op::I64GtS,
+        Op::I64GtU,
+        Op::I64LeS,
+        Op::I64LeU,
+        Op::I64GeS,
+        Op::I64GeU,
+        Op::I64Add,
+        Op::I64Sub,
+        Op::I64Mul,
+        Op::I64DivS,
+        Op::I64DivU,
+        Op::I64RemS,
+        Op::I64RemU,
+        Op::I64And,
+        Op::I64Or,
+        Op::I64Xor,
+        Op::I64Shl,
+        Op::I64ShrS,
+        Op::I64ShrU,
+        Op::I64ExtendI32U,
+    ];
+
+    fn opcode(self) -> u8 {
+        match self {
+            Op::I32Eqz => 0x45,
+            Op::I64Eqz => 0x50,
+            Op::I64Eq => 0x51,
+            Op::I64Ne => 0x52,
+            Op::I64LtS => 0x53,
+            Op::I64LtU => 0x54,
+            Op::I64GtS => 0x55,
+            Op::I64GtU => 0x56,
+            Op::I64LeS => 0x57,
+            Op::I64LeU => 0x58,
+            Op::I64GeS => 0x59,
+            Op::I64GeU => 0x5a,
+            Op::I64Add => 0x7c,
+            Op::I64Sub => 0x7d,
+            Op::I64Mul => 0x7e,
+            Op::I64DivS => 0x7f,
+            Op::I64DivU => 0x80,
+            Op::I64RemS => 0x81,
+            Op::I64RemU => 0x82,
+            Op::I64And => 0x83,
+            Op::I64Or => 0x84,
+            Op::I64Xor => 0x85,
+            Op::I64Shl => 0x86,
+            Op::I64ShrS => 0x87,
+            Op::I64ShrU => 0x88,
+            Op::I64ExtendI32U => 0xad,
+        }
+    }
+}
+
+/// The Wasm instructions this backend emits. Branch depths count enclosing
+/// blocks outward from the innermost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    Drop,
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    I64Const(i64),
+    Op(Op),
+}
+
+const OPCODE_BLOCK: u8 = 0x02;
+const OPCODE_LOOP: u8 = 0x03;
+const OPCODE_IF: u8 = 0x04;
+const OPCODE_ELSE: u8 = 0x05;
+const OPCODE_END: u8 = 0x0b;
+const OPCODE_BR: u8 = 0x0c;
+const OPCODE_BR_IF: u8 = 0x0d;
+const OPCODE_RETURN: u8 = 0x0f;
+const OPCODE_CALL: u8 = 0x10;
+const OPCODE_DROP: u8 = 0x1a;
+const OPCODE_LOCAL_GET: u8 = 0x20;
+const OPCODE_LOCAL_SET: u8 = 0x21;
+const OPCODE_LOCAL_TEE: u8 = 0x22;
+const OPCODE_I64_CONST: u8 = 0x42;
+
+impl Instruction {
+    /// Append the binary encoding to `out`.
+    pub fn encode(self, out: &mut Vec<u8>) {
+        match self {
+            Instruction::Block(ty) => out.extend([OPCODE_BLOCK, ty.encode()]),
+            Instruction::Loop(ty) => out.extend([OPCODE_LOOP, ty.encode()]),
+            Instruction::If(ty) => out.extend([OPCODE_IF, ty.encode()]),
+            Instruction::Else => out.push(OPCODE_ELSE),
+            Instruction::End => out.push(OPCODE_END),
+            Instruction::Return => out.push(OPCODE_RETURN),
+            Instruction::Drop => out.push(OPCODE_DROP),
+            Instruction::Br(depth) => encode_index(out, OPCODE_BR, depth),
+            Instruction::BrIf(depth) => encode_index(out, OPCODE_BR_IF, depth),
+            Instruction::Call(function) => encode_index(out, OPCODE_CALL, function),
+            Instruction::LocalGet(local) => encode_index(out, OPCODE_LOCAL_GET, local),
+            Instruction::LocalSet(local) => encode_index(out, OPCODE_LOCAL_SET, local),
+            Instruction::LocalTee(local) => encode_index(out, OPCODE_LOCAL_TEE, local),
+            Instruction::I64Const(value) => {
+                out.push(OPCODE_I64_CONST);
+                write_signed(out, value);
+            }
+            Instruction::Op(op) => out.push(op.opcode()),
+        }
+    }
+
+    /// Decode the instruction at the start of `bytes`, returning it and its
+    /// length; `None` for anything outside the subset this backend emits.
+    #[allow(dead_code)]
+    pub fn decode(bytes: &[u8]) -> Option<(Self, usize)> {
+        let (&opcode, rest) = bytes.split_first()?;
+        let block_type = || match rest.first()? {
+            0x40 => Some(BlockType::Empty),
+            &TYPE_I64 => Some(BlockType::I64),
+            _ => None,
+        };
+        let index = |make: fn(u32) -> Instruction| {
+            let (value, length) = read_unsigned(rest)?;
+            Some((make(u32::try_from(value).ok()?), length + 1))
+        };
+        match opcode {
+            OPCODE_BLOCK => Some((Instruction::Block(block_type()?), 2)),
+            OPCODE_LOOP => Some((Instruction::Loop(block_type()?), 2)),
+            OPCODE_IF => Some((Instruction::If(block_type()?), 2)),
+            OPCODE_ELSE => Some((Instruction::Else, 1)),
+            OPCODE_END => Some((Instruction::End, 1)),
+            OPCODE_RETURN => Some((Instruction::Return, 1)),
+            OPCODE_DROP => Some((Instruction::Drop, 1)),
+            OPCODE_BR => index(Instruction::Br),
+            OPCODE_BR_IF => index(Instruction::BrIf),
+            OPCODE_CALL => index(Instruction::Call),
+            OPCODE_LOCAL_GET => index(Instruction::LocalGet),
+            OPCODE_LOCAL_SET => index(Instruction::LocalSet),
+            OPCODE_LOCAL_TEE => index(Instruction::LocalTee),
+            OPCODE_I64_CONST => {
+                let (value, length) = read_signed(rest)?;
+                Some((Instruction::I64Const(value), length + 1))
+            }
+            _ => Op::ALL
+                .into_iter()
+                .find(|op| op.opcode() == opcode)
+                .map(|op| (Instruction::Op(op), 1)),
+        }
+    }
+}
+
+fn encode_index(out: &mut Vec<u8>, opcode: u8, index: u32) {
+    out.push(opcode);
+    write_unsigned(out, index as u64);
+}
+
+/// Unsigned LEB128.
+fn write_unsigned(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Signed LEB128.
+fn write_signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Read unsigned LEB128, returning the value and the bytes it took.
+fn read_unsigned(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Read signed LEB128, returning the value and the bytes it took.
+fn read_signed(bytes: &[u8]) -> Option<(i64, usize)> {
+    let mut value = 0i64;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        let shift = 7 * i as u32;
+        value |= ((byte & 0x7f) as i64) << shift;
+        if byte & 0x80 == 0 {
+            if shift + 7 < 64 && byte & 0x40 != 0 {
+                value |= -1 << (shift + 7);
+            }
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// A generated function: `params` and then `locals` extra `i64` locals,
+/// and a body ending in `end`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub params: u32,
+    pub locals: u32,
+    pub body: Vec<Instruction>,
+}
+
+pub struct WasmCodeGen {
+    /// Function index of each function; the entry function is 0
+    function_indices: HashMap<String, u32>,
+    return_types: HashMap<String, Type>,
+    scopes: Vec<HashMap<String, Variable>>,
+    /// Locals of the current function, parameters included
+    local_count: u32,
+    body: Vec<Instruction>,
+    /// What each open `block`, `loop` and `if` of a statement is for,
+    /// outermost first, so `br` depths can be counted back from the end
+    labels: Vec<Label>,
+}
+
+/// The role of a structured control label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Label {
+    /// Branching here leaves a loop
+    Break,
+    /// Branching here starts a loop's next iteration
+    Continue,
+    Other,
+}
+
+/// A local variable's index and declared type.
+#[derive(Debug, Clone)]
+struct Variable {
+    index: u32,
+    ty: Type,
+}
+
+impl Default for WasmCodeGen {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WasmCodeGen {
+    pub fn new() -> Self {
+        Self {
+            function_indices: HashMap::new(),
+            return_types: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            local_count: 0,
+            body: Vec::new(),
+            labels: Vec::new(),
+        }
+    }
+
+    /// Generate every function in `ast`, entry function first.
+    pub fn generate(&mut self, ast: &Node) -> Result<Vec<Function>, CodeGenError> {
+        let mut functions = Vec::new();
+        for child in &ast.children {
+            if child.node_type != NodeType::FunctionDecl {
+                return Err(CodeGenError::UnsupportedNodeType(child.node_type.clone()));
+            }
+            if let Some((ret, name)) = child.value.as_deref().and_then(|v| v.split_once(':')) {
+                let ret = Type::parse(ret).unwrap_or(Type::I64);
+                self.return_types.insert(name.to_string(), ret);
+            }
+            functions.push(child);
+        }
+
+        // Wasm calls by index, so number every function before generating any
+        if let Some(entry) = CodeGen::entry_index(&functions) {
+            let entry = functions.remove(entry);
+            functions.insert(0, entry);
+        }
+        for (index, function) in functions.iter().enumerate() {
+            if let Some(name) = codegen::function_name(function) {
+                self.function_indices.insert(name.to_string(), index as u32);
+            }
+        }
+
+        functions
+            .into_iter()
+            .map(|function| self.generate_function(function))
+            .collect()
+    }
+
+    fn generate_function(&mut self, node: &Node) -> Result<Function, CodeGenError> {
+        if node.variadic {
+            return Err(CodeGenError::InvalidInstruction(
+                "WebAssembly backend doesn't support variadic functions".to_string(),
+            ));
+        }
+        self.scopes = vec![HashMap::new()];
+        self.local_count = 0;
+
+        // Parameters are the first locals
+        let params = &node.children[..node.children.len().saturating_sub(1)];
+        for param in params {
+            if let Some((ty, name)) = param.value.as_deref().and_then(|v| v.split_once(':')) {
+                self.declare_variable(name, ty)?;
+            }
+        }
+        let param_count = self.local_count;
+
+        if let Some(body) = node.children.last() {
+            self.visit_node(body)?;
+        }
+
+        // Implicit `return 0` for bodies that fall off the end
+        self.emit(Instruction::I64Const(0));
+        self.emit(Instruction::End);
+        Ok(Function {
+            params: param_count,
+            locals: self.local_count - param_count,
+            body: core::mem::take(&mut self.body),
+        })
+    }
+
+    fn generate_var_declaration(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let Some((ty, name)) = node
+            .children
+            .first()
+            .and_then(|child| child.value.as_deref())
+            .and_then(|value| value.split_once(':'))
+        else {
+            return Ok(());
+        };
+
+        // Evaluate the initializer first, so it can't see the new variable
+        let initializer = node.children.get(1);
+        if let Some(initializer) = initializer {
+            self.generate_expression(initializer)?;
+        }
+        let index = self.declare_variable(name, ty)?;
+        if initializer.is_some() {
+            self.emit(Instruction::LocalSet(index));
+        }
+        Ok(())
+    }
+
+    /// An `if` and its `else if` arms, each arm after the first nested in
+    /// the `else` of the one before.
+    fn generate_if(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let arms = node.children.chunks_exact(2);
+        let otherwise = arms.remainder().first();
+        let count = arms.len();
+        for (i, arm) in arms.enumerate() {
+            self.generate_condition(&arm[0])?;
+            self.emit(Instruction::If(BlockType::Empty));
+            self.labels.push(Label::Other);
+            self.visit_node(&arm[1])?;
+            if i + 1 < count || otherwise.is_some() {
+                self.emit(Instruction::Else);
+            }
+        }
+        if let Some(otherwise) = otherwise {
+            self.visit_node(otherwise)?;
+        }
+        for _ in 0..count {
+            self.labels.pop();
+            self.emit(Instruction::End);
+        }
+        Ok(())
+    }
+
+    fn generate_while(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        self.emit(Instruction::Block(BlockType::Empty));
+        self.emit(Instruction::Loop(BlockType::Empty));
+        self.generate_exit_unless(&node.children[0])?;
+        self.labels.extend([Label::Break, Label::Continue]);
+        self.visit_node(&node.children[1])?;
+        self.labels.truncate(self.labels.len() - 2);
+        self.emit(Instruction::Br(0));
+        self.emit(Instruction::End);
+        self.emit(Instruction::End);
+        Ok(())
+    }
+
+    fn generate_for(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        // The init clause may declare a variable scoped to the loop
+        self.scopes.push(HashMap::new());
+        self.visit_node(&node.children[0])?;
+
+        self.emit(Instruction::Block(BlockType::Empty));
+        self.emit(Instruction::Loop(BlockType::Empty));
+        let condition = &node.children[1];
+        if !codegen::is_empty(condition) {
+            self.generate_exit_unless(condition)?;
+        }
+        // The body gets its own block, so `continue` still runs the step
+        self.emit(Instruction::Block(BlockType::Empty));
+        self.labels
+            .extend([Label::Break, Label::Other, Label::Continue]);
+        self.visit_node(&node.children[3])?;
+        self.labels.truncate(self.labels.len() - 3);
+        self.emit(Instruction::End);
+        if !codegen::is_empty(&node.children[2]) {
+            self.visit_node(&node.children[2])?;
+        }
+        self.emit(Instruction::Br(0));
+        self.emit(Instruction::End);
+        self.emit(Instruction::End);
+
+        self.scopes.pop();
+        Ok(())
+    }
+
+    /// `break` or `continue`: branch to the innermost label of that kind.
+    fn generate_branch_to(&mut self, label: Label, keyword: &str) -> Result<(), CodeGenError> {
+        let Some(index) = self.labels.iter().rposition(|&open| open == label) else {
+            return Err(CodeGenError::OutsideLoop(keyword.to_string()));
+        };
+        let depth = (self.labels.len() - 1 - index) as u32;
+        self.emit(Instruction::Br(depth));
+        Ok(())
+    }
+
+    /// Inside a `block` wrapping a `loop`, leave the block when `condition`
+    /// is zero.
+    fn generate_exit_unless(&mut self, condition: &Node) -> Result<(), CodeGenError> {
+        self.generate_expression(condition)?;
+        self.emit(Instruction::Op(Op::I64Eqz));
+        self.emit(Instruction::BrIf(1));
+        Ok(())
+    }
+
+    /// Evaluate `condition` to the `i32` that `if` tests.
+    fn generate_condition(&mut self, condition: &Node) -> Result<(), CodeGenError> {
+        self.generate_expression(condition)?;
+        self.emit(Instruction::Op(Op::I64Eqz));
+        self.emit(Instruction::Op(Op::I32Eqz));
+        Ok(())
+    }
+
+    /// Emit code leaving the `i64` value of `node` on the stack.
+    fn generate_expression(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        let value = node.value.as_deref().unwrap_or("");
+        match node.node_type {
+            NodeType::Literal => {
+                let number = match value {
+                    "true" => 1,
+                    "false" => 0,
+                    _ => codegen::integer_value(value).ok_or_else(|| {
+                        CodeGenError::LiteralOverflow(format!("{} doesn't fit in U64", value))
+                    })?,
+                };
+                self.emit(Instruction::I64Const(number));
+            }
+            NodeType::Identifier => {
+                let index = self.variable(value)?.index;
+                self.emit(Instruction::LocalGet(index));
+            }
+            NodeType::Cast => {
+                let to = Type::parse(value)
+                    .filter(|ty| matches!(ty, Type::Int { .. } | Type::Bool))
+                    .ok_or_else(|| {
+                        CodeGenError::InvalidInstruction(format!(
+                            "WebAssembly backend has no {} values",
+                            value
+                        ))
+                    })?;
+                let operand = &node.children[0];
+                let from = self.expression_type(operand);
+                self.generate_expression(operand)?;
+                match (&from, &to) {
+                    (Type::Int { .. }, Type::Bool) => self.emit_truth(),
+                    (
+                        Type::Int {
+                            bits: from_bits, ..
+                        },
+                        Type::Int { bits, .. },
+                    ) => {
+                        if from_bits < bits {
+                            self.emit_extend(&from);
+                        }
+                        self.emit_extend(&to);
+                    }
+                    _ => {}
+                }
+            }
+            NodeType::Ternary => {
+                self.generate_condition(&node.children[0])?;
+                self.emit(Instruction::If(BlockType::I64));
+                self.generate_expression(&node.children[1])?;
+                self.emit(Instruction::Else);
+                self.generate_expression(&node.children[2])?;
+                self.emit(Instruction::End);
+            }
+            NodeType::Expression => match (value, node.children.as_slice()) {
+                ("call", [callee, args @ ..]) => {
+                    self.generate_call(callee.value.as_deref().unwrap_or(""), args)?
+                }
+                ("=", [target, value]) => {
+                    let index = match target.node_type {
+                        NodeType::Identifier => {
+                            self.variable(target.value.as_deref().unwrap_or(""))?.index
+                        }
+                        _ => {
+                            return Err(CodeGenError::InvalidInstruction(
+                                "WebAssembly backend can only assign to variables".to_string(),
+                            ))
+                        }
+                    };
+                    self.generate_expression(value)?;
+                    self.emit(Instruction::LocalTee(index));
+                }
+                ("neg", [operand]) => {
+                    self.emit(Instruction::I64Const(0));
+                    self.generate_expression(operand)?;
+                    self.emit(Instruction::Op(Op::I64Sub));
+                }
+                ("!", [operand]) => {
+                    self.generate_expression(operand)?;
+                    self.emit(Instruction::Op(Op::I64Eqz));
+                    self.emit(Instruction::Op(Op::I64ExtendI32U));
+                }
+                (_, [left, right]) => self.generate_binary(value, left, right)?,
+                _ => {
+                    return Err(CodeGenError::InvalidInstruction(format!(
+                        "WebAssembly backend has no `{}` expressions",
+                        value
+                    )))
+                }
+            },
+            _ => return Err(CodeGenError::UnsupportedNodeType(node.node_type.clone())),
+        }
+        Ok(())
+    }
+
+    fn generate_binary(
+        &mut self,
+        operator: &str,
+        left: &Node,
+        right: &Node,
+    ) -> Result<(), CodeGenError> {
+        if matches!(operator, "&&" | "||") {
+            return self.generate_logical(operator, left, right);
+        }
+        let left_type = self.expression_type(left);
+        let signed = Type::common(&left_type, &self.expression_type(right)).is_signed();
+        let pick = |signed_op: Op, unsigned_op: Op| if signed { signed_op } else { unsigned_op };
+
+        let (op, comparison) = match operator {
+            "+" => (Op::I64Add, false),
+            "-" => (Op::I64Sub, false),
+            "*" => (Op::I64Mul, false),
+            "/" => (pick(Op::I64DivS, Op::I64DivU), false),
+            "%" => (pick(Op::I64RemS, Op::I64RemU), false),
+            "&" => (Op::I64And, false),
+            "|" => (Op::I64Or, false),
+            "^" => (Op::I64Xor, false),
+            "<<" => (Op::I64Shl, false),
+            ">>" if left_type.is_signed() => (Op::I64ShrS, false),
+            ">>" => (Op::I64ShrU, false),
+            "==" => (Op::I64Eq, true),
+            "!=" => (Op::I64Ne, true),
+            "<" => (pick(Op::I64LtS, Op::I64LtU), true),
+            ">" => (pick(Op::I64GtS, Op::I64GtU), true),
+            "<=" => (pick(Op::I64LeS, Op::I64LeU), true),
+            ">=" => (pick(Op::I64GeS, Op::I64GeU), true),
+            _ => {
+                return Err(CodeGenError::InvalidInstruction(format!(
+                    "Unsupported operator: {}",
+                    operator
+                )))
+            }
+        };
+
+        self.generate_expression(left)?;
+        self.generate_expression(right)?;
+        self.emit(Instruction::Op(op));
+        if comparison {
+            self.emit(Instruction::Op(Op::I64ExtendI32U));
+        }
+        Ok(())
+    }
+
+    /// `&&` and `||`, skipping the right operand when the left one decides
+    /// the result.
+    fn generate_logical(
+        &mut self,
+        operator: &str,
+        left: &Node,
+        right: &Node,
+    ) -> Result<(), CodeGenError> {
+        self.generate_condition(left)?;
+        self.emit(Instruction::If(BlockType::I64));
+        if operator == "&&" {
+            self.generate_expression(right)?;
+            self.emit_truth();
+            self.emit(Instruction::Else);
+            self.emit(Instruction::I64Const(0));
+        } else {
+            self.emit(Instruction::I64Const(1));
+            self.emit(Instruction::Else);
+            self.generate_expression(right)?;
+            self.emit_truth();
+        }
+        self.emit(Instruction::End);
+        Ok(())
+    }
+
+    fn generate_call(&mut self, callee: &str, args: &[Node]) -> Result<(), CodeGenError> {
+        let index = *self
+            .function_indices
+            .get(callee)
+            .ok_or_else(|| CodeGenError::FunctionNotFound(callee.to_string()))?;
+        for arg in args {
+            self.generate_expression(arg)?;
+        }
+        self.emit(Instruction::Call(index));
+        Ok(())
+    }
+
+    /// Static type of an expression, used to pick signed or unsigned instructions.
+    fn expression_type(&self, node: &Node) -> Type {
+        let value = node.value.as_deref().unwrap_or("");
+        match node.node_type {
+            NodeType::Literal if value == "true" || value == "false" => Type::Bool,
+            NodeType::Identifier => self
+                .lookup_variable(value)
+                .map_or(Type::I64, |variable| variable.ty.clone()),
+            NodeType::Cast => Type::parse(value).unwrap_or(Type::I64),
+            NodeType::Ternary => match node.children.as_slice() {
+                [_, then, otherwise] => Type::common(
+                    &self.expression_type(then),
+                    &self.expression_type(otherwise),
+                ),
+                _ => Type::I64,
+            },
+            NodeType::Expression => match value {
+                "==" | "!=" | "<" | "<=" | ">" | ">=" | "&&" | "||" | "!" => Type::Bool,
+                "call" => node
+                    .children
+                    .first()
+                    .and_then(|callee| callee.value.as_deref())
+                    .and_then(|name| self.return_types.get(name))
+                    .cloned()
+                    .unwrap_or(Type::I64),
+                "=" | "neg" | "<<" | ">>" => node
+                    .children
+                    .first()
+                    .map_or(Type::I64, |operand| self.expression_type(operand)),
+                _ => match node.children.as_slice() {
+                    [left, right] => {
+                        Type::common(&self.expression_type(left), &self.expression_type(right))
+                    }
+                    _ => Type::I64,
+                },
+            },
+            _ => Type::I64,
+        }
+    }
+
+    /// Give `name` a new local; only integer and `Bool` values are supported.
+    fn declare_variable(&mut self, name: &str, type_name: &str) -> Result<u32, CodeGenError> {
+        let ty = Type::parse(type_name)
+            .filter(|ty| matches!(ty, Type::Int { .. } | Type::Bool))
+            .ok_or_else(|| {
+                CodeGenError::InvalidInstruction(format!(
+                    "WebAssembly backend has no {} values",
+                    type_name
+                ))
+            })?;
+        let index = self.local_count;
+        self.local_count += 1;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string(), Variable { index, ty });
+        }
+        Ok(index)
+    }
+
+    fn lookup_variable(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn variable(&self, name: &str) -> Result<&Variable, CodeGenError> {
+        self.lookup_variable(name)
+            .ok_or_else(|| CodeGenError::InvalidInstruction(format!("undefined variable {}", name)))
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.body.push(instruction);
+    }
+
+    /// Turn the `i64` on the stack into 1 if it is non-zero, 0 otherwise.
+    fn emit_truth(&mut self) {
+        self.emit(Instruction::I64Const(0));
+        self.emit(Instruction::Op(Op::I64Ne));
+        self.emit(Instruction::Op(Op::I64ExtendI32U));
+    }
+
+    /// Sign- or zero-extend the low bits of the value on the stack that hold
+    /// an integer of `ty`.
+    fn emit_extend(&mut self, ty: &Type) {
+        let Type::Int { bits, signed } = *ty else {
+            return;
+        };
+        if bits >= 64 {
+            return;
+        }
+        let shift = 64 - bits as i64;
+        let right = if signed { Op::I64ShrS } else { Op::I64ShrU };
+        for op in [Op::I64Shl, right] {
+            self.emit(Instruction::I64Const(shift));
+            self.emit(Instruction::Op(op));
+        }
+    }
+}
+
+impl Visitor for WasmCodeGen {
+    type Error = CodeGenError;
+
+    fn visit_node(&mut self, node: &Node) -> Result<(), CodeGenError> {
+        match node.node_type {
+            NodeType::Block => {
+                self.scopes.push(HashMap::new());
+                walk_node(self, node)?;
+                self.scopes.pop();
+            }
+            NodeType::Statement => match node.value.as_deref().unwrap_or("") {
+                "return" => {
+                    match node.children.first() {
+                        Some(value) => self.generate_expression(value)?,
+                        None => self.emit(Instruction::I64Const(0)),
+                    }
+                    self.emit(Instruction::Return);
+                }
+                "var" => self.generate_var_declaration(node)?,
+                "if" => self.generate_if(node)?,
+                "while" => self.generate_while(node)?,
+                "for" => self.generate_for(node)?,
+                "break" => self.generate_branch_to(Label::Break, "break")?,
+                "continue" => self.generate_branch_to(Label::Continue, "continue")?,
+                _ => walk_node(self, node)?,
+            },
+            NodeType::Expression
+            | NodeType::Identifier
+            | NodeType::Literal
+            | NodeType::Ternary
+            | NodeType::Cast => {
+                // Expression statement - evaluate for side effects
+                self.generate_expression(node)?;
+                self.emit(Instruction::Drop);
+            }
+            _ => return Err(CodeGenError::UnsupportedNodeType(node.node_type.clone())),
+        }
+        Ok(())
+    }
+}
+
+/// Assemble `functions` into a module exporting the first as `main`. Each
+/// function takes its parameters as `i64`s and returns an `i64`.
+pub fn to_bytes(functions: &[Function]) -> Vec<u8> {
+    let mut module = Vec::new();
+    module.extend(MAGIC);
+    module.extend(VERSION);
+
+    // One signature per function keeps indices trivial
+    let mut types = Vec::new();
+    write_unsigned(&mut types, functions.len() as u64);
+    for function in functions {
+        types.push(TYPE_FUNCTION);
+        write_unsigned(&mut types, function.params as u64);
+        types.extend(core::iter::repeat_n(TYPE_I64, function.params as usize));
+        types.extend([1, TYPE_I64]);
+    }
+    write_section(&mut module, SECTION_TYPE, &types);
+
+    let mut declarations = Vec::new();
+    write_unsigned(&mut declarations, functions.len() as u64);
+    for index in 0..functions.len() {
+        write_unsigned(&mut declarations, index as u64);
+    }
+    write_section(&mut module, SECTION_FUNCTION, &declarations);
+
+    if !functions.is_empty() {
+        let mut exports = vec![1];
+        write_unsigned(&mut exports, 4);
+        exports.extend(b"main");
+        exports.extend([EXPORT_FUNCTION, 0]);
+        write_section(&mut module, SECTION_EXPORT, &exports);
+    }
+
+    let mut code = Vec::new();
+    write_unsigned(&mut code, functions.len() as u64);
+    for function in functions {
+        let mut body = Vec::new();
+        if function.locals == 0 {
+            body.push(0);
+        } else {
+            body.push(1);
+            write_unsigned(&mut body, function.locals as u64);
+            body.push(TYPE_I64);
+        }
+        for instruction in &function.body {
+            instruction.encode(&mut body);
+        }
+        write_unsigned(&mut code, body.len() as u64);
+        code.extend(body);
+    }
+    write_section(&mut module, SECTION_CODE, &code);
+    module
+}
+
+fn write_section(module: &mut Vec<u8>, id: u8, contents: &[u8]) {
+    module.push(id);
+    write_unsigned(module, contents.len() as u64);
+    module.extend_from_slice(contents);
+}
+
+/// Decode the function bodies of a module written by [`to_bytes`]; `None`
+/// if it is malformed or uses instructions this backend doesn't emit.
+#[allow(dead_code)]
+pub fn decode_functions(module: &[u8]) -> Option<Vec<Function>> {
+    let mut rest = module.strip_prefix(&MAGIC)?.strip_prefix(&VERSION)?;
+    let mut params = Vec::new();
+    while let Some((&id, after_id)) = rest.split_first() {
+        let (size, length) = read_unsigned(after_id)?;
+        let contents = after_id.get(length..length + size as usize)?;
+        rest = &after_id[length + size as usize..];
+        match id {
+            SECTION_TYPE => {
+                let (count, mut offset) = read_unsigned(contents)?;
+                for _ in 0..count {
+                    let (param_count, length) = read_unsigned(contents.get(offset + 1..)?)?;
+                    params.push(param_count as u32);
+                    // Tag, parameter count and types, then one result type
+                    offset += 1 + length + param_count as usize + 2;
+                }
+            }
+            SECTION_CODE => {
+                let (count, mut offset) = read_unsigned(contents)?;
+                let mut functions = Vec::new();
+                for index in 0..count as usize {
+                    let (size, length) = read_unsigned(contents.get(offset..)?)?;
+                    let start = offset + length;
+                    let body = contents.get(start..start + size as usize)?;
+                    offset = start + size as usize;
+
+                    let (groups, mut position) = read_unsigned(body)?;
+                    let mut locals = 0;
+                    for _ in 0..groups {
+                        let (count, length) = read_unsigned(body.get(position..)?)?;
+                        locals += count as u32;
+                        position += length + 1;
+                    }
+                    let mut instructions = Vec::new();
+                    while position < body.len() {
+                        let (instruction, length) = Instruction::decode(&body[position..])?;
+                        instructions.push(instruction);
+                        position += length;
+                    }
+                    functions.push(Function {
+                        params: *params.get(index)?,
+                        locals,
+                        body: instructions,
+                    });
+                }
+                return Some(functions);
+            }
+            _ => {}
+        }
+    }
+    Some(Vec::new())
+}
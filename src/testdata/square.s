@@ -0,0 +1,51 @@
+mov r6, 0
+stxdw [r10-8], r6
+mov r6, 0
+stxdw [r10-16], r6
+ldxdw r6, [r10-16]
+mov r7, 4
+jlt r6, r7, +2
+mov r6, 0
+ja +1
+mov r6, 1
+jeq r6, 0, +12
+ldxdw r6, [r10-8]
+ldxdw r7, [r10-16]
+stxdw [r10-24], r7
+ldxdw r1, [r10-24]
+call local +25
+mov r7, r0
+add r6, r7
+stxdw [r10-8], r6
+ldxdw r6, [r10-16]
+add r6, 1
+stxdw [r10-16], r6
+ja -19
+ldxdw r6, [r10-8]
+mov r7, 14
+jne r6, r7, +2
+mov r6, 0
+ja +1
+mov r6, 1
+jeq r6, 0, +4
+mov r6, 1
+neg r6
+mov r0, r6
+exit
+ldxdw r6, [r10-8]
+div r6, 2
+mod r6, 5
+mov r0, r6
+exit
+mov r0, 0
+exit
+stxdw [r10-8], r1
+ldxdw r6, [r10-8]
+ldxdw r7, [r10-8]
+mul r6, r7
+mov r0, r6
+exit
+mov r0, 0
+exit
+mov r0, 0
+exit
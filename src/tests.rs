@@ -1,12 +1,59 @@
 use crate::pible::{
+    analysis::Severity,
     bpf_vm::BpfVm,
     codegen::{BpfInstruction, CodeGen},
-    compiler::{CompileOptions, CompileTarget, Compiler},
+    compiler::{CompileError, CompileOptions, CompileTarget, Compiler, OutputFormat, ProgramType},
     lexer::{Lexer, TokenType},
     parser::Parser,
     solana_bpf::SolanaBpf,
 };
 
+/// Exit code of `source` compiled with `options` and run in the VM.
+#[cfg(test)]
+fn run_with(source: &str, options: &CompileOptions) -> i32 {
+    Compiler::new()
+        .compile_and_run(source, options)
+        .expect("Program should compile and run")
+        .exit_code
+}
+
+/// Exit code of `source` compiled with the default options and run in the VM.
+#[cfg(test)]
+fn run(source: &str) -> i32 {
+    run_with(source, &CompileOptions::default())
+}
+
+/// Like [`run`], but giving the compile or execution error instead of
+/// panicking.
+#[cfg(test)]
+fn try_run(source: &str) -> Result<i32, CompileError> {
+    Compiler::new()
+        .compile_and_run(source, &CompileOptions::default())
+        .map(|result| result.exit_code)
+}
+
+/// Lex, parse and generate `source` without the compiler's checks and
+/// optimizations.
+#[cfg(test)]
+fn generate_instructions(source: &str) -> Vec<BpfInstruction> {
+    let mut lexer = Lexer::new(source);
+    let mut parser = Parser::new(lexer.scan_tokens().expect("Lexing should succeed"));
+    let ast = parser.parse().expect("Parsing should succeed");
+    CodeGen::new()
+        .generate(&ast)
+        .expect("Code generation should succeed")
+}
+
+/// Run [`generate_instructions`]'s output: the instructions and the final R0.
+#[cfg(test)]
+fn run_codegen(source: &str) -> (Vec<BpfInstruction>, i64) {
+    let instructions = generate_instructions(source);
+    let mut vm = BpfVm::new(&instructions);
+    vm.execute().expect("Execution should succeed");
+    let r0 = vm.registers()[0];
+    (instructions, r0)
+}
+
 #[cfg(test)]
 #[allow(clippy::module_inception)]
 mod tests {
@@ -73,6 +120,28 @@ mod tests {
         assert!(instructions.iter().any(|instr| instr.opcode == 0x95)); // BPF_EXIT
     }
 
+    #[test]
+    fn test_codegen_reset_allows_reuse() {
+        let parse = |source: &str| {
+            let mut lexer = Lexer::new(source);
+            let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+            parser.parse().unwrap()
+        };
+        let first =
+            parse("U64 helper() { return 1; } U64 main() { PrintF(\"a\"); return helper(); }");
+        let second = parse("U64 main() { return 2; }");
+
+        let mut codegen = CodeGen::new();
+        codegen.generate(&first).unwrap();
+        codegen.reset();
+        let reused = codegen.generate(&second).unwrap();
+
+        let mut fresh = CodeGen::new();
+        assert_eq!(reused, fresh.generate(&second).unwrap());
+        assert_eq!(codegen.functions(), fresh.functions());
+        assert!(codegen.rodata().is_empty());
+    }
+
     #[test]
     fn test_compiler_end_to_end() {
         let source = "U0 main() { return 0; }";
@@ -233,10 +302,13 @@ mod solana_bpf_entrypoint_tests {
         solana_bpf.generate_entrypoint("main").unwrap();
         let instructions = solana_bpf.get_instructions();
 
-        // Should contain immediate move to register 10 (stack pointer)
+        // R10 is read-only; account infos are addressed relative to it
+        assert!(!instructions
+            .iter()
+            .any(|inst| inst.opcode & 0x07 >= 0x04 && inst.dst_reg == 10));
         assert!(instructions
             .iter()
-            .any(|inst| inst.opcode == 0xb7 && inst.dst_reg == 10));
+            .any(|inst| inst.opcode == 0xbf && inst.src_reg == 10));
     }
 
     #[test]
@@ -400,11 +472,17 @@ mod solana_bpf_instruction_tests {
         let instructions = solana_bpf.get_instructions();
 
         // Find CALL instruction
-        let call_instr = instructions.iter().find(|inst| inst.opcode == 0x85);
-        assert!(call_instr.is_some());
-
-        let instr = call_instr.unwrap();
-        assert_eq!(instr.immediate, 1); // Function index
+        let call_pc = instructions.iter().position(|inst| inst.opcode == 0x85);
+        assert!(call_pc.is_some());
+
+        // An unknown function resolves to the first instruction after the entrypoint
+        let call_pc = call_pc.unwrap();
+        let instr = &instructions[call_pc];
+        assert_eq!(instr.src_reg, 1); // BPF_PSEUDO_CALL
+        assert_eq!(
+            call_pc as i32 + instr.immediate + 1,
+            instructions.len() as i32
+        );
     }
 
     #[test]
@@ -460,7 +538,7 @@ mod solana_bpf_instruction_tests {
 
     #[test]
     fn test_instruction_with_immediate() {
-        let instr = BpfInstruction::new(0xb7, 10, 0, 0, 512);
+        let instr = BpfInstruction::new(0xb7, 1, 0, 0, 512);
         let bytes = instr.as_bytes();
 
         assert_eq!(bytes[4], 0x00); // 512 = 0x0200, little endian low byte
@@ -494,12 +572,12 @@ mod solana_bpf_instruction_tests {
     #[test]
     fn test_multiple_instruction_sequence() {
         let instructions = [
-            BpfInstruction::new(0x79, 6, 2, 0, 0),    // ldx r6, [r2+0]
-            BpfInstruction::new(0x79, 7, 3, 0, 0),    // ldx r7, [r3+0]
-            BpfInstruction::new(0xb7, 10, 0, 0, 512), // mov r10, 512
-            BpfInstruction::new(0x85, 0, 0, 0, 1),    // call 1
-            BpfInstruction::new(0xb7, 0, 0, 0, 0),    // mov r0, 0
-            BpfInstruction::new(0x95, 0, 0, 0, 0),    // exit
+            BpfInstruction::new(0x79, 6, 2, 0, 0),  // ldx r6, [r2+0]
+            BpfInstruction::new(0x79, 7, 3, 0, 0),  // ldx r7, [r3+0]
+            BpfInstruction::new(0xbf, 1, 10, 0, 0), // mov r1, r10
+            BpfInstruction::new(0x85, 0, 0, 0, 1),  // call 1
+            BpfInstruction::new(0xb7, 0, 0, 0, 0),  // mov r0, 0
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
         ];
 
         assert_eq!(instructions.len(), 6);
@@ -507,7 +585,7 @@ mod solana_bpf_instruction_tests {
         // Verify each instruction type
         assert_eq!(instructions[0].opcode, 0x79); // LDX
         assert_eq!(instructions[1].opcode, 0x79); // LDX
-        assert_eq!(instructions[2].opcode, 0xb7); // MOV
+        assert_eq!(instructions[2].opcode, 0xbf); // MOV
         assert_eq!(instructions[3].opcode, 0x85); // CALL
         assert_eq!(instructions[4].opcode, 0xb7); // MOV
         assert_eq!(instructions[5].opcode, 0x95); // EXIT
@@ -580,13 +658,63 @@ mod solana_bpf_validation_tests {
         let solana_bpf = SolanaBpf::new(&mut codegen);
 
         let instructions = vec![
-            BpfInstruction::new(0xb7, 10, 0, 0, 512), // mov r10, 512 (valid)
-            BpfInstruction::new(0x79, 6, 2, 0, 0),    // ldx r6, [r2+0] (valid)
-            BpfInstruction::new(0x95, 0, 0, 0, 0),    // exit
+            BpfInstruction::new(0x79, 6, 10, -8, 0), // ldx r6, [r10-8] (valid)
+            BpfInstruction::new(0x79, 6, 2, 0, 0),   // ldx r6, [r2+0] (valid)
+            BpfInstruction::new(0x95, 0, 0, 0, 0),   // exit
         ];
         assert!(solana_bpf.validate_solana_program(&instructions));
     }
 
+    #[test]
+    fn test_validate_frame_pointer_write() {
+        let mut codegen = CodeGen::new();
+        let solana_bpf = SolanaBpf::new(&mut codegen);
+
+        for write in [
+            BpfInstruction::new(0xb7, 10, 0, 0, 512), // mov r10, 512
+            BpfInstruction::new(0x07, 10, 0, 0, -8),  // add r10, -8
+            BpfInstruction::new(0x79, 10, 1, 0, 0),   // ldx r10, [r1+0]
+            BpfInstruction::new(0x18, 10, 0, 0, 0),   // lddw r10, 0
+        ] {
+            let mut instructions = vec![write];
+            if write.opcode == 0x18 {
+                instructions.push(BpfInstruction::new(0, 0, 0, 0, 0));
+            }
+            instructions.push(BpfInstruction::new(0x95, 0, 0, 0, 0));
+            assert!(!solana_bpf.validate_solana_program(&instructions));
+        }
+    }
+
+    #[test]
+    fn test_compiled_solana_program_leaves_frame_pointer_alone() {
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..Default::default()
+        };
+        let bytecode = Compiler::new()
+            .compile(
+                "U64 main() { U64 a[4] = {1, 2, 3, 4}; U64 b = a[3]; return b + a[0]; }",
+                &options,
+            )
+            .unwrap();
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        // Writes are to ALU, LD and LDX destinations; stores only read r10
+        assert!(!instructions.iter().any(|inst| matches!(
+            inst.opcode & 0x07,
+            0x00 | 0x01 | 0x04 | 0x07
+        ) && inst.opcode != 0
+            && inst.dst_reg == 10));
+        assert!(instructions
+            .iter()
+            .any(|inst| inst.opcode == 0x7b && inst.dst_reg == 10 && inst.offset < 0));
+        let mut codegen = CodeGen::new();
+        assert!(SolanaBpf::new(&mut codegen).validate_solana_program(&instructions));
+    }
+
     #[test]
     fn test_validate_jump_forward() {
         let mut codegen = CodeGen::new();
@@ -637,6 +765,44 @@ mod solana_bpf_validation_tests {
         assert!(!solana_bpf.validate_solana_program(&instructions));
     }
 
+    #[test]
+    fn test_validate_checks_every_jump_opcode() {
+        let mut codegen = CodeGen::new();
+        let solana_bpf = SolanaBpf::new(&mut codegen);
+
+        for opcode in [0x05, 0x15, 0x1d, 0x25, 0x2d, 0xa5, 0xad, 0xd5] {
+            let instructions = vec![
+                BpfInstruction::new(opcode, 1, 2, 5, 0), // out of bounds
+                BpfInstruction::new(0x95, 0, 0, 0, 0),   // exit
+            ];
+            assert!(
+                !solana_bpf.validate_solana_program(&instructions),
+                "opcode 0x{:02x}",
+                opcode
+            );
+        }
+
+        let instructions = vec![
+            BpfInstruction::new(0x15, 1, 0, 1, 0), // jeq r1, 0, +1
+            BpfInstruction::new(0xb7, 0, 0, 0, 1), // mov r0, 1
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        assert!(solana_bpf.validate_solana_program(&instructions));
+    }
+
+    #[test]
+    fn test_validate_ignores_offsets_outside_jumps() {
+        let mut codegen = CodeGen::new();
+        let solana_bpf = SolanaBpf::new(&mut codegen);
+
+        let instructions = vec![
+            BpfInstruction::new(0x61, 1, 10, -8, 0), // ldxw r1, [r10-8]
+            BpfInstruction::new(0x85, 0, 0, 0, 6),   // call 6
+            BpfInstruction::new(0x95, 0, 0, 0, 0),   // exit
+        ];
+        assert!(solana_bpf.validate_solana_program(&instructions));
+    }
+
     #[test]
     fn test_validate_program_size_limit() {
         let mut codegen = CodeGen::new();
@@ -743,6 +909,39 @@ mod solana_bpf_compilation_tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn test_solana_output_starts_with_the_entrypoint_prologue() {
+        let source = r#"
+            export U64 entrypoint(U8* input, U64 input_len) {
+                return 7;
+            }
+        "#;
+        let compile = |target| {
+            let options = CompileOptions {
+                target,
+                ..Default::default()
+            };
+            Compiler::new().compile(source, &options).unwrap()
+        };
+        let solana = compile(CompileTarget::SolanaBpf);
+        let linux = compile(CompileTarget::LinuxBpf);
+        assert_ne!(solana, linux);
+
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let mut codegen = CodeGen::for_target(CompileTarget::SolanaBpf);
+        codegen.generate(&parser.parse().unwrap()).unwrap();
+        let mut solana_bpf = SolanaBpf::new(&mut codegen);
+        solana_bpf.generate_entrypoint("entrypoint").unwrap();
+        let prologue: Vec<u8> = solana_bpf
+            .get_instructions()
+            .iter()
+            .flat_map(|instruction| instruction.as_bytes())
+            .collect();
+        assert!(solana.starts_with(&prologue));
+        assert!(solana.len() > linux.len());
+    }
+
     #[test]
     fn test_compile_solana_printf_function() {
         let source = r#"
@@ -1021,21 +1220,10 @@ mod solana_bpf_vm_tests {
         let bytecode = compiler.compile(source, &options).unwrap();
 
         // Convert bytecode to instructions
-        let mut instructions = Vec::new();
-        for chunk in bytecode.chunks(8) {
-            if chunk.len() == 8 {
-                let opcode = chunk[0];
-                let regs = chunk[1];
-                let dst_reg = regs & 0x0f;
-                let src_reg = (regs & 0xf0) >> 4;
-                let offset = i16::from_le_bytes([chunk[2], chunk[3]]);
-                let immediate = i32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
-
-                instructions.push(BpfInstruction::new(
-                    opcode, dst_reg, src_reg, offset, immediate,
-                ));
-            }
-        }
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
 
         let mut vm = BpfVm::new(&instructions);
         let result = vm.execute();
@@ -1133,17 +1321,17 @@ mod solana_bpf_vm_tests {
     #[test]
     fn test_vm_stack_pointer_initialization() {
         let instructions = vec![
-            BpfInstruction::new(0xb7, 10, 0, 0, 512), // mov r10, 512 (stack pointer)
-            BpfInstruction::new(0xbf, 0, 10, 0, 0),   // mov r0, r10
-            BpfInstruction::new(0x95, 0, 0, 0, 0),    // exit
+            BpfInstruction::new(0xbf, 0, 10, 0, 0), // mov r0, r10
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
         ];
 
         let mut vm = BpfVm::new(&instructions);
         let result = vm.execute();
 
+        // The VM sets r10 to the top of the stack; programs never write it
         assert!(result.is_ok());
         let vm_result = result.unwrap();
-        assert_eq!(vm_result.exit_code, 512);
+        assert_eq!(vm_result.exit_code, vm.memory.len() as i32);
     }
 
     #[test]
@@ -1315,21 +1503,10 @@ mod solana_bpf_integration_tests {
         let bytecode = compiler.compile(source, &options).unwrap();
 
         // Convert to instructions for validation
-        let mut instructions = Vec::new();
-        for chunk in bytecode.chunks(8) {
-            if chunk.len() == 8 {
-                let opcode = chunk[0];
-                let regs = chunk[1];
-                let dst_reg = regs & 0x0f;
-                let src_reg = (regs & 0xf0) >> 4;
-                let offset = i16::from_le_bytes([chunk[2], chunk[3]]);
-                let immediate = i32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
-
-                instructions.push(BpfInstruction::new(
-                    opcode, dst_reg, src_reg, offset, immediate,
-                ));
-            }
-        }
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
 
         // Validate
         let mut codegen = CodeGen::new();
@@ -1634,8 +1811,8 @@ mod solana_bpf_integration_tests {
         assert!(vm.execute_instruction(&rsh_instr).is_ok());
         assert_eq!(vm.get_register(2), 0x1234567);
 
-        // Test arithmetic right shift
-        vm.set_register(3, 0x80000000);
+        // Test arithmetic right shift (sign-extends across all 64 bits)
+        vm.set_register(3, -0x80000000);
         let arsh_instr = BpfInstruction {
             opcode: 0xcf, // ARSH64
             dst_reg: 3,
@@ -1644,7 +1821,7 @@ mod solana_bpf_integration_tests {
             immediate: 4,
         };
         assert!(vm.execute_instruction(&arsh_instr).is_ok());
-        assert_eq!(vm.get_register(3), 0xf8000000);
+        assert_eq!(vm.get_register(3), -0x8000000);
     }
 
     #[test]
@@ -1743,10 +1920,141 @@ mod solana_bpf_integration_tests {
         assert_eq!(vm.memory[403], 0x12);
     }
 
+    #[test]
+    fn test_immediate_stores_of_every_width() {
+        let mut vm = BpfVm::new(&[]);
+        vm.memory = vec![0xee; 1024];
+        vm.set_register(1, 100);
+
+        // (opcode, offset, immediate, bytes written)
+        let stores: [(u8, i16, i32, &[u8]); 4] = [
+            (0x72, 0, 0x1234_56ab, &[0xab]),
+            (0x6a, 8, 0x1234_cdef, &[0xef, 0xcd]),
+            (0x62, 16, 0x1234_5678, &[0x78, 0x56, 0x34, 0x12]),
+            (
+                0x7a,
+                24,
+                -2,
+                &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            ),
+        ];
+        for (opcode, offset, immediate, bytes) in stores {
+            let store = BpfInstruction {
+                opcode,
+                dst_reg: 1,
+                src_reg: 0,
+                offset,
+                immediate,
+            };
+            vm.execute_instruction(&store)
+                .expect("Store should succeed");
+            let start = 100 + offset as usize;
+            assert_eq!(&vm.memory[start..start + bytes.len()], bytes);
+            // Nothing past the store's width changes
+            assert_eq!(vm.memory[start + bytes.len()], 0xee);
+        }
+
+        // A store running past the end of memory is an error
+        vm.set_register(1, 1020);
+        let overflowing = BpfInstruction {
+            opcode: 0x7a,
+            dst_reg: 1,
+            src_reg: 0,
+            offset: 0,
+            immediate: 1,
+        };
+        assert!(vm.execute_instruction(&overflowing).is_err());
+        assert_eq!(&vm.memory[1020..], &[0xee; 4]);
+    }
+
+    #[test]
+    fn test_doubleword_accesses_out_of_bounds_are_errors() {
+        let mut vm = BpfVm::new(&[]);
+        vm.memory = vec![0xee; 1024];
+        vm.set_register(0, 7);
+        vm.set_register(2, 0x11);
+
+        for address in [1020, -8] {
+            vm.set_register(1, address);
+            let load = BpfInstruction::new(0x79, 0, 1, 0, 0); // ldxdw r0, [r1+0]
+            assert!(vm.execute_instruction(&load).is_err());
+            assert_eq!(vm.registers()[0], 7);
+            let store = BpfInstruction::new(0x7b, 1, 2, 0, 0); // stxdw [r1+0], r2
+            assert!(vm.execute_instruction(&store).is_err());
+        }
+        assert_eq!(&vm.memory[1016..], &[0xee; 8]);
+
+        vm.set_register(1, 1016);
+        let store = BpfInstruction::new(0x7b, 1, 2, 0, 0);
+        vm.execute_instruction(&store).unwrap();
+        let load = BpfInstruction::new(0x79, 0, 1, 0, 0);
+        vm.execute_instruction(&load).unwrap();
+        assert_eq!(vm.registers()[0], 0x11);
+    }
+
+    #[test]
+    fn test_narrow_accesses_out_of_bounds_are_errors() {
+        let mut vm = BpfVm::new(&[]);
+        vm.memory = vec![0xee; 1024];
+        vm.set_register(0, 7);
+        vm.set_register(2, 0x11);
+
+        // (load, store, width)
+        for (load, store, width) in [(0x61, 0x63, 4), (0x69, 0x6b, 2), (0x71, 0x73, 1)] {
+            for address in [1025 - width, -1, i64::MAX] {
+                vm.set_register(1, address);
+                let load = BpfInstruction::new(load, 0, 1, 0, 0);
+                assert!(vm.execute_instruction(&load).is_err());
+                assert_eq!(vm.registers()[0], 7);
+                let store = BpfInstruction::new(store, 1, 2, 0, 0);
+                assert!(vm.execute_instruction(&store).is_err());
+            }
+        }
+        assert_eq!(&vm.memory[1020..], &[0xee; 4]);
+    }
+
+    #[test]
+    fn test_immediate_alu_operations() {
+        let mut vm = BpfVm::new(&[]);
+
+        // (opcode, offset, starting value, immediate, result)
+        let operations: [(u8, i16, i64, i32, i64); 10] = [
+            (0x07, 0, 40, 2, 42),
+            (0x07, 0, 40, -2, 38),
+            (0x17, 0, 50, 8, 42),
+            (0x27, 0, 6, 7, 42),
+            (0x37, 0, 85, 2, 42),
+            (0x37, 1, -85, 2, -42),
+            (0x97, 0, 47, 5, 2),
+            (0x97, 1, -47, 5, -2),
+            (0x47, 0, 0x40, 0x02, 0x42),
+            (0x57, 0, 0xff, 0x2a, 0x2a),
+        ];
+        for (opcode, offset, value, immediate, expected) in operations {
+            vm.set_register(1, value);
+            let instruction = BpfInstruction::new(opcode, 1, 0, offset, immediate);
+            vm.execute_instruction(&instruction)
+                .expect("ALU operation should succeed");
+            assert_eq!(vm.get_register(1), expected, "opcode {:#04x}", opcode);
+        }
+
+        vm.set_register(1, 0x0f);
+        vm.execute_instruction(&BpfInstruction::new(0xa7, 1, 0, 0, 0x25))
+            .unwrap();
+        assert_eq!(vm.get_register(1), 0x2a);
+
+        // Dividing by an immediate zero is still caught
+        let divide_by_zero = BpfInstruction::new(0x37, 1, 0, 0, 0);
+        assert!(vm.execute_instruction(&divide_by_zero).is_err());
+    }
+
     #[test]
     fn test_solana_bpf_conditional_jumps() {
         let mut vm = BpfVm::new(&[]);
 
+        // Offsets are relative to the instruction after the jump, so a
+        // taken jump at pc 0 with offset n lands on n + 1
+
         // Test JEQ (jump if equal)
         vm.set_register(1, 42);
         vm.set_register(2, 42);
@@ -1758,7 +2066,7 @@ mod solana_bpf_integration_tests {
             immediate: 0,
         };
         assert!(vm.execute_instruction(&jeq_instr).is_ok());
-        assert_eq!(vm.get_pc(), 5);
+        assert_eq!(vm.get_pc(), 6);
 
         // Test JGT (jump if greater than)
         vm.set_pc(0);
@@ -1772,7 +2080,7 @@ mod solana_bpf_integration_tests {
             immediate: 0,
         };
         assert!(vm.execute_instruction(&jgt_instr).is_ok());
-        assert_eq!(vm.get_pc(), 10);
+        assert_eq!(vm.get_pc(), 11);
 
         // Test JLT (jump if less than)
         vm.set_pc(0);
@@ -1786,7 +2094,7 @@ mod solana_bpf_integration_tests {
             immediate: 0,
         };
         assert!(vm.execute_instruction(&jlt_instr).is_ok());
-        assert_eq!(vm.get_pc(), 15);
+        assert_eq!(vm.get_pc(), 16);
     }
 
     #[test]
@@ -1803,7 +2111,7 @@ mod solana_bpf_integration_tests {
             immediate: 100,
         };
         assert!(vm.execute_instruction(&jeqi_instr).is_ok());
-        assert_eq!(vm.get_pc(), 8);
+        assert_eq!(vm.get_pc(), 9);
 
         // Test JGTI (jump if greater than immediate)
         vm.set_pc(0);
@@ -1816,7 +2124,7 @@ mod solana_bpf_integration_tests {
             immediate: 100,
         };
         assert!(vm.execute_instruction(&jgti_instr).is_ok());
-        assert_eq!(vm.get_pc(), 12);
+        assert_eq!(vm.get_pc(), 13);
 
         // Test JLTI (jump if less than immediate)
         vm.set_pc(0);
@@ -1829,7 +2137,33 @@ mod solana_bpf_integration_tests {
             immediate: 100,
         };
         assert!(vm.execute_instruction(&jlti_instr).is_ok());
-        assert_eq!(vm.get_pc(), 16);
+        assert_eq!(vm.get_pc(), 17);
+    }
+
+    #[test]
+    fn test_single_instruction_jumps_match_step() {
+        // jne r1, 0, -3 at pc 4: taken, it lands on 4 - 3 + 1
+        let jump = BpfInstruction::new(0x55, 1, 0, -3, 0);
+        let mut program = vec![BpfInstruction::new(0xb7, 0, 0, 0, 0); 4];
+        program.push(jump);
+
+        let mut vm = BpfVm::new(&program);
+        vm.set_register(1, 1);
+        vm.set_pc(4);
+        vm.execute_instruction(&jump).unwrap();
+        assert_eq!(vm.get_pc(), 2);
+
+        let mut stepped = BpfVm::new(&program);
+        stepped.set_register(1, 1);
+        stepped.set_pc(4);
+        stepped.step().unwrap();
+        assert_eq!(stepped.get_pc(), vm.get_pc());
+
+        // Not taken, the PC stays put
+        vm.set_register(1, 0);
+        vm.set_pc(4);
+        vm.execute_instruction(&jump).unwrap();
+        assert_eq!(vm.get_pc(), 4);
     }
 
     // SECTION 2: Solana Runtime Environment Tests
@@ -1850,6 +2184,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -1892,6 +2235,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -1931,6 +2283,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -1970,6 +2331,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2011,6 +2381,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: true,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2067,6 +2446,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2105,6 +2493,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2142,6 +2539,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2192,6 +2598,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2257,6 +2672,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2308,6 +2732,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2368,6 +2801,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2444,6 +2886,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2507,6 +2958,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -2576,6 +3036,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -3048,10 +3517,24 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
-        let result = compiler.compile(holyc_code, &options);
-        assert!(result.is_ok(), "Solana syscalls should compile");
+        // The runtime has no rent syscall; rent comes from the Rent sysvar
+        match compiler.compile(holyc_code, &options) {
+            Err(CompileError::UndefinedFunction(name)) => {
+                assert_eq!(name, "sol_get_minimum_balance_for_rent_exemption")
+            }
+            other => panic!("expected UndefinedFunction, got {:?}", other),
+        }
     }
 
     #[test]
@@ -3096,6 +3579,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -3156,6 +3648,15 @@ mod solana_bpf_integration_tests {
             enable_vm_testing: false,
             solana_program_id: None,
             output_directory: None,
+            output_format: OutputFormat::Raw,
+            emit_asm: false,
+            opt_level: 0,
+            program_type: ProgramType::SocketFilter,
+            strict: false,
+            verify: false,
+            log_helper_id: None,
+            safe_arrays: false,
+            inline_threshold: 0,
         };
 
         let result = compiler.compile(holyc_code, &options);
@@ -3167,3 +3668,6899 @@ mod solana_bpf_integration_tests {
     // NFT marketplace, AMM protocols, governance systems, oracle integrations, etc.
     // Each test validates specific Solana BPF compilation aspects as requested.
 }
+
+#[cfg(test)]
+mod bpf_vm_step_tests {
+    use super::*;
+
+    #[test]
+    fn test_step_advances_pc_and_reports_opcode() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 1, 0, 0, 7), // mov r1, 7
+            BpfInstruction::new(0xbf, 0, 1, 0, 0), // mov r0, r1
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+
+        let mut vm = BpfVm::new(&instructions);
+
+        let step = vm.step().unwrap();
+        assert_eq!(step.opcode, 0xb7);
+        assert!(!step.exited);
+        assert_eq!(vm.get_pc(), 1);
+        assert_eq!(vm.registers()[1], 7);
+
+        let step = vm.step().unwrap();
+        assert_eq!(step.opcode, 0xbf);
+        assert_eq!(vm.registers()[0], 7);
+
+        let step = vm.step().unwrap();
+        assert_eq!(step.opcode, 0x95);
+        assert!(step.exited);
+    }
+
+    #[test]
+    fn test_step_takes_relative_jumps() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 1, 0, 0, 3), // mov r1, 3
+            BpfInstruction::new(0x15, 1, 0, 1, 3), // jeq r1, 3, +1
+            BpfInstruction::new(0xb7, 0, 0, 0, 1), // mov r0, 1
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+
+        let mut vm = BpfVm::new(&instructions);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.get_pc(), 3);
+
+        let result = vm.execute().unwrap();
+        assert_eq!(result.exit_code, 0);
+    }
+
+    #[test]
+    fn test_dump_state_snapshot() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 2, 0, 0, 99), // mov r2, 99
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
+        ];
+
+        let mut vm = BpfVm::new(&instructions);
+        vm.step().unwrap();
+
+        let state = vm.dump_state();
+        assert_eq!(state.pc, 1);
+        assert_eq!(state.registers[2], 99);
+        assert_eq!(state.compute_units, 1);
+    }
+
+    #[test]
+    fn test_step_past_end_is_an_error() {
+        let mut vm = BpfVm::new(&[]);
+        assert!(vm.step().is_err());
+    }
+}
+
+#[cfg(test)]
+mod bpf_vm_config_tests {
+    use super::*;
+    use crate::pible::bpf_vm::{Overflow, TraceEntry, VmConfig, VmError};
+
+    #[test]
+    fn test_default_config_matches_solana_limits() {
+        let config = VmConfig::default();
+        assert_eq!(config.compute_budget, 1_400_000);
+        assert_eq!(config.memory_size, 32 * 1024);
+        assert_eq!(config.stack_size, 4096);
+        assert_eq!(config.max_call_depth, 64);
+
+        let vm = BpfVm::new(&[]);
+        assert_eq!(
+            vm.memory.len(),
+            config.memory_size + config.stack_size * config.max_call_depth
+        );
+        assert_eq!(vm.get_register(10), vm.memory.len() as i64);
+    }
+
+    #[test]
+    fn test_with_config_sizes_memory() {
+        let config = VmConfig {
+            memory_size: 256,
+            compute_budget: 100,
+            stack_size: 64,
+            max_call_depth: 2,
+            trace: false,
+            overflow: Overflow::Wrap,
+        };
+        let vm = BpfVm::with_config(&[], config);
+        assert_eq!(vm.memory.len(), 384);
+        assert_eq!(vm.config().compute_budget, 100);
+    }
+
+    #[test]
+    fn test_runaway_loop_hits_compute_budget() {
+        let instructions = vec![
+            BpfInstruction::new(0x07, 0, 0, 0, 1),  // add r0, 1
+            BpfInstruction::new(0x05, 0, 0, -2, 0), // ja -2
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
+        ];
+
+        let config = VmConfig {
+            compute_budget: 1000,
+            ..Default::default()
+        };
+        let mut vm = BpfVm::with_config(&instructions, config);
+
+        match vm.execute() {
+            Err(VmError::ComputeBudgetExceeded(budget)) => assert_eq!(budget, 1000),
+            other => panic!("Expected compute budget error, got {:?}", other),
+        }
+        assert_eq!(vm.dump_state().compute_units, 1000);
+    }
+
+    #[test]
+    fn test_stack_frame_is_addressable() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 1, 0, 0, 1234), // mov r1, 1234
+            BpfInstruction::new(0x63, 10, 1, -4, 0),  // stxw [r10-4], r1
+            BpfInstruction::new(0x61, 0, 10, -4, 0),  // ldxw r0, [r10-4]
+            BpfInstruction::new(0x95, 0, 0, 0, 0),    // exit
+        ];
+
+        let mut vm = BpfVm::new(&instructions);
+        let result = vm.execute().unwrap();
+        assert_eq!(result.exit_code, 1234);
+    }
+
+    #[test]
+    fn test_trace_records_loop_iterations() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 1, 0, 0, 3),  // mov r1, 3
+            BpfInstruction::new(0x07, 0, 0, 0, 2),  // add r0, 2
+            BpfInstruction::new(0x07, 1, 0, 0, -1), // add r1, -1
+            BpfInstruction::new(0x55, 1, 0, -3, 0), // jne r1, 0, -3
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
+        ];
+
+        let config = VmConfig {
+            trace: true,
+            ..Default::default()
+        };
+        let mut vm = BpfVm::with_config(&instructions, config);
+        let result = vm.execute().unwrap();
+        assert_eq!(result.exit_code, 6);
+
+        // mov, three passes over the loop body, then exit
+        let trace = vm.trace();
+        assert_eq!(trace.len(), 1 + 3 * 3 + 1);
+        assert_eq!(trace.len() as u64, result.compute_units);
+        assert_eq!(
+            trace[0],
+            TraceEntry {
+                pc: 0,
+                opcode: 0xb7,
+                line: None
+            }
+        );
+        let pcs: Vec<usize> = trace.iter().map(|entry| entry.pc).collect();
+        assert_eq!(pcs, vec![0, 1, 2, 3, 1, 2, 3, 1, 2, 3, 4]);
+        assert_eq!(trace.last().unwrap().opcode, 0x95);
+    }
+
+    #[test]
+    fn test_trace_is_off_by_default() {
+        let instructions = vec![BpfInstruction::new(0x95, 0, 0, 0, 0)];
+        let mut vm = BpfVm::new(&instructions);
+        vm.execute().unwrap();
+        assert!(vm.trace().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod bpf_vm_helper_tests {
+    use super::*;
+
+    #[test]
+    fn test_registered_helper_returns_into_r0() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 1, 0, 0, 40), // mov r1, 40
+            BpfInstruction::new(0xb7, 2, 0, 0, 2),  // mov r2, 2
+            BpfInstruction::new(0x85, 0, 0, 0, 77), // call 77
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
+        ];
+
+        let mut vm = BpfVm::new(&instructions);
+        vm.register_helper(77, Box::new(|regs, _memory| regs[1] + regs[2]));
+
+        let result = vm.execute().unwrap();
+        assert_eq!(result.exit_code, 42);
+    }
+
+    #[test]
+    fn test_registered_helper_overrides_builtin_and_touches_memory() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 1, 0, 0, 16), // mov r1, 16
+            BpfInstruction::new(0x85, 0, 0, 0, 6),  // call 6 (trace_printk)
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
+        ];
+
+        let mut vm = BpfVm::new(&instructions);
+        vm.register_helper(
+            6,
+            Box::new(|regs, memory| {
+                memory[regs[1] as usize] = 0xAA;
+                7
+            }),
+        );
+
+        let result = vm.execute().unwrap();
+        assert_eq!(result.exit_code, 7);
+        assert_eq!(vm.memory[16], 0xAA);
+    }
+}
+
+#[cfg(test)]
+mod bpf_vm_neg_endian_tests {
+    use super::*;
+
+    const SWAP_INPUT: i64 = 0x0102030405060708;
+
+    fn convert(opcode: u8, width: i32) -> i64 {
+        let mut vm = BpfVm::new(&[]);
+        vm.set_register(1, SWAP_INPUT);
+        vm.execute_instruction(&BpfInstruction::new(opcode, 1, 0, 0, width))
+            .expect("Byte swap should succeed");
+        vm.get_register(1)
+    }
+
+    #[test]
+    fn test_byte_swap_to_big_endian() {
+        assert_eq!(convert(0xdc, 16), 0x0807);
+        assert_eq!(convert(0xdc, 32), 0x08070605);
+        assert_eq!(convert(0xdc, 64), 0x0807060504030201);
+    }
+
+    #[test]
+    fn test_byte_swap_to_little_endian() {
+        assert_eq!(convert(0xd4, 16), 0x0708);
+        assert_eq!(convert(0xd4, 32), 0x05060708);
+        assert_eq!(convert(0xd4, 64), SWAP_INPUT);
+    }
+
+    #[test]
+    fn test_byte_swap_rejects_invalid_width() {
+        let mut vm = BpfVm::new(&[]);
+        let instr = BpfInstruction::new(0xdc, 1, 0, 0, 24);
+        assert!(vm.execute_instruction(&instr).is_err());
+    }
+
+    #[test]
+    fn test_neg64_signed_and_unsigned() {
+        let mut vm = BpfVm::new(&[]);
+        let neg = BpfInstruction::new(0x87, 1, 0, 0, 0);
+
+        vm.set_register(1, 42);
+        vm.execute_instruction(&neg).unwrap();
+        assert_eq!(vm.get_register(1), -42);
+
+        vm.set_register(1, -42);
+        vm.execute_instruction(&neg).unwrap();
+        assert_eq!(vm.get_register(1), 42);
+
+        vm.set_register(1, i64::MIN);
+        vm.execute_instruction(&neg).unwrap();
+        assert_eq!(vm.get_register(1), i64::MIN);
+    }
+
+    #[test]
+    fn test_neg32_zero_extends() {
+        let mut vm = BpfVm::new(&[]);
+        let neg = BpfInstruction::new(0x84, 1, 0, 0, 0);
+
+        vm.set_register(1, 1);
+        vm.execute_instruction(&neg).unwrap();
+        assert_eq!(vm.get_register(1), 0xffff_ffff);
+
+        vm.set_register(1, 0xffff_ffff);
+        vm.execute_instruction(&neg).unwrap();
+        assert_eq!(vm.get_register(1), 1);
+    }
+}
+
+#[cfg(test)]
+mod elf_emission_tests {
+    use super::*;
+    use crate::pible::elf::{self, elf_consts, ElfHeader};
+
+    fn compile_elf(source: &str) -> Vec<u8> {
+        let compiler = Compiler::new();
+        let options = CompileOptions {
+            target: CompileTarget::LinuxBpf,
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        compiler
+            .compile(source, &options)
+            .expect("ELF compilation should succeed")
+    }
+
+    #[test]
+    fn test_elf_header_round_trips() {
+        let object = compile_elf("U0 main() { return 0; }");
+
+        let header = ElfHeader::parse(&object).expect("Header should parse");
+        assert_eq!(header.elf_type, elf_consts::ET_REL);
+        assert_eq!(header.machine, elf_consts::EM_BPF);
+        // null, socket, license, .BTF, .symtab, .strtab, .shstrtab
+        assert_eq!(header.section_count, 7);
+        assert!(header.section_header_offset as usize <= object.len());
+    }
+
+    #[test]
+    fn test_elf_contains_program_license_and_symbol() {
+        let object = compile_elf("U0 main() { return 0; }");
+
+        let raw = Compiler::new()
+            .compile("U0 main() { return 0; }", &CompileOptions::default())
+            .unwrap();
+        let program = elf::find_section(&object, "socket").unwrap().unwrap();
+        assert_eq!(program, raw.as_slice());
+
+        // Without a pragma the license is a conservative non-GPL one
+        let license = elf::find_section(&object, "license").unwrap().unwrap();
+        assert_eq!(license, b"Proprietary\0");
+
+        let strtab = elf::find_section(&object, ".strtab").unwrap().unwrap();
+        assert!(strtab.windows(5).any(|window| window == b"main\0"));
+    }
+
+    #[test]
+    fn test_pragmas_set_license_and_section() {
+        let object = compile_elf(
+            "#pragma license(\"Dual MIT/GPL\")\n#pragma section(\"xdp\")\nU0 main() { return 0; }",
+        );
+
+        let license = elf::find_section(&object, "license").unwrap().unwrap();
+        assert_eq!(license, b"Dual MIT/GPL\0");
+        assert!(elf::find_section(&object, "xdp").unwrap().is_some());
+        assert!(elf::find_section(&object, "socket").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_malformed_pragmas_are_rejected() {
+        for source in [
+            "#pragma license(GPL)\nU0 main() { return 0; }",
+            "#pragma license(\"\")\nU0 main() { return 0; }",
+            "#pragma optimize(\"fast\")\nU0 main() { return 0; }",
+        ] {
+            assert!(matches!(
+                Compiler::new().compile(source, &CompileOptions::default()),
+                Err(CompileError::IncludeError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_gpl_only_helpers_are_found() {
+        assert!(elf::is_gpl_compatible("GPL"));
+        assert!(!elf::is_gpl_compatible(elf::DEFAULT_LICENSE));
+
+        let instructions = vec![
+            BpfInstruction::new(0x85, 0, 0, 0, 6),
+            BpfInstruction::new(0x85, 0, 0, 0, 1),
+            BpfInstruction::new(0x85, 0, 0, 0, 6),
+            BpfInstruction::new(0x95, 0, 0, 0, 0),
+        ];
+        assert_eq!(elf::gpl_only_helpers(&instructions), vec!["trace_printk"]);
+    }
+
+    #[test]
+    fn test_elf_rejected_for_vm_target() {
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        assert!(Compiler::new()
+            .compile("U0 main() { return 0; }", &options)
+            .is_err());
+    }
+
+    #[test]
+    fn test_solana_elf_relocates_syscalls_by_name() {
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        let bytes = Compiler::new()
+            .compile(
+                "U0 main() { PrintF(\"hi\"); PrintF(\"there\"); return 0; }",
+                &options,
+            )
+            .unwrap();
+
+        assert!(elf::find_section(&bytes, "license").unwrap().is_none());
+        let text = elf::find_section(&bytes, ".text").unwrap().unwrap();
+        let relocations = elf::parse_relocations(&bytes, ".text").unwrap();
+        let calls: Vec<_> = relocations
+            .iter()
+            .filter(|relocation| relocation.rel_type == elf_consts::R_BPF_64_32)
+            .collect();
+        assert_eq!(calls.len(), 2);
+        for call in calls {
+            assert_eq!(call.symbol, "sol_log_");
+            assert_eq!(text[call.offset as usize], 0x85);
+        }
+
+        // Both calls share one undefined symbol
+        let strtab = elf::find_section(&bytes, ".strtab").unwrap().unwrap();
+        assert_eq!(
+            strtab
+                .windows(9)
+                .filter(|name| name == b"sol_log_\0")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_linux_elf_keeps_helper_ids() {
+        let bytes = compile_elf("U0 main() { PrintF(\"hi\"); return 0; }");
+        let relocations = elf::parse_relocations(&bytes, "xdp").unwrap();
+        assert!(relocations
+            .iter()
+            .all(|relocation| relocation.rel_type != elf_consts::R_BPF_64_32));
+    }
+
+    #[test]
+    fn test_elf_parse_rejects_bad_magic() {
+        assert!(ElfHeader::parse(&[0u8; 64]).is_err());
+        assert!(ElfHeader::parse(&[0x7f, b'E']).is_err());
+    }
+}
+
+#[cfg(test)]
+mod disasm_tests {
+    use super::*;
+    use crate::pible::disasm::{disassemble, disassemble_bytes};
+
+    #[test]
+    fn test_disassemble_known_program() {
+        let instructions = vec![
+            BpfInstruction::new(0x79, 6, 2, 0, 0),   // ldxdw r6, [r2+0]
+            BpfInstruction::new(0xb7, 1, 0, 0, 10),  // mov64 r1, 10
+            BpfInstruction::new(0x1d, 1, 2, 1, 0),   // jeq r1, r2, +1
+            BpfInstruction::new(0x85, 0, 0, 0, 6),   // call 6
+            BpfInstruction::new(0x63, 10, 1, -8, 0), // stxw [r10-8], r1
+            BpfInstruction::new(0xb7, 0, 0, 0, 0),   // mov64 r0, 0
+            BpfInstruction::new(0x95, 0, 0, 0, 0),   // exit
+        ];
+
+        let expected = "    ldxdw r6, [r2+0]\n\
+                        \x20   mov64 r1, 10\n\
+                        \x20   jeq r1, r2, +1 ; lbl_4\n\
+                        \x20   call 6 ; trace_printk\n\
+                        lbl_4:\n\
+                        \x20   stxw [r10-8], r1\n\
+                        \x20   mov64 r0, 0\n\
+                        \x20   exit\n";
+        assert_eq!(disassemble(&instructions), expected);
+    }
+
+    #[test]
+    fn test_mnemonics_and_classes() {
+        let named = |opcode, offset, immediate| {
+            BpfInstruction::new(opcode, 1, 2, offset, immediate).mnemonic()
+        };
+        assert_eq!(named(0x95, 0, 0), "exit");
+        assert_eq!(named(0x85, 0, 6), "call");
+        assert_eq!(named(0xb7, 0, 0), "mov64");
+        assert_eq!(named(0x79, 0, 0), "ldxdw");
+        assert_eq!(named(0x0f, 0, 0), "add64");
+        assert_eq!(named(0x3f, 1, 0), "sdiv64");
+        assert_eq!(named(0xdc, 0, 32), "be32");
+        assert_eq!(named(0x18, 0, 0), "lddw");
+        assert_eq!(named(0xff, 0, 0), "unknown");
+
+        assert_eq!(BpfInstruction::new(0x95, 0, 0, 0, 0).class(), 0x05);
+        assert_eq!(BpfInstruction::new(0x0f, 1, 2, 0, 0).class(), 0x07);
+        assert_eq!(BpfInstruction::new(0x79, 1, 2, 0, 0).class(), 0x01);
+    }
+
+    #[test]
+    fn test_disassemble_alu_and_immediate_forms() {
+        let instructions = vec![
+            BpfInstruction::new(0x0f, 1, 2, 0, 0),  // add64 r1, r2
+            BpfInstruction::new(0x17, 1, 0, 0, 4),  // sub64 r1, 4
+            BpfInstruction::new(0x87, 1, 0, 0, 0),  // neg64 r1
+            BpfInstruction::new(0xdc, 1, 0, 0, 32), // be32 r1
+            BpfInstruction::new(0x62, 1, 0, 4, 7),  // stw [r1+4], 7
+        ];
+
+        let text = disassemble(&instructions);
+        let lines: Vec<&str> = text.lines().map(str::trim).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "add64 r1, r2",
+                "sub64 r1, 4",
+                "neg64 r1",
+                "be32 r1",
+                "stw [r1+4], 7"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_bytes_matches_instructions() {
+        let source = "U0 main() { return 0; }";
+        let compiler = Compiler::new();
+        let bytecode = compiler
+            .compile(source, &CompileOptions::default())
+            .unwrap();
+
+        let text = disassemble_bytes(&bytecode);
+        assert!(text.trim_end().ends_with("exit"));
+        assert_eq!(text.lines().count(), bytecode.len() / 8);
+    }
+}
+
+#[cfg(test)]
+mod rodata_string_tests {
+    use super::*;
+    use crate::pible::elf;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn generate(source: &str) -> CodeGen {
+        let tokens = Lexer::new(source).scan_tokens().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut codegen = CodeGen::new();
+        codegen.generate(&ast).unwrap();
+        codegen
+    }
+
+    #[test]
+    fn test_string_literals_collected_into_rodata() {
+        let codegen = generate(r#"U0 main() { PrintF("hi"); PrintF("a\n"); PrintF("hi"); }"#);
+        assert_eq!(codegen.rodata(), b"hi\0a\n\0");
+        assert_eq!(codegen.rodata_relocations().len(), 3);
+    }
+
+    #[test]
+    fn test_printf_helper_sees_string_bytes() {
+        let source = r#"U0 main() { PrintF("hi"); return 0; }"#;
+        let tokens = Lexer::new(source).scan_tokens().unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut codegen = CodeGen::new();
+        let instructions = codegen.generate(&ast).unwrap();
+
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let captured = Rc::clone(&seen);
+
+        let mut vm = BpfVm::new(&instructions);
+        vm.load_data(0, codegen.rodata()).unwrap();
+        vm.register_helper(
+            6,
+            Box::new(move |regs, memory| {
+                let start = regs[1] as usize;
+                let len = regs[2] as usize;
+                captured
+                    .borrow_mut()
+                    .extend_from_slice(&memory[start..start + len]);
+                0
+            }),
+        );
+
+        vm.execute().unwrap();
+        assert_eq!(seen.borrow().as_slice(), b"hi\0");
+    }
+
+    #[test]
+    fn test_elf_rodata_section_and_relocation() {
+        let options = CompileOptions {
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        let object = Compiler::new()
+            .compile(r#"U0 main() { PrintF("hello"); }"#, &options)
+            .unwrap();
+
+        let rodata = elf::find_section(&object, ".rodata").unwrap().unwrap();
+        assert_eq!(rodata, b"hello\0");
+
+        let relocations = elf::find_section(&object, ".relsocket").unwrap().unwrap();
+        assert_eq!(relocations.len(), 16);
+        let info = u64::from_le_bytes(relocations[8..16].try_into().unwrap());
+        assert_eq!(info & 0xffff_ffff, elf::elf_consts::R_BPF_64_64 as u64);
+    }
+}
+
+#[cfg(test)]
+mod calling_convention_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+
+    #[test]
+    fn test_two_function_program() {
+        let source = r#"
+            U64 add_numbers(U64 a, U64 b) {
+                return a + b;
+            }
+
+            U64 main() {
+                return add_numbers(40, 2);
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 42);
+    }
+
+    #[test]
+    fn test_callee_defined_before_and_after_caller() {
+        let source = r#"
+            U64 main() {
+                return twice(5) + square(3);
+            }
+
+            U64 twice(U64 x) {
+                return x * 2;
+            }
+
+            U64 square(U64 x) {
+                return x * x;
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 19);
+    }
+
+    #[test]
+    fn test_recursive_fibonacci() {
+        let source = r#"
+            U64 fibonacci(U64 n) {
+                if (n <= 1) {
+                    return n;
+                }
+                return fibonacci(n - 1) + fibonacci(n - 2);
+            }
+
+            U64 main() {
+                return fibonacci(10);
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 55);
+    }
+
+    #[test]
+    fn test_call_emits_pseudo_call_with_relative_target() {
+        let source = "U64 one() { return 1; } U64 main() { return one(); }";
+        let instructions = generate_instructions(source);
+
+        let (index, call) = instructions
+            .iter()
+            .enumerate()
+            .find(|(_, instruction)| instruction.opcode == 0x85 && instruction.src_reg == 1)
+            .expect("local call should be a pseudo-call");
+        let target = (index as i64 + 1 + call.immediate as i64) as usize;
+        // `one` loads 1 into r6 on entry
+        assert_eq!(instructions[target].opcode, 0xb7);
+        assert_eq!(instructions[target].immediate, 1);
+    }
+
+    #[test]
+    fn test_locals_and_loops() {
+        let source = r#"
+            U64 sum_to(U64 n) {
+                U64 total = 0;
+                for (U64 i = 1; i <= n; i = i + 1) {
+                    total = total + i;
+                }
+                return total;
+            }
+
+            U64 main() {
+                return sum_to(10);
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 55);
+    }
+
+    #[test]
+    fn test_registers_limit_parameters_and_arguments_to_five() {
+        let error = |source: &str| {
+            let tokens = Lexer::new(source).scan_tokens().unwrap();
+            let ast = Parser::new(tokens).parse().unwrap();
+            CodeGen::new().generate(&ast).unwrap_err()
+        };
+        let six = "U64 six(U64 a, U64 b, U64 c, U64 d, U64 e, U64 f) { return f; }";
+        for source in [
+            format!("{}\nU64 main() {{ return 0; }}", six),
+            format!("{}\nU64 main() {{ return six(1, 2, 3, 4, 5, 6); }}", six),
+            // The format's length takes a register too
+            r#"U0 main() { PrintF("%d %d %d %d", 1, 2, 3, 4); }"#.to_string(),
+        ] {
+            let error = error(&source);
+            assert!(
+                matches!(&error, CodeGenError::InvalidInstruction(message) if message.contains("at most 5")),
+                "{}: {:?}",
+                source,
+                error
+            );
+        }
+        assert!(matches!(
+            error("U64 main() { return missing; }"),
+            CodeGenError::UndefinedVariable(name) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn test_call_to_undefined_function_is_an_error() {
+        let tokens = Lexer::new("U64 main() { return missing(1); }")
+            .scan_tokens()
+            .unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        assert!(matches!(
+            CodeGen::new().generate(&ast),
+            Err(CodeGenError::FunctionNotFound(name)) if name == "missing"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod optimizer_tests {
+    use super::*;
+    use crate::pible::optimizer;
+
+    fn compile_with(source: &str, opt_level: u8) -> Vec<u8> {
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            opt_level,
+            ..Default::default()
+        };
+        Compiler::new()
+            .compile(source, &options)
+            .expect("Compilation should succeed")
+    }
+
+    fn run_bytes(bytecode: &[u8]) -> i32 {
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let mut vm = BpfVm::new(&instructions);
+        vm.execute().expect("Execution should succeed").exit_code
+    }
+
+    #[test]
+    fn test_constant_program_shrinks_with_same_result() {
+        let source = r#"
+            U64 main() {
+                U64 c = 2 + 3 * 4;
+                U64 d = (c - 4) / 2 == 5;
+                return c + d * 100;
+            }
+        "#;
+        let unoptimized = compile_with(source, 0);
+        let optimized = compile_with(source, 1);
+
+        assert!(optimized.len() < unoptimized.len());
+        assert_eq!(run_bytes(&unoptimized), 114);
+        assert_eq!(run_bytes(&optimized), 114);
+    }
+
+    #[test]
+    fn test_fold_constants_leaves_division_by_zero() {
+        let mut lexer = Lexer::new("U64 main() { return 1 / 0; }");
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let ast = optimizer::fold_constants(&parser.parse().unwrap());
+
+        let body = ast.children[0].children.last().unwrap();
+        let value = &body.children[0].children[0];
+        assert_eq!(value.value.as_deref(), Some("/"));
+    }
+
+    #[test]
+    fn test_peephole_removes_redundant_moves() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 0, 0, 0, 0), // mov r0, 0 (overwritten)
+            BpfInstruction::new(0xb7, 0, 0, 0, 7), // mov r0, 7
+            BpfInstruction::new(0xbf, 3, 3, 0, 0), // mov r3, r3
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        let (optimized, _) = optimizer::peephole(&instructions);
+
+        assert_eq!(optimized.len(), 2);
+        assert_eq!(optimized[0].immediate, 7);
+        assert_eq!(optimized[1].opcode, 0x95);
+    }
+
+    #[test]
+    fn test_peephole_rewrites_jump_offsets() {
+        let instructions = vec![
+            BpfInstruction::new(0x15, 1, 0, 3, 0), // jeq r1, 0, +3
+            BpfInstruction::new(0xbf, 2, 2, 0, 0), // mov r2, r2
+            BpfInstruction::new(0xb7, 0, 0, 0, 1), // mov r0, 1
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+            BpfInstruction::new(0xb7, 0, 0, 0, 2), // mov r0, 2
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        let (optimized, index_map) = optimizer::peephole(&instructions);
+
+        assert_eq!(optimized.len(), 5);
+        assert_eq!(optimized[0].offset, 2);
+        assert_eq!(index_map[4], 3);
+
+        let mut vm = BpfVm::new(&optimized);
+        assert_eq!(vm.execute().unwrap().exit_code, 2);
+    }
+
+    #[test]
+    fn test_optimized_function_calls_still_resolve() {
+        let source = r#"
+            U64 square(U64 x) { return x * x; }
+            U64 main() { return square(3 + 4); }
+        "#;
+        assert_eq!(run_bytes(&compile_with(source, 1)), 49);
+    }
+}
+
+#[cfg(test)]
+mod diagnostic_tests {
+    use super::*;
+    use crate::pible::compiler::{render_diagnostic, CompileError};
+
+    #[test]
+    fn test_unterminated_string_diagnostic() {
+        let source = "U0 main() {\n    PrintF(\"oops);\n}\n";
+        let error = Compiler::new()
+            .compile(source, &CompileOptions::default())
+            .unwrap_err();
+
+        match &error {
+            CompileError::LexError { line, column, .. } => {
+                assert_eq!((*line, *column), (2, 12));
+            }
+            other => panic!("expected a lex error, got {:?}", other),
+        }
+
+        let rendered = error.to_string();
+        assert!(rendered.contains("2 |     PrintF(\"oops);"));
+        assert!(rendered.contains("  |            ^"));
+    }
+
+    #[test]
+    fn test_missing_paren_diagnostic() {
+        let source = "U64 helper() { return 1; }\nU0 main( { return 0; }\n";
+        let error = Compiler::new()
+            .compile(source, &CompileOptions::default())
+            .unwrap_err();
+
+        match &error {
+            CompileError::ParseError { line, column, .. } => {
+                assert_eq!((*line, *column), (2, 10));
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+
+        let rendered = error.to_string();
+        assert!(rendered.contains("2 | U0 main( { return 0; }"));
+        assert!(rendered.contains("  |          ^"));
+        assert!(!rendered.contains("helper"));
+    }
+
+    #[test]
+    fn test_render_diagnostic_layout() {
+        let rendered = render_diagnostic("a\n\tbad here\n", 2, 6, "something broke");
+        let lines: Vec<&str> = rendered.lines().collect();
+
+        assert_eq!(lines[0], "error: something broke");
+        assert_eq!(lines[1], " --> line 2, column 6");
+        assert_eq!(lines[3], "2 | \tbad here");
+        assert_eq!(lines[4], "  | \t    ^");
+    }
+
+    #[test]
+    fn test_token_columns_point_at_token_start() {
+        let mut lexer = Lexer::new("U64 total = 42;");
+        let tokens = lexer.scan_tokens().unwrap();
+        let columns: Vec<usize> = tokens.iter().map(|token| token.column).collect();
+        assert_eq!(columns, vec![1, 5, 11, 13, 15, 16]);
+    }
+}
+
+#[cfg(test)]
+mod signedness_tests {
+    use super::*;
+    use crate::pible::types::Type;
+
+    fn has_opcode(instructions: &[BpfInstruction], opcode: u8) -> bool {
+        instructions
+            .iter()
+            .any(|instruction| instruction.opcode == opcode)
+    }
+
+    #[test]
+    fn test_signed_right_shift_sign_extends() {
+        let (instructions, result) = run_codegen("I64 main() { I64 x = -64; return x >> 2; }");
+        assert!(has_opcode(&instructions, 0xc7));
+        assert_eq!(result, -16);
+    }
+
+    #[test]
+    fn test_unsigned_right_shift_zero_fills() {
+        let (instructions, result) = run_codegen("U64 main() { U64 x = -64; return x >> 60; }");
+        assert!(has_opcode(&instructions, 0x77));
+        assert_eq!(result, 0xf);
+    }
+
+    #[test]
+    fn test_shift_by_variable_amount() {
+        let source = r#"
+            I64 main() {
+                I64 x = -64;
+                U64 y = -64;
+                U64 amount = 4;
+                return (x >> amount) + (y >> amount == 1152921504606846972);
+            }
+        "#;
+        let (instructions, result) = run_codegen(source);
+        assert!(has_opcode(&instructions, 0xcf));
+        assert!(has_opcode(&instructions, 0x7f));
+        assert_eq!(result, -3);
+    }
+
+    #[test]
+    fn test_comparisons_follow_operand_signedness() {
+        let (instructions, result) =
+            run_codegen("I64 main() { I64 a = -1; I64 b = 1; return a < b; }");
+        assert!(has_opcode(&instructions, 0xcd));
+        assert_eq!(result, 1);
+
+        let (instructions, result) =
+            run_codegen("U64 main() { U64 a = -1; U64 b = 1; return a < b; }");
+        assert!(has_opcode(&instructions, 0xad));
+        assert_eq!(result, 0);
+    }
+
+    #[test]
+    fn test_call_results_carry_return_type() {
+        let source = r#"
+            U64 big() { return -1; }
+            I64 main() { return big() > 1; }
+        "#;
+        let (instructions, result) = run_codegen(source);
+        assert!(has_opcode(&instructions, 0x2d));
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn test_type_parsing_and_promotion() {
+        let u64_type = Type::parse("U64").unwrap();
+        assert!(!u64_type.is_signed());
+        assert!(Type::parse("I32").unwrap().is_signed());
+        assert!(matches!(Type::parse("U8*"), Some(Type::Pointer(_))));
+        assert_eq!(Type::parse("Account"), None);
+        assert_eq!(Type::common(&Type::I64, &u64_type), u64_type);
+        assert_eq!(Type::common(&Type::Bool, &Type::Bool), Type::I64);
+    }
+}
+
+#[cfg(test)]
+mod struct_layout_tests {
+    use super::*;
+    use crate::pible::types::{StructLayout, Type};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_layout_uses_natural_alignment() {
+        let fields = vec![
+            ("flag".to_string(), Type::parse("U8").unwrap()),
+            ("amount".to_string(), Type::parse("U64").unwrap()),
+            ("kind".to_string(), Type::parse("U16").unwrap()),
+            ("owner".to_string(), Type::parse("U8*").unwrap()),
+            ("bump".to_string(), Type::parse("U8").unwrap()),
+        ];
+        let layout = StructLayout::new("Account", &fields, &HashMap::new()).unwrap();
+
+        let offsets: Vec<(usize, usize)> = layout
+            .fields
+            .iter()
+            .map(|field| (field.offset, field.size))
+            .collect();
+        assert_eq!(offsets, vec![(0, 1), (8, 8), (16, 2), (24, 8), (32, 1)]);
+        assert_eq!(layout.align, 8);
+        assert_eq!(layout.size, 40);
+    }
+
+    #[test]
+    fn test_nested_struct_layout() {
+        let mut structs = HashMap::new();
+        let inner = StructLayout::new(
+            "Pair",
+            &[
+                ("a".to_string(), Type::parse("U32").unwrap()),
+                ("b".to_string(), Type::parse("U8").unwrap()),
+            ],
+            &structs,
+        )
+        .unwrap();
+        assert_eq!(inner.size, 8);
+        structs.insert("Pair".to_string(), inner);
+
+        let outer = StructLayout::new(
+            "Outer",
+            &[
+                ("tag".to_string(), Type::parse("U8").unwrap()),
+                ("pair".to_string(), Type::parse("struct Pair").unwrap()),
+            ],
+            &structs,
+        )
+        .unwrap();
+        assert_eq!(outer.field("pair").unwrap().offset, 4);
+        assert_eq!(outer.size, 12);
+
+        let unknown = StructLayout::new(
+            "Broken",
+            &[("x".to_string(), Type::parse("struct Missing").unwrap())],
+            &structs,
+        );
+        assert_eq!(unknown, Err("x".to_string()));
+    }
+
+    #[test]
+    fn test_write_and_read_back_fields() {
+        let source = r#"
+            struct Account {
+                U8 is_signer;
+                U64 lamports;
+            };
+
+            U64 main() {
+                struct Account acc;
+                acc.lamports = 5000;
+                U64 wide = 257;
+                acc.is_signer = wide;
+                return acc.lamports + acc.is_signer;
+            }
+        "#;
+        let (instructions, result) = run_codegen(source);
+
+        // U8 field truncates to one byte: 5000 + (257 & 0xff)
+        assert_eq!(result, 5001);
+        assert!(instructions.iter().any(|i| i.opcode == 0x73)); // stxb
+        assert!(instructions.iter().any(|i| i.opcode == 0x71)); // ldxb
+        assert!(instructions.iter().any(|i| i.opcode == 0x7b)); // stxdw
+    }
+
+    #[test]
+    fn test_member_access_through_pointer() {
+        let source = r#"
+            struct Account {
+                U8 is_signer;
+                U64 lamports;
+            };
+
+            U0 credit(Account* account, U64 amount) {
+                account->lamports = account->lamports + amount;
+                account->is_signer = 1;
+            }
+
+            U64 main() {
+                struct Account acc;
+                acc.lamports = 40;
+                credit(acc, 2);
+                return acc.lamports * 10 + acc.is_signer;
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 421);
+    }
+
+    #[test]
+    fn test_unknown_field_is_an_error() {
+        let source = r#"
+            struct Account { U64 lamports; };
+            U64 main() { struct Account acc; return acc.owner; }
+        "#;
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let ast = parser.parse().unwrap();
+        assert!(CodeGen::new().generate(&ast).is_err());
+    }
+}
+
+#[cfg(test)]
+mod array_indexing_tests {
+    use super::*;
+
+    #[test]
+    fn test_write_element_and_read_back_at_computed_index() {
+        let source = r#"
+            U64 main() {
+                U64 balances[4];
+                balances[0] = 11;
+                balances[2] = 77;
+                balances[3] = 99;
+                U64 i = 1 + 1;
+                return balances[i];
+            }
+        "#;
+        let (instructions, result) = run_codegen(source);
+        assert_eq!(result, 77);
+        // Variable index is scaled by the element size before the add
+        assert!(instructions.iter().any(|i| i.opcode == 0x2f)); // mul64 reg
+        assert!(instructions.iter().any(|i| i.opcode == 0x0f)); // add64 reg
+    }
+
+    #[test]
+    fn test_byte_array_uses_unit_scale_and_byte_stores() {
+        let source = r#"
+            U64 main() {
+                U8 bytes[8];
+                U64 i = 0;
+                while (i < 8) {
+                    bytes[i] = i + 250;
+                    i = i + 1;
+                }
+                return bytes[3] + bytes[7];
+            }
+        "#;
+        let (instructions, result) = run_codegen(source);
+        // 253 + (257 & 0xff)
+        assert_eq!(result, 254);
+        assert!(instructions.iter().any(|i| i.opcode == 0x73)); // stxb
+        assert!(!instructions.iter().any(|i| i.opcode == 0x2f));
+    }
+
+    #[test]
+    fn test_initializer_list_bytes_land_in_memory() {
+        let source = r#"
+            U64 main() {
+                U8 key[] = {0x01, 0x02, 0xAB, 255};
+                return key;
+            }
+        "#;
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer.scan_tokens().expect("Lexing should succeed"));
+        let ast = parser.parse().expect("Parsing should succeed");
+        let instructions = CodeGen::new()
+            .generate(&ast)
+            .expect("Code generation should succeed");
+
+        let mut vm = BpfVm::new(&instructions);
+        vm.execute().expect("Execution should succeed");
+        let address = vm.registers()[0] as usize;
+        assert_eq!(&vm.memory[address..address + 4], &[0x01, 0x02, 0xab, 0xff]);
+    }
+
+    #[test]
+    fn test_initializer_list_sizes_and_fills_arrays() {
+        let source = r#"
+            U64 main() {
+                U64 values[4] = {10, 20};
+                values[3] = 3;
+                U16 halves[] = {0x100, 0x200, 0x300,};
+                return values[0] + values[1] + values[3] + halves[2] + sizeof(halves);
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 10 + 20 + 3 + 0x300 + 6);
+
+        let too_many = r#"
+            U64 main() {
+                U8 key[2] = {1, 2, 3};
+                return 0;
+            }
+        "#;
+        let mut lexer = Lexer::new(too_many);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let ast = parser.parse().unwrap();
+        assert!(CodeGen::new().generate(&ast).is_err());
+    }
+
+    #[test]
+    fn test_pointer_arithmetic_scales_by_pointee_size() {
+        let source = r#"
+            U64 main() {
+                U64 values[4];
+                values[1] = 5;
+                values[3] = 9;
+                U64* p = values;
+                U64* q = p + 3;
+                U64* r = p + 1;
+                return r[0] * 100 + q[0] * 10 + (q - p);
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 593);
+    }
+
+    #[test]
+    fn test_array_field_inside_struct() {
+        let source = r#"
+            struct Ledger {
+                U8 count;
+                U32 entries[3];
+            };
+
+            U64 main() {
+                struct Ledger ledger;
+                ledger.count = 2;
+                ledger.entries[0] = 4;
+                ledger.entries[ledger.count] = 6;
+                return ledger.entries[0] + ledger.entries[2];
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 10);
+    }
+
+    #[test]
+    fn test_two_dimensional_array_is_row_major() {
+        let source = r#"
+            U64 main() {
+                U8 signers[11][32];
+                U64 row = 1;
+                U64 column = 4;
+                signers[row + 1][column + 1] = 77;
+                signers[10][31] = 3;
+                U8* bytes = signers;
+                return bytes[2 * 32 + 5] * 100 + signers[2][5] + bytes[10 * 32 + 31];
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 7700 + 77 + 3);
+    }
+
+    #[test]
+    fn test_struct_array_elements_are_padded_to_struct_size() {
+        let source = r#"
+            struct Order {
+                U8 side;
+                U64 price;
+            };
+
+            struct Book {
+                U64 count;
+                struct Order orders[4];
+            };
+
+            U64 main() {
+                struct Book book;
+                struct Book* b = &book;
+                U64 i = 2;
+                b->orders[i].price = 1000;
+                b->orders[i + 1].side = 9;
+                U64 stride = (U64)&book.orders[1] - (U64)&book.orders[0];
+                return b->orders[2].price + book.orders[3].side + stride * 10000 + sizeof(book);
+            }
+        "#;
+        assert_eq!(run_codegen(source).1, 1000 + 9 + 16 * 10000 + 8 + 4 * 16);
+    }
+}
+
+#[cfg(test)]
+mod pointer_operator_tests {
+    use super::*;
+
+    #[test]
+    fn test_dereference_and_address_of() {
+        let source = r#"
+            U0 add(U64* total, U64 amount) {
+                *total = *total + amount;
+            }
+
+            U64 main() {
+                U64 value = 40;
+                U64* pointer = &value;
+                add(pointer, 2);
+                U8 bytes[2];
+                *bytes = 7;
+                *(bytes + 1) = 9;
+                (*pointer)++;
+                return value * 100 + bytes[0] * 10 + bytes[1];
+            }
+        "#;
+        assert_eq!(run(source), 43 * 100 + 79);
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let source = r#"
+            U64 main() {
+                U64 flags = 0x0F;
+                U64 mask = 0x3C;
+                U64 both = flags & mask;
+                U64 either = flags | mask;
+                U64 one = flags ^ mask;
+                flags &= 6;
+                flags |= 0x40;
+                flags ^= 0x41;
+                return (both == 0x0C) + (either == 0x3F) * 2 + (one == 0x33) * 4
+                    + (flags == 0x07) * 8 + (1 | 2 & 4 ^ 8) * 16;
+            }
+        "#;
+        for opt_level in 0..=2 {
+            let options = CompileOptions {
+                opt_level,
+                ..CompileOptions::default()
+            };
+            assert_eq!(run_with(source, &options), 15 + 9 * 16);
+        }
+    }
+}
+
+#[cfg(test)]
+mod ast_dump_tests {
+    use super::*;
+    use crate::pible::parser::{Node, Position};
+
+    const SOURCE: &str = "U64 main() {\n    U64 x = 1 + 2;\n    return x;\n}\n";
+
+    #[test]
+    fn test_ast_json_round_trips() {
+        let json = Compiler::new()
+            .dump_ast(SOURCE)
+            .expect("Parsing should succeed");
+        let ast: Node = serde_json::from_str(&json).expect("JSON should deserialize");
+
+        let mut lexer = Lexer::new(SOURCE);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        assert_eq!(ast, parser.parse().unwrap());
+    }
+
+    #[test]
+    fn test_ast_json_has_node_types_and_positions() {
+        let json = Compiler::new().dump_ast(SOURCE).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(value["node_type"], "Program");
+        let function = &value["children"][0];
+        assert_eq!(function["node_type"], "FunctionDecl");
+        assert_eq!(function["value"], "U64:main");
+
+        let body = &function["children"][0];
+        assert_eq!(body["node_type"], "Block");
+        let declaration = &body["children"][0];
+        assert_eq!(declaration["value"], "var");
+        assert_eq!(declaration["position"]["line"], 2);
+        assert_eq!(declaration["position"]["column"], 5);
+
+        let sum = &declaration["children"][1];
+        assert_eq!(sum["node_type"], "Expression");
+        assert_eq!(sum["value"], "+");
+        assert_eq!(sum["children"][1]["node_type"], "Literal");
+        assert_eq!(sum["children"][1]["position"]["column"], 17);
+    }
+
+    #[test]
+    fn test_parser_records_expression_positions() {
+        let mut lexer = Lexer::new(SOURCE);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let ast = parser.parse().unwrap();
+
+        let ret = &ast.children[0].children[0].children[1];
+        assert_eq!(ret.value.as_deref(), Some("return"));
+        assert_eq!(ret.position, Some(Position { line: 3, column: 5 }));
+        assert_eq!(
+            ret.children[0].position,
+            Some(Position {
+                line: 3,
+                column: 12
+            })
+        );
+    }
+}
+
+#[cfg(test)]
+mod solana_syscall_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+    use crate::pible::solana_bpf::syscall_id;
+
+    #[test]
+    fn test_syscall_ids_match_runtime_registry() {
+        assert_eq!(syscall_id("sol_log_"), 0x207559bd);
+        assert_eq!(syscall_id("sol_log_64_"), 0x5c2a3178);
+    }
+
+    fn generate(source: &str, target: CompileTarget) -> Vec<BpfInstruction> {
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let ast = parser.parse().unwrap();
+        CodeGen::for_target(target).generate(&ast).unwrap()
+    }
+
+    fn helper_calls(instructions: &[BpfInstruction]) -> Vec<i32> {
+        instructions
+            .iter()
+            .filter(|i| i.opcode == 0x85 && i.src_reg == 0)
+            .map(|i| i.immediate)
+            .collect()
+    }
+
+    #[test]
+    fn test_solana_printf_calls_sol_log() {
+        let source = r#"U0 entrypoint() { PrintF("hello"); }"#;
+        let instructions = generate(source, CompileTarget::SolanaBpf);
+
+        let sol_log = syscall_id("sol_log_") as i32;
+        assert_eq!(helper_calls(&instructions), vec![sol_log]);
+
+        // Message pointer in r1, length without the NUL in r2
+        let call = instructions.iter().position(|i| i.opcode == 0x85).unwrap();
+        let length = instructions[..call]
+            .iter()
+            .rev()
+            .find(|i| i.opcode == 0xb7 && i.dst_reg == 2)
+            .unwrap();
+        assert_eq!(length.immediate, 5);
+        assert!(instructions[..call]
+            .iter()
+            .any(|i| i.opcode == 0x79 && i.dst_reg == 1));
+    }
+
+    #[test]
+    fn test_linux_printf_still_uses_trace_printk() {
+        let source = r#"U0 main() { PrintF("hello"); sol_log("hi"); }"#;
+        let instructions = generate(source, CompileTarget::LinuxBpf);
+        assert_eq!(helper_calls(&instructions), vec![6, 6]);
+    }
+
+    #[test]
+    fn test_sol_log_64_passes_five_values() {
+        let source = r#"U0 entrypoint() { sol_log_64(1, 2, 3, 4, 5); }"#;
+        let instructions = generate(source, CompileTarget::SolanaBpf);
+        assert_eq!(
+            helper_calls(&instructions),
+            vec![syscall_id("sol_log_64_") as i32]
+        );
+        let loaded: Vec<u8> = instructions
+            .iter()
+            .filter(|i| i.opcode == 0x79 && i.src_reg == 10)
+            .map(|i| i.dst_reg)
+            .collect();
+        assert_eq!(loaded, vec![1, 2, 3, 4, 5]);
+    }
+
+    const CPI_PROGRAM: &str = r#"
+        struct Instruction {
+            U8* program_id;
+            U8* accounts;
+            U64 accounts_len;
+            U8* data;
+            U64 data_len;
+        };
+
+        U64 entrypoint(U8* program_id, U8* accounts, U8** seeds) {
+            struct Instruction ix;
+            ix.program_id = program_id;
+            ix.accounts = accounts;
+            ix.accounts_len = 2;
+            ix.data_len = 0;
+            sol_invoke(ix, accounts, 2);
+            return sol_invoke_signed(ix, accounts, 2, seeds, 1);
+        }
+    "#;
+
+    /// Last instruction writing `reg` before the instruction at `end`.
+    fn last_write(instructions: &[BpfInstruction], end: usize, reg: u8) -> BpfInstruction {
+        *instructions[..end]
+            .iter()
+            .rev()
+            .find(|i| i.dst_reg == reg && i.opcode & 0x07 != 0x03)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_invoke_lowers_to_cpi_syscall() {
+        assert_eq!(syscall_id("sol_invoke_signed_c"), 0xa22b9c85);
+
+        let instructions = generate(CPI_PROGRAM, CompileTarget::SolanaBpf);
+        let invoke = syscall_id("sol_invoke_signed_c") as i32;
+        assert_eq!(helper_calls(&instructions), vec![invoke, invoke]);
+
+        let calls: Vec<usize> = instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, i)| i.opcode == 0x85)
+            .map(|(pc, _)| pc)
+            .collect();
+
+        // sol_invoke: instruction, account infos and count, then no seeds
+        for reg in 1..=3 {
+            assert_eq!(last_write(&instructions, calls[0], reg).opcode, 0x79);
+        }
+        for reg in 4..=5 {
+            let write = last_write(&instructions, calls[0], reg);
+            assert_eq!((write.opcode, write.immediate), (0xb7, 0));
+        }
+
+        // sol_invoke_signed: all five arguments come from the caller
+        for reg in 1..=5 {
+            assert_eq!(last_write(&instructions, calls[1], reg).opcode, 0x79);
+        }
+    }
+
+    #[test]
+    fn test_invoke_is_only_available_on_solana() {
+        for target in [CompileTarget::LinuxBpf, CompileTarget::BpfVm] {
+            let options = CompileOptions {
+                target,
+                ..CompileOptions::default()
+            };
+            let error = Compiler::new().compile(CPI_PROGRAM, &options).unwrap_err();
+            assert!(
+                matches!(
+                    &error,
+                    CompileError::CodeGenError(CodeGenError::InvalidInstruction(message))
+                        if message == "sol_invoke is only available on solana-bpf"
+                ),
+                "{:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn test_invoke_passes_struct_address() {
+        let instructions = generate(CPI_PROGRAM, CompileTarget::SolanaBpf);
+        let call = instructions.iter().position(|i| i.opcode == 0x85).unwrap();
+
+        // The first staged argument is the address of `ix` on the stack
+        let load = last_write(&instructions, call, 1);
+        let store = instructions[..call]
+            .iter()
+            .rposition(|i| i.opcode == 0x7b && i.dst_reg == 10 && i.offset == load.offset)
+            .unwrap();
+        let address = last_write(&instructions, store, instructions[store].src_reg);
+        assert_eq!(address.opcode, 0x07); // add64 reg, offset from r10
+    }
+
+    #[test]
+    fn test_program_address_builtins_lower_to_pda_syscalls() {
+        assert_eq!(syscall_id("sol_create_program_address"), 0x9377323c);
+        assert_eq!(syscall_id("sol_try_find_program_address"), 0x48504a38);
+
+        let source = r#"
+            U64 entrypoint(U8** seeds, U8* program_id) {
+                U8 address[32];
+                U8 bump[1];
+                if (sol_create_program_address(seeds, 2, program_id, address) != 0) {
+                    return 1;
+                }
+                return sol_try_find_program_address(seeds, 2, program_id, address, bump);
+            }
+        "#;
+        let instructions = generate(source, CompileTarget::SolanaBpf);
+        assert_eq!(
+            helper_calls(&instructions),
+            vec![
+                syscall_id("sol_create_program_address") as i32,
+                syscall_id("sol_try_find_program_address") as i32,
+            ]
+        );
+
+        // The output buffer is the fourth argument: the address of `address`
+        let call = instructions.iter().position(|i| i.opcode == 0x85).unwrap();
+        let load = last_write(&instructions, call, 4);
+        assert_eq!((load.opcode, load.src_reg), (0x79, 10));
+        let store = instructions[..call]
+            .iter()
+            .rposition(|i| i.opcode == 0x7b && i.dst_reg == 10 && i.offset == load.offset)
+            .unwrap();
+        let reg = instructions[store].src_reg;
+        let address = instructions[..store]
+            .iter()
+            .rposition(|i| i.dst_reg == reg && i.opcode == 0xbf)
+            .unwrap();
+        assert_eq!(instructions[address].src_reg, 10); // mov reg, r10
+        assert_eq!(instructions[address + 1].opcode, 0x07); // add reg, offset
+    }
+
+    #[test]
+    fn test_program_address_builtins_are_only_available_on_solana() {
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            ..CompileOptions::default()
+        };
+        for builtin in ["sol_create_program_address", "sol_try_find_program_address"] {
+            let source = format!(
+                "U64 main() {{ U8 address[32]; return {}(0, 0, 0, address); }}",
+                builtin
+            );
+            let error = Compiler::new().compile(&source, &options).unwrap_err();
+            let expected = format!("{} is only available on solana-bpf", builtin);
+            assert!(
+                matches!(
+                    &error,
+                    CompileError::CodeGenError(CodeGenError::InvalidInstruction(message))
+                        if *message == expected
+                ),
+                "{:?}",
+                error
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod solana_input_tests {
+    use super::*;
+    use crate::pible::solana_bpf::MAX_PERMITTED_DATA_INCREASE;
+
+    enum Account<'a> {
+        Fresh {
+            is_signer: bool,
+            key: u8,
+            lamports: u64,
+            data: &'a [u8],
+        },
+        Duplicate(u8),
+    }
+
+    /// Serialize accounts and instruction data the way the runtime lays out
+    /// a program's input region.
+    fn serialize(accounts: &[Account], data: &[u8]) -> Vec<u8> {
+        let mut input = (accounts.len() as u64).to_le_bytes().to_vec();
+        for account in accounts {
+            match account {
+                Account::Fresh {
+                    is_signer,
+                    key,
+                    lamports,
+                    data,
+                } => {
+                    input.extend_from_slice(&[0xff, *is_signer as u8, 1, 0, 0, 0, 0, 0]);
+                    input.extend_from_slice(&[*key; 32]);
+                    input.extend_from_slice(&[0xee; 32]); // owner
+                    input.extend_from_slice(&lamports.to_le_bytes());
+                    input.extend_from_slice(&(data.len() as u64).to_le_bytes());
+                    input.extend_from_slice(data);
+                    input.resize(input.len() + MAX_PERMITTED_DATA_INCREASE as usize, 0);
+                    input.resize(input.len().next_multiple_of(8), 0);
+                    input.extend_from_slice(&7u64.to_le_bytes()); // rent epoch
+                }
+                Account::Duplicate(index) => {
+                    input.extend_from_slice(&[*index, 0, 0, 0, 0, 0, 0, 0]);
+                }
+            }
+        }
+        input.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&[0xaa; 32]); // program id
+        input
+    }
+
+    fn run(body: &str, input: &[u8]) -> i64 {
+        let source = format!(
+            r#"
+            struct AccountInfo {{
+                U8* key;
+                U64* lamports;
+                U64 data_len;
+                U8* data;
+                U8* owner;
+                U64 rent_epoch;
+                Bool is_signer;
+                Bool is_writable;
+                Bool executable;
+            }};
+
+            export U64 entrypoint(AccountInfo* accounts, U64 count, U8* data, U64 data_len) {{
+                {}
+            }}
+            "#,
+            body
+        );
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..Default::default()
+        };
+        let bytecode = Compiler::new()
+            .compile(&source, &options)
+            .expect("Compilation should succeed");
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        let mut vm = BpfVm::with_input(&instructions, input).unwrap();
+        vm.execute().expect("Execution should succeed");
+        vm.registers()[0]
+    }
+
+    fn sample_input() -> Vec<u8> {
+        serialize(
+            &[
+                Account::Fresh {
+                    is_signer: true,
+                    key: 1,
+                    lamports: 1000,
+                    data: &[9, 8, 7],
+                },
+                Account::Fresh {
+                    is_signer: false,
+                    key: 2,
+                    lamports: 50,
+                    data: &[],
+                },
+                Account::Duplicate(0),
+            ],
+            &[4, 5],
+        )
+    }
+
+    #[test]
+    fn test_entrypoint_sees_account_count() {
+        assert_eq!(run("return count;", &sample_input()), 3);
+    }
+
+    #[test]
+    fn test_with_input_follows_entrypoint_abi() {
+        let input = serialize(&[Account::Duplicate(0), Account::Duplicate(0)], &[1, 2, 3]);
+        // Return the input's first word, the account count, read through r1
+        let instructions = [
+            BpfInstruction::new(0x79, 0, 1, 0, 0), // ldxdw r0, [r1+0]
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        let mut vm = BpfVm::with_input(&instructions, &input).unwrap();
+        let address = vm.registers()[1] as usize;
+        assert_eq!(address % 8, 0);
+        assert_eq!(vm.registers()[2], input.len() as i64);
+        assert_eq!(&vm.memory[address..address + input.len()], &input[..]);
+        vm.execute().unwrap();
+        assert_eq!(vm.registers()[0], 2);
+
+        let too_large = vec![0; vm.config().memory_size + 1];
+        assert!(BpfVm::with_input(&instructions, &too_large).is_err());
+    }
+
+    #[test]
+    fn test_entrypoint_sees_account_fields_and_data() {
+        let input = sample_input();
+        assert_eq!(run("return accounts[1].lamports[0];", &input), 50);
+        assert_eq!(
+            run("return accounts[0].data[2] + accounts[0].data_len;", &input),
+            10
+        );
+        assert_eq!(
+            run(
+                "return accounts[0].is_signer * 10 + accounts[1].is_signer;",
+                &input
+            ),
+            10
+        );
+        assert_eq!(
+            run(
+                "return accounts[1].key[31] + accounts[1].rent_epoch;",
+                &input
+            ),
+            9
+        );
+        assert_eq!(run("return data[1] * 10 + data_len;", &input), 52);
+    }
+
+    #[test]
+    fn test_duplicate_account_repeats_original() {
+        let input = sample_input();
+        assert_eq!(run("return accounts[2].lamports[0];", &input), 1000);
+        assert_eq!(run("return accounts[2].key[0];", &input), 1);
+    }
+
+    #[test]
+    fn test_too_many_accounts_is_rejected() {
+        let input = 1000u64.to_le_bytes();
+        assert_eq!(run("return 0;", &input), 2 << 32);
+    }
+}
+
+#[cfg(test)]
+mod compute_unit_tests {
+    use super::*;
+    use crate::pible::solana_bpf::{estimate_compute_units, DEFAULT_COMPUTE_UNIT_LIMIT};
+
+    fn generate(source: &str) -> Vec<BpfInstruction> {
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let ast = parser.parse().unwrap();
+        CodeGen::for_target(CompileTarget::SolanaBpf)
+            .generate(&ast)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_straight_line_program_costs_one_unit_per_instruction() {
+        let instructions = generate("U64 entrypoint() { return 1 + 2; }");
+        assert_eq!(
+            estimate_compute_units(&instructions),
+            instructions.len() as u64
+        );
+        assert!(estimate_compute_units(&instructions) < 20);
+    }
+
+    #[test]
+    fn test_syscalls_add_their_cost() {
+        let plain = generate("U0 entrypoint() { }");
+        let logging = generate(r#"U0 entrypoint() { PrintF("hi"); }"#);
+        assert!(estimate_compute_units(&logging) > estimate_compute_units(&plain) + 100);
+    }
+
+    #[test]
+    fn test_loops_multiply_their_body() {
+        let source = r#"
+            U64 entrypoint() {
+                U64 total = 0;
+                for (U64 i = 0; i < 10; i = i + 1) {
+                    total = total + i;
+                }
+                return total;
+            }
+        "#;
+        let instructions = generate(source);
+        assert!(estimate_compute_units(&instructions) > 100 * 5);
+    }
+
+    #[test]
+    fn test_large_program_exceeds_default_limit() {
+        let mut body = String::new();
+        for i in 0..20 {
+            body.push_str(&format!("total = total + {} * j;\n", i));
+        }
+        let source = format!(
+            r#"
+            U64 entrypoint() {{
+                U64 total = 0;
+                for (U64 i = 0; i < 1000; i = i + 1) {{
+                    for (U64 j = 0; j < 1000; j = j + 1) {{
+                        {}
+                    }}
+                }}
+                return total;
+            }}
+            "#,
+            body
+        );
+        let instructions = generate(&source);
+        assert!(estimate_compute_units(&instructions) > DEFAULT_COMPUTE_UNIT_LIMIT);
+    }
+}
+
+#[cfg(test)]
+mod compile_and_run_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+
+    #[test]
+    fn test_returns_exit_code() {
+        let result = Compiler::new()
+            .compile_and_run("U0 main() { return 7; }", &CompileOptions::default())
+            .expect("Program should compile and run");
+        assert_eq!(result.exit_code, 7);
+        assert!(result.compute_units > 0);
+    }
+
+    #[test]
+    fn test_runs_calls_and_string_literals() {
+        let source = r#"
+            U64 square(U64 x) { return x * x; }
+            U64 main() {
+                PrintF("squaring\n");
+                return square(6) + 1;
+            }
+        "#;
+        let result = Compiler::new()
+            .compile_and_run(source, &CompileOptions::default())
+            .unwrap();
+        assert_eq!(result.exit_code, 37);
+    }
+
+    #[test]
+    fn test_runtime_errors_are_reported() {
+        let source = "U64 main() { U64 zero = 0; return 1 / zero; }";
+        let result = Compiler::new().compile_and_run(source, &CompileOptions::default());
+        assert!(matches!(result, Err(CompileError::VmExecutionError(_))));
+    }
+}
+
+#[cfg(test)]
+mod include_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+    use crate::pible::preprocessor::PreprocessError;
+    use std::fs;
+
+    fn run_file(path: &std::path::Path) -> Result<i64, CompileError> {
+        let output = path.with_extension("bpf");
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            output_path: Some(output.display().to_string()),
+            ..Default::default()
+        };
+        Compiler::new().compile_file(path.to_str().unwrap(), &options)?;
+
+        let bytecode = fs::read(&output).unwrap();
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+        let mut vm = BpfVm::new(&instructions);
+        vm.execute().unwrap();
+        Ok(vm.registers()[0])
+    }
+
+    #[test]
+    fn test_main_file_calls_function_from_include() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        fs::write(
+            dir.path().join("lib/math.hc"),
+            "U64 triple(U64 x) {\n    return x * 3;\n}\n",
+        )
+        .unwrap();
+        let main = dir.path().join("main.hc");
+        fs::write(
+            &main,
+            "#include \"lib/math.hc\"\n\nU64 main() {\n    return triple(14);\n}\n",
+        )
+        .unwrap();
+
+        assert_eq!(run_file(&main).unwrap(), 42);
+    }
+
+    #[test]
+    fn test_nested_includes_resolve_relative_to_including_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        fs::write(dir.path().join("lib/base.hc"), "U64 one() { return 1; }\n").unwrap();
+        fs::write(
+            dir.path().join("lib/two.hc"),
+            "#include \"base.hc\"\nU64 two() { return one() + one(); }\n",
+        )
+        .unwrap();
+        let main = dir.path().join("main.hc");
+        fs::write(
+            &main,
+            "#include \"lib/two.hc\"\nU64 main() { return two(); }\n",
+        )
+        .unwrap();
+
+        assert_eq!(run_file(&main).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_dependencies_list_transitive_includes() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        fs::write(dir.path().join("lib/base.hc"), "U64 one() { return 1; }\n").unwrap();
+        fs::write(
+            dir.path().join("lib/two.hc"),
+            "#include \"base.hc\"\nU64 two() { return one() + one(); }\n",
+        )
+        .unwrap();
+        let main = dir.path().join("main.hc");
+        fs::write(
+            &main,
+            "#include \"lib/two.hc\"\n#include \"lib/base.hc\"\nU64 main() { return two(); }\n",
+        )
+        .unwrap();
+        let output = dir.path().join("main.bpf");
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            output_path: Some(output.display().to_string()),
+            ..Default::default()
+        };
+
+        let rule = Compiler::new()
+            .dependencies(main.to_str().unwrap(), &options)
+            .unwrap();
+        let lib = dir.path().join("lib");
+        assert_eq!(
+            rule,
+            format!(
+                "{}: {} {} {}\n",
+                output.display(),
+                main.display(),
+                lib.join("two.hc").display(),
+                lib.join("base.hc").display()
+            )
+        );
+        // Nothing is compiled
+        assert!(!output.exists());
+    }
+
+    #[test]
+    fn test_missing_include_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.hc");
+        fs::write(&main, "U64 x;\n#include \"nowhere.hc\"\n").unwrap();
+
+        match run_file(&main) {
+            Err(CompileError::IncludeError(PreprocessError::MissingInclude {
+                line, path, ..
+            })) => {
+                assert_eq!(line, 2);
+                assert_eq!(path, "nowhere.hc");
+            }
+            other => panic!("expected a missing include, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_include_cycle_is_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.hc"), "#include \"b.hc\"\n").unwrap();
+        fs::write(dir.path().join("b.hc"), "#include \"a.hc\"\n").unwrap();
+
+        match run_file(&dir.path().join("a.hc")) {
+            Err(CompileError::IncludeError(PreprocessError::IncludeCycle(cycle))) => {
+                assert_eq!(cycle.len(), 3);
+                assert!(cycle[0].ends_with("a.hc") && cycle[2].ends_with("a.hc"));
+            }
+            other => panic!("expected an include cycle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_errors_in_included_file_name_that_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("helper.hc"),
+            "U64 helper() {\n    return 1;\n}\nU0 broken( {\n}\n",
+        )
+        .unwrap();
+        let main = dir.path().join("main.hc");
+        fs::write(&main, "#include \"helper.hc\"\nU64 main() { return 0; }\n").unwrap();
+
+        match run_file(&main) {
+            Err(error @ CompileError::ParseError { line, .. }) => {
+                assert_eq!(line, 4);
+                let rendered = error.to_string();
+                assert!(rendered.contains("helper.hc, line 4, column 12"));
+                assert!(rendered.contains("4 | U0 broken( {"));
+            }
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod macro_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+    use crate::pible::preprocessor::{preprocess, PreprocessError};
+
+    #[test]
+    fn test_object_macro_as_array_size() {
+        let source = r#"
+            #define MAX_SIGNERS 11
+            U64 main() {
+                U64 signers[MAX_SIGNERS];
+                signers[MAX_SIGNERS - 1] = 5;
+                return signers[10] + MAX_SIGNERS;
+            }
+        "#;
+        assert_eq!(run(source), 16);
+    }
+
+    #[test]
+    fn test_function_macro_in_expression() {
+        let source = r#"
+            #define SQUARE(x) ((x) * (x))
+            #define BPS(amount, rate) (amount * rate / 10000)
+            U64 main() {
+                U64 n = 3;
+                return SQUARE(n + 1) + BPS(SQUARE(100), 30);
+            }
+        "#;
+        assert_eq!(run(source), 16 + 30);
+    }
+
+    #[test]
+    fn test_undef_and_redefine() {
+        let expanded = preprocess(
+            "#define LIMIT 1\nA = LIMIT;\n#undef LIMIT\nB = LIMIT;\n#define LIMIT 2\nC = LIMIT;\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(expanded.source, "\nA = 1;\n\nB = LIMIT;\n\nC = 2;\n");
+    }
+
+    #[test]
+    fn test_recursive_macros_terminate() {
+        let expanded = preprocess(
+            "#define A B + 1\n#define B A * 2\n#define SELF SELF\nx = A; y = SELF;\n",
+            None,
+        )
+        .unwrap();
+        assert_eq!(expanded.origin(4).line, 4);
+        assert_eq!(
+            expanded.source.lines().nth(3),
+            Some("x = A * 2 + 1; y = SELF;")
+        );
+    }
+
+    #[test]
+    fn test_strings_comments_and_partial_names_are_untouched() {
+        let expanded =
+            preprocess("#define N 4\nPrintF(\"N\"); N_MAX = N2 + N; // N\n", None).unwrap();
+        assert_eq!(
+            expanded.source.lines().nth(1),
+            Some("PrintF(\"N\"); N_MAX = N2 + 4; // N")
+        );
+    }
+
+    #[test]
+    fn test_wrong_argument_count_is_an_error() {
+        let source = "#define ADD(a, b) (a + b)\nU64 main() { return ADD(1); }\n";
+        let error = Compiler::new()
+            .compile(source, &CompileOptions::default())
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CompileError::IncludeError(PreprocessError::MacroArguments {
+                line: 2,
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod formatter_tests {
+    use crate::pible::formatter::format_source;
+
+    #[test]
+    fn test_format_canonicalizes_layout() {
+        let messy = "#define LIMIT 10\n\
+                     // Sum the numbers below LIMIT\n\
+                     U64 sum(U64 *values,U64 n){U64 total=0;   // running total\n\
+                     for(U64 i=0;i<n;i=i+1){if(values[i]>LIMIT){continue;}\n\n\n\
+                     total=total+values[i]*-1;}\n\
+                     if (!n) { return 0; }\n\
+                     else{return total;}\n\
+                     }\n";
+        let expected = "#define LIMIT 10\n\
+                        // Sum the numbers below LIMIT\n\
+                        U64 sum(U64* values, U64 n) {\n    \
+                        U64 total = 0; // running total\n    \
+                        for (U64 i = 0; i < n; i = i + 1) {\n        \
+                        if (values[i] > LIMIT) {\n            \
+                        continue;\n        \
+                        }\n\n        \
+                        total = total + values[i] * -1;\n    \
+                        }\n    \
+                        if (!n) {\n        \
+                        return 0;\n    \
+                        } else {\n        \
+                        return total;\n    \
+                        }\n\
+                        }\n";
+
+        let formatted = format_source(messy).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_keeps_block_comments_and_pointer_declarations() {
+        let source = "/*\n * Header\n */\nstruct Account {U8* key;U64 lamports;};\n\
+                      U0 f(Account *acc) { acc->lamports = acc->lamports - 1; }\n";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted,
+            "/*\n * Header\n */\nstruct Account {\n    U8* key;\n    U64 lamports;\n};\n\
+             U0 f(Account* acc) {\n    acc->lamports = acc->lamports - 1;\n}\n"
+        );
+        assert_eq!(format_source(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_attaches_increments() {
+        let source = "U0 f() { U64 a , *b; for (;;a ++) { a+=1; b = -- a; } }\n";
+        let formatted = format_source(source).unwrap();
+        assert_eq!(
+            formatted,
+            "U0 f() {\n    U64 a, *b;\n    for (;; a++) {\n        a += 1;\n        b = --a;\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_spaces_bitwise_operators_but_not_address_of() {
+        let formatted = format_source("U0 f() { x = a&b|c ^ d; p = & x; y = *p; }").unwrap();
+        assert_eq!(
+            formatted,
+            "U0 f() {\n    x = a & b | c ^ d;\n    p = &x;\n    y = *p;\n}\n"
+        );
+        assert!(format_source("U0 f() { x = a @ b; }").is_err());
+    }
+}
+
+#[cfg(test)]
+mod btf_tests {
+    use super::*;
+    use crate::pible::btf::{btf_consts::*, generate_btf, parse_btf};
+    use crate::pible::elf::find_section;
+
+    const SOURCE: &str = "struct Point { U64 x; I32 y; U8* name; };\n\
+                          U64 sum(Point* p, U8 scale) { return p->x * scale; }\n";
+
+    #[test]
+    fn test_btf_header_and_struct_entry() {
+        let ast = Compiler::new().parse(SOURCE).unwrap();
+        let blob = generate_btf(&ast).unwrap();
+        assert_eq!(u16::from_le_bytes([blob[0], blob[1]]), 0xeb9f);
+        assert_eq!(blob[2], 1);
+
+        let types = parse_btf(&blob).unwrap();
+        let point = types
+            .iter()
+            .find(|ty| ty.kind == BTF_KIND_STRUCT && ty.name == "Point")
+            .expect("struct Point is described");
+        assert_eq!(point.size_or_type, 24);
+        let names: Vec<&str> = point
+            .members
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["x", "y", "name"]);
+        let name = &types[point.members[2].1 as usize - 1];
+        assert_eq!(name.kind, BTF_KIND_PTR);
+        assert_eq!(types[name.size_or_type as usize - 1].name, "U8");
+
+        let func = types
+            .iter()
+            .find(|ty| ty.kind == BTF_KIND_FUNC)
+            .expect("function is described");
+        assert_eq!(func.name, "sum");
+        let proto = &types[func.size_or_type as usize - 1];
+        assert_eq!(proto.kind, BTF_KIND_FUNC_PROTO);
+        let params: Vec<&str> = proto
+            .members
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(params, ["p", "scale"]);
+        assert_eq!(types[proto.size_or_type as usize - 1].name, "U64");
+    }
+
+    #[test]
+    fn test_elf_output_embeds_btf() {
+        let options = CompileOptions {
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        let object = Compiler::new().compile(SOURCE, &options).unwrap();
+        let btf = find_section(&object, ".BTF")
+            .unwrap()
+            .expect(".BTF section");
+        assert!(parse_btf(btf)
+            .unwrap()
+            .iter()
+            .any(|ty| ty.kind == BTF_KIND_STRUCT && ty.name == "Point"));
+    }
+}
+
+#[cfg(test)]
+mod map_tests {
+    use super::*;
+    use crate::pible::codegen::{CodeGenError, MapDefinition};
+    use crate::pible::elf::find_section;
+    use crate::pible::formatter::format_source;
+
+    const SOURCE: &str = "struct Stats { U64 packets; U32 bytes; };\n\
+                          map hash<U32, U64> counts[1024];\n\
+                          map array<U32, Stats> stats[16];\n\
+                          U64 main() {\n\
+                              U32 key[1];\n\
+                              key[0] = 7;\n\
+                              U64* value = map_lookup_elem(counts, key);\n\
+                              if (value == 0) { return 0; }\n\
+                              return value[0];\n\
+                          }\n";
+
+    fn generate(source: &str) -> Result<(CodeGen, Vec<BpfInstruction>), CodeGenError> {
+        let ast = Compiler::new().parse(source).unwrap();
+        let mut codegen = CodeGen::new();
+        let instructions = codegen.generate(&ast)?;
+        Ok((codegen, instructions))
+    }
+
+    #[test]
+    fn test_declared_maps_have_their_sizes() {
+        let (codegen, _) = generate(SOURCE).unwrap();
+        assert_eq!(
+            codegen.maps(),
+            [
+                MapDefinition {
+                    name: "counts".to_string(),
+                    map_type: MapDefinition::HASH,
+                    key_size: 4,
+                    value_size: 8,
+                    max_entries: 1024,
+                },
+                MapDefinition {
+                    name: "stats".to_string(),
+                    map_type: MapDefinition::ARRAY,
+                    key_size: 4,
+                    value_size: 16,
+                    max_entries: 16,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_map_lookup_loads_the_map_and_calls_the_helper() {
+        let (codegen, instructions) = generate(SOURCE).unwrap();
+        let &[(load, ref name)] = codegen.map_relocations() else {
+            panic!("expected one map reference");
+        };
+        assert_eq!(name, "counts");
+        assert_eq!(instructions[load].opcode, 0x18);
+        assert_eq!(instructions[load].src_reg, 1);
+        assert!(instructions[load..]
+            .iter()
+            .any(|i| i.opcode == 0x85 && i.src_reg == 0 && i.immediate == 1));
+    }
+
+    #[test]
+    fn test_elf_maps_section_and_relocation() {
+        let options = CompileOptions {
+            output_format: OutputFormat::Elf,
+            ..Default::default()
+        };
+        let object = Compiler::new().compile(SOURCE, &options).unwrap();
+
+        let maps = find_section(&object, "maps")
+            .unwrap()
+            .expect("maps section");
+        let words: Vec<u32> = maps
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(words, [1, 4, 8, 1024, 0, 2, 4, 16, 16, 0]);
+
+        let strtab = find_section(&object, ".strtab").unwrap().unwrap();
+        for name in ["counts", "stats"] {
+            assert!(strtab
+                .split(|&b| b == 0)
+                .any(|entry| entry == name.as_bytes()));
+        }
+
+        // The rodata-free program's only relocation targets the map load
+        let (codegen, _) = generate(SOURCE).unwrap();
+        let relocations = find_section(&object, ".relsocket").unwrap().unwrap();
+        assert_eq!(relocations.len(), 16);
+        let offset = u64::from_le_bytes(relocations[..8].try_into().unwrap());
+        assert_eq!(offset, codegen.map_relocations()[0].0 as u64 * 8);
+    }
+
+    #[test]
+    fn test_array_map_needs_four_byte_key() {
+        let Err(error) = generate("map array<U64, U64> bad[4];") else {
+            panic!("an 8-byte array map key should be rejected");
+        };
+        assert!(error.to_string().contains("4-byte key"));
+    }
+
+    #[test]
+    fn test_formatter_keeps_map_type_brackets() {
+        assert_eq!(
+            format_source("map  hash < U32 , U64 >counts [ 64 ] ;").unwrap(),
+            "map hash<U32, U64> counts[64];\n"
+        );
+    }
+}
+
+#[cfg(test)]
+mod program_type_tests {
+    use super::*;
+    use crate::pible::elf::parse_sections;
+
+    fn section_names(program_type: ProgramType) -> Vec<String> {
+        let options = CompileOptions {
+            output_format: OutputFormat::Elf,
+            program_type,
+            ..Default::default()
+        };
+        let object = Compiler::new()
+            .compile("U32 main() { return 2; }", &options)
+            .unwrap();
+        parse_sections(&object)
+            .unwrap()
+            .into_iter()
+            .map(|section| section.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_program_type_selects_section() {
+        assert!(section_names(ProgramType::Xdp).contains(&"xdp".to_string()));
+        let kprobe = section_names(ProgramType::Kprobe("do_sys_open".to_string()));
+        assert!(kprobe.contains(&"kprobe/do_sys_open".to_string()));
+        assert!(section_names(ProgramType::default()).contains(&"socket".to_string()));
+    }
+
+    #[test]
+    fn test_parse_program_type() {
+        assert_eq!(ProgramType::parse("xdp"), Some(ProgramType::Xdp));
+        assert_eq!(
+            ProgramType::parse("tracepoint:syscalls/sys_enter_open"),
+            Some(ProgramType::Tracepoint(
+                "syscalls/sys_enter_open".to_string()
+            ))
+        );
+        assert_eq!(
+            ProgramType::Tracepoint("syscalls/sys_enter_open".to_string()).section_name(),
+            "tracepoint/syscalls/sys_enter_open"
+        );
+        assert_eq!(ProgramType::parse("kprobe:"), None);
+        assert_eq!(ProgramType::parse("uprobe"), None);
+    }
+
+    #[test]
+    fn test_xdp_context_struct_is_predeclared() {
+        let source = "U32 main(XdpMd* ctx) { return ctx->data_end; }";
+        let ast = Compiler::new().parse(source).unwrap();
+        let mut codegen = CodeGen::new().with_program_type(ProgramType::Xdp);
+        let instructions = codegen.generate(&ast).unwrap();
+        // `data_end` is the second U32 of `struct xdp_md`
+        assert!(instructions
+            .iter()
+            .any(|i| i.opcode & 0x07 == 0x01 && i.offset == 4));
+    }
+
+    #[test]
+    fn test_context_register_survives_top_level_code() {
+        let source = "I64 counter = 5;\nU32 main(XdpMd* ctx) { return ctx->data; }";
+        let ast = Compiler::new().parse(source).unwrap();
+        let mut codegen = CodeGen::new().with_program_type(ProgramType::Xdp);
+        let instructions = codegen.generate(&ast).unwrap();
+
+        let entry = codegen.functions()["main"];
+        let spill = &instructions[0];
+        assert_eq!((spill.opcode, spill.dst_reg, spill.src_reg), (0x7b, 10, 1));
+        let reload = &instructions[entry - 1];
+        assert_eq!(
+            (reload.opcode, reload.dst_reg, reload.src_reg),
+            (0x79, 1, 10)
+        );
+        assert_eq!(reload.offset, spill.offset);
+    }
+}
+
+#[cfg(test)]
+mod float_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+    use crate::pible::parser::NodeType;
+
+    #[test]
+    fn test_float_literal_is_distinct_from_integers() {
+        let tokens = Lexer::new("1.5 15").scan_tokens().unwrap();
+        assert_eq!(tokens[0].token_type, TokenType::FloatLiteral);
+        assert_eq!(tokens[1].token_type, TokenType::NumberLiteral);
+
+        let ast = Compiler::new().parse("F64 x = 1.5;").unwrap();
+        let initializer = &ast.children[0].children[1];
+        assert_eq!(initializer.node_type, NodeType::FloatLiteral);
+        assert_eq!(initializer.value.as_deref(), Some("1.5"));
+    }
+
+    #[test]
+    fn test_float_literal_is_not_truncated() {
+        assert_eq!(run("Bool main() { F64 x = 1.5; return x > 1; }"), 1);
+        assert_eq!(
+            run("I64 main() { F64 x = 1.5; I64 n = x + x; return n; }"),
+            3
+        );
+    }
+
+    #[test]
+    fn test_float_add() {
+        let source = "Bool main() { F64 x = 1.5; F64 y = x + 2.25; return y == 3.75; }";
+        assert_eq!(run(source), 1);
+        let source = "I64 main() { F64 y = 1.5 + 2.25; I64 n = y + 0.25; return n; }";
+        assert_eq!(run(source), 4);
+    }
+
+    #[test]
+    fn test_mixed_arithmetic_converts_integers() {
+        let source = "I64 main() { F64 x = 2.5; I64 n = x + 1; return n; }";
+        assert_eq!(run(source), 3);
+        assert_eq!(run("Bool main() { F64 x = 0.5; return x < 1; }"), 1);
+    }
+
+    #[test]
+    fn test_float_multiplication_is_rejected() {
+        let error = Compiler::new()
+            .compile(
+                "F64 main() { F64 x = 1.5; return x * 2.0; }",
+                &CompileOptions::default(),
+            )
+            .unwrap_err();
+        assert!(matches!(error, CompileError::UnsupportedFloat(_)));
+    }
+}
+
+#[cfg(test)]
+mod ternary_tests {
+    use super::*;
+    use crate::pible::parser::NodeType;
+
+    #[test]
+    fn test_ternary_selects_branch() {
+        let program = |x: i32| {
+            format!(
+                "I64 pick(I64 x) {{ return x > 0 ? 1 : 2; }}\nI64 main() {{ return pick({}); }}",
+                x
+            )
+        };
+        assert_eq!(run(&program(5)), 1);
+        assert_eq!(run(&program(0)), 2);
+        assert_eq!(run(&program(-3)), 2);
+    }
+
+    #[test]
+    fn test_ternary_nests_to_the_right() {
+        let source = "I64 sign(I64 x) { return x > 0 ? 1 : x < 0 ? -1 : 0; }\n\
+                      I64 main() { return sign(-4) * 100 + sign(0) * 10 + sign(9); }";
+        assert_eq!(run(source), -99);
+    }
+
+    #[test]
+    fn test_ternary_binds_looser_than_or() {
+        let ast = Compiler::new().parse("I64 x = 0 || 1 ? 2 : 3;").unwrap();
+        let ternary = &ast.children[0].children[1];
+        assert_eq!(ternary.node_type, NodeType::Ternary);
+        assert_eq!(ternary.children[0].value.as_deref(), Some("||"));
+        assert_eq!(
+            run("I64 main() { I64 y = 7; return y == 7 ? y + 1 : y - 1; }"),
+            8
+        );
+    }
+}
+
+#[cfg(test)]
+mod sizeof_tests {
+    use super::*;
+    use crate::pible::formatter::format_source;
+
+    #[test]
+    fn test_sizeof_struct_matches_layout() {
+        let source = "struct TokenAccount { U8 mint[32]; U8 owner[32]; U64 amount; U8 state; };\n\
+                      I64 main() { return sizeof(struct TokenAccount); }";
+        // 32 + 32 + 8 + 1, padded to the 8-byte alignment of `amount`
+        assert_eq!(run(source), 80);
+        let bare = source.replace("struct TokenAccount)", "TokenAccount)");
+        assert_eq!(run(&bare), 80);
+    }
+
+    #[test]
+    fn test_sizeof_types() {
+        assert_eq!(run("I64 main() { return sizeof(U8); }"), 1);
+        assert_eq!(run("I64 main() { return sizeof(U8*); }"), 8);
+        assert_eq!(run("I64 main() { return sizeof(U32[3]); }"), 12);
+        let source = "struct Point { I64 x; I64 y; };\n\
+                      I64 main() { return sizeof(Point*) + sizeof(struct Point*); }";
+        assert_eq!(run(source), 16);
+    }
+
+    #[test]
+    fn test_sizeof_expressions() {
+        assert_eq!(run("I64 main() { U64 a[4]; return sizeof(a); }"), 32);
+        assert_eq!(
+            run("I64 main() { U16 h = 3; return sizeof(h) + sizeof h; }"),
+            4
+        );
+        assert_eq!(
+            run("I64 main() { U8* p; return sizeof(p[0]) + sizeof(p); }"),
+            9
+        );
+    }
+
+    #[test]
+    fn test_sizeof_formatting() {
+        assert_eq!(
+            format_source("I64 n = sizeof ( U8* ) ;").unwrap(),
+            "I64 n = sizeof(U8*);\n"
+        );
+    }
+}
+
+#[cfg(test)]
+mod compile_many_tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_many_matches_single_compiles() {
+        let sources = [
+            "I64 fib(I64 n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }\n\
+             I64 main() { return fib(10); }",
+            "struct Point { I64 x; I64 y; };\n\
+             I64 main() { struct Point p; p.x = 3; p.y = 4; return p.x * p.y; }",
+            "I64 main() { U8 buf[4]; I64 sum = 0; for (I64 i = 0; i < 4; i = i + 1) { buf[i] = i; sum = sum + buf[i]; } return sum; }",
+        ];
+        let compiler = Compiler::new();
+        for target in [
+            CompileTarget::LinuxBpf,
+            CompileTarget::SolanaBpf,
+            CompileTarget::BpfVm,
+        ] {
+            let options = CompileOptions {
+                target,
+                ..CompileOptions::default()
+            };
+            let batch = compiler.compile_many(&sources, &options).unwrap();
+            assert_eq!(batch.len(), sources.len());
+            for (source, bytecode) in sources.iter().zip(&batch) {
+                assert_eq!(*bytecode, compiler.compile(source, &options).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_many_stops_at_first_error() {
+        let sources = ["I64 main() { return 1; }", "I64 main() { return 1 @ 2; }"];
+        assert!(Compiler::new()
+            .compile_many(&sources, &CompileOptions::default())
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod encoding_tests {
+    use super::*;
+
+    /// xorshift64, so the round trip covers many instructions reproducibly
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    #[test]
+    fn test_instruction_round_trips_through_bytes() {
+        let mut state = 0x9e37_79b9_7f4a_7c15;
+        for _ in 0..10_000 {
+            let bits = next(&mut state);
+            let instruction = BpfInstruction::new(
+                bits as u8,
+                (bits >> 8) as u8 & 0x0f,
+                (bits >> 12) as u8 & 0x0f,
+                (bits >> 16) as i16,
+                (bits >> 32) as i32,
+            );
+            assert_eq!(
+                BpfInstruction::from_bytes(instruction.as_bytes()),
+                instruction
+            );
+        }
+    }
+
+    #[test]
+    fn test_instruction_encoding_edge_values() {
+        for (offset, immediate) in [(-1, -1), (i16::MIN, i32::MIN), (i16::MAX, i32::MAX), (0, 0)] {
+            let instruction = BpfInstruction::new(0x15, 10, 9, offset, immediate);
+            let bytes = instruction.as_bytes();
+            assert_eq!(bytes[1], 0x9a);
+            assert_eq!(BpfInstruction::from_bytes(bytes), instruction);
+        }
+        assert_eq!(
+            BpfInstruction::new(0xb7, 0, 0, -2, -3).as_bytes(),
+            [0xb7, 0x00, 0xfe, 0xff, 0xfd, 0xff, 0xff, 0xff]
+        );
+    }
+}
+
+#[cfg(all(test, feature = "riscv"))]
+mod riscv_tests {
+    use super::*;
+    use crate::pible::riscv::{registers::*, AluOp, BranchKind, Instruction, RiscVCodeGen};
+
+    fn compile(source: &str) -> Vec<u8> {
+        let options = CompileOptions {
+            target: CompileTarget::RiscV,
+            ..CompileOptions::default()
+        };
+        Compiler::new().compile(source, &options).unwrap()
+    }
+
+    fn decode(bytecode: &[u8]) -> Vec<Instruction> {
+        bytecode
+            .chunks_exact(4)
+            .map(|word| Instruction::decode(u32::from_le_bytes(word.try_into().unwrap())).unwrap())
+            .collect()
+    }
+
+    /// Run the program from its first instruction until it returns, with
+    /// just enough of RV64I for what the backend emits.
+    fn run(source: &str) -> i64 {
+        const STACK: usize = 4096;
+        const RETURN: u64 = !3;
+        let program = decode(&compile(source));
+        let mut regs = [0u64; 32];
+        let mut memory = vec![0u8; STACK];
+        regs[SP as usize] = STACK as u64;
+        regs[RA as usize] = RETURN;
+        let mut pc = 0u64;
+
+        for _ in 0..100_000 {
+            if pc == RETURN {
+                return regs[A0 as usize] as i64;
+            }
+            let mut next = pc + 4;
+            let write = |regs: &mut [u64; 32], rd: u8, value: u64| {
+                if rd != ZERO {
+                    regs[rd as usize] = value;
+                }
+            };
+            match program[pc as usize / 4] {
+                Instruction::Lui { rd, imm } => write(&mut regs, rd, ((imm as i64) << 12) as u64),
+                Instruction::Jal { rd, offset } => {
+                    write(&mut regs, rd, next);
+                    next = pc.wrapping_add(offset as i64 as u64);
+                }
+                Instruction::Jalr { rd, rs1, offset } => {
+                    let target = regs[rs1 as usize].wrapping_add(offset as i64 as u64);
+                    write(&mut regs, rd, next);
+                    next = target;
+                }
+                Instruction::Branch {
+                    kind,
+                    rs1,
+                    rs2,
+                    offset,
+                } => {
+                    let (a, b) = (regs[rs1 as usize], regs[rs2 as usize]);
+                    let taken = match kind {
+                        BranchKind::Eq => a == b,
+                        BranchKind::Ne => a != b,
+                        BranchKind::Lt => (a as i64) < b as i64,
+                        BranchKind::Ge => a as i64 >= b as i64,
+                        BranchKind::Ltu => a < b,
+                        BranchKind::Geu => a >= b,
+                    };
+                    if taken {
+                        next = pc.wrapping_add(offset as i64 as u64);
+                    }
+                }
+                Instruction::Ld { rd, rs1, offset } => {
+                    let addr = regs[rs1 as usize].wrapping_add(offset as i64 as u64) as usize;
+                    let value = u64::from_le_bytes(memory[addr..addr + 8].try_into().unwrap());
+                    write(&mut regs, rd, value);
+                }
+                Instruction::Sd { rs2, rs1, offset } => {
+                    let addr = regs[rs1 as usize].wrapping_add(offset as i64 as u64) as usize;
+                    memory[addr..addr + 8].copy_from_slice(&regs[rs2 as usize].to_le_bytes());
+                }
+                Instruction::OpImm { op, rd, rs1, imm } => {
+                    let value = alu(op, regs[rs1 as usize], imm as i64 as u64);
+                    write(&mut regs, rd, value);
+                }
+                Instruction::Addiw { rd, rs1, imm } => {
+                    let value = (regs[rs1 as usize] as i32).wrapping_add(imm);
+                    write(&mut regs, rd, value as i64 as u64);
+                }
+                Instruction::Op { op, rd, rs1, rs2 } => {
+                    let value = alu(op, regs[rs1 as usize], regs[rs2 as usize]);
+                    write(&mut regs, rd, value);
+                }
+            }
+            pc = next;
+        }
+        panic!("program didn't return");
+    }
+
+    fn alu(op: AluOp, a: u64, b: u64) -> u64 {
+        let shift = (b & 0x3f) as u32;
+        match op {
+            AluOp::Add => a.wrapping_add(b),
+            AluOp::Sub => a.wrapping_sub(b),
+            AluOp::Sll => a << shift,
+            AluOp::Slt => ((a as i64) < b as i64) as u64,
+            AluOp::Sltu => (a < b) as u64,
+            AluOp::Xor => a ^ b,
+            AluOp::Srl => a >> shift,
+            AluOp::Sra => ((a as i64) >> shift) as u64,
+            AluOp::Or => a | b,
+            AluOp::And => a & b,
+        }
+    }
+
+    #[test]
+    fn test_riscv_add_and_return_encodings() {
+        let bytecode = compile("I64 add(I64 a, I64 b) { return a + b; }");
+        let op_imm = |rd, rs1, imm| Instruction::OpImm {
+            op: AluOp::Add,
+            rd,
+            rs1,
+            imm,
+        };
+        let (s1, s2) = (9, 18);
+        let expected = vec![
+            // Prologue: 16-byte header, two locals and two saved temporaries
+            op_imm(SP, SP, -48),
+            Instruction::Sd {
+                rs2: RA,
+                rs1: SP,
+                offset: 40,
+            },
+            Instruction::Sd {
+                rs2: S0,
+                rs1: SP,
+                offset: 32,
+            },
+            op_imm(S0, SP, 48),
+            Instruction::Sd {
+                rs2: s1,
+                rs1: S0,
+                offset: -40,
+            },
+            Instruction::Sd {
+                rs2: s2,
+                rs1: S0,
+                offset: -48,
+            },
+            // Spill the parameters, add them and return
+            Instruction::Sd {
+                rs2: A0,
+                rs1: S0,
+                offset: -24,
+            },
+            Instruction::Sd {
+                rs2: A0 + 1,
+                rs1: S0,
+                offset: -32,
+            },
+            Instruction::Ld {
+                rd: s1,
+                rs1: S0,
+                offset: -24,
+            },
+            Instruction::Ld {
+                rd: s2,
+                rs1: S0,
+                offset: -32,
+            },
+            Instruction::Op {
+                op: AluOp::Add,
+                rd: s1,
+                rs1: s1,
+                rs2: s2,
+            },
+            op_imm(A0, s1, 0),
+            Instruction::Jal {
+                rd: ZERO,
+                offset: 8,
+            },
+            op_imm(A0, ZERO, 0),
+            // Epilogue
+            Instruction::Ld {
+                rd: s1,
+                rs1: S0,
+                offset: -40,
+            },
+            Instruction::Ld {
+                rd: s2,
+                rs1: S0,
+                offset: -48,
+            },
+            Instruction::Ld {
+                rd: RA,
+                rs1: S0,
+                offset: -8,
+            },
+            op_imm(T0, S0, 0),
+            Instruction::Ld {
+                rd: S0,
+                rs1: S0,
+                offset: -16,
+            },
+            op_imm(SP, T0, 0),
+            Instruction::Jalr {
+                rd: ZERO,
+                rs1: RA,
+                offset: 0,
+            },
+        ];
+        assert_eq!(decode(&bytecode), expected);
+
+        // Spot-check words against the RV64I encodings an assembler produces
+        let words: Vec<u32> = bytecode
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        assert_eq!(words[0], 0xfd01_0113); // addi sp, sp, -48
+        assert_eq!(words[1], 0x0211_3423); // sd ra, 40(sp)
+        assert_eq!(words[10], 0x0124_84b3); // add s1, s1, s2
+        assert_eq!(words[12], 0x0080_006f); // j +8
+        assert_eq!(*words.last().unwrap(), 0x0000_8067); // ret
+    }
+
+    #[test]
+    fn test_riscv_instruction_round_trip() {
+        let instructions = [
+            Instruction::Lui { rd: 5, imm: -1 },
+            Instruction::Lui {
+                rd: 31,
+                imm: 0x7ffff,
+            },
+            Instruction::Jal {
+                rd: RA,
+                offset: -(1 << 20),
+            },
+            Instruction::Jal {
+                rd: ZERO,
+                offset: (1 << 20) - 2,
+            },
+            Instruction::Jalr {
+                rd: ZERO,
+                rs1: RA,
+                offset: -2048,
+            },
+            Instruction::Branch {
+                kind: BranchKind::Ltu,
+                rs1: 9,
+                rs2: 18,
+                offset: -4096,
+            },
+            Instruction::Branch {
+                kind: BranchKind::Ge,
+                rs1: 1,
+                rs2: 2,
+                offset: 4094,
+            },
+            Instruction::Ld {
+                rd: 27,
+                rs1: S0,
+                offset: 2047,
+            },
+            Instruction::Sd {
+                rs2: 27,
+                rs1: SP,
+                offset: -2048,
+            },
+            Instruction::OpImm {
+                op: AluOp::Sra,
+                rd: 9,
+                rs1: 9,
+                imm: 63,
+            },
+            Instruction::OpImm {
+                op: AluOp::Sltu,
+                rd: 9,
+                rs1: 9,
+                imm: -1,
+            },
+            Instruction::Addiw {
+                rd: 9,
+                rs1: 9,
+                imm: -2048,
+            },
+            Instruction::Op {
+                op: AluOp::Sub,
+                rd: 9,
+                rs1: ZERO,
+                rs2: 9,
+            },
+        ];
+        for instruction in instructions {
+            assert_eq!(
+                Instruction::decode(instruction.encode()),
+                Some(instruction),
+                "{:#010x}",
+                instruction.encode()
+            );
+        }
+    }
+
+    #[test]
+    fn test_riscv_programs_run() {
+        assert_eq!(
+            run("I64 main() { return fib(10); }\n\
+                 I64 fib(I64 n) { if (n < 2) { return n; } return fib(n - 1) + fib(n - 2); }"),
+            55
+        );
+        assert_eq!(
+            run("I64 main() { I64 sum = 0; for (I64 i = 0; i <= 10; i = i + 1) { sum = sum + i; } return sum; }"),
+            55
+        );
+        assert_eq!(
+            run("I64 main() { I64 n = -7; while (n < 0 && n != -3) { n = n + 1; } return n > 0 ? 1 : n; }"),
+            -3
+        );
+        assert_eq!(
+            run("U64 main() { U64 big = 0; big = big - 1; return big > 1; }"),
+            1
+        );
+        assert_eq!(
+            run("I64 main() { I64 x = 12; return (x & 10 | 1) ^ 4; }"),
+            13
+        );
+    }
+
+    #[test]
+    fn test_riscv_loads_wide_immediates() {
+        for value in [
+            2047i64,
+            -2048,
+            2048,
+            0x7fff_f800,
+            i32::MIN as i64,
+            0x1234_5678_9abc_def0,
+            i64::MIN + 1,
+            i64::MAX,
+            -0x1_0000_0001,
+        ] {
+            let source = format!("I64 main() {{ return {}; }}", value);
+            assert_eq!(run(&source), value, "{}", value);
+        }
+    }
+
+    #[test]
+    fn test_riscv_rejects_unsupported_code() {
+        let reject = |source: &str| {
+            let ast = Compiler::new().parse(source).unwrap();
+            assert!(RiscVCodeGen::new().generate(&ast).is_err(), "{}", source);
+        };
+        reject("I64 main() { I64 a = 6; return a * 7; }");
+        reject("I64 main() { U8* p; return 0; }");
+        reject("struct Point { I64 x; };\nI64 main() { return 0; }");
+        reject("I64 main() { return missing(); }");
+    }
+
+    #[test]
+    fn test_casts_truncate_and_extend() {
+        assert_eq!(run("I64 main() { return (U8)300; }"), 44);
+        assert_eq!(run("I64 main() { return (I8)200; }"), -56);
+        assert_eq!(
+            run("I64 main() { I64 x = 7; return (Bool)x + (Bool)(x - 7); }"),
+            1
+        );
+    }
+    #[test]
+    fn test_break_and_continue() {
+        let breaks =
+            "I64 main() { I64 i = 0; while (1) { if (i == 5) { break; } i = i + 1; } return i; }";
+        assert_eq!(run(breaks), 5);
+        let skips = "I64 main() { I64 sum = 0; for (I64 i = 0; i < 10; i = i + 1) { if ((i >> 1) << 1 == i) { continue; } sum = sum + i; } return sum; }";
+        assert_eq!(run(skips), 25);
+    }
+}
+
+#[cfg(test)]
+mod compile_error_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+    use crate::pible::compiler::CompileError;
+    use crate::pible::lexer::LexError;
+    use crate::pible::parser::ParseError;
+    use std::error::Error;
+
+    #[test]
+    fn test_parse_error_is_nested_in_compile_error() {
+        let error = Compiler::new()
+            .compile("U0 main( { return 0; }", &CompileOptions::default())
+            .unwrap_err();
+        match &error {
+            CompileError::ParseError {
+                source:
+                    parse_error @ (ParseError::UnexpectedToken(..) | ParseError::ExpectedToken { .. }),
+                ..
+            } => assert_eq!(parse_error.position(), Some((1, 10))),
+            other => panic!("expected a nested ParseError, got {:?}", other),
+        }
+        assert!(error
+            .source()
+            .is_some_and(|source| source.is::<ParseError>()));
+    }
+
+    #[test]
+    fn test_lex_and_codegen_errors_keep_their_types() {
+        let error = Compiler::new()
+            .compile("I64 main() { return \"open; }", &CompileOptions::default())
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CompileError::LexError {
+                source: LexError::UnterminatedString(1, _),
+                ..
+            }
+        ));
+
+        let error = Compiler::new()
+            .compile(
+                "I64 main() { break; return 0; }",
+                &CompileOptions::default(),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CompileError::CodeGenError(CodeGenError::OutsideLoop(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod short_circuit_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+
+    #[test]
+    fn test_and_skips_right_operand() {
+        // Evaluating `10 / zero` would stop the VM with a division by zero
+        let source =
+            "I64 main() { I64 zero = 0; if (zero != 0 && 10 / zero > 1) { return 1; } return 2; }";
+        assert_eq!(run(source), 2);
+        let source = "I64 main() { I64 zero = 0; return zero == 0 || 10 / zero > 1; }";
+        assert_eq!(run(source), 1);
+    }
+
+    #[test]
+    fn test_right_operand_call_skipped() {
+        let program = |body: &str| {
+            format!(
+                "I64 boom() {{ I64 zero = 0; return 1 / zero; }}\nI64 main() {{ {} }}",
+                body
+            )
+        };
+        assert_eq!(run(&program("return 0 && boom();")), 0);
+        assert_eq!(run(&program("return 1 || boom();")), 1);
+        let evaluated = try_run(&program("return 1 && boom();"));
+        assert!(matches!(evaluated, Err(CompileError::VmExecutionError(_))));
+    }
+
+    #[test]
+    fn test_logical_operators_yield_booleans() {
+        assert_eq!(
+            run("I64 main() { I64 a = 5; I64 b = -2; return (a && b) * 10 + (a || 0); }"),
+            11
+        );
+        assert_eq!(
+            run("I64 main() { I64 a = 0; return (a && 7) * 10 + (a || 0); }"),
+            0
+        );
+        assert_eq!(
+            run("I64 main() { I64 x = 3; return x > 1 && x < 5 || x == 9; }"),
+            1
+        );
+    }
+}
+
+#[cfg(test)]
+mod const_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+    use crate::pible::compiler::CompileError;
+    use crate::pible::parser::NodeType;
+
+    #[test]
+    fn test_const_in_array_size_and_expression() {
+        let source = "const U64 SLOTS = 4;\n\
+                      const U64 RENT = 165 + SLOTS;\n\
+                      I64 main() { U64 slots[SLOTS]; slots[SLOTS - 1] = RENT; return slots[3] + sizeof(slots); }";
+        assert_eq!(run(source), 169 + 32);
+    }
+
+    #[test]
+    fn test_const_parses_to_const_decl() {
+        let ast = Compiler::new()
+            .parse("const I64 LIMIT = 2 * 8;\nI64 main() { return LIMIT; }")
+            .unwrap();
+        let constant = &ast.children[0];
+        assert_eq!(constant.node_type, NodeType::ConstDecl);
+        assert_eq!(constant.value.as_deref(), Some("I64:LIMIT"));
+        assert_eq!(
+            run("const I64 LIMIT = 2 * 8;\nI64 main() { return LIMIT; }"),
+            16
+        );
+    }
+
+    #[test]
+    fn test_const_takes_no_storage_and_wraps_to_its_type() {
+        let with_const = Compiler::new()
+            .compile(
+                "const U8 SMALL = 300;\nI64 main() { return SMALL; }",
+                &CompileOptions::default(),
+            )
+            .unwrap();
+        let with_literal = Compiler::new()
+            .compile("I64 main() { return 44; }", &CompileOptions::default())
+            .unwrap();
+        assert_eq!(with_const, with_literal);
+    }
+
+    #[test]
+    fn test_assigning_a_const_is_an_error() {
+        let result = Compiler::new().compile(
+            "const U64 RENT = 165;\nI64 main() { RENT = 1; return RENT; }",
+            &CompileOptions::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(CompileError::CodeGenError(CodeGenError::AssignToConstant(name))) if name == "RENT"
+        ));
+
+        // A local of the same name shadows the constant
+        assert_eq!(
+            run(
+                "const U64 RENT = 165;\nI64 main() { I64 RENT = 2; RENT = RENT + 1; return RENT; }"
+            ),
+            3
+        );
+    }
+
+    #[test]
+    fn test_const_needs_a_constant_value() {
+        let result = Compiler::new().compile(
+            "I64 f() { return 1; }\nconst I64 BAD = f();\nI64 main() { return BAD; }",
+            &CompileOptions::default(),
+        );
+        assert!(matches!(
+            result,
+            Err(CompileError::CodeGenError(
+                CodeGenError::NonConstantInitializer(_)
+            ))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod token_iterator_tests {
+    use super::*;
+
+    const PROGRAM: &str = "struct Point { I64 x; I64 y; };\n\
+                           // Sum the coordinates\n\
+                           I64 main() {\n    struct Point p;\n    p.x = 3;\n    p.y = 4 >= 2 ? 1.5 : 0;\n    PrintF(\"%d\\n\", p.x);\n    return p.x + p.y;\n}\n";
+
+    #[test]
+    fn test_iterator_matches_scan_tokens() {
+        let scanned = Lexer::new(PROGRAM).scan_tokens().unwrap();
+        let iterated: Vec<_> = Lexer::new(PROGRAM).map(Result::unwrap).collect();
+        assert_eq!(iterated.len(), scanned.len());
+        for (left, right) in iterated.iter().zip(&scanned) {
+            assert_eq!(
+                (left.token_type, left.lexeme, left.line, left.column),
+                (right.token_type, right.lexeme, right.line, right.column)
+            );
+        }
+        assert_eq!(iterated.last().unwrap().token_type, TokenType::Eof);
+    }
+
+    /// Token types of `source`, without the final `Eof`.
+    fn token_types(source: &str) -> Vec<TokenType> {
+        let mut tokens: Vec<_> = Lexer::new(source)
+            .map(|token| token.unwrap().token_type)
+            .collect();
+        assert_eq!(tokens.pop(), Some(TokenType::Eof));
+        tokens
+    }
+
+    #[test]
+    fn test_dot_is_a_decimal_point_only_between_digits() {
+        use TokenType::*;
+
+        assert_eq!(token_types("3.14"), [FloatLiteral]);
+        assert_eq!(token_types("x.y"), [Identifier, Dot, Identifier]);
+        assert_eq!(
+            token_types("arr[0].field"),
+            [
+                Identifier,
+                LeftBracket,
+                NumberLiteral,
+                RightBracket,
+                Dot,
+                Identifier
+            ]
+        );
+        assert_eq!(
+            token_types("a.b->c.d"),
+            [Identifier, Dot, Identifier, Arrow, Identifier, Dot, Identifier]
+        );
+        // No digit follows, so these dots are member accesses
+        assert_eq!(token_types("1.b"), [NumberLiteral, Dot, Identifier]);
+        assert_eq!(token_types("3."), [NumberLiteral, Dot]);
+        // Nor does a digit inside an identifier start a float
+        assert_eq!(token_types("x1.y2"), [Identifier, Dot, Identifier]);
+        assert_eq!(token_types("1.5.x"), [FloatLiteral, Dot, Identifier]);
+    }
+
+    #[test]
+    fn test_iterator_is_lazy_and_stops_after_errors() {
+        let mut lexer = Lexer::new("I64 x = 1; @ never reached");
+        let first: Vec<_> = lexer.by_ref().take(2).map(Result::unwrap).collect();
+        assert_eq!(first[0].token_type, TokenType::I64);
+        assert_eq!(first[1].lexeme, "x");
+
+        let rest: Vec<_> = lexer.collect();
+        assert_eq!(rest.len(), 4);
+        assert!(rest[3].is_err());
+
+        let mut empty = Lexer::new("");
+        assert_eq!(empty.next().unwrap().unwrap().token_type, TokenType::Eof);
+        assert!(empty.next().is_none());
+    }
+}
+
+#[cfg(test)]
+mod nesting_limit_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+    use crate::pible::parser::ParseError;
+
+    fn nesting_error(source: &str) -> bool {
+        matches!(
+            Compiler::new().compile(source, &CompileOptions::default()),
+            Err(CompileError::ParseError {
+                source: ParseError::NestingTooDeep(..),
+                ..
+            })
+        )
+    }
+
+    #[test]
+    fn test_deep_parentheses_fail_cleanly() {
+        let depth = 100_000;
+        let source = format!(
+            "I64 main() {{ return {}1{}; }}",
+            "(".repeat(depth),
+            ")".repeat(depth)
+        );
+        assert!(nesting_error(&source));
+    }
+
+    #[test]
+    fn test_deep_blocks_and_unary_chains_fail_cleanly() {
+        let blocks = format!(
+            "U0 main() {{ {}{} }}",
+            "{".repeat(10_000),
+            "}".repeat(10_000)
+        );
+        assert!(nesting_error(&blocks));
+        let negations = format!("I64 main() {{ return {}1; }}", "- ".repeat(10_000));
+        assert!(nesting_error(&negations));
+    }
+
+    #[test]
+    fn test_moderate_nesting_still_compiles() {
+        let source = format!(
+            "I64 main() {{ {{{{ return {}1{}; }}}} }}",
+            "(".repeat(40),
+            ")".repeat(40)
+        );
+        assert!(Compiler::new()
+            .compile(&source, &CompileOptions::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_long_flat_chains_compile() {
+        let terms = vec!["1"; 150].join(" + ");
+        let source = format!("I64 main() {{ return {}; }}", terms);
+        assert_eq!(run(&source), 150);
+
+        let terms = vec!["1"; 10_000].join(" + ");
+        assert!(nesting_error(&format!(
+            "I64 main() {{ return {}; }}",
+            terms
+        )));
+    }
+
+    #[test]
+    fn test_a_declaration_that_does_not_parse_fails_the_program() {
+        for source in [
+            "U64 main() { U64 s = ; return 0; }",
+            "U64 helper() { return 1 }\nU64 main() { return 0; }",
+            "U64 main() { for (U64 i = 0, j = 0; i < 1; i++) {} return 0; }",
+        ] {
+            assert!(
+                matches!(
+                    Compiler::new().compile(source, &CompileOptions::default()),
+                    Err(CompileError::ParseError { .. })
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_max_depth_is_configurable() {
+        let tokens = Lexer::new("I64 main() { return ((((1)))); }")
+            .scan_tokens()
+            .unwrap();
+        let error = Parser::new(tokens.clone())
+            .with_max_depth(4)
+            .parse()
+            .unwrap_err();
+        assert_eq!(error.position(), Some((1, 24)));
+        assert!(Parser::new(tokens).with_max_depth(8).parse().is_ok());
+    }
+}
+
+#[cfg(test)]
+mod anchor_dispatch_tests {
+    use super::*;
+    use crate::pible::solana_bpf::{
+        anchor_discriminator, ANCHOR_FALLBACK_NOT_FOUND, ANCHOR_INSTRUCTION_MISSING,
+    };
+
+    const PROGRAM: &str = r#"
+        export U64 initialize(U8* accounts, U64 count, U8* data, U64 data_len) {
+            return 10 + data_len;
+        }
+
+        export U64 increment(U8* accounts, U64 count, U8* data, U64 data_len) {
+            return data[0] + 1;
+        }
+    "#;
+
+    fn solana_options() -> CompileOptions<'static> {
+        CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..Default::default()
+        }
+    }
+
+    fn compile(source: &str) -> Vec<BpfInstruction> {
+        Compiler::new()
+            .compile(source, &solana_options())
+            .expect("Compilation should succeed")
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Run the program on an input region with no accounts and `data`.
+    fn run(data: &[u8]) -> i64 {
+        run_program(PROGRAM, data)
+    }
+
+    fn run_program(source: &str, data: &[u8]) -> i64 {
+        let mut input = 0u64.to_le_bytes().to_vec();
+        input.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        input.extend_from_slice(data);
+        input.extend_from_slice(&[0xaa; 32]); // program id
+
+        let instructions = compile(source);
+        let mut vm = BpfVm::with_input(&instructions, &input).unwrap();
+        vm.execute().expect("Execution should succeed");
+        vm.registers()[0]
+    }
+
+    fn instruction_data(name: &str, args: &[u8]) -> Vec<u8> {
+        let mut data = anchor_discriminator(name).to_vec();
+        data.extend_from_slice(args);
+        data
+    }
+
+    #[test]
+    fn test_discriminator_matches_anchor() {
+        assert_eq!(
+            anchor_discriminator("initialize"),
+            [175, 175, 109, 31, 13, 152, 155, 237]
+        );
+    }
+
+    #[test]
+    fn test_dispatcher_compares_each_discriminator() {
+        let instructions = compile(PROGRAM);
+        for name in ["initialize", "increment"] {
+            let discriminator = i64::from_le_bytes(anchor_discriminator(name));
+            let load = instructions
+                .windows(3)
+                .position(|window| {
+                    window[0].opcode == 0x18
+                        && window[0].immediate == discriminator as i32
+                        && window[1].immediate == (discriminator >> 32) as i32
+                })
+                .unwrap_or_else(|| panic!("no discriminator load for {}", name));
+            // jne against the discriminator read from the instruction data
+            assert_eq!(instructions[load + 2].opcode, 0x5d);
+            assert_eq!(instructions[load + 2].src_reg, instructions[load].dst_reg);
+        }
+    }
+
+    #[test]
+    fn test_dispatcher_calls_selected_handler() {
+        assert_eq!(run(&instruction_data("initialize", &[1, 2, 3])), 13);
+        assert_eq!(run(&instruction_data("increment", &[41])), 42);
+        assert_eq!(
+            run(&instruction_data("unknown", &[])),
+            ANCHOR_FALLBACK_NOT_FOUND as i64
+        );
+        assert_eq!(run(&[1, 2, 3]), ANCHOR_INSTRUCTION_MISSING as i64);
+    }
+
+    #[test]
+    fn test_idl_lists_discriminators() {
+        let compiler = Compiler::new();
+        let ast = compiler.parse(PROGRAM).unwrap();
+        let idl: serde_json::Value = serde_json::from_str(
+            &compiler
+                .generate_idl_json(PROGRAM, &ast, &solana_options())
+                .unwrap(),
+        )
+        .unwrap();
+        let instructions = idl["instructions"].as_array().unwrap();
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(instructions[1]["name"], "increment");
+        assert_eq!(
+            instructions[0]["discriminator"],
+            serde_json::json!([175, 175, 109, 31, 13, 152, 155, 237])
+        );
+    }
+
+    #[test]
+    fn test_only_exported_functions_are_instructions() {
+        let source = r#"
+            U64 double(U64 x) {
+                return x * 2;
+            }
+
+            export U64 initialize(U8* accounts, U64 count, U8* data, U64 data_len) {
+                return double(data_len);
+            }
+        "#;
+        let compiler = Compiler::new();
+        let ast = compiler.parse(source).unwrap();
+        let exported: Vec<bool> = ast.children.iter().map(|node| node.exported).collect();
+        assert_eq!(exported, vec![false, true]);
+
+        let idl: serde_json::Value = serde_json::from_str(
+            &compiler
+                .generate_idl_json(source, &ast, &solana_options())
+                .unwrap(),
+        )
+        .unwrap();
+        let names: Vec<&str> = idl["instructions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|instruction| instruction["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["initialize"]);
+
+        // The dispatcher selects `initialize` but has no way to reach `double`
+        let helper = i64::from_le_bytes(anchor_discriminator("double"));
+        assert!(!compile(source).iter().any(
+            |instruction| instruction.opcode == 0x18 && instruction.immediate == helper as i32
+        ));
+        assert_eq!(
+            run_program(source, &instruction_data("initialize", &[1, 2, 3])),
+            6
+        );
+    }
+}
+
+#[cfg(test)]
+mod borsh_tests {
+    use super::*;
+    use crate::pible::codegen::ERROR_DID_NOT_DESERIALIZE;
+    use crate::pible::solana_bpf::anchor_discriminator;
+
+    const DATA_ADDRESS: usize = 0x100;
+
+    const PAIR: &str = "struct Pair { U32 a; U64 b; };";
+
+    /// Run `main(U8* data, U64 len)` on `data` placed in VM memory.
+    fn run(body: &str, data: &[u8]) -> i64 {
+        let source = format!("{}\nI64 main(U8* data, U64 len) {{ {} }}", PAIR, body);
+        let ast = Compiler::new().parse(&source).unwrap();
+        let instructions = CodeGen::new()
+            .generate(&ast)
+            .expect("Code generation should succeed");
+
+        let mut vm = BpfVm::new(&instructions);
+        vm.load_data(DATA_ADDRESS, data).unwrap();
+        vm.set_register(1, DATA_ADDRESS as i64);
+        vm.set_register(2, data.len() as i64);
+        vm.execute().expect("Execution should succeed");
+        vm.registers()[0]
+    }
+
+    fn pair_bytes(a: u32, b: u64) -> Vec<u8> {
+        let mut bytes = a.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&b.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_deserializes_two_field_struct() {
+        let data = pair_bytes(7, 0x1_0000_0002);
+        let decode = "struct Pair p; I64 used = borsh_deserialize(p, data, len);";
+        assert_eq!(run(&format!("{} return p.a;", decode), &data), 7);
+        assert_eq!(
+            run(&format!("{} return p.b;", decode), &data),
+            0x1_0000_0002
+        );
+        assert_eq!(run(&format!("{} return used;", decode), &data), 12);
+    }
+
+    #[test]
+    fn test_short_input_fails() {
+        let data = pair_bytes(7, 2);
+        let body = "struct Pair p; return borsh_deserialize(p, data, len - 1);";
+        assert_eq!(run(body, &data), -1);
+    }
+
+    #[test]
+    fn test_serialize_round_trips() {
+        let body = r#"
+            struct Pair p;
+            p.a = 5;
+            p.b = 9;
+            U8 buffer[16];
+            I64 written = borsh_serialize(p, buffer, 16);
+            struct Pair q;
+            borsh_deserialize(q, buffer, written);
+            return written * 100 + q.a * 10 + q.b;
+        "#;
+        assert_eq!(run(body, &[]), 1259);
+    }
+
+    #[test]
+    fn test_arrays_are_length_prefixed() {
+        let source = r#"
+            struct Tagged { U8 tag[2]; U16 value; };
+            I64 main(U8* data, U64 len) {
+                struct Tagged t;
+                if (borsh_deserialize(t, data, len) < 0) {
+                    return -1;
+                }
+                return t.tag[0] + t.tag[1] + t.value;
+            }
+        "#;
+        let run_tagged = |data: &[u8]| {
+            let ast = Compiler::new().parse(source).unwrap();
+            let instructions = CodeGen::new().generate(&ast).unwrap();
+            let mut vm = BpfVm::new(&instructions);
+            vm.load_data(DATA_ADDRESS, data).unwrap();
+            vm.set_register(1, DATA_ADDRESS as i64);
+            vm.set_register(2, data.len() as i64);
+            vm.execute().unwrap();
+            vm.registers()[0]
+        };
+        assert_eq!(run_tagged(&[2, 0, 0, 0, 1, 2, 100, 0]), 103);
+        assert_eq!(run_tagged(&[3, 0, 0, 0, 1, 2, 100, 0]), -1);
+    }
+
+    #[test]
+    fn test_exported_handler_decodes_struct_argument() {
+        let source = format!(
+            "{}\nexport U64 transfer(U8* accounts, U64 count, Pair args, U64 args_len) {{ return args.a + args.b; }}",
+            PAIR
+        );
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..Default::default()
+        };
+        let instructions: Vec<BpfInstruction> = Compiler::new()
+            .compile(&source, &options)
+            .unwrap()
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+
+        let invoke = |args: &[u8]| {
+            let mut data = anchor_discriminator("transfer").to_vec();
+            data.extend_from_slice(args);
+            let mut input = 0u64.to_le_bytes().to_vec();
+            input.extend_from_slice(&(data.len() as u64).to_le_bytes());
+            input.extend_from_slice(&data);
+            input.extend_from_slice(&[0xaa; 32]);
+
+            let mut vm = BpfVm::new(&instructions);
+            vm.load_data(DATA_ADDRESS, &input).unwrap();
+            vm.set_register(1, DATA_ADDRESS as i64);
+            vm.execute().unwrap();
+            vm.registers()[0]
+        };
+        assert_eq!(invoke(&pair_bytes(40, 2)), 42);
+        assert_eq!(invoke(&[1, 2, 3]), ERROR_DID_NOT_DESERIALIZE as i64);
+    }
+}
+
+#[cfg(test)]
+mod typedef_tests {
+    use super::*;
+
+    #[test]
+    fn test_typedef_integer_and_struct() {
+        let source = r#"
+            typedef U64 Lamports;
+            typedef struct {
+                Lamports balance;
+                U8 bump;
+            } Vault;
+
+            Lamports total(Vault* vault, Lamports extra) {
+                return vault->balance + extra;
+            }
+
+            U64 main() {
+                Vault vault;
+                vault.balance = 40;
+                vault.bump = 255;
+                Lamports extra = 2;
+                return total(vault, extra) + sizeof(Vault) + sizeof(Lamports);
+            }
+        "#;
+        assert_eq!(run(source), 42 + 16 + 8);
+    }
+
+    #[test]
+    fn test_typedef_of_tagged_struct_and_pointer() {
+        let source = r#"
+            typedef struct Point { I32 x; I32 y; } Point;
+            typedef struct Point* PointRef;
+            typedef U8* Bytes;
+
+            U64 main() {
+                Point p;
+                p.x = 3;
+                p.y = 4;
+                PointRef r = p;
+                U8 raw[2];
+                raw[1] = 5;
+                Bytes b = raw;
+                for (Point q; r->x < 5; r->x = r->x + 1) {}
+                return r->x * 10 + p.y + b[1] + sizeof(PointRef);
+            }
+        "#;
+        assert_eq!(run(source), 50 + 4 + 5 + 8);
+    }
+
+    #[test]
+    fn test_aliases_resolve_in_the_ast() {
+        let ast = Compiler::new()
+            .parse("typedef U8 Byte; typedef Byte* Bytes; U0 f(Bytes p, Byte b) { Bytes q; }")
+            .unwrap();
+        let function = ast.children.last().unwrap();
+        assert_eq!(function.children[0].value.as_deref(), Some("U8*:p"));
+        assert_eq!(function.children[1].value.as_deref(), Some("U8:b"));
+        let declaration = &function.children[2].children[0].children[0];
+        assert_eq!(declaration.value.as_deref(), Some("U8*:q"));
+    }
+}
+
+#[cfg(test)]
+mod cast_tests {
+    use super::*;
+    use crate::pible::formatter::format_source;
+    use crate::pible::parser::NodeType;
+
+    #[test]
+    fn test_widening_cast_extends() {
+        let source = r#"
+            I64 main() {
+                U8 bytes[1];
+                bytes[0] = 253;
+                I64 wide = (I64)(I8)bytes[0];
+                U32 big = 4000000000;
+                return wide + ((U64)big == 4000000000);
+            }
+        "#;
+        assert_eq!(run(source), -2);
+    }
+
+    #[test]
+    fn test_narrowing_cast_truncates() {
+        assert_eq!(run("I64 main() { return (U8)300; }"), 44);
+        assert_eq!(run("I64 main() { return (I8)200; }"), -56);
+        assert_eq!(run("I64 main() { I64 x = 65537; return (U16)x; }"), 1);
+        assert_eq!(run("I64 main() { return (Bool)42 + (Bool)0; }"), 1);
+    }
+
+    #[test]
+    fn test_pointer_cast_keeps_address() {
+        let source = r#"
+            I64 main() {
+                U8 raw[16];
+                U64* words = (U64*)raw;
+                words[1] = 7;
+                return raw[8];
+            }
+        "#;
+        assert_eq!(run(source), 7);
+    }
+
+    #[test]
+    fn test_parenthesized_expression_is_not_a_cast() {
+        let ast = Compiler::new()
+            .parse("typedef U32 Id; I64 main() { I64 x = 1; return (x) + (Id)x; }")
+            .unwrap();
+        let body = &ast.children.last().unwrap().children[0];
+        let sum = &body.children[1].children[0];
+        assert_eq!(sum.children[0].node_type, NodeType::Identifier);
+        assert_eq!(sum.children[1].node_type, NodeType::Cast);
+        assert_eq!(sum.children[1].value.as_deref(), Some("U32"));
+    }
+
+    #[test]
+    fn test_formatter_attaches_casts() {
+        let source = "U64* lamports = ( U64 * ) accounts;\nI64 y = (I64) - x + sizeof(U64) * 2;\n";
+        assert_eq!(
+            format_source(source).unwrap(),
+            "U64* lamports = (U64*)accounts;\nI64 y = (I64)-x + sizeof(U64) * 2;\n"
+        );
+    }
+}
+
+#[cfg(test)]
+mod narrow_integer_tests {
+    use super::*;
+
+    fn has_opcode(instructions: &[BpfInstruction], opcode: u8) -> bool {
+        instructions
+            .iter()
+            .any(|instruction| instruction.opcode == opcode)
+    }
+
+    #[test]
+    fn test_u8_truncates_on_store() {
+        let (instructions, result) =
+            run_codegen("U64 main() { U64 big = 300; U8 x = big; return x; }");
+        assert!(has_opcode(&instructions, 0x73));
+        assert!(has_opcode(&instructions, 0x71));
+        assert_eq!(result, 44);
+    }
+
+    #[test]
+    fn test_out_of_bounds_narrow_pointer_accesses_fail() {
+        for source in [
+            "U64 main() { U32* p = 0; I64 i = -1; return p[i]; }",
+            "U64 main() { U8* p = 0; p = p - 1; return *p; }",
+            "U64 main() { U16* p = 0; p = p - 1; *p = 5; return 0; }",
+        ] {
+            assert!(try_run(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_i8_sign_extends_on_load() {
+        let (_, result) =
+            run_codegen("I64 main() { I64 big = 200; I8 x = big; I64 y = x; return y; }");
+        assert_eq!(result, -56);
+    }
+
+    #[test]
+    fn test_16_and_32_bit_widths() {
+        let (instructions, result) =
+            run_codegen("I64 main() { I64 big = 40000; I16 x = big; return x; }");
+        assert!(has_opcode(&instructions, 0x6b));
+        assert!(has_opcode(&instructions, 0x69));
+        assert_eq!(result, -25536);
+
+        let (instructions, result) =
+            run_codegen("U64 main() { U64 big = 4294967297; U32 x = big; return x; }");
+        assert!(has_opcode(&instructions, 0x63));
+        assert!(has_opcode(&instructions, 0x61));
+        assert_eq!(result, 1);
+    }
+
+    #[test]
+    fn test_arithmetic_wraps_when_stored() {
+        let (_, result) = run_codegen("U64 main() { U8 x = 250; x = x + 10; return x; }");
+        assert_eq!(result, 4);
+    }
+
+    #[test]
+    fn test_assignment_value_is_truncated() {
+        let (_, result) = run_codegen(
+            "U64 main() { U64 big = 300; U8 x = 0; U64 y = 0; y = x = big; return y; }",
+        );
+        assert_eq!(result, 44);
+    }
+
+    #[test]
+    fn test_signed_struct_field_sign_extends() {
+        let source = r#"
+            struct Pair { I8 low; I16 high; };
+            I64 main() {
+                struct Pair pair;
+                I64 big = 200;
+                pair.low = big;
+                pair.high = -2;
+                return pair.low + pair.high;
+            }
+        "#;
+        let (_, result) = run_codegen(source);
+        assert_eq!(result, -58);
+    }
+}
+
+#[cfg(test)]
+mod bool_tests {
+    use super::*;
+
+    #[test]
+    fn test_bool_normalizes_on_declaration() {
+        assert_eq!(run("U64 main() { Bool b = 5; return b; }"), 1);
+        assert_eq!(run("U64 main() { Bool b = 256; return b; }"), 1);
+        assert_eq!(run("U64 main() { Bool b = 0; return b; }"), 0);
+    }
+
+    #[test]
+    fn test_not_gives_zero_or_one() {
+        assert_eq!(run("U64 main() { return !0; }"), 1);
+        assert_eq!(run("U64 main() { return !5; }"), 0);
+        assert_eq!(
+            run("U64 main() { U64 x = 7; return (x > 3) + (x == 7) + (3 && 5); }"),
+            3
+        );
+    }
+
+    #[test]
+    fn test_bool_normalizes_on_every_store() {
+        assert_eq!(run("U64 main() { U64 x = 7; Bool b; b = x; return b; }"), 1);
+        assert_eq!(
+            run("struct S { Bool b; }; U64 main() { struct S s; s.b = 6; return s.b; }"),
+            1
+        );
+        assert_eq!(run("U64 main() { Bool a[2]; a[1] = 4; return a[1]; }"), 1);
+        assert_eq!(
+            run("U64 main() { Bool a[3] = {0, 9, 2}; return a[0] + a[1] + a[2]; }"),
+            2
+        );
+    }
+
+    #[test]
+    fn test_bool_parameters_and_returns_normalize() {
+        assert_eq!(
+            run("U64 g(Bool b) { return b; } U64 main() { return g(256); }"),
+            1
+        );
+        assert_eq!(
+            run("Bool f(U64 x) { return x; } U64 main() { return f(9) + f(0); }"),
+            1
+        );
+    }
+
+    #[test]
+    fn test_bool_local_is_one_byte() {
+        let ast = Compiler::new()
+            .parse("U64 main() { Bool b = 2; return b; }")
+            .unwrap();
+        let instructions = CodeGen::new().generate(&ast).unwrap();
+        assert!(instructions
+            .iter()
+            .any(|instruction| instruction.opcode == 0x73));
+        assert!(instructions
+            .iter()
+            .any(|instruction| instruction.opcode == 0x71));
+    }
+}
+
+#[cfg(test)]
+mod analysis_tests {
+    use super::*;
+
+    fn vm_options() -> CompileOptions<'static> {
+        CompileOptions {
+            target: CompileTarget::BpfVm,
+            ..CompileOptions::default()
+        }
+    }
+
+    const DEAD_CODE: &str =
+        "U64 main() {\n    U64 x = 1;\n    return x;\n    x = 2;\n    x = 3;\n}\n";
+
+    #[test]
+    fn test_statement_after_return_warns_once() {
+        let compilation = Compiler::new()
+            .compile_with_warnings(DEAD_CODE, &vm_options())
+            .unwrap();
+        assert_eq!(compilation.warnings.len(), 1);
+        assert_eq!(compilation.warnings[0].line, 4);
+        assert!(compilation.warnings[0].message.contains("return"));
+        assert!(!compilation.bytecode.is_empty());
+    }
+
+    #[test]
+    fn test_nested_blocks_are_checked() {
+        let source = "U64 main() {\n    if (1) {\n        return 1;\n        U64 y = 2;\n    }\n    return 0;\n}\n";
+        let compilation = Compiler::new()
+            .compile_with_warnings(source, &vm_options())
+            .unwrap();
+        assert_eq!(compilation.warnings.len(), 1);
+        assert_eq!(compilation.warnings[0].line, 4);
+    }
+
+    #[test]
+    fn test_reachable_code_has_no_warnings() {
+        let source = "U64 main() {\n    if (1) return 1;\n    return 0;\n}\n";
+        let compilation = Compiler::new()
+            .compile_with_warnings(source, &vm_options())
+            .unwrap();
+        assert!(compilation.warnings.is_empty());
+    }
+
+    #[test]
+    fn test_strict_mode_rejects_warnings() {
+        let options = CompileOptions {
+            strict: true,
+            verify: false,
+            ..vm_options()
+        };
+        match Compiler::new().compile(DEAD_CODE, &options) {
+            Err(CompileError::StrictWarnings(warnings)) => {
+                assert_eq!(warnings.len(), 1);
+                assert_eq!(warnings[0].line, 4);
+            }
+            other => panic!("expected strict warning error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    const GPL_DEAD_CODE: &str =
+        "U0 main() {\n    PrintF(\"hi\\n\");\n    return 0;\n    PrintF(\"never\\n\");\n}\n";
+
+    fn elf_options() -> CompileOptions<'static> {
+        CompileOptions {
+            target: CompileTarget::LinuxBpf,
+            output_format: OutputFormat::Elf,
+            ..CompileOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_analysis_and_codegen_warnings_are_both_returned() {
+        let compilation = Compiler::new()
+            .compile_with_warnings(GPL_DEAD_CODE, &elf_options())
+            .unwrap();
+        assert_eq!(compilation.warnings.len(), 2);
+        assert!(compilation
+            .warnings
+            .iter()
+            .all(|warning| warning.severity == Severity::Warning));
+        assert_eq!(compilation.warnings[0].line, 4);
+        assert!(compilation.warnings[0].message.contains("unreachable"));
+        assert_eq!(compilation.warnings[1].line, 0);
+        assert!(compilation.warnings[1].message.contains("trace_printk"));
+        assert_eq!(
+            compilation.warnings[1].to_string(),
+            compilation.warnings[1].message
+        );
+    }
+
+    #[test]
+    fn test_denied_warnings_become_errors() {
+        let options = CompileOptions {
+            strict: true,
+            ..elf_options()
+        };
+        match Compiler::new().compile(GPL_DEAD_CODE, &options) {
+            Err(CompileError::StrictWarnings(errors)) => {
+                assert_eq!(errors.len(), 2);
+                assert!(errors.iter().all(|error| error.severity == Severity::Error));
+            }
+            other => panic!("expected strict warning error, got {:?}", other.map(|_| ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod resolve_tests {
+    use super::*;
+
+    fn compile(source: &str) -> Result<Vec<u8>, CompileError> {
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            ..CompileOptions::default()
+        };
+        Compiler::new().compile(source, &options)
+    }
+
+    #[test]
+    fn test_undeclared_variable_is_rejected() {
+        match compile("U64 main() { U64 x = 1; return x + foo; }") {
+            Err(CompileError::UndefinedVariable(name)) => assert_eq!(name, "foo"),
+            other => panic!("expected UndefinedVariable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_function_is_rejected() {
+        match compile("U64 main() { return bar(); }") {
+            Err(CompileError::UndefinedFunction(name)) => assert_eq!(name, "bar"),
+            other => panic!("expected UndefinedFunction, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_block_locals_go_out_of_scope() {
+        let source = "U64 main() { if (1) { U64 y = 2; } return y; }";
+        assert!(matches!(
+            compile(source),
+            Err(CompileError::UndefinedVariable(name)) if name == "y"
+        ));
+    }
+
+    #[test]
+    fn test_declared_names_and_builtins_resolve() {
+        let source = r#"
+            const U64 LIMIT = 10;
+            struct Pair { U64 a; U64 b; };
+            U64 twice(U64 n) { return n * 2; }
+            U64 main() {
+                struct Pair p;
+                p.a = LIMIT;
+                PrintF("value\n");
+                for (U64 i = 0; i < 2; i = i + 1) { p.a = p.a + i; }
+                return twice(p.a) + sizeof(Pair);
+            }
+        "#;
+        assert!(compile(source).is_ok());
+    }
+
+    #[test]
+    fn test_increments_compound_assignments_and_declarator_lists() {
+        let source = r#"
+            struct Meta { U64 weight; };
+            U64 main() {
+                U64 total = 0, i, *unused;
+                Meta metas[3];
+                for (i = 0; i < 3; i++) { metas[i].weight = i + 1; }
+                U64 offset = 0;
+                U8 bytes[4];
+                bytes[offset++] = 7;
+                bytes[offset++] = 9;
+                total += bytes[0] * 10 + bytes[1];
+                total -= 1;
+                total *= 2;
+                total <<= 1;
+                total += offset--;
+                total += --offset;
+                total += metas[2].weight;
+                return total;
+            }
+        "#;
+        assert_eq!(run(source), (((70 + 9 - 1) * 2) << 1) + 2 + 3);
+    }
+
+    #[test]
+    fn test_updates_of_targets_with_side_effects_are_rejected() {
+        for body in [
+            "U8 a[4]; U64 i = 0; a[i++] += 1;",
+            "U64 x = 0; (x = 1)++;",
+            "U64 x = 0; x++ ++;",
+            "U64 x = 0; ++f();",
+        ] {
+            let source = format!(
+                "U64 f() {{ return 0; }}\nU64 main() {{ {} return 0; }}",
+                body
+            );
+            assert!(
+                matches!(
+                    Compiler::new().compile(&source, &CompileOptions::default()),
+                    Err(CompileError::ParseError { .. })
+                ),
+                "{}",
+                body
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod typecheck_tests {
+    use super::*;
+
+    fn type_error(source: &str) -> String {
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            ..CompileOptions::default()
+        };
+        match Compiler::new().compile(source, &options) {
+            Err(CompileError::TypeMismatch(message)) => message,
+            other => panic!("expected TypeMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_call_with_wrong_argument_count() {
+        let source = "U64 add(U64 a, U64 b) { return a + b; }\nU64 main() { return add(1); }";
+        assert_eq!(type_error(source), "add takes 2 arguments but 1 were given");
+    }
+
+    #[test]
+    fn test_indexing_a_scalar() {
+        let message = type_error("U64 main() { U64 x = 5; return x[0]; }");
+        assert!(message.contains("cannot index"), "{}", message);
+    }
+
+    #[test]
+    fn test_struct_assigned_to_integer() {
+        let source =
+            "struct Pair { U64 a; U64 b; };\nU64 main() { struct Pair p; U64 x = p; return x; }";
+        assert!(type_error(source).contains("Struct(\"Pair\")"));
+    }
+
+    #[test]
+    fn test_calling_a_variable() {
+        let message = type_error("U64 main() { U64 f = 1; return f(); }");
+        assert_eq!(message, "f is not a function");
+    }
+
+    #[test]
+    fn test_pointers_and_integers_convert_freely() {
+        let source = r#"
+            struct Pair { U64 a; U64 b; };
+            U64 first(struct Pair* pair) { return pair->a; }
+            U64 main() {
+                struct Pair p;
+                p.a = 7;
+                U8* bytes = "hi";
+                U64 address = bytes;
+                U64 values[2];
+                values[1] = first(p);
+                return values[1] + address - address;
+            }
+        "#;
+        assert_eq!(run(source), 7);
+    }
+}
+
+#[cfg(test)]
+mod bpf_vm_atomic_tests {
+    use super::*;
+    use crate::pible::disasm;
+
+    #[test]
+    fn test_sequential_xadds_accumulate() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 1, 0, 0, 100), // mov r1, 100
+            BpfInstruction::new(0xb7, 3, 0, 0, 40),  // mov r3, 40
+            BpfInstruction::new(0x7b, 1, 3, 0, 0),   // stxdw [r1+0], r3
+            BpfInstruction::new(0xb7, 2, 0, 0, 5),   // mov r2, 5
+            BpfInstruction::new(0xdb, 1, 2, 0, 0),   // xadddw [r1+0], r2
+            BpfInstruction::new(0xb7, 2, 0, 0, -3),  // mov r2, -3
+            BpfInstruction::new(0xdb, 1, 2, 0, 0),   // xadddw [r1+0], r2
+            BpfInstruction::new(0x79, 0, 1, 0, 0),   // ldxdw r0, [r1+0]
+            BpfInstruction::new(0x95, 0, 0, 0, 0),   // exit
+        ];
+        let mut vm = BpfVm::new(&instructions);
+        assert_eq!(vm.execute().unwrap().exit_code, 42);
+    }
+
+    #[test]
+    fn test_word_xadd_wraps_without_touching_neighbours() {
+        let mut vm = BpfVm::new(&[]);
+        vm.load_data(8, &[0xff, 0xff, 0xff, 0xff, 0x11]).unwrap();
+        vm.set_register(1, 8);
+        vm.set_register(2, 2);
+        vm.execute_instruction(&BpfInstruction::new(0xc3, 1, 2, 0, 0))
+            .unwrap();
+        assert_eq!(&vm.memory[8..13], &[1, 0, 0, 0, 0x11]);
+    }
+
+    #[test]
+    fn test_xadd_out_of_bounds_is_an_error() {
+        let mut vm = BpfVm::new(&[]);
+        vm.set_register(1, vm.memory.len() as i64 - 4);
+        assert!(vm
+            .execute_instruction(&BpfInstruction::new(0xdb, 1, 2, 0, 0))
+            .is_err());
+        vm.set_register(1, -8);
+        assert!(vm
+            .execute_instruction(&BpfInstruction::new(0xc3, 1, 2, 0, 0))
+            .is_err());
+    }
+
+    #[test]
+    fn test_disassemble_xadd() {
+        assert_eq!(
+            disasm::disassemble(&[BpfInstruction::new(0xdb, 1, 2, 8, 0)]).trim(),
+            "xadddw [r1+8], r2"
+        );
+    }
+}
+
+#[cfg(test)]
+mod no_std_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGen;
+
+    #[test]
+    fn test_default_compiler_compiles_source_text() {
+        let source = String::from("U64 main() { return 7; }");
+        let bytecode: Vec<u8> = Compiler::default()
+            .compile(&source, &CompileOptions::default())
+            .unwrap();
+        assert!(!bytecode.is_empty());
+        assert_eq!(bytecode.len() % 8, 0);
+        assert!(CodeGen::default()
+            .generate(&Compiler::new().parse(&source).unwrap())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_float_literal_rounds_to_nearest_fixed_point_step() {
+        // 2e-10 is 0.86 of a 32.32 step, so it rounds up rather than to zero
+        assert_eq!(
+            run("Bool main() { F64 x = 0.0000000002; return x > 0; }"),
+            1
+        );
+    }
+}
+
+#[cfg(test)]
+mod printf_format_tests {
+    use super::*;
+
+    fn compile(source: &str) -> Result<Vec<u8>, CompileError> {
+        Compiler::new().compile(source, &CompileOptions::default())
+    }
+
+    fn format_error(source: &str) -> String {
+        match compile(source) {
+            Err(CompileError::TypeMismatch(message)) => message,
+            other => panic!("expected TypeMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_matching_call_compiles() {
+        // bpf_trace_printk takes at most 3 values after the format
+        let source = r#"U0 main() { U64 x = 3; U8* name = "pible"; PrintF("%d %5u %02x\n", x, x, x); PrintF("%c %s 100%%\n", 65, name); }"#;
+        assert!(compile(source).is_ok());
+    }
+
+    #[test]
+    fn test_too_few_arguments() {
+        assert_eq!(
+            format_error(r#"U0 main() { U64 x = 1; PrintF("%d %d", x); }"#),
+            "PrintF format has 2 conversions but 1 arguments were given"
+        );
+    }
+
+    #[test]
+    fn test_too_many_arguments() {
+        assert_eq!(
+            format_error(r#"U0 main() { PrintF("done\n", 1); }"#),
+            "PrintF format has 0 conversions but 1 arguments were given"
+        );
+    }
+
+    #[test]
+    fn test_string_conversion_with_integer() {
+        let message = format_error(r#"U0 main() { U64 x = 1; PrintF("%s", x); }"#);
+        assert!(
+            message.starts_with("%s in a PrintF format can't print"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_unsupported_conversion() {
+        assert_eq!(
+            format_error(r#"U0 main() { PrintF("%q", 1); }"#),
+            "unsupported PrintF conversion %q"
+        );
+    }
+}
+
+#[cfg(test)]
+mod signed_division_tests {
+    use super::*;
+    use crate::pible::disasm;
+
+    fn run_solana(source: &str) -> i32 {
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..CompileOptions::default()
+        };
+        let bytecode = Compiler::new().compile(source, &options).unwrap();
+        // Solana's BPF has no signed division instructions
+        assert!(!disasm::disassemble_bytes(&bytecode).contains("sdiv"));
+        let instructions: Vec<BpfInstruction> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+        BpfVm::new(&instructions).execute().unwrap().exit_code
+    }
+
+    #[test]
+    fn test_signed_division_truncates_toward_zero() {
+        assert_eq!(run("I64 main() { I64 x = -7; return x / 2; }"), -3);
+        assert_eq!(run("I64 main() { I64 x = -7; return x % 2; }"), -1);
+        assert_eq!(run("I64 main() { I64 x = 7; return x / -2; }"), -3);
+    }
+
+    #[test]
+    fn test_folded_division_is_signed() {
+        for opt_level in 0..=2 {
+            let options = CompileOptions {
+                opt_level,
+                ..CompileOptions::default()
+            };
+            assert_eq!(run_with("I64 main() { return -7 / 2; }", &options), -3);
+            assert_eq!(run_with("I64 main() { return -7 % 3; }", &options), -1);
+            assert_eq!(run_with("I64 main() { return 7 / -2; }", &options), -3);
+        }
+    }
+
+    #[test]
+    fn test_unsigned_division_treats_operand_as_unsigned() {
+        // -7 as a U64 is 2^64 - 7, so halving it leaves 0x7fffffff in the top half
+        let source = "U64 main() { U64 x = -7; U64 q = x / 2; return q >> 32; }";
+        assert_eq!(run(source), 0x7fff_ffff);
+        assert_eq!(run("U64 main() { U64 x = -7; return x % 2; }"), 1);
+    }
+
+    #[test]
+    fn test_mixed_operands_divide_unsigned() {
+        let source = "U64 main() { I64 x = -7; U64 two = 2; U64 q = x / two; return q >> 32; }";
+        assert_eq!(run(source), 0x7fff_ffff);
+    }
+
+    #[test]
+    fn test_signed_division_is_marked_in_disassembly() {
+        let bytecode = Compiler::new()
+            .compile(
+                "I64 main() { I64 x = -7; I64 y = 2; return x / y; }",
+                &CompileOptions::default(),
+            )
+            .unwrap();
+        assert!(disasm::disassemble_bytes(&bytecode).contains("sdiv64"));
+    }
+
+    #[test]
+    fn test_solana_emulates_signed_division() {
+        let quotient = "I64 main() { I64 x = -7; I64 y = 2; return x / y; }";
+        assert_eq!(run_solana(quotient), -3);
+        let remainder = "I64 main() { I64 x = -7; I64 y = 2; return x % y; }";
+        assert_eq!(run_solana(remainder), -1);
+        let negative_divisor = "I64 main() { I64 x = 7; I64 y = -2; return x / y; }";
+        assert_eq!(run_solana(negative_divisor), -3);
+    }
+}
+
+#[cfg(test)]
+mod variadic_tests {
+    use super::*;
+
+    const SUM: &str = "I64 sum(I64 base, ...) {\n    I64 total = base;\n    for (I64 i = 0; i < argc; i = i + 1) {\n        total = total + argv[i];\n    }\n    return total;\n}\n";
+
+    #[test]
+    fn test_ellipsis_marks_function_variadic() {
+        let ast = Compiler::new()
+            .parse("U0 log(U8* fmt, ...) { return; }")
+            .unwrap();
+        let function = &ast.children[0];
+        assert!(function.variadic);
+        assert_eq!(function.children.len(), 2);
+    }
+
+    #[test]
+    fn test_variadic_call_with_three_trailing_arguments() {
+        let source = format!(
+            "{}I64 main() {{ I64 x = 4; return sum(100, 20, 3, x * 2); }}",
+            SUM
+        );
+        assert_eq!(run(&source), 131);
+    }
+
+    #[test]
+    fn test_variadic_call_without_extra_arguments() {
+        let source = format!("{}I64 main() {{ return sum(7); }}", SUM);
+        assert_eq!(run(&source), 7);
+    }
+
+    #[test]
+    fn test_missing_named_argument_is_rejected() {
+        let source = format!("{}I64 main() {{ return sum(); }}", SUM);
+        match try_run(&source) {
+            Err(CompileError::TypeMismatch(message)) => {
+                assert_eq!(message, "sum takes at least 1 arguments but 0 were given")
+            }
+            other => panic!("expected TypeMismatch, got {:?}", other),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod wasm_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+    use crate::pible::wasm::{self, BlockType, Function, Instruction, Op};
+
+    fn compile(source: &str) -> Vec<u8> {
+        let options = CompileOptions {
+            target: CompileTarget::Wasm,
+            ..CompileOptions::default()
+        };
+        Compiler::new().compile(source, &options).unwrap()
+    }
+
+    /// Run the exported function, with just enough of Wasm for what the
+    /// backend emits.
+    fn run(source: &str) -> i64 {
+        let functions = wasm::decode_functions(&compile(source)).unwrap();
+        call(&functions, 0, &[])
+    }
+
+    fn call(functions: &[Function], index: usize, args: &[i64]) -> i64 {
+        let function = &functions[index];
+        let body = &function.body;
+        let mut locals = args.to_vec();
+        locals.resize((function.params + function.locals) as usize, 0);
+        let mut stack: Vec<i64> = Vec::new();
+        // Start of each open block and whether it is a loop
+        let mut labels: Vec<(usize, bool)> = Vec::new();
+        let mut pc = 0;
+
+        loop {
+            let mut next = pc + 1;
+            match body[pc] {
+                Instruction::Block(_) => labels.push((pc, false)),
+                Instruction::Loop(_) => labels.push((pc, true)),
+                Instruction::If(_) => {
+                    let (otherwise, end) = matching(body, pc);
+                    if stack.pop().unwrap() != 0 {
+                        labels.push((pc, false));
+                    } else if let Some(otherwise) = otherwise {
+                        labels.push((pc, false));
+                        next = otherwise + 1;
+                    } else {
+                        next = end + 1;
+                    }
+                }
+                Instruction::Else => next = matching(body, labels.last().unwrap().0).1,
+                Instruction::End => {
+                    if labels.pop().is_none() {
+                        return stack.pop().unwrap();
+                    }
+                }
+                Instruction::Br(depth) => next = branch(body, &mut labels, depth),
+                Instruction::BrIf(depth) => {
+                    if stack.pop().unwrap() != 0 {
+                        next = branch(body, &mut labels, depth);
+                    }
+                }
+                Instruction::Return => return stack.pop().unwrap(),
+                Instruction::Call(callee) => {
+                    let params = functions[callee as usize].params as usize;
+                    let args = stack.split_off(stack.len() - params);
+                    stack.push(call(functions, callee as usize, &args));
+                }
+                Instruction::Drop => {
+                    stack.pop();
+                }
+                Instruction::LocalGet(local) => stack.push(locals[local as usize]),
+                Instruction::LocalSet(local) => locals[local as usize] = stack.pop().unwrap(),
+                Instruction::LocalTee(local) => locals[local as usize] = *stack.last().unwrap(),
+                Instruction::I64Const(value) => stack.push(value),
+                Instruction::Op(Op::I32Eqz | Op::I64Eqz) => {
+                    let value = stack.pop().unwrap();
+                    stack.push((value == 0) as i64);
+                }
+                Instruction::Op(Op::I64ExtendI32U) => {}
+                Instruction::Op(op) => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.pop().unwrap();
+                    stack.push(binary(op, a, b));
+                }
+            }
+            pc = next;
+        }
+    }
+
+    /// Take the branch to the label `depth` blocks out: a loop restarts, any
+    /// other block ends.
+    fn branch(body: &[Instruction], labels: &mut Vec<(usize, bool)>, depth: u32) -> usize {
+        let target = labels.len() - 1 - depth as usize;
+        let (start, is_loop) = labels[target];
+        if is_loop {
+            labels.truncate(target + 1);
+            start + 1
+        } else {
+            labels.truncate(target);
+            matching(body, start).1 + 1
+        }
+    }
+
+    /// Positions of the `else`, if any, and the `end` closing the block at `start`.
+    fn matching(body: &[Instruction], start: usize) -> (Option<usize>, usize) {
+        let mut depth = 0;
+        let mut otherwise = None;
+        for (pc, instruction) in body.iter().enumerate().skip(start + 1) {
+            match instruction {
+                Instruction::Block(_) | Instruction::Loop(_) | Instruction::If(_) => depth += 1,
+                Instruction::Else if depth == 0 => otherwise = Some(pc),
+                Instruction::End if depth == 0 => return (otherwise, pc),
+                Instruction::End => depth -= 1,
+                _ => {}
+            }
+        }
+        panic!("unterminated block");
+    }
+
+    fn binary(op: Op, a: i64, b: i64) -> i64 {
+        let shift = (b & 63) as u32;
+        match op {
+            Op::I64Eq => (a == b) as i64,
+            Op::I64Ne => (a != b) as i64,
+            Op::I64LtS => (a < b) as i64,
+            Op::I64LtU => ((a as u64) < b as u64) as i64,
+            Op::I64GtS => (a > b) as i64,
+            Op::I64GtU => (a as u64 > b as u64) as i64,
+            Op::I64LeS => (a <= b) as i64,
+            Op::I64LeU => (a as u64 <= b as u64) as i64,
+            Op::I64GeS => (a >= b) as i64,
+            Op::I64GeU => (a as u64 >= b as u64) as i64,
+            Op::I64Add => a.wrapping_add(b),
+            Op::I64Sub => a.wrapping_sub(b),
+            Op::I64Mul => a.wrapping_mul(b),
+            Op::I64DivS => a.wrapping_div(b),
+            Op::I64DivU => (a as u64 / b as u64) as i64,
+            Op::I64RemS => a.wrapping_rem(b),
+            Op::I64RemU => (a as u64 % b as u64) as i64,
+            Op::I64And => a & b,
+            Op::I64Or => a | b,
+            Op::I64Xor => a ^ b,
+            Op::I64Shl => a << shift,
+            Op::I64ShrS => a >> shift,
+            Op::I64ShrU => ((a as u64) >> shift) as i64,
+            Op::I32Eqz | Op::I64Eqz | Op::I64ExtendI32U => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_wasm_module_header() {
+        let module = compile("I64 main() { return 0; }");
+        assert_eq!(&module[..4], b"\0asm");
+        assert_eq!(&module[4..8], &[1, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_wasm_add_function_decodes() {
+        let module = compile("I64 add(I64 a, I64 b) { return a + b; }");
+        let functions = wasm::decode_functions(&module).unwrap();
+        assert_eq!(
+            functions,
+            vec![Function {
+                params: 2,
+                locals: 0,
+                body: vec![
+                    Instruction::LocalGet(0),
+                    Instruction::LocalGet(1),
+                    Instruction::Op(Op::I64Add),
+                    Instruction::Return,
+                    Instruction::I64Const(0),
+                    Instruction::End,
+                ],
+            }]
+        );
+        // The entry function is exported as `main`
+        assert!(module.windows(4).any(|window| window == b"main"));
+    }
+
+    #[test]
+    fn test_wasm_instruction_round_trip() {
+        let instructions = [
+            Instruction::Block(BlockType::Empty),
+            Instruction::If(BlockType::I64),
+            Instruction::BrIf(1),
+            Instruction::Call(300),
+            Instruction::LocalTee(2),
+            Instruction::I64Const(-129),
+            Instruction::I64Const(i64::MAX),
+            Instruction::Op(Op::I64ShrU),
+        ];
+        for instruction in instructions {
+            let mut bytes = Vec::new();
+            instruction.encode(&mut bytes);
+            assert_eq!(
+                Instruction::decode(&bytes),
+                Some((instruction, bytes.len()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_wasm_arithmetic_and_locals() {
+        assert_eq!(
+            run("I64 main() { I64 x = 6; I64 y = x * 7; return y - 2; }"),
+            40
+        );
+        assert_eq!(run("I64 main() { I64 x = -7; return x / 2; }"), -3);
+        assert_eq!(run("U64 main() { U64 x = -1; return x >> 60; }"), 15);
+        assert_eq!(
+            run("I64 main() { I64 x = 12; return (x & 10 | 1) ^ 4; }"),
+            13
+        );
+    }
+
+    #[test]
+    fn test_wasm_branches_and_loops() {
+        let source = "I64 main() {\n    I64 total = 0;\n    for (I64 i = 1; i <= 10; i = i + 1) {\n        if (i % 2 == 0) {\n            total = total + i;\n        } else {\n            total = total - 1;\n        }\n    }\n    while (total > 20) {\n        total = total - 3;\n    }\n    return total > 0 && total < 20 ? total : -1;\n}\n";
+        assert_eq!(run(source), 19);
+    }
+
+    #[test]
+    fn test_wasm_else_if_chain() {
+        let source = "I64 main() {\n    I64 total = 0;\n    for (I64 i = 0; i < 6; i = i + 1) {\n        if (i == 0) {\n            total = total + 1;\n        } else if (i < 3) {\n            total = total + 10;\n        } else if (i == 3) {\n            break;\n        } else {\n            total = total + 1000;\n        }\n    }\n    return total;\n}\n";
+        assert_eq!(run(source), 21);
+    }
+
+    #[test]
+    fn test_wasm_calls() {
+        let source =
+            "I64 square(I64 x) { return x * x; }\nI64 main() { return square(3) + square(4); }";
+        assert_eq!(run(source), 25);
+    }
+
+    #[test]
+    fn test_wasm_rejects_builtins() {
+        let options = CompileOptions {
+            target: CompileTarget::Wasm,
+            ..CompileOptions::default()
+        };
+        let error = Compiler::new()
+            .compile(r#"U0 main() { PrintF("hi"); }"#, &options)
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CompileError::CodeGenError(CodeGenError::FunctionNotFound(_))
+        ));
+    }
+    #[test]
+    fn test_break_and_continue() {
+        let breaks =
+            "I64 main() { I64 i = 0; while (1) { if (i == 5) { break; } i = i + 1; } return i; }";
+        assert_eq!(run(breaks), 5);
+        let skips = "I64 main() { I64 sum = 0; for (I64 i = 0; i < 10; i = i + 1) { if ((i >> 1) << 1 == i) { continue; } sum = sum + i; } return sum; }";
+        assert_eq!(run(skips), 25);
+    }
+}
+
+#[cfg(test)]
+mod class_tests {
+    use super::*;
+    use crate::pible::parser::NodeType;
+
+    const COUNTER: &str = "class Counter {\n    U64 count;\n    private U64 step;\n\n    public U0 add(U64 amount) {\n        this->count = this->count + amount * this->step;\n    }\n};\n";
+
+    #[test]
+    fn test_class_parses_fields_and_methods() {
+        let ast = Compiler::new().parse(COUNTER).unwrap();
+        let class = &ast.children[0];
+        assert_eq!(class.node_type, NodeType::ClassDecl);
+        assert_eq!(class.value.as_deref(), Some("Counter"));
+
+        let [count, step, add] = &class.children[..] else {
+            panic!("expected two fields and a method: {:?}", class.children);
+        };
+        assert_eq!(count.value.as_deref(), Some("U64:count"));
+        assert!(!count.private);
+        assert!(step.private);
+        assert_eq!(add.node_type, NodeType::FunctionDecl);
+        assert_eq!(
+            add.children[0].value.as_deref(),
+            Some("struct Counter*:this")
+        );
+        assert_eq!(add.children[1].value.as_deref(), Some("U64:amount"));
+    }
+
+    #[test]
+    fn test_method_mutates_field_in_vm() {
+        let source = format!(
+            "{}U64 main() {{\n    Counter c;\n    c.count = 5;\n    c.step = 2;\n    c.add(3);\n    Counter* p = c;\n    p->add(1);\n    return c.count;\n}}\n",
+            COUNTER
+        );
+        assert_eq!(run(&source), 13);
+    }
+
+    #[test]
+    fn test_class_compiles_for_solana() {
+        let source = format!(
+            "{}export U64 entrypoint(U8* input) {{ Counter c; c.count = 0; c.step = 1; c.add(4); return c.count; }}",
+            COUNTER
+        );
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..CompileOptions::default()
+        };
+        assert!(Compiler::new().compile(&source, &options).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod exit_path_tests {
+    use super::*;
+    use crate::pible::cfg::{self, BasicBlock};
+    use crate::pible::codegen::CodeGenError;
+
+    /// `if (r1 == 0) return 0;` followed by `r0 = 1` with no exit after it.
+    fn missing_else_return() -> Vec<BpfInstruction> {
+        vec![
+            BpfInstruction::new(0x55, 1, 0, 2, 0), // jne r1, 0, +2
+            BpfInstruction::new(0xb7, 0, 0, 0, 0), // mov r0, 0
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+            BpfInstruction::new(0xb7, 0, 0, 0, 1), // mov r0, 1
+        ]
+    }
+
+    #[test]
+    fn test_basic_blocks_split_at_branches_and_exits() {
+        let blocks = cfg::basic_blocks(&missing_else_return());
+        assert_eq!(
+            blocks,
+            vec![
+                BasicBlock {
+                    start: 0,
+                    end: 1,
+                    successors: vec![1, 2],
+                    falls_off: false,
+                },
+                BasicBlock {
+                    start: 1,
+                    end: 3,
+                    successors: vec![],
+                    falls_off: false,
+                },
+                BasicBlock {
+                    start: 3,
+                    end: 4,
+                    successors: vec![],
+                    falls_off: true,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_without_exit_is_flagged() {
+        assert!(matches!(
+            cfg::check_exit_paths(&missing_else_return()),
+            Err(CodeGenError::MissingReturn(3))
+        ));
+
+        let mut fixed = missing_else_return();
+        fixed.push(BpfInstruction::new(0x95, 0, 0, 0, 0));
+        assert!(cfg::check_exit_paths(&fixed).is_ok());
+    }
+
+    #[test]
+    fn test_called_function_must_exit() {
+        // main calls a function at 3 that falls off the end
+        let instructions = vec![
+            BpfInstruction::new(0x85, 0, 1, 0, 2), // call +2
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+            BpfInstruction::new(0x18, 0, 0, 0, 7), // lddw r0, 7
+            BpfInstruction::new(0x00, 0, 0, 0, 0),
+            BpfInstruction::new(0xb7, 0, 0, 0, 1), // mov r0, 1
+        ];
+        assert!(matches!(
+            cfg::check_exit_paths(&instructions),
+            Err(CodeGenError::MissingReturn(4))
+        ));
+    }
+
+    #[test]
+    fn test_generated_branches_all_exit() {
+        let source = "U64 pick(U64 x) { if (x == 0) { return 1; } else { x = x + 1; } }\nU64 main() { return pick(0); }";
+        assert_eq!(run(source), 1);
+    }
+}
+
+#[cfg(test)]
+mod source_line_tests {
+    use super::*;
+    use crate::pible::bpf_vm::VmConfig;
+    use crate::pible::disasm;
+
+    const SOURCE: &str = "U64 main() {\n    U64 x = 7;\n    return x + 35;\n}\n";
+
+    fn generate(optimize: bool) -> (CodeGen, Vec<BpfInstruction>) {
+        let ast = Compiler::new().parse(SOURCE).unwrap();
+        let mut codegen = CodeGen::new();
+        let mut instructions = codegen.generate(&ast).unwrap();
+        if optimize {
+            instructions = codegen.optimize(&instructions);
+        }
+        (codegen, instructions)
+    }
+
+    /// Line of the instruction using the immediate `value`, as a load or an
+    /// immediate ALU operand.
+    fn line_of_immediate(
+        codegen: &CodeGen,
+        instructions: &[BpfInstruction],
+        value: i32,
+    ) -> Option<usize> {
+        let index = instructions
+            .iter()
+            .position(|instruction| {
+                matches!(instruction.opcode, 0xb7 | 0x07) && instruction.immediate == value
+            })
+            .expect("immediate is used");
+        codegen.source_lines()[index]
+    }
+
+    #[test]
+    fn test_statements_map_to_their_lines() {
+        let (codegen, instructions) = generate(false);
+        assert_eq!(codegen.source_lines().len(), instructions.len());
+        assert_eq!(line_of_immediate(&codegen, &instructions, 7), Some(2));
+        assert_eq!(line_of_immediate(&codegen, &instructions, 35), Some(3));
+    }
+
+    #[test]
+    fn test_lines_follow_instructions_through_optimization() {
+        let (codegen, instructions) = generate(true);
+        assert_eq!(codegen.source_lines().len(), instructions.len());
+        assert_eq!(line_of_immediate(&codegen, &instructions, 35), Some(3));
+    }
+
+    #[test]
+    fn test_disassembly_and_trace_show_lines() {
+        let (codegen, instructions) = generate(false);
+        let listing = disasm::disassemble_with_lines(&instructions, codegen.source_lines());
+        let line_3 = listing.find("; line 3").expect("line 3 is annotated");
+        assert!(listing.find("; line 2").unwrap() < line_3);
+
+        let config = VmConfig {
+            trace: true,
+            ..Default::default()
+        };
+        let mut vm =
+            BpfVm::with_config(&instructions, config).with_source_lines(codegen.source_lines());
+        vm.execute().unwrap();
+        let lines: Vec<usize> = vm.trace().iter().filter_map(|entry| entry.line).collect();
+        assert!(lines.contains(&2) && lines.contains(&3));
+    }
+}
+
+#[cfg(test)]
+mod memory_builtin_tests {
+    use super::*;
+    use crate::pible::solana_bpf;
+
+    /// `main` filling two 4-byte buffers, the second differing from the
+    /// first by `delta` in byte 2, and returning `MemCmp` over `len` bytes.
+    fn compare(delta: i32, len: usize) -> String {
+        format!(
+            "I64 main() {{\n    U8 a[4];\n    U8 b[4];\n    for (I64 i = 0; i < 4; i = i + 1) {{\n        a[i] = i + 10;\n        b[i] = i + 10;\n    }}\n    b[2] = b[2] + {};\n    return MemCmp(a, b, {});\n}}",
+            delta, len
+        )
+    }
+
+    #[test]
+    fn test_memcmp_of_equal_and_unequal_buffers() {
+        assert_eq!(run(&compare(0, 4)), 0);
+        assert_eq!(run(&compare(3, 4)), -3);
+        assert_eq!(run(&compare(-5, 4)), 5);
+        // Only the bytes before the difference are compared
+        assert_eq!(run(&compare(3, 2)), 0);
+        assert_eq!(run(&compare(3, 0)), 0);
+    }
+
+    #[test]
+    fn test_strcmp_and_strcpy() {
+        assert_eq!(run("I64 main() { return StrCmp(\"seed\", \"seed\"); }"), 0);
+        assert_eq!(
+            run("I64 main() { return StrCmp(\"seed\", \"seeds\"); }"),
+            -(b's' as i32)
+        );
+        let copy = "I64 main() {\n    U8 buffer[8];\n    StrCpy(buffer, \"vault\");\n    return StrCmp(buffer, \"vault\") + buffer[4];\n}";
+        assert_eq!(run(copy), b't' as i32);
+    }
+
+    #[test]
+    fn test_builtin_arity_is_checked() {
+        assert!(matches!(
+            try_run("I64 main() { U8 a[2]; return MemCmp(a, a); }"),
+            Err(CompileError::CodeGenError(_))
+        ));
+    }
+
+    #[test]
+    fn test_memcmp_uses_syscall_on_solana() {
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..CompileOptions::default()
+        };
+        let source = "export U64 entrypoint(U8* input) { return MemCmp(input, input, 32); }";
+        let bytecode = Compiler::new().compile(source, &options).unwrap();
+        let memcmp = solana_bpf::syscall_id("sol_memcmp_") as i32;
+        assert!(bytecode.chunks_exact(8).any(|chunk| {
+            let instruction = BpfInstruction::from_bytes(chunk.try_into().unwrap());
+            instruction.opcode == 0x85 && instruction.immediate == memcmp
+        }));
+    }
+
+    /// `main` filling a 24-byte buffer with 1..=24, zeroing `len` bytes
+    /// from index 2, with `n` set to `value`, and returning the buffer's sum.
+    fn zero(value: i64, len: &str) -> String {
+        format!(
+            "I64 main() {{\n    U8 a[24];\n    I64 n = {};\n    for (I64 i = 0; i < 24; i = i + 1) {{\n        a[i] = i + 1;\n    }}\n    MemSet(a + 2, 0, {});\n    I64 sum = 0;\n    for (I64 i = 0; i < 24; i = i + 1) {{\n        sum = sum + a[i];\n    }}\n    return sum;\n}}",
+            value, len
+        )
+    }
+
+    #[test]
+    fn test_memset_zeroes_a_region() {
+        let untouched = |len: i32| (1..=24).filter(|i| *i <= 2 || *i > 2 + len).sum::<i32>();
+        // Unrolled constant lengths and the loop over a variable one
+        assert_eq!(run(&zero(0, "5")), untouched(5));
+        assert_eq!(run(&zero(0, "0")), untouched(0));
+        assert_eq!(run(&zero(20, "n")), untouched(20));
+        assert_eq!(
+            run("I64 main() {\n    U8 a[4];\n    MemSet(a, 0x1ab, 4);\n    return a[0] + a[3] * 256;\n}"),
+            0xabab
+        );
+    }
+
+    #[test]
+    fn test_memcpy_copies_between_regions() {
+        let copy = |value: i64, len: &str| {
+            format!(
+                "I64 main() {{\n    U8 src[20];\n    U8 dst[20];\n    I64 n = {};\n    for (I64 i = 0; i < 20; i = i + 1) {{\n        src[i] = i + 100;\n        dst[i] = 0;\n    }}\n    U8* end = MemCpy(dst, src, {});\n    I64 sum = 0;\n    for (I64 i = 0; i < 20; i = i + 1) {{\n        sum = sum * 3 + dst[i];\n    }}\n    return sum + (end == dst);\n}}",
+                value, len
+            )
+        };
+        let expected = |len: i64| {
+            (0..20).fold(0i64, |sum, i| sum * 3 + if i < len { i + 100 } else { 0 }) as i32 + 1
+        };
+        assert_eq!(run(&copy(0, "7")), expected(7));
+        assert_eq!(run(&copy(20, "n")), expected(20));
+        assert_eq!(run(&copy(13, "n")), expected(13));
+    }
+
+    #[test]
+    fn test_small_constant_memcpy_is_unrolled() {
+        let source = "U0 main() { U8 a[8]; U8 b[8]; MemCpy(a, b, 8); }";
+        let bytecode = Compiler::new()
+            .compile(source, &CompileOptions::default())
+            .unwrap();
+        let instructions: Vec<_> = bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect();
+        // Eight byte loads and stores, with no backward jump
+        assert_eq!(instructions.iter().filter(|i| i.opcode == 0x71).count(), 8);
+        assert!(!instructions
+            .iter()
+            .any(|i| i.opcode == 0x05 && i.offset < 0));
+    }
+
+    #[test]
+    fn test_memset_and_memcpy_use_syscalls_on_solana() {
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..CompileOptions::default()
+        };
+        let source = "export U64 entrypoint(U8* input) {\n    MemSet(input, 0, 32);\n    MemCpy(input, input + 32, 32);\n    return 0;\n}";
+        let bytecode = Compiler::new().compile(source, &options).unwrap();
+        for symbol in ["sol_memset_", "sol_memcpy_"] {
+            let id = solana_bpf::syscall_id(symbol) as i32;
+            assert!(bytecode.chunks_exact(8).any(|chunk| {
+                let instruction = BpfInstruction::from_bytes(chunk.try_into().unwrap());
+                instruction.opcode == 0x85 && instruction.immediate == id
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod verifier_tests {
+    use super::*;
+    use crate::pible::solana_bpf::VerifyError;
+
+    fn solana_options(verify: bool) -> CompileOptions<'static> {
+        CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            verify,
+            ..CompileOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_uninitialized_read_is_rejected() {
+        let instructions = vec![
+            BpfInstruction::new(0xbf, 0, 2, 0, 0), // mov r0, r2
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        assert_eq!(
+            SolanaBpf::verify(&instructions),
+            Err(vec![VerifyError::UninitializedRead {
+                index: 0,
+                register: 2
+            }])
+        );
+    }
+
+    #[test]
+    fn test_register_set_on_one_path_only_is_rejected() {
+        let instructions = vec![
+            BpfInstruction::new(0x15, 1, 0, 1, 0), // jeq r1, 0, +1
+            BpfInstruction::new(0xb7, 3, 0, 0, 1), // mov r3, 1
+            BpfInstruction::new(0xbf, 0, 3, 0, 0), // mov r0, r3
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        assert_eq!(
+            SolanaBpf::verify(&instructions),
+            Err(vec![VerifyError::UninitializedRead {
+                index: 2,
+                register: 3
+            }])
+        );
+
+        let mut both_paths = instructions;
+        both_paths.insert(0, BpfInstruction::new(0xb7, 3, 0, 0, 0));
+        assert_eq!(SolanaBpf::verify(&both_paths), Ok(()));
+    }
+
+    #[test]
+    fn test_jump_into_lddw_is_rejected() {
+        let instructions = vec![
+            BpfInstruction::new(0x05, 0, 0, 1, 0), // ja +1, the lddw's second half
+            BpfInstruction::new(0x18, 0, 0, 0, 7), // lddw r0, 7
+            BpfInstruction::new(0x00, 0, 0, 0, 0),
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        assert_eq!(
+            SolanaBpf::verify(&instructions),
+            Err(vec![VerifyError::BadJumpTarget {
+                index: 0,
+                target: 2
+            }])
+        );
+    }
+
+    #[test]
+    fn test_frame_pointer_and_stack_rules() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 10, 0, 0, 0),     // mov r10, 0
+            BpfInstruction::new(0x07, 10, 0, 0, -64),   // add r10, -64
+            BpfInstruction::new(0x7b, 10, 1, -4, 0),    // stxdw [r10-4], r1
+            BpfInstruction::new(0x79, 0, 10, -4104, 0), // ldxdw r0, [r10-4104]
+            BpfInstruction::new(0x95, 0, 0, 0, 0),      // exit
+        ];
+        assert_eq!(
+            SolanaBpf::verify(&instructions),
+            Err(vec![
+                VerifyError::FramePointerWrite { index: 0 },
+                VerifyError::StackOutOfBounds {
+                    index: 2,
+                    offset: -4,
+                    size: 8
+                },
+                VerifyError::StackOutOfBounds {
+                    index: 3,
+                    offset: -4104,
+                    size: 8
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_verify_option_checks_compiled_programs() {
+        let entrypoint =
+            "export U64 entrypoint(U8* input) { U64 x = 3; if (x > 2) { return 1; } return 0; }";
+        assert!(Compiler::new()
+            .compile(entrypoint, &solana_options(true))
+            .is_ok());
+
+        // Without an entrypoint the program starts in `add`, whose second
+        // argument the runtime never sets
+        let library = "U64 add(U64 a, U64 b) { return a + b; }";
+        assert!(Compiler::new()
+            .compile(library, &solana_options(false))
+            .is_ok());
+        match Compiler::new().compile(library, &solana_options(true)) {
+            Err(CompileError::VerifyFailed(errors)) => {
+                assert!(errors.contains(&VerifyError::UninitializedRead {
+                    index: 1,
+                    register: 2
+                }))
+            }
+            other => panic!("expected a verifier failure, got {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod stream_tests {
+    use super::*;
+    use std::io::{self, Cursor, Read};
+
+    const SOURCE: &str = "U64 main() { return 42; }";
+
+    #[test]
+    fn test_compile_from_cursor_to_vec() {
+        let mut output = Vec::new();
+        Compiler::new()
+            .compile_to_writer(Cursor::new(SOURCE), &mut output, &CompileOptions::default())
+            .unwrap();
+        let expected = Compiler::new()
+            .compile(SOURCE, &CompileOptions::default())
+            .unwrap();
+        assert_eq!(output, expected);
+        assert!(!output.is_empty());
+    }
+
+    #[test]
+    fn test_read_failure_is_an_io_error() {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("pipe closed"))
+            }
+        }
+
+        assert!(matches!(
+            Compiler::new().compile_reader(Broken, &CompileOptions::default()),
+            Err(CompileError::IoError(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod symbol_tests {
+    use super::*;
+    use crate::pible::compiler::Symbol;
+
+    fn decode(bytecode: &[u8]) -> Vec<BpfInstruction> {
+        bytecode
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Targets of the local calls in `instructions`.
+    fn call_targets(instructions: &[BpfInstruction]) -> Vec<usize> {
+        instructions
+            .iter()
+            .enumerate()
+            .filter(|(_, instruction)| instruction.opcode == 0x85 && instruction.src_reg == 1)
+            .map(|(index, instruction)| (index as i64 + 1 + instruction.immediate as i64) as usize)
+            .collect()
+    }
+
+    fn symbol<'a>(symbols: &'a [Symbol], name: &str) -> &'a Symbol {
+        symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .unwrap_or_else(|| panic!("no symbol for {}: {:?}", name, symbols))
+    }
+
+    #[test]
+    fn test_two_functions_get_distinct_offsets() {
+        let source = "U64 twice(U64 x) { return x * 2; }\nU64 main() { return twice(21); }";
+        let compilation = Compiler::new()
+            .compile_with_warnings(source, &CompileOptions::default())
+            .unwrap();
+        let symbols = &compilation.symbols;
+        assert_eq!(symbols.len(), 2);
+
+        // `main` is laid out first as the entry function
+        let (main, twice) = (symbol(symbols, "main"), symbol(symbols, "twice"));
+        assert_eq!(main.instruction, 0);
+        assert!(twice.instruction > main.instruction);
+        assert_eq!(twice.offset, twice.instruction * 8);
+        assert_eq!(
+            call_targets(&decode(&compilation.bytecode)),
+            vec![twice.instruction]
+        );
+    }
+
+    #[test]
+    fn test_solana_symbols_account_for_the_entrypoint_stub() {
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..CompileOptions::default()
+        };
+        let source = "export U64 entrypoint(U8* input) { return 0; }";
+        let compilation = Compiler::new()
+            .compile_with_warnings(source, &options)
+            .unwrap();
+        let entrypoint = symbol(&compilation.symbols, "entrypoint");
+        assert!(entrypoint.instruction > 0);
+        assert!(call_targets(&decode(&compilation.bytecode)).contains(&entrypoint.instruction));
+    }
+}
+
+#[cfg(test)]
+mod overflow_tests {
+    use super::*;
+    use crate::pible::bpf_vm::{Overflow, VmConfig, VmError};
+
+    /// `r0 = start; r1 = 1; r0 += r1; exit`
+    fn add_one(start: i32) -> Vec<BpfInstruction> {
+        vec![
+            BpfInstruction::new(0xb7, 0, 0, 0, start), // mov r0, start
+            BpfInstruction::new(0xb7, 1, 0, 0, 1),     // mov r1, 1
+            BpfInstruction::new(0x0f, 0, 1, 0, 0),     // add r0, r1
+            BpfInstruction::new(0x95, 0, 0, 0, 0),     // exit
+        ]
+    }
+
+    fn run(instructions: &[BpfInstruction], overflow: Overflow) -> Result<i32, VmError> {
+        let config = VmConfig {
+            overflow,
+            ..Default::default()
+        };
+        BpfVm::with_config(instructions, config)
+            .execute()
+            .map(|result| result.exit_code)
+    }
+
+    #[test]
+    fn test_u64_max_plus_one_wraps_by_default() {
+        // -1 is U64::MAX
+        assert_eq!(run(&add_one(-1), Overflow::default()).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_u64_max_plus_one_traps_when_checked() {
+        assert!(matches!(
+            run(&add_one(-1), Overflow::Unsigned),
+            Err(VmError::Overflow(2))
+        ));
+        // The same bits are -1 + 1 to a signed reading
+        assert_eq!(run(&add_one(-1), Overflow::Signed).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_signed_overflow_traps_when_checked() {
+        let mut instructions = add_one(-1);
+        // r0 = i64::MAX, all ones shifted right once
+        instructions.insert(1, BpfInstruction::new(0x77, 0, 0, 0, 1)); // rsh r0, 1
+        assert!(matches!(
+            run(&instructions, Overflow::Signed),
+            Err(VmError::Overflow(3))
+        ));
+        assert!(run(&instructions, Overflow::Unsigned).is_ok());
+    }
+
+    #[test]
+    fn test_negative_immediate_is_not_an_unsigned_overflow() {
+        let instructions = vec![
+            BpfInstruction::new(0xb7, 0, 0, 0, 10), // mov r0, 10
+            BpfInstruction::new(0x07, 0, 0, 0, -3), // add r0, -3
+            BpfInstruction::new(0x95, 0, 0, 0, 0),  // exit
+        ];
+        assert_eq!(run(&instructions, Overflow::Unsigned).unwrap(), 7);
+    }
+}
+
+#[cfg(test)]
+mod token_span_tests {
+    use super::*;
+
+    #[test]
+    fn test_spans_slice_back_to_lexemes() {
+        let source = "/* header\n   spans lines */\nU64 main() {\n  // café\n  U8 *s = \"two\nlines\";\n  return 0x1F >> 2;\n}\n";
+        let tokens = Lexer::new(source).keeping_comments().scan_tokens().unwrap();
+        assert!(tokens
+            .iter()
+            .any(|tok| tok.token_type == TokenType::Comment));
+        for tok in &tokens {
+            assert_eq!(&source[tok.start..tok.end], tok.lexeme, "{:?}", tok);
+        }
+    }
+
+    #[test]
+    fn test_eof_span_is_empty_at_end_of_source() {
+        let source = "U0 f() {}  ";
+        let tokens = Lexer::new(source).scan_tokens().unwrap();
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.token_type, TokenType::Eof);
+        assert_eq!((eof.start, eof.end), (source.len(), source.len()));
+    }
+
+    #[test]
+    fn test_spans_are_ordered_and_disjoint() {
+        let source = "I64 x = a+b*c; x->y.z;";
+        let tokens = Lexer::new(source).scan_tokens().unwrap();
+        for pair in tokens.windows(2) {
+            assert!(pair[0].end <= pair[1].start);
+        }
+    }
+}
+
+#[cfg(test)]
+mod loop_jump_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+
+    #[test]
+    fn test_break_leaves_loop_at_five() {
+        let source = "U64 main() {\n    U64 i = 0;\n    while (i < 100) {\n        if (i == 5) {\n            break;\n        }\n        i = i + 1;\n    }\n    return i;\n}\n";
+        assert_eq!(run(source), 5);
+    }
+
+    #[test]
+    fn test_continue_skips_even_values() {
+        let source = "U64 main() {\n    U64 sum = 0;\n    for (U64 i = 0; i < 10; i = i + 1) {\n        if (i % 2 == 0) {\n            continue;\n        }\n        sum = sum + i;\n    }\n    return sum;\n}\n";
+        assert_eq!(run(source), 25);
+    }
+
+    #[test]
+    fn test_jumps_target_innermost_loop() {
+        // The inner `break` only leaves the inner loop, and the outer
+        // `continue` re-tests the outer condition
+        let source = "U64 main() {\n    U64 count = 0;\n    U64 i = 0;\n    while (i < 4) {\n        i = i + 1;\n        for (U64 j = 0; j < 10; j = j + 1) {\n            if (j == i) {\n                break;\n            }\n            count = count + 1;\n        }\n        if (i == 2) {\n            continue;\n        }\n        count = count + 100;\n    }\n    return count;\n}\n";
+        // Inner loop counts 1 + 2 + 3 + 4; the outer adds 100 on all but i == 2
+        assert_eq!(run(source), 310);
+    }
+
+    #[test]
+    fn test_continue_in_nested_for_loop() {
+        // Count the pairs on opposite sides, skipping pairs on the same side
+        let source = "U64 main() {\n    U64 sides[5];\n    sides[0] = 0;\n    sides[1] = 1;\n    sides[2] = 1;\n    sides[3] = 0;\n    sides[4] = 1;\n    U64 matches = 0;\n    for (U64 i = 0; i < 5; i++) {\n        for (U64 j = i + 1; j < 5; j++) {\n            if (sides[i] == sides[j]) continue;\n            matches += 1;\n        }\n    }\n    return matches;\n}\n";
+        assert_eq!(run(source), 6);
+    }
+
+    #[test]
+    fn test_break_and_continue_compile_for_solana() {
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..CompileOptions::default()
+        };
+        let source = "U64 entrypoint(U8* input) {\n    U64 total = 0;\n    for (U64 i = 0; i < 32; i++) {\n        if (input[i] == 0) continue;\n        if (input[i] == 255) break;\n        total += input[i];\n    }\n    return total;\n}\n";
+        assert!(Compiler::new().compile(source, &options).is_ok());
+    }
+
+    #[test]
+    fn test_break_outside_loop_is_an_error() {
+        let error = try_run("U64 main() { break; return 0; }").unwrap_err();
+        assert!(matches!(
+            error,
+            CompileError::CodeGenError(CodeGenError::OutsideLoop(ref kind)) if kind == "break"
+        ));
+    }
+}
+
+#[cfg(test)]
+mod run_bytecode_tests {
+    use super::*;
+    use crate::pible::bpf_vm::{run_bytecode, VmError};
+
+    fn compile(source: &str) -> Vec<u8> {
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            ..CompileOptions::default()
+        };
+        Compiler::new().compile(source, &options).unwrap()
+    }
+
+    #[test]
+    fn test_compiled_program_runs_from_bytecode() {
+        let bytecode = compile("U64 main() { return 42; }");
+        let result = run_bytecode(&bytecode, &[]).unwrap();
+        assert_eq!(result.exit_code, 42);
+        assert!(result.to_string().starts_with("exit code: 42\n"));
+    }
+
+    #[test]
+    fn test_input_is_passed_in_r1() {
+        let bytecode = compile("U64 main(U8* input) { return input[0] + input[1]; }");
+        let result = run_bytecode(&bytecode, &[0x0a, 0x0b]).unwrap();
+        assert_eq!(result.exit_code, 21);
+    }
+
+    #[test]
+    fn test_partial_instruction_is_rejected() {
+        let mut bytecode = compile("U64 main() { return 0; }");
+        bytecode.pop();
+        assert!(matches!(
+            run_bytecode(&bytecode, &[]),
+            Err(VmError::InvalidInstruction(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod register_spill_tests {
+    use super::*;
+
+    /// `(x * 0 + (x * 1 + (... + x)))`, which keeps one more temporary live
+    /// at each level.
+    fn nested(levels: usize, innermost: &str) -> String {
+        (0..levels).fold(innermost.to_string(), |acc, i| {
+            format!("(x * {} + {})", i, acc)
+        })
+    }
+
+    #[test]
+    fn test_deeply_nested_additions_spill_to_the_stack() {
+        let source = format!("U64 main() {{ U64 x = 2; return {}; }}", nested(12, "x"));
+        let instructions = generate_instructions(&source);
+        // stxdw r6 to the frame
+        assert!(instructions
+            .iter()
+            .any(|i| i.opcode == 0x7b && i.dst_reg == 10 && i.src_reg == 6));
+        assert_eq!(run_codegen(&source).1, 2 * 66 + 2);
+    }
+
+    #[test]
+    fn test_branches_inside_spilled_expressions() {
+        let innermost = "(x > 1 && x < 5 ? x + 10 : 100)";
+        let source = format!(
+            "U64 main() {{ U64 x = 2; return {}; }}",
+            nested(6, innermost)
+        );
+        assert_eq!(run_codegen(&source).1, 2 * 15 + 12);
+
+        let source = format!(
+            "U64 main() {{ U64 x = 7; return {}; }}",
+            nested(6, innermost)
+        );
+        assert_eq!(run_codegen(&source).1, 7 * 15 + 100);
+    }
+
+    #[test]
+    fn test_spilled_temporaries_survive_calls() {
+        let source = format!(
+            "U64 twice(U64 n) {{ return n + n; }} U64 main() {{ U64 x = 3; return {}; }}",
+            nested(8, "twice(x + twice(x))")
+        );
+        assert_eq!(run_codegen(&source).1, 3 * 28 + 18);
+    }
+}
+
+#[cfg(test)]
+mod require_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+    use crate::pible::compiler::CompileError;
+
+    #[test]
+    fn test_failing_require_exits_with_its_code() {
+        let source = r#"
+            U64 main() {
+                U64 balance = 5;
+                Require(balance > 0, 10);
+                Require(balance >= 8, 6001);
+                return 0;
+            }
+        "#;
+        assert_eq!(run(source), 6001);
+    }
+
+    #[test]
+    fn test_passing_require_continues() {
+        let source = r#"
+            U64 check(U64 amount) {
+                Require(amount < 100, 7);
+                return amount * 2;
+            }
+
+            U64 main() {
+                U64 doubled = check(21);
+                Require(doubled == 42, 1);
+                return doubled + check(200);
+            }
+        "#;
+        assert_eq!(run(source), 49);
+    }
+
+    #[test]
+    fn test_require_arity_is_checked() {
+        let error = Compiler::new()
+            .compile(
+                "U64 main() { Require(1); return 0; }",
+                &CompileOptions::default(),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            CompileError::CodeGenError(CodeGenError::InvalidInstruction(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod literal_range_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+
+    fn compile(source: &str) -> Result<Vec<u8>, CompileError> {
+        Compiler::new().compile(source, &CompileOptions::default())
+    }
+
+    #[test]
+    fn test_literal_fitting_u32() {
+        let source = "U64 main() { U32 x = 4000000000; return x / 1000000; }";
+        assert_eq!(run(source), 4000);
+    }
+
+    #[test]
+    fn test_literal_needing_u64_loads_with_lddw() {
+        let source = "U64 main() { U64 x = 5000000000; return x / 1000000; }";
+        assert_eq!(run(source), 5000);
+
+        let mut lexer = Lexer::new(source);
+        let mut parser = Parser::new(lexer.scan_tokens().unwrap());
+        let instructions = CodeGen::new().generate(&parser.parse().unwrap()).unwrap();
+        assert!(instructions.iter().any(|i| i.opcode == 0x18)); // lddw
+
+        let source = "U64 main() { U64 x = 18446744073709551615; return x == 0 - 1; }";
+        assert_eq!(run(source), 1);
+    }
+
+    #[test]
+    fn test_literal_overflowing_its_type_is_reported() {
+        let error = compile("U64 main() { U64 x = 18446744073709551616; return 0; }").unwrap_err();
+        assert!(matches!(error, CompileError::LiteralOverflow(_)));
+        assert!(error
+            .to_string()
+            .contains("18446744073709551616 doesn't fit in U64"));
+
+        let error = compile("U64 main() { U8 x = 0; x = 256; return x; }").unwrap_err();
+        assert!(error.to_string().contains("256 doesn't fit in U8"));
+        assert!(compile("U64 main() { I8 x = -128; U8 y = 255; return x + y; }").is_ok());
+        assert!(compile("U64 main() { I8 x = -129; return x; }").is_err());
+    }
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use crate::pible::repl::{self, Session};
+
+    fn transcript(input: &str) -> String {
+        let mut output = Vec::new();
+        repl::run(input.as_bytes(), &mut output, false).expect("Writing to a Vec should succeed");
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_session_keeps_definitions_and_statements() {
+        let output = transcript(
+            "U64 square(U64 n) { return n * n; }\n\
+             U64 x = 6;\n\
+             square(x) + 1\n\
+             x = x + 1;\n\
+             square(x)\n",
+        );
+        assert_eq!(output, "37\n49\n");
+    }
+
+    #[test]
+    fn test_bad_lines_report_errors_and_leave_the_session_alone() {
+        let mut session = Session::new();
+        assert_eq!(session.eval("U64 x = 2;").unwrap(), None);
+        assert!(session.eval("1 +").is_err());
+        assert!(session.eval("y = 3;").is_err());
+        assert_eq!(session.eval("x * 21").unwrap(), Some(42));
+
+        let output = transcript("nope(\n5\n");
+        assert!(output.starts_with("error: "));
+        assert!(output.ends_with("\n5\n"));
+    }
+}
+
+#[cfg(test)]
+mod function_pointer_tests {
+    use super::*;
+    use crate::pible::types::Type;
+
+    const TABLE: &str = r#"
+        U64 double(U64 n) { return n * 2; }
+        U64 square(U64 n) { return n * n; }
+        U64 apply(U64 (*op)(U64), U64 value) { return op(value); }
+        U64 main() {
+            U64 (*ops[2])(U64) = {double, square};
+            U64 total = 0;
+            for (U64 i = 0; i < 2; i = i + 1) {
+                total = total + ops[i](5);
+            }
+            return total + apply(square, 10);
+        }
+    "#;
+
+    #[test]
+    fn test_parse_double_and_function_pointers() {
+        let source = r#"
+            U0 sign(U8** seeds, U0 (*callback)(U64)) { }
+            U64 main() { U64 (*op)(U64 n, U8* data); return 0; }
+        "#;
+        let ast = Compiler::new()
+            .parse(source)
+            .expect("Parsing should succeed");
+        let params: Vec<_> = ast.children[0]
+            .children
+            .iter()
+            .filter_map(|param| param.value.as_deref())
+            .collect();
+        assert_eq!(params, ["U8**:seeds", "U0(*)(U64):callback"]);
+        let body = &ast.children[1].children[0];
+        assert_eq!(
+            body.children[0].children[0].value.as_deref(),
+            Some("U64(*)(U64,U8*):op")
+        );
+
+        let u64 = Type::parse("U64").unwrap();
+        assert_eq!(
+            Type::parse("U0(*)(U64)[2]"),
+            Some(Type::Array(
+                Box::new(Type::Pointer(Box::new(Type::Function {
+                    ret: Box::new(Type::Void),
+                    params: vec![u64.clone()],
+                }))),
+                2
+            ))
+        );
+        assert_eq!(
+            Type::parse("U8**"),
+            Some(Type::Pointer(Box::new(Type::Pointer(Box::new(
+                Type::parse("U8").unwrap()
+            )))))
+        );
+    }
+
+    #[test]
+    fn test_indirect_calls_through_a_function_table() {
+        assert_eq!(run(TABLE), 10 + 25 + 100);
+
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            ..CompileOptions::default()
+        };
+        assert!(Compiler::new().compile(TABLE, &options).is_ok());
+    }
+
+    #[test]
+    fn test_indirect_calls_are_type_checked() {
+        let compile = |source| Compiler::new().compile(source, &CompileOptions::default());
+        let source = r#"
+            U64 square(U64 n) { return n * n; }
+            U64 main() { U64 (*op)(U64) = square; return op(1, 2); }
+        "#;
+        match compile(source) {
+            Err(CompileError::TypeMismatch(message)) => {
+                assert_eq!(message, "op takes 1 arguments but 2 were given")
+            }
+            other => panic!("expected TypeMismatch, got {:?}", other),
+        }
+        assert!(matches!(
+            compile("U64 main() { U64 table[2]; return table[0](1); }"),
+            Err(CompileError::TypeMismatch(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod reproducible_build_tests {
+    use super::*;
+    use crate::pible::solana_bpf;
+
+    const SOURCE: &str = r#"
+        U64 zeta(U64 n) { return n + 1; }
+        U64 alpha(U64 n) { return zeta(n) * 2; }
+        U64 middle(U64 n) { return alpha(n) - 3; }
+        export U64 entrypoint(U8* input) { PrintF("hi\n"); return middle(4); }
+    "#;
+
+    #[test]
+    fn test_same_source_gives_identical_bytes_and_hash() {
+        for (target, output_format) in [
+            (CompileTarget::LinuxBpf, OutputFormat::Elf),
+            (CompileTarget::SolanaBpf, OutputFormat::Raw),
+            (CompileTarget::BpfVm, OutputFormat::Raw),
+        ] {
+            let options = CompileOptions {
+                target,
+                output_format,
+                ..CompileOptions::default()
+            };
+            let (first, first_hash) = Compiler::new()
+                .compile_with_hash(SOURCE, &options)
+                .expect("Compilation should succeed");
+            let (second, second_hash) = Compiler::new()
+                .compile_with_hash(SOURCE, &options)
+                .expect("Compilation should succeed");
+            assert_eq!(first, second);
+            assert_eq!(first_hash, second_hash);
+            assert_eq!(first_hash, solana_bpf::sha256(&first));
+        }
+    }
+
+    #[test]
+    fn test_sha256_matches_known_digests() {
+        let hex = |digest: [u8; 32]| -> String {
+            digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+        };
+        assert_eq!(
+            hex(solana_bpf::sha256(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(solana_bpf::sha256(b"abc")),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
+
+#[cfg(test)]
+mod log_helper_tests {
+    use super::*;
+
+    /// Immediates of the helper calls in `source` compiled for `target`.
+    fn helper_calls(target: CompileTarget, log_helper_id: Option<i32>) -> Vec<i32> {
+        let options = CompileOptions {
+            target,
+            log_helper_id,
+            ..CompileOptions::default()
+        };
+        let source = r#"U64 main() { PrintF("hello\n"); return 0; }"#;
+        Compiler::new()
+            .compile(source, &options)
+            .expect("Compilation should succeed")
+            .chunks_exact(8)
+            .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+            .filter(|instruction| instruction.opcode == 0x85 && instruction.src_reg == 0)
+            .map(|instruction| instruction.immediate)
+            .collect()
+    }
+
+    #[test]
+    fn test_log_helper_override_replaces_the_target_default() {
+        assert_eq!(helper_calls(CompileTarget::LinuxBpf, None), [6]);
+        assert_eq!(helper_calls(CompileTarget::LinuxBpf, Some(42)), [42]);
+
+        let solana_default = helper_calls(CompileTarget::SolanaBpf, None);
+        assert_ne!(solana_default, [42]);
+        assert_eq!(helper_calls(CompileTarget::SolanaBpf, Some(42)), [42]);
+    }
+}
+
+#[cfg(test)]
+mod visitor_tests {
+    use crate::pible::compiler::Compiler;
+    use crate::pible::parser::{Node, NodeType};
+    use crate::pible::visit::{self, walk_transform, Transform};
+
+    #[test]
+    fn test_node_counter_counts_every_node() {
+        // Program, function, block, return statement and literal
+        let ast = Compiler::new()
+            .parse("U64 main() { return 1; }")
+            .expect("Parsing should succeed");
+        assert_eq!(visit::count_nodes(&ast), 5);
+
+        let ast = Compiler::new()
+            .parse("U64 main() { U64 x = 1 + 2; return x; }")
+            .expect("Parsing should succeed");
+        // ... plus var statement, declared name, `+` and two literals,
+        // and the returned identifier instead of a literal
+        assert_eq!(visit::count_nodes(&ast), 10);
+    }
+
+    /// Renames every identifier `from` to `to`.
+    struct Rename {
+        from: &'static str,
+        to: &'static str,
+    }
+
+    impl Transform for Rename {
+        fn transform_node(&mut self, node: &Node) -> Node {
+            let mut node = walk_transform(self, node);
+            if node.node_type == NodeType::Identifier && node.value.as_deref() == Some(self.from) {
+                node.value = Some(self.to.to_string());
+            }
+            node
+        }
+    }
+
+    #[test]
+    fn test_transform_rebuilds_the_tree() {
+        let ast = Compiler::new()
+            .parse("U64 main() { return a + a * b; }")
+            .expect("Parsing should succeed");
+        let renamed = Rename { from: "a", to: "c" }.transform_node(&ast);
+        let json = renamed.to_json();
+        assert!(!json.contains("\"a\""));
+        assert_eq!(json.matches("\"c\"").count(), 2);
+        assert_eq!(visit::count_nodes(&renamed), visit::count_nodes(&ast));
+    }
+}
+
+#[cfg(test)]
+mod immediate_alu_tests {
+    use super::*;
+
+    #[test]
+    fn test_constant_operand_uses_immediate_form() {
+        let source = "U64 main() { U64 x = 2; return x + 5; }";
+        let ast = Compiler::new().parse(source).unwrap();
+        let instructions = CodeGen::new().generate(&ast).unwrap();
+        assert!(instructions
+            .iter()
+            .any(|instruction| instruction.opcode == 0x07 && instruction.immediate == 5));
+        // No register-register add is left
+        assert!(!instructions
+            .iter()
+            .any(|instruction| instruction.opcode == 0x0f));
+
+        assert_eq!(run(source), 7);
+    }
+
+    #[test]
+    fn test_immediate_forms_compute_the_same_results() {
+        let source = "I64 main() { I64 x = 47; I64 y = -47; return (x % 5) * 100 + (y / 5) + (x - 40) * 10 + (x * 3) % 7; }";
+        assert_eq!(run(source), 2 * 100 + -9 + 7 * 10 + (47 * 3) % 7);
+    }
+}
+
+#[cfg(test)]
+mod fixed_point_tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_mul_and_div() {
+        // 3.0 * 2.0 and 6.0 / 4.0 with six decimal places
+        assert_eq!(
+            run("U64 main() { return FixedMul(3000000, 2000000, 1000000); }"),
+            6_000_000
+        );
+        assert_eq!(
+            run("U64 main() { return FixedDiv(6000000, 4000000, 1000000); }"),
+            1_500_000
+        );
+        // Fractions round down: 1.5 * 0.333333
+        assert_eq!(
+            run("U64 main() { U64 scale = 1000000; return FixedMul(1500000, 333333, scale); }"),
+            499_999
+        );
+    }
+
+    #[test]
+    fn test_fixed_mul_avoids_intermediate_overflow() {
+        // 3e12 * 2e12 overflows 64 bits, though the scaled product doesn't
+        let source = "U64 main() { U64 one = 1000000000000; return FixedMul(3 * one, 2 * one, one) == 6 * one; }";
+        assert_eq!(run(source), 1);
+    }
+
+    #[test]
+    fn test_fixed_point_arity_is_checked() {
+        let result = Compiler::new().compile(
+            "U64 main() { return FixedMul(1, 2); }",
+            &CompileOptions::default(),
+        );
+        assert!(result.is_err());
+    }
+}
+
+#[cfg(test)]
+mod safe_array_tests {
+    use super::*;
+    use crate::pible::codegen::INDEX_OUT_OF_BOUNDS;
+
+    /// Indexes a four-element array with `index`, after a sentinel that an
+    /// unchecked out-of-bounds read lands on.
+    fn program(index: &str) -> String {
+        format!(
+            "I64 pick(I64 i) {{ I64 after = 77; I64 values[4]; values[0] = 1; values[3] = 4; return values[{}]; }}\n\
+             I64 main() {{ return pick(4); }}",
+            index
+        )
+    }
+
+    fn run(source: &str, safe_arrays: bool) -> i32 {
+        let options = CompileOptions {
+            safe_arrays,
+            ..Default::default()
+        };
+        run_with(source, &options)
+    }
+
+    #[test]
+    fn test_out_of_bounds_index_traps_in_safe_mode() {
+        assert_eq!(run(&program("i"), true), INDEX_OUT_OF_BOUNDS);
+        assert_eq!(run(&program("i - 5"), true), INDEX_OUT_OF_BOUNDS);
+        assert_eq!(run(&program("4"), true), INDEX_OUT_OF_BOUNDS);
+    }
+
+    #[test]
+    fn test_out_of_bounds_index_reads_silently_by_default() {
+        // Past the end of the array is the variable declared before it
+        assert_eq!(run(&program("i"), false), 77);
+        assert_eq!(run(&program("4"), false), 77);
+    }
+
+    #[test]
+    fn test_in_bounds_indices_are_unaffected() {
+        for safe_arrays in [false, true] {
+            assert_eq!(run(&program("i - 1"), safe_arrays), 4);
+            assert_eq!(run(&program("0"), safe_arrays), 1);
+        }
+        // Stores are checked too
+        let source = "I64 main() { I64 values[2]; I64 i = 2; values[i] = 5; return 0; }";
+        assert_eq!(run(source, true), INDEX_OUT_OF_BOUNDS);
+    }
+}
+
+#[cfg(test)]
+mod inline_tests {
+    use super::*;
+    use crate::pible::bpf_vm::VmResult;
+
+    const SOURCE: &str = "U64 square(U64 x) { return x * x; }\n\
+                          U64 main() { U64 total = 0; U64 i = 1; while (i <= 4) { total = total + square(i); i = i + 1; } return total; }";
+
+    fn compile(source: &str, inline_threshold: usize) -> Vec<BpfInstruction> {
+        let ast = Compiler::new().parse(source).unwrap();
+        CodeGen::new()
+            .with_inline_threshold(inline_threshold)
+            .generate(&ast)
+            .unwrap()
+    }
+
+    fn run(source: &str, inline_threshold: usize) -> VmResult {
+        let options = CompileOptions {
+            inline_threshold,
+            ..Default::default()
+        };
+        Compiler::new().compile_and_run(source, &options).unwrap()
+    }
+
+    fn calls(instructions: &[BpfInstruction]) -> usize {
+        instructions
+            .iter()
+            .filter(|instruction| instruction.opcode == 0x85)
+            .count()
+    }
+
+    #[test]
+    fn test_inlined_helper_matches_called_helper() {
+        assert_eq!(calls(&compile(SOURCE, 0)), 1);
+        assert_eq!(calls(&compile(SOURCE, 20)), 0);
+
+        let (called, inlined) = (run(SOURCE, 0), run(SOURCE, 20));
+        assert_eq!(called.exit_code, 30);
+        assert_eq!(inlined.exit_code, 30);
+        // No call, parameter spill or return per iteration
+        assert!(inlined.compute_units < called.compute_units);
+    }
+
+    #[test]
+    fn test_only_small_functions_are_inlined() {
+        // `square` has more nodes than the threshold
+        assert_eq!(calls(&compile(SOURCE, 5)), 1);
+    }
+
+    #[test]
+    fn test_inlined_returns_and_requires_leave_the_helper_only() {
+        let source =
+            "U64 clamp(U64 x) { Require(x != 13, 99); if (x > 10) { return 10; } return x; }\n\
+                      U64 none() { }\n\
+                      U64 main() { return clamp(4) * 1000 + clamp(50) * 10 + none() + clamp(13); }";
+        assert_eq!(run(source, 0).exit_code, 4000 + 100 + 99);
+        assert_eq!(run(source, 100).exit_code, 4000 + 100 + 99);
+        assert_eq!(calls(&compile(source, 100)), 0);
+    }
+
+    #[test]
+    fn test_trap_in_inlined_body_keeps_spilled_temporaries() {
+        // The caller holds more temporaries than there are registers when
+        // the inlined bounds check fails
+        let source =
+            "I64 pick(I64 i) { I64 values[2]; values[0] = 7; values[1] = 8; return values[i]; }\n\
+                      I64 main() { return 1 + (2 + (3 + (4 + (5 + (pick(1) + pick(5)))))); }";
+        let expected = 15 + 8 + crate::pible::codegen::INDEX_OUT_OF_BOUNDS;
+        for inline_threshold in [0, 100] {
+            let options = CompileOptions {
+                inline_threshold,
+                safe_arrays: true,
+                ..Default::default()
+            };
+            assert_eq!(run_with(source, &options), expected);
+        }
+    }
+
+    #[test]
+    fn test_recursive_function_still_calls_itself() {
+        let source = "U64 fact(U64 n) { if (n < 2) { return 1; } return n * fact(n - 1); }\n\
+                      U64 main() { return fact(5); }";
+        assert_eq!(run(source, 100).exit_code, 120);
+        assert!(calls(&compile(source, 100)) > 0);
+    }
+}
+
+#[cfg(test)]
+mod tooling_tests {
+    use super::*;
+    use crate::pible::compiler::CompileError;
+    use crate::pible::parser::NodeType;
+
+    const SOURCE: &str = "#define LIMIT 3\n\
+                          // A counter\n\
+                          class Counter { I64 count; };\n\
+                          I64 main() {\n    return LIMIT;\n}\n";
+
+    #[test]
+    fn test_lex_only_keeps_source_as_written() {
+        let tokens = Compiler::new().lex_only(SOURCE).unwrap();
+        let types: Vec<TokenType> = tokens.iter().map(|token| token.token_type).collect();
+        assert_eq!(
+            &types[..4],
+            &[
+                TokenType::Directive,
+                TokenType::Comment,
+                TokenType::Class,
+                TokenType::Identifier,
+            ]
+        );
+        assert_eq!(types.last(), Some(&TokenType::Eof));
+
+        // The macro isn't expanded, and positions point into `SOURCE`
+        let limit = tokens
+            .iter()
+            .rfind(|token| token.lexeme == "LIMIT")
+            .unwrap();
+        assert_eq!((limit.line, limit.column), (5, 12));
+        assert_eq!(&SOURCE[limit.start..limit.end], "LIMIT");
+
+        assert!(Compiler::new().lex_only("I64 x = 1 @ 2;").is_err());
+    }
+
+    #[test]
+    fn test_parse_only_gives_the_unlowered_ast() {
+        let ast = Compiler::new().parse_only(SOURCE).unwrap();
+        assert_eq!(ast.node_type, NodeType::Program);
+        let kinds: Vec<&NodeType> = ast.children.iter().map(|child| &child.node_type).collect();
+        assert_eq!(kinds, [&NodeType::ClassDecl, &NodeType::FunctionDecl]);
+
+        let main = &ast.children[1];
+        assert_eq!(main.value.as_deref(), Some("I64:main"));
+        let body = main.children.last().unwrap();
+        assert_eq!(body.node_type, NodeType::Block);
+        // The macro was expanded before parsing
+        let returned = &body.children[0].children[0];
+        assert_eq!(returned.value.as_deref(), Some("3"));
+
+        // As when compiling, any function that doesn't parse is an error
+        assert!(matches!(
+            Compiler::new().parse_only("I64 main() { return 1; }\nI64 broken() { return 1; "),
+            Err(CompileError::ParseError { .. })
+        ));
+        assert!(matches!(
+            Compiler::new().parse_only("I64 main() { return 1; "),
+            Err(CompileError::ParseError { .. })
+        ));
+        assert!(matches!(
+            Compiler::new().parse_only("I64 main() { return 1 @ 2; }"),
+            Err(CompileError::LexError { .. })
+        ));
+    }
+}
+
+#[cfg(test)]
+mod regalloc_tests {
+    use super::*;
+    use crate::pible::regalloc::{self, Block, Inst, LiveRange, Location};
+
+    const ADD: u8 = 0x00;
+    const SUB: u8 = 0x10;
+    const MUL: u8 = 0x20;
+    const REGISTERS: [u8; 9] = [6, 7, 8, 9, 1, 2, 3, 4, 5];
+
+    /// `v0 op (v1 op (v2 ...))` over `count` constants 1, 2, 3..., which keeps
+    /// every value live until the innermost operation.
+    fn nested(count: usize) -> (Block, i64) {
+        let mut block = Block::new();
+        let values: Vec<_> = (0..count)
+            .map(|i| {
+                let dst = block.vreg();
+                block.push(Inst::Const {
+                    dst,
+                    value: i as i64 + 1,
+                });
+                dst
+            })
+            .collect();
+        let mut expected = count as i64;
+        for i in (0..count - 1).rev() {
+            let (op, value) = match i % 3 {
+                0 => (ADD, i as i64 + 1 + expected),
+                1 => (SUB, i as i64 + 1 - expected),
+                _ => (MUL, (i as i64 + 1) * expected),
+            };
+            block.push(Inst::Alu {
+                op,
+                dst: values[i],
+                src: values[i + 1],
+                offset: 0,
+            });
+            expected = value;
+        }
+        (block, expected)
+    }
+
+    fn run(block: &Block, registers: &[u8]) -> (Vec<BpfInstruction>, i32) {
+        let allocation = regalloc::allocate(block, registers, &[0]);
+        let mut program = regalloc::lower(block, &allocation, |slot| -8 * (slot as i16 + 1));
+        program.push(match allocation.result(0) {
+            Location::Register(reg) => BpfInstruction::new(0xbf, 0, reg, 0, 0),
+            Location::Spill(slot) => BpfInstruction::new(0x79, 0, 10, -8 * (slot as i16 + 1), 0),
+        });
+        program.push(BpfInstruction::new(0x95, 0, 0, 0, 0));
+        let exit_code = BpfVm::new(&program).execute().unwrap().exit_code;
+        (program, exit_code)
+    }
+
+    #[test]
+    fn test_live_ranges_span_definition_to_last_read() {
+        let (block, _) = nested(3);
+        let ranges = regalloc::live_ranges(&block, &[0]);
+        // Constants at 0-2, then v1 -= v2 at 3 and v0 += v1 at 4
+        assert_eq!(ranges[0], Some(LiveRange { start: 0, end: 5 }));
+        assert_eq!(ranges[1], Some(LiveRange { start: 1, end: 4 }));
+        assert_eq!(ranges[2], Some(LiveRange { start: 2, end: 3 }));
+    }
+
+    #[test]
+    fn test_values_fitting_in_registers_are_not_spilled() {
+        let (block, expected) = nested(9);
+        let allocation = regalloc::allocate(&block, &REGISTERS, &[0]);
+        assert_eq!(allocation.spill_slots, 0);
+        assert_eq!(allocation.result(0), Location::Register(6));
+        assert_eq!(run(&block, &REGISTERS).1, expected as i32);
+    }
+
+    #[test]
+    fn test_more_live_values_than_registers_spill() {
+        let (block, expected) = nested(14);
+        let allocation = regalloc::allocate(&block, &REGISTERS, &[0]);
+        // Five values past nine registers, each stored once and loaded back
+        assert_eq!(allocation.spills(), (5, 5));
+        // The same input always gets the same allocation
+        assert_eq!(allocation, regalloc::allocate(&block, &REGISTERS, &[0]));
+
+        let (program, exit_code) = run(&block, &REGISTERS);
+        assert_eq!(exit_code, expected as i32);
+        let stores = program.iter().filter(|i| i.opcode == 0x7b).count();
+        let reloads = program.iter().filter(|i| i.opcode == 0x79).count();
+        assert!(stores > 0 && reloads > 0);
+    }
+
+    #[test]
+    fn test_three_registers_are_enough() {
+        let (block, expected) = nested(6);
+        assert_eq!(run(&block, &[6, 7, 8]).1, expected as i32);
+    }
+
+    const SOURCE: &str = "I64 main() {\n\
+        I64 a = 1; I64 b = 2; I64 c = 3; I64 d = 4; I64 e = 5; I64 f = 6;\n\
+        I64 g = 7; I64 h = 8; I64 i = 9; I64 j = 10; I64 k = 11; I64 l = 12;\n\
+        return a - (b * (c - (d + (e - (f * (g - (h + (i - (j * (k - l))))))))));\n\
+        }";
+
+    fn stores(instructions: &[BpfInstruction]) -> usize {
+        instructions.iter().filter(|i| i.opcode == 0x7b).count()
+    }
+
+    #[test]
+    fn test_allocated_expression_matches_temporaries() {
+        let (a, b, c, d, e, f, g, h, i, j, k, l) = (1i64, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12);
+        let expected = a - (b * (c - (d + (e - (f * (g - (h + (i - (j * (k - l))))))))));
+
+        for opt_level in [0, 2] {
+            let options = CompileOptions {
+                opt_level,
+                ..Default::default()
+            };
+            assert_eq!(
+                run_with(SOURCE, &options),
+                expected as i32,
+                "-O{}",
+                opt_level
+            );
+        }
+    }
+
+    #[test]
+    fn test_allocated_expression_uses_all_registers_before_spilling() {
+        let ast = Compiler::new().parse(SOURCE).unwrap();
+        let stacked = CodeGen::new().generate(&ast).unwrap();
+        let allocated = CodeGen::new()
+            .with_register_allocation(true)
+            .generate(&ast)
+            .unwrap();
+
+        // Twelve values live at once: the stack of temporaries spills the
+        // eight past r6-r9, the allocator only the three past r1-r9
+        let declarations = 12;
+        assert_eq!(stores(&stacked) - declarations, 8);
+        assert_eq!(stores(&allocated) - declarations, 3);
+        assert!(allocated.iter().any(|i| (1..=5).contains(&i.dst_reg)));
+        assert!(allocated.len() < stacked.len());
+    }
+}
+
+#[cfg(test)]
+mod else_if_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+
+    /// `pick(n)` with an arm per `n` below `arms`, each adding `body`
+    /// increments to `n * 3`, and an `else` giving 7.
+    fn ladder(arms: usize, body: usize) -> String {
+        let increments = "x = x + 1; ".repeat(body);
+        let mut source = String::from("U64 pick(U64 n) {\n    U64 x = 0;\n    ");
+        for arm in 0..arms {
+            source += &format!(
+                "if (n == {}) {{ x = {}; {}}}\n    else ",
+                arm,
+                arm * 3,
+                increments
+            );
+        }
+        source += "{ x = 7; }\n    return x;\n}\n";
+        source
+    }
+
+    fn run_pick(source: &str, n: usize) -> i32 {
+        run(&format!("{}U64 main() {{ return pick({}); }}", source, n))
+    }
+
+    #[test]
+    fn test_else_if_is_one_flat_statement() {
+        let ast = Compiler::new().parse(&ladder(30, 0)).unwrap();
+        let body = ast.children[0].children.last().unwrap();
+        let chain = &body.children[1];
+        assert_eq!(chain.value.as_deref(), Some("if"));
+        // A condition and body per arm, then the else
+        assert_eq!(chain.children.len(), 61);
+    }
+
+    #[test]
+    fn test_thirty_arm_ladder_runs() {
+        let source = ladder(30, 0);
+        for n in [0, 1, 14, 28, 29] {
+            assert_eq!(run_pick(&source, n), n as i32 * 3);
+        }
+        assert_eq!(run_pick(&source, 30), 7);
+        assert_eq!(run_pick(&source, 1000), 7);
+    }
+
+    #[test]
+    fn test_long_ladder_is_not_nesting() {
+        let source = format!("{}U64 main() {{ return pick(1999); }}", ladder(2000, 0));
+        assert_eq!(run(&source), 1999 * 3);
+    }
+
+    #[test]
+    fn test_far_exits_go_through_later_exits() {
+        let source = ladder(30, 400);
+        let ast = Compiler::new().parse(&source).unwrap();
+        let instructions = CodeGen::new().generate(&ast).unwrap();
+        // The first arm's exit is further from the end than a jump reaches
+        assert!(instructions.len() > i16::MAX as usize);
+
+        assert_eq!(run_pick(&source, 0), 400);
+        assert_eq!(run_pick(&source, 12), 12 * 3 + 400);
+        assert_eq!(run_pick(&source, 29), 29 * 3 + 400);
+        assert_eq!(run_pick(&source, 30), 7);
+    }
+
+    #[test]
+    fn test_too_long_jump_is_an_error() {
+        let source = format!(
+            "U64 main() {{ U64 x = 0; if (x == 0) {{ {}}} return x; }}",
+            "x = x + 1; ".repeat(12_000)
+        );
+        let ast = Compiler::new().parse(&source).unwrap();
+        assert!(matches!(
+            CodeGen::new().generate(&ast),
+            Err(CodeGenError::JumpTooFar(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod jump_range_tests {
+    use super::*;
+    use crate::pible::codegen::CodeGenError;
+    use crate::pible::compiler::CompileError;
+
+    /// A loop that runs once, its body `statements` increments long, so
+    /// its backward jump spans a little over three times that.
+    fn long_loop(statements: usize) -> String {
+        format!(
+            "U64 main() {{ U64 x = 0; while (x < 1) {{ {}}} return x; }}",
+            "x = x + 1; ".repeat(statements)
+        )
+    }
+
+    #[test]
+    fn test_jump_just_in_range_runs() {
+        assert_eq!(run(&long_loop(10_900)), 10_900);
+    }
+
+    #[test]
+    fn test_jump_out_of_range_is_an_error() {
+        let error = Compiler::new()
+            .compile(&long_loop(11_000), &CompileOptions::default())
+            .unwrap_err();
+        let CompileError::CodeGenError(CodeGenError::JumpTooFar(distance)) = error else {
+            panic!("expected JumpTooFar, got {:?}", error);
+        };
+        assert!(distance.unsigned_abs() > i16::MAX as u64);
+        assert!(error.to_string().contains("too far"));
+    }
+}
+
+#[cfg(test)]
+mod try_compile_tests {
+    use super::*;
+
+    const PROGRAM: &str = r#"
+        U64 sum(U64* values, U64 count) {
+            U64 total = 0;
+            U64 i = 0;
+            while (i < count) { total = total + values[i]; i = i + 1; }
+            return total;
+        }
+        U64 main() {
+            U64 values[3] = {1, 2, 3};
+            U8 low = values[2];
+            if (low > 2) { return sum(values, 3) << 2; } else if (low) { return 0; }
+            return -1;
+        }
+    "#;
+
+    fn targets() -> [CompileOptions<'static>; 3] {
+        [
+            CompileTarget::BpfVm,
+            CompileTarget::LinuxBpf,
+            CompileTarget::SolanaBpf,
+        ]
+        .map(|target| CompileOptions {
+            target,
+            opt_level: 2,
+            ..CompileOptions::default()
+        })
+    }
+
+    #[test]
+    fn test_malformed_input_is_an_error() {
+        let deep_parens = format!(
+            "U64 main() {{ return {}1{}; }}",
+            "(".repeat(100_000),
+            ")".repeat(100_000)
+        );
+        let long_chain = format!("U64 main() {{ return 1{}; }}", " + 1".repeat(100_000));
+        let deep_pointer = format!("U64 main() {{ U8{} p; return 0; }}", "*".repeat(100_000));
+        let long_postfix = format!("U64 main() {{ U64 a; return a{}; }}", "[0]".repeat(100_000));
+        let inputs = [
+            "",
+            "{",
+            "}",
+            "\0\u{1}\u{7f}\u{ff}",
+            "U64 main() { return \"unterminated; }",
+            "U64 main() { U64 a[2]; return a[-9223372036854775808]; }",
+            &deep_parens,
+            &long_chain,
+            &deep_pointer,
+            &long_postfix,
+        ];
+        for options in targets() {
+            for input in inputs {
+                match Compiler::new().try_compile(input, &options) {
+                    Err(CompileError::InternalError(message)) => {
+                        panic!("{:?} panicked: {}", &input[..input.len().min(40)], message)
+                    }
+                    Err(_) => {}
+                    Ok(_) => panic!("{:?} compiled", &input[..input.len().min(40)]),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_truncated_and_mangled_programs_never_panic() {
+        assert!(Compiler::new().try_compile(PROGRAM, &targets()[0]).is_ok());
+        let boundaries: Vec<usize> = (0..=PROGRAM.len())
+            .filter(|&end| PROGRAM.is_char_boundary(end))
+            .collect();
+        for options in targets() {
+            for &end in &boundaries {
+                let truncated = &PROGRAM[..end];
+                let dropped = format!(
+                    "{}{}",
+                    &PROGRAM[..end],
+                    &PROGRAM[(end + 1).min(PROGRAM.len())..]
+                );
+                for source in [truncated, dropped.as_str()] {
+                    if let Err(CompileError::InternalError(message)) =
+                        Compiler::new().try_compile(source, &options)
+                    {
+                        panic!("{:?} panicked: {}", source, message);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_parser_accepts_tokens_without_eof() {
+        let mut lexer = Lexer::new("U64 main() { return 1; }");
+        let mut tokens = lexer.scan_tokens().unwrap();
+        tokens.pop();
+        assert_eq!(Parser::new(tokens).parse().unwrap().children.len(), 1);
+        assert!(Parser::new(Vec::new()).parse().unwrap().children.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod asm_tests {
+    use super::*;
+    use crate::pible::asm::{assemble, emit, AsmError};
+
+    const GOLDEN_SOURCE: &str = r#"
+        U64 square(U64 x) { return x * x; }
+        U64 main() {
+            U64 total = 0;
+            U64 i = 0;
+            while (i < 4) { total = total + square(i); i = i + 1; }
+            if (total != 14) { return -1; }
+            return total / 2 % 5;
+        }
+    "#;
+
+    fn assembly(source: &str, target: CompileTarget) -> String {
+        let options = CompileOptions {
+            target,
+            output_format: OutputFormat::Assembly,
+            ..CompileOptions::default()
+        };
+        String::from_utf8(Compiler::new().compile(source, &options).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_matches_golden_file() {
+        assert_eq!(
+            assembly(GOLDEN_SOURCE, CompileTarget::BpfVm),
+            include_str!("testdata/square.s")
+        );
+    }
+
+    #[test]
+    fn test_every_form_round_trips() {
+        let instructions = vec![
+            BpfInstruction::new(0x07, 1, 0, 0, -5), // add r1, -5
+            BpfInstruction::new(0x0c, 1, 2, 0, 0),  // add32 r1, r2
+            BpfInstruction::new(0x3f, 3, 4, 1, 0),  // sdiv r3, r4
+            BpfInstruction::new(0x97, 3, 0, 1, 7),  // smod r3, 7
+            BpfInstruction::new(0x87, 5, 0, 0, 0),  // neg r5
+            BpfInstruction::new(0x84, 5, 0, 0, 0),  // neg32 r5
+            BpfInstruction::new(0xc7, 5, 0, 0, 63), // arsh r5, 63
+            BpfInstruction::new(0xd4, 6, 0, 0, 16), // le16 r6
+            BpfInstruction::new(0xdc, 6, 0, 0, 64), // be64 r6
+            BpfInstruction::new(0x18, 7, 0, 0, -1), // lddw r7, 0x1ffffffff
+            BpfInstruction::new(0, 0, 0, 0, 1),
+            BpfInstruction::new(0x18, 1, 1, 0, 3), // lddw r1, map_fd 3
+            BpfInstruction::new(0, 0, 0, 0, 0),
+            BpfInstruction::new(0x71, 0, 1, 2, 0), // ldxb r0, [r1+2]
+            BpfInstruction::new(0x69, 0, 10, -6, 0), // ldxh r0, [r10-6]
+            BpfInstruction::new(0x62, 10, 0, -8, 9), // stw [r10-8], 9
+            BpfInstruction::new(0x7b, 10, 1, -16, 0), // stxdw [r10-16], r1
+            BpfInstruction::new(0xc3, 1, 2, 4, 0), // xaddw [r1+4], r2
+            BpfInstruction::new(0x05, 0, 0, -3, 0), // ja -3
+            BpfInstruction::new(0x15, 1, 0, 2, 0), // jeq r1, 0, +2
+            BpfInstruction::new(0xdd, 1, 2, -1, 0), // jsle r1, r2, -1
+            BpfInstruction::new(0x85, 0, 0, 0, 6), // call 6
+            BpfInstruction::new(0x85, 0, 1, 0, -12), // call local -12
+            BpfInstruction::new(0x95, 0, 0, 0, 0), // exit
+        ];
+        let text = emit(&instructions).unwrap();
+        assert!(text.contains("lddw r7, 0x1ffffffff\nlddw r1, map_fd 3\n"));
+        assert!(text.contains("sdiv r3, r4\nsmod r3, 7\n"));
+        assert!(text.contains("xaddw [r1+4], r2\nja -3\njeq r1, 0, +2\n"));
+        assert_eq!(text.lines().count(), instructions.len() - 2);
+        assert_eq!(assemble(&text).unwrap(), instructions);
+    }
+
+    #[test]
+    fn test_compiled_programs_round_trip() {
+        let all = [
+            CompileTarget::BpfVm,
+            CompileTarget::LinuxBpf,
+            CompileTarget::SolanaBpf,
+        ];
+        let programs: [(&str, &[CompileTarget]); 3] = [
+            (GOLDEN_SOURCE, &all),
+            (
+                "map hash<U32, U64> counts[64];\n\
+                 U64 main() { U32 key[1]; key[0] = 7;\n\
+                 U64* value = map_lookup_elem(counts, key);\n\
+                 if (value == 0) { return 0; } return value[0]; }",
+                &[CompileTarget::LinuxBpf],
+            ),
+            (
+                "U64 main() { I8 small = -3; U8* text = \"hi\"; return small / 2 + text[1]; }",
+                &all,
+            ),
+        ];
+        for (source, targets) in programs {
+            for &target in targets {
+                let raw = Compiler::new()
+                    .compile(
+                        source,
+                        &CompileOptions {
+                            target,
+                            ..CompileOptions::default()
+                        },
+                    )
+                    .unwrap();
+                let instructions: Vec<BpfInstruction> = raw
+                    .chunks(8)
+                    .map(|chunk| BpfInstruction::from_bytes(chunk.try_into().unwrap()))
+                    .collect();
+                let text = assembly(source, target);
+                assert_eq!(emit(&instructions).unwrap(), text);
+                assert_eq!(assemble(&text).unwrap(), instructions);
+            }
+        }
+    }
+
+    #[test]
+    fn test_assemble_skips_comments_and_reports_bad_lines() {
+        let instructions = assemble("# entry\n\n  mov r0, 1  # result\nexit\n").unwrap();
+        assert_eq!(
+            instructions,
+            [
+                BpfInstruction::new(0xb7, 0, 0, 0, 1),
+                BpfInstruction::new(0x95, 0, 0, 0, 0),
+            ]
+        );
+        for (text, message) in [
+            ("exit\nfrob r1", "unknown mnemonic `frob`"),
+            ("exit\nja 3", "expected an offset"),
+            ("exit\nadd r11, 1", "expected a register"),
+            ("exit\nldxw r0, [r1]", "needs an offset"),
+        ] {
+            let error = assemble(text).unwrap_err();
+            let AsmError::Syntax { line: 2, .. } = error else {
+                panic!("expected a syntax error on line 2, got {:?}", error);
+            };
+            assert!(error.to_string().contains(message), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_unencodable_instructions_are_errors() {
+        for instruction in [
+            BpfInstruction::new(0xff, 0, 0, 0, 0), // no such opcode
+            BpfInstruction::new(0xb7, 0, 0, 5, 1), // mov with a stray offset
+            BpfInstruction::new(0x95, 1, 0, 0, 0), // exit with a register
+            BpfInstruction::new(0xdb, 1, 2, 0, 1), // atomic other than add
+        ] {
+            assert_eq!(
+                emit(&[instruction]),
+                Err(AsmError::Unsupported {
+                    index: 0,
+                    opcode: instruction.opcode
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_assembly_needs_a_bpf_target() {
+        let options = CompileOptions {
+            target: CompileTarget::BpfVm,
+            output_format: OutputFormat::Assembly,
+            ..CompileOptions::default()
+        };
+        assert!(Compiler::new()
+            .compile("U64 main() { return 0; }", &options)
+            .is_ok());
+        #[cfg(feature = "riscv")]
+        assert!(matches!(
+            Compiler::new().compile(
+                "U64 main() { return 0; }",
+                &CompileOptions {
+                    target: CompileTarget::RiscV,
+                    ..options
+                }
+            ),
+            Err(crate::pible::compiler::CompileError::UnsupportedTarget(_))
+        ));
+    }
+}
+
+#[cfg(test)]
+mod compiler_cache_tests {
+    use super::*;
+    use crate::pible::CompilerCache;
+    use std::fs;
+    use std::path::Path;
+
+    fn options(output: &Path) -> CompileOptions<'static> {
+        CompileOptions {
+            target: CompileTarget::BpfVm,
+            output_path: Some(output.display().to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_unchanged_file_hits_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.hc");
+        let output = dir.path().join("main.bpf");
+        fs::write(&main, "U64 main() { return 7; }\n").unwrap();
+        let mut cache = CompilerCache::new();
+
+        let path = main.to_str().unwrap();
+        Compiler::new()
+            .compile_file_cached(path, &options(&output), &mut cache)
+            .unwrap();
+        let compiled = fs::read(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        Compiler::new()
+            .compile_file_cached(path, &options(&output), &mut cache)
+            .unwrap();
+
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(fs::read(&output).unwrap(), compiled);
+    }
+
+    #[test]
+    fn test_changed_source_or_options_miss_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.hc");
+        let output = dir.path().join("main.bpf");
+        let path = main.to_str().unwrap();
+        let mut cache = CompilerCache::new();
+        let mut compile = |options: &CompileOptions| {
+            Compiler::new()
+                .compile_file_cached(path, options, &mut cache)
+                .unwrap();
+            fs::read(&output).unwrap()
+        };
+
+        fs::write(&main, "U64 main() { return 7; }\n").unwrap();
+        let first = compile(&options(&output));
+        fs::write(&main, "U64 main() { return 8; }\n").unwrap();
+        let edited = compile(&options(&output));
+        let assembly = compile(&CompileOptions {
+            output_format: OutputFormat::Assembly,
+            ..options(&output)
+        });
+
+        assert_ne!(first, edited);
+        assert_ne!(edited, assembly);
+        assert_eq!((cache.hits(), cache.misses()), (0, 3));
+    }
+
+    #[test]
+    fn test_changed_include_misses_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("lib")).unwrap();
+        let lib = dir.path().join("lib/value.hc");
+        let main = dir.path().join("main.hc");
+        let output = dir.path().join("main.bpf");
+        fs::write(&lib, "U64 value() { return 1; }\n").unwrap();
+        fs::write(
+            &main,
+            "#include \"lib/value.hc\"\nU64 main() { return value(); }\n",
+        )
+        .unwrap();
+        let path = main.to_str().unwrap();
+        let mut cache = CompilerCache::new();
+
+        Compiler::new()
+            .compile_file_cached(path, &options(&output), &mut cache)
+            .unwrap();
+        Compiler::new()
+            .compile_file_cached(path, &options(&output), &mut cache)
+            .unwrap();
+        let before = fs::read(&output).unwrap();
+        fs::write(&lib, "U64 value() { return 2; }\n").unwrap();
+        Compiler::new()
+            .compile_file_cached(path, &options(&output), &mut cache)
+            .unwrap();
+
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+        assert_ne!(fs::read(&output).unwrap(), before);
+    }
+
+    #[test]
+    fn test_failed_compile_is_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.hc");
+        let output = dir.path().join("main.bpf");
+        fs::write(&main, "U64 main() { return missing; }\n").unwrap();
+        let path = main.to_str().unwrap();
+        let mut cache = CompilerCache::new();
+
+        for _ in 0..2 {
+            assert!(Compiler::new()
+                .compile_file_cached(path, &options(&output), &mut cache)
+                .is_err());
+        }
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+        assert!(!output.exists());
+    }
+}
+
+#[cfg(test)]
+mod enum_tests {
+    use super::*;
+    use crate::pible::formatter::format_source;
+    use std::fs;
+
+    const ERROR_CODE: &str = r#"
+        enum ErrorCode {
+            OK,
+            INSUFFICIENT_FUNDS = 1,
+            INVALID_OWNER,
+            OVERFLOW = 10,
+            UNDERFLOW,
+        };
+        enum State { IDLE, RUNNING };
+
+        ErrorCode check(U64 balance, U64 amount) {
+            if (balance < amount) { return INSUFFICIENT_FUNDS; }
+            return OK;
+        }
+
+        U64 main() {
+            return check(1, 2) * 100 + UNDERFLOW + RUNNING;
+        }
+    "#;
+
+    #[test]
+    fn test_variants_count_up_from_the_last_value() {
+        assert_eq!(run(ERROR_CODE), 100 + 11 + 1);
+    }
+
+    #[test]
+    fn test_variants_are_constants() {
+        let source = "enum Size { SMALL = 2, LARGE = SMALL * 4 };\n\
+                      U64 main() { U64 buffer[LARGE]; return sizeof(buffer); }";
+        assert_eq!(run(source), 64);
+
+        let assigned = "enum E { A }; U64 main() { A = 3; return A; }";
+        assert!(Compiler::new()
+            .compile(assigned, &CompileOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_idl_lists_error_code_variants() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("program.hc");
+        fs::write(&input, ERROR_CODE).unwrap();
+        let options = CompileOptions {
+            target: CompileTarget::SolanaBpf,
+            generate_idl: true,
+            output_directory: dir.path().to_str(),
+            ..Default::default()
+        };
+        Compiler::new()
+            .compile_file(input.to_str().unwrap(), &options)
+            .unwrap();
+
+        let idl: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("program.json")).unwrap())
+                .unwrap();
+        // `State` doesn't name an error enum, so only `ErrorCode` is listed
+        assert_eq!(
+            idl["errors"],
+            serde_json::json!([
+                { "code": 0, "name": "OK" },
+                { "code": 1, "name": "INSUFFICIENT_FUNDS" },
+                { "code": 2, "name": "INVALID_OWNER" },
+                { "code": 10, "name": "OVERFLOW" },
+                { "code": 11, "name": "UNDERFLOW" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_formatter_puts_each_variant_on_its_own_line() {
+        assert_eq!(
+            format_source("enum  E{A=1,B};").unwrap(),
+            "enum E {\n    A = 1,\n    B\n};\n"
+        );
+    }
+}